@@ -0,0 +1,23 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Generate the gRPC service of mpf grpc from proto/mpf.proto. protox compiles the .proto in
+// process, so the build does not need protoc.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/mpf.proto");
+    let descriptors = protox::compile(["mpf.proto"], ["proto"])?;
+    tonic_prost_build::compile_fds(descriptors)?;
+    Ok(())
+}
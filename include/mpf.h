@@ -0,0 +1,79 @@
+/*
+ * Copyright [2022] [Mark Benvenuto]
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/*
+ * C API of libmpf. Link with -lmpf from the cdylib or staticlib that cargo builds next to the
+ * mpf binary.
+ *
+ * Results are NUL terminated JSON strings owned by the caller, release them with mpf_free.
+ * Failures return NULL and mpf_last_error says why. Calls from several threads are safe and
+ * run concurrently, each with its own connection options.
+ */
+
+#ifndef MPF_H
+#define MPF_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * The JSON summary printed by mpf without arguments, without running classifier plugins.
+ * NULL on failure.
+ */
+char *mpf_scan(void);
+
+/**
+ * The processes matched by command line filters such as {"-p", "20000-20010", "--replset",
+ * "rs0"}, as a JSON array of {pid, type, port, uri}. NULL on failure, including for arguments
+ * mpf would reject. Connection options among the arguments, such as --timeout, --tlsCAFile
+ * or --username, only apply to this call.
+ *
+ * # Safety
+ *
+ * argv must point to argc valid NUL terminated strings, or be NULL when argc is 0.
+ */
+char *mpf_find(const char *const *argv, size_t argc);
+
+/**
+ * Release a string returned by mpf_scan or mpf_find, NULL is ignored
+ *
+ * # Safety
+ *
+ * s must come from mpf_scan or mpf_find and not have been freed yet.
+ */
+void mpf_free(char *s);
+
+/**
+ * Why the last call on this thread returned NULL, or NULL after a success. The string stays
+ * valid until the next mpf call on the thread.
+ */
+const char *mpf_last_error(void);
+
+/**
+ * Version of the library, a static string
+ */
+const char *mpf_version(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MPF_H */
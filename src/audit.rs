@@ -0,0 +1,254 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Static checks of server configurations for settings that are risky outside a dev box
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use super::auth::check_mixed_cluster_auth;
+use super::config::{get_path, local_effective_config};
+use super::encryption::check_mixed_encryption;
+use super::fork::check_pidfiles;
+use super::keyfile::check_key_files;
+use super::storage::check_mixed_engines;
+use super::types::CommonProcInfo;
+use super::{has_cmd_line_flag, MongoPSInfo};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    High,
+    Medium,
+    Low,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::High => "HIGH",
+            Severity::Medium => "MEDIUM",
+            Severity::Low => "LOW",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Finding {
+    pub pid: i32,
+    pub severity: Severity,
+    pub message: String,
+}
+
+fn is_true(value: Option<&Value>) -> bool {
+    match value {
+        Some(Value::Bool(b)) => *b,
+        Some(Value::Number(n)) => n.as_i64() == Some(1),
+        Some(Value::String(s)) => s == "true" || s == "1",
+        _ => false,
+    }
+}
+
+fn is_local_address(address: &str) -> bool {
+    matches!(address, "localhost" | "::1")
+        || address.starts_with("127.")
+        || address.starts_with('/')
+}
+
+/// Addresses the server listens on that are reachable from other machines
+pub fn external_bind_addresses(config: &Value) -> Vec<String> {
+    if is_true(get_path(config, "net.bindIpAll")) {
+        return vec!["*".to_owned()];
+    }
+
+    let bind_ip = match get_path(config, "net.bindIp") {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(a)) => a
+            .iter()
+            .filter_map(|v| v.as_str())
+            .collect::<Vec<_>>()
+            .join(","),
+        _ => return Vec::new(),
+    };
+    bind_ip
+        .split(',')
+        .map(|a| a.trim())
+        .filter(|a| !a.is_empty() && !is_local_address(a))
+        .map(|a| a.to_owned())
+        .collect()
+}
+
+fn audit_server(proc: &CommonProcInfo, config: &Value) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut add = |severity, message: String| {
+        findings.push(Finding {
+            pid: proc.pid,
+            severity,
+            message,
+        })
+    };
+
+    let external = external_bind_addresses(config);
+    let auth = get_path(config, "security.authorization").and_then(|v| v.as_str())
+        == Some("enabled")
+        || get_path(config, "security.keyFile").is_some();
+
+    if !external.is_empty() && !auth {
+        add(
+            Severity::High,
+            format!(
+                "bound to {} without --auth or --keyFile",
+                external.join(",")
+            ),
+        );
+    }
+
+    if has_cmd_line_flag("--noauth", &proc.cmdline) {
+        add(
+            Severity::Medium,
+            "authorization explicitly disabled with --noauth".to_owned(),
+        );
+    }
+
+    if is_true(get_path(config, "setParameter.enableTestCommands")) {
+        if external.is_empty() {
+            add(Severity::Low, "enableTestCommands is set".to_owned());
+        } else {
+            add(
+                Severity::High,
+                format!(
+                    "enableTestCommands is set while bound to {}",
+                    external.join(",")
+                ),
+            );
+        }
+    }
+
+    findings
+}
+
+/// Flag the members of a group whose value of a setting differs from the most common one, or
+/// every member when no value is the most common
+pub fn mixed_setting(setting: &str, group: &str, members: &[(i32, String)]) -> Vec<Finding> {
+    let mut values: BTreeMap<&str, usize> = BTreeMap::new();
+    for (_, value) in members {
+        *values.entry(value).or_default() += 1;
+    }
+    if values.len() < 2 {
+        return Vec::new();
+    }
+    let count = *values.values().max().unwrap();
+    let common: Vec<&str> = values
+        .iter()
+        .filter(|(_, c)| **c == count)
+        .map(|(value, _)| *value)
+        .collect();
+
+    // Without a single most common value there is no telling which members are wrong
+    let message = |value: &str| match common.as_slice() {
+        [common] => format!(
+            "{} is {} while {} of {} members of {} use {}",
+            setting,
+            value,
+            count,
+            members.len(),
+            group,
+            common
+        ),
+        _ => format!(
+            "{} is {} while members of {} mix {}",
+            setting,
+            value,
+            group,
+            values.keys().cloned().collect::<Vec<&str>>().join(", ")
+        ),
+    };
+    members
+        .iter()
+        .filter(|(_, value)| common.len() > 1 || *value != common[0])
+        .map(|(pid, value)| Finding {
+            pid: *pid,
+            severity: Severity::Medium,
+            message: message(value),
+        })
+        .collect()
+}
+
+pub fn audit(info: &MongoPSInfo, pids: &[i32]) -> Vec<Finding> {
+    let mut configs = Vec::new();
+    for pid in pids {
+        let is_server =
+            info.mongod.iter().any(|d| d.pid == *pid) || info.mongos.iter().any(|s| s.pid == *pid);
+        let Some(proc) = info.proc_info(*pid).filter(|_| is_server) else {
+            continue;
+        };
+
+        match local_effective_config(proc) {
+            Ok(config) => configs.push((proc, config)),
+            Err(e) => eprintln!("{}: {}", pid, e),
+        }
+    }
+
+    let mut findings: Vec<Finding> = configs
+        .iter()
+        .flat_map(|(proc, config)| audit_server(proc, config))
+        .collect();
+    findings.extend(check_key_files(info, &configs));
+    findings.extend(check_mixed_encryption(info, pids));
+    findings.extend(check_mixed_cluster_auth(info, pids));
+    findings.extend(check_mixed_engines(info, pids));
+    findings.extend(check_pidfiles(info, pids));
+
+    findings.sort_by_key(|f| (f.severity, f.pid));
+    findings
+}
+
+/// Print the findings, failing when there are any so scripts can gate on the result
+pub fn print_audit(findings: &[Finding]) -> Result<()> {
+    if findings.is_empty() {
+        println!("No findings");
+        return Ok(());
+    }
+
+    for finding in findings {
+        println!(
+            "{:<6} {:>7}  {}",
+            finding.severity, finding.pid, finding.message
+        );
+    }
+
+    let count = |severity| findings.iter().filter(|f| f.severity == severity).count();
+    bail!(
+        "{} finding(s): {} high, {} medium, {} low",
+        findings.len(),
+        count(Severity::High),
+        count(Severity::Medium),
+        count(Severity::Low)
+    )
+}
+
+#[test]
+fn test_external_bind_addresses() {
+    use serde_json::json;
+
+    let config = json!({"net": {"bindIp": "localhost,127.0.0.1,10.0.0.5"}});
+    assert_eq!(external_bind_addresses(&config), vec!["10.0.0.5"]);
+    let config = json!({"net": {"bindIp": "localhost", "bindIpAll": true}});
+    assert_eq!(external_bind_addresses(&config), vec!["*"]);
+    let config = json!({"net": {"bindIp": "::1,/tmp/mongodb-27017.sock"}});
+    assert!(external_bind_addresses(&config).is_empty());
+}
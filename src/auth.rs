@@ -0,0 +1,224 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Authentication settings of a server, used to pick how to connect to it
+
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::audit::{mixed_setting, Finding};
+use super::config::get_path;
+use super::types::CommonProcInfo;
+use super::MongoPSInfo;
+
+const DEFAULT_MECHANISMS: &[&str] = &["SCRAM-SHA-1", "SCRAM-SHA-256"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AuthInfo {
+    pub enabled: bool,
+    pub mechanisms: Vec<String>,
+    pub cluster_auth_mode: Option<String>,
+    pub key_file: Option<String>,
+    pub transition_to_auth: bool,
+}
+
+fn as_str<'a>(config: &'a Value, path: &str) -> Option<&'a str> {
+    get_path(config, path).and_then(|v| v.as_str())
+}
+
+/// Authentication settings from the effective configuration. TLS enables x509 authentication
+/// and a Kerberos keytab in the environment enables GSSAPI.
+pub fn auth_info(proc: &CommonProcInfo, config: &Value, tls_enabled: bool) -> AuthInfo {
+    let key_file = as_str(config, "security.keyFile").map(|s| s.to_owned());
+    let cluster_auth_mode = as_str(config, "security.clusterAuthMode").map(|s| s.to_owned());
+    let transition_to_auth = get_path(config, "security.transitionToAuth")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    let enabled = !transition_to_auth
+        && (as_str(config, "security.authorization") == Some("enabled")
+            || key_file.is_some()
+            || cluster_auth_mode
+                .as_deref()
+                .is_some_and(|mode| mode != "keyFile" || key_file.is_some()));
+
+    let mut mechanisms: Vec<String> = match as_str(config, "setParameter.authenticationMechanisms")
+    {
+        Some(list) => list
+            .split(',')
+            .map(|m| m.trim().to_owned())
+            .filter(|m| !m.is_empty())
+            .collect(),
+        None => {
+            let mut mechanisms: Vec<String> =
+                DEFAULT_MECHANISMS.iter().map(|m| m.to_string()).collect();
+            if tls_enabled {
+                mechanisms.push("MONGODB-X509".to_owned());
+            }
+            if get_path(config, "security.ldap.servers").is_some()
+                || get_path(config, "security.sasl.saslauthdSocketPath").is_some()
+            {
+                mechanisms.push("PLAIN".to_owned());
+            }
+            if get_path(config, "security.sasl.serviceName").is_some()
+                || proc.env.contains_key(std::ffi::OsStr::new("KRB5_KTNAME"))
+            {
+                mechanisms.push("GSSAPI".to_owned());
+            }
+            mechanisms
+        }
+    };
+    mechanisms.dedup();
+
+    AuthInfo {
+        enabled,
+        mechanisms,
+        cluster_auth_mode,
+        key_file,
+        transition_to_auth,
+    }
+}
+
+impl AuthInfo {
+    /// Mechanism a client should use, None when drivers can negotiate SCRAM on their own
+    pub fn client_mechanism(&self) -> Option<&str> {
+        if !self.enabled || self.mechanisms.iter().any(|m| m.starts_with("SCRAM")) {
+            return None;
+        }
+        ["MONGODB-X509", "GSSAPI", "PLAIN"]
+            .into_iter()
+            .find(|m| self.mechanisms.iter().any(|s| s == m))
+    }
+
+    /// Connection string options needed to authenticate
+    pub fn uri_options(&self) -> Vec<String> {
+        match self.client_mechanism() {
+            // Only SCRAM users live in a regular database, the rest are external
+            Some(mechanism) => vec![
+                format!("authMechanism={}", mechanism),
+                "authSource=$external".to_owned(),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    /// How the server authenticates to the other members of its replica set or cluster
+    pub fn internal_auth(&self) -> String {
+        let mode = match (&self.cluster_auth_mode, &self.key_file) {
+            (Some(mode), _) => mode.clone(),
+            (None, Some(_)) => "keyFile".to_owned(),
+            (None, None) => "none".to_owned(),
+        };
+        if self.transition_to_auth {
+            format!("{} with transitionToAuth", mode)
+        } else {
+            mode
+        }
+    }
+}
+
+/// Flag replica sets and sharded clusters whose members authenticate to each other with
+/// different keyFile, clusterAuthMode or x509 settings, which fills the logs with failed
+/// authentication between nodes
+pub fn check_mixed_cluster_auth(info: &MongoPSInfo, pids: &[i32]) -> Vec<Finding> {
+    let member = |pid: &i32| Some((*pid, info.auth_info(*pid)?.internal_auth()));
+
+    let mut groups: Vec<(String, Vec<(i32, String)>)> = Vec::new();
+    let mut sets: BTreeMap<&str, Vec<i32>> = BTreeMap::new();
+    for d in info.mongod.iter().filter(|d| pids.contains(&d.pid)) {
+        if let Some(ref repl_set) = d.replica_set_name {
+            sets.entry(repl_set).or_default().push(d.pid);
+        }
+    }
+    for (repl_set, members) in sets {
+        let members = members.iter().filter_map(member).collect();
+        groups.push((format!("replica set {}", repl_set), members));
+    }
+    for cluster in &info.clusters {
+        let Some(ref config) = cluster.config_replica_set else {
+            continue;
+        };
+        let members = cluster
+            .config_servers
+            .iter()
+            .chain(&cluster.mongos)
+            .chain(cluster.shards.iter().flat_map(|s| &s.pids))
+            .filter(|pid| pids.contains(pid))
+            .filter_map(member)
+            .collect();
+        groups.push((format!("cluster {}", config), members));
+    }
+
+    groups
+        .iter()
+        .flat_map(|(group, members)| mixed_setting("internal auth", group, members))
+        .collect()
+}
+
+#[test]
+fn test_auth_info() {
+    use serde_json::json;
+
+    let proc = CommonProcInfo {
+        pid: 1,
+        ppid: None,
+        program: "mongod".to_owned(),
+        cmdline: vec![],
+        env: Default::default(),
+        cwd: None,
+        exe: None,
+        uid: None,
+        start_time: None,
+        rss: None,
+    };
+
+    let auth = auth_info(
+        &proc,
+        &json!({"security": {"authorization": "disabled"}}),
+        false,
+    );
+    assert!(!auth.enabled);
+
+    let auth = auth_info(&proc, &json!({"security": {"keyFile": "/tmp/key"}}), false);
+    assert!(auth.enabled);
+    assert!(auth.uri_options().is_empty());
+
+    let config = json!({
+        "security": {"authorization": "enabled", "clusterAuthMode": "x509"},
+        "setParameter": {"authenticationMechanisms": "MONGODB-X509"}
+    });
+    let auth = auth_info(&proc, &config, true);
+    assert_eq!(auth.client_mechanism(), Some("MONGODB-X509"));
+    assert_eq!(auth.internal_auth(), "x509");
+
+    let members = vec![
+        (1, "keyFile".to_owned()),
+        (2, "keyFile".to_owned()),
+        (3, "none".to_owned()),
+    ];
+    let findings = mixed_setting("internal auth", "replica set rs0", &members);
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].pid, 3);
+    assert_eq!(
+        findings[0].message,
+        "internal auth is none while 2 of 3 members of replica set rs0 use keyFile"
+    );
+    assert!(mixed_setting("internal auth", "replica set rs0", &members[..2]).is_empty());
+    assert_eq!(
+        mixed_setting("internal auth", "replica set rs0", &members[1..]).len(),
+        2
+    );
+}
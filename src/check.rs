@@ -0,0 +1,454 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Health checks of a local fixture for CI, reported as JSON and failing when any check fails
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+
+use super::client::{server_version, Connection, Settings};
+use super::describe::format_age;
+#[cfg(target_os = "linux")]
+use super::linux::listening_processes;
+#[cfg(target_os = "macos")]
+use super::macos::listening_processes;
+use super::probe::probe_all;
+use super::replication::date_millis;
+use super::startup::log_startup_phase;
+use super::{MongoPSInfo, ServerTarget};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Check {
+    /// Every server answers hello on its port
+    AllListening,
+    /// Every replica set has a primary
+    HasPrimary,
+    /// Every server runs the same version
+    NoVersionSkew,
+    /// No two servers use the same port or dbpath
+    NoConflicts,
+    /// No server clock or last write optime is off from the host clock by more than --max-skew
+    NoClockSkew,
+    /// Every mongod of a sharded cluster or replica set has the same featureCompatibilityVersion
+    NoFcvSkew,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct CheckResult {
+    pub name: String,
+    pub ok: bool,
+    pub failures: Vec<String>,
+}
+
+/// What the checks look at, gathered from the servers once
+pub struct Observed {
+    pub hello: Vec<Option<Value>>,
+    /// Host time in milliseconds when each hello reply arrived
+    pub received: Vec<Option<i64>>,
+    pub versions: Vec<Option<String>>,
+    /// featureCompatibilityVersion of each mongod, None for mongos
+    pub fcv: Vec<Option<String>>,
+    /// Processes listening on the port of each server, with their program names
+    pub listeners: Vec<Vec<(i32, String)>>,
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+fn hello(settings: &Settings, port: i32) -> Option<(Value, i64)> {
+    let mut conn = Connection::connect_local(settings, port, Duration::from_secs(2)).ok()?;
+    let reply = conn.run_command("admin", json!({"hello": 1})).ok()?;
+    Some((reply, now_millis()))
+}
+
+fn fcv(settings: &Settings, port: i32) -> Option<String> {
+    let mut conn = Connection::connect_local(settings, port, Duration::from_secs(2)).ok()?;
+    let reply = conn
+        .run_command(
+            "admin",
+            json!({"getParameter": 1, "featureCompatibilityVersion": 1}),
+        )
+        .ok()?;
+    let version = reply.get("featureCompatibilityVersion")?.get("version")?;
+    Some(version.as_str()?.to_owned())
+}
+
+pub fn observe(info: &MongoPSInfo, targets: &[ServerTarget]) -> Observed {
+    let settings = &info.settings;
+    let (hello, received) = probe_all(settings, targets, |t| hello(settings, t.port))
+        .into_iter()
+        .map(|h| match h {
+            Some((reply, received)) => (Some(reply), Some(received)),
+            None => (None, None),
+        })
+        .unzip();
+    Observed {
+        hello,
+        received,
+        versions: probe_all(settings, targets, |t| server_version(settings, t.port).ok()),
+        fcv: probe_all(settings, targets, |t| {
+            if info.mongod.iter().any(|d| d.pid == t.pid) {
+                fcv(settings, t.port)
+            } else {
+                None
+            }
+        }),
+        listeners: targets
+            .iter()
+            .map(|t| listening_processes(t.port as u16).unwrap_or_default())
+            .collect(),
+    }
+}
+
+// How far ahead of the host a server clock is, negative when behind
+fn describe_offset(millis: i64) -> String {
+    if millis >= 0 {
+        format!("{}ms ahead of", millis)
+    } else {
+        format!("{}ms behind", -millis)
+    }
+}
+
+fn name(check: Check) -> String {
+    check.to_possible_value().unwrap().get_name().to_owned()
+}
+
+// Values used by more than one server, with the servers using them
+fn duplicates<I>(values: I) -> Vec<(String, Vec<i32>)>
+where
+    I: Iterator<Item = (String, i32)>,
+{
+    let mut seen: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+    for (value, pid) in values {
+        seen.entry(value).or_default().push(pid);
+    }
+    seen.into_iter()
+        .filter(|(_, pids)| pids.len() > 1)
+        .collect()
+}
+
+// Pids of the mongods that must agree on FCV: each sharded cluster and each replica set
+// outside of one
+fn fcv_groups(info: &MongoPSInfo) -> Vec<(String, Vec<i32>)> {
+    let mut groups = Vec::new();
+    let mut in_cluster = Vec::new();
+    for cluster in &info.clusters {
+        let Some(ref config) = cluster.config_replica_set else {
+            continue;
+        };
+        let pids: Vec<i32> = cluster
+            .config_servers
+            .iter()
+            .chain(cluster.shards.iter().flat_map(|s| &s.pids))
+            .cloned()
+            .collect();
+        in_cluster.extend(pids.iter().cloned());
+        groups.push((format!("cluster {}", config), pids));
+    }
+
+    let mut sets: BTreeMap<&str, Vec<i32>> = BTreeMap::new();
+    for d in info.mongod.iter().filter(|d| !in_cluster.contains(&d.pid)) {
+        if let Some(ref repl_set) = d.replica_set_name {
+            sets.entry(repl_set).or_default().push(d.pid);
+        }
+    }
+    groups.extend(
+        sets.into_iter()
+            .map(|(set, pids)| (format!("replica set {}", set), pids)),
+    );
+    groups
+}
+
+// A pid with how long ago it started when known
+fn started(info: &MongoPSInfo, pid: i32) -> String {
+    match info.proc_info(pid).and_then(|p| p.start_time) {
+        Some(start) => {
+            let now = (now_millis() / 1000) as u64;
+            format!(
+                "{} (started {} ago)",
+                pid,
+                format_age(now.saturating_sub(start))
+            )
+        }
+        None => pid.to_string(),
+    }
+}
+
+pub fn run_check(
+    check: Check,
+    info: &MongoPSInfo,
+    targets: &[ServerTarget],
+    observed: &Observed,
+    max_skew: Duration,
+) -> CheckResult {
+    let mut failures = Vec::new();
+    match check {
+        Check::AllListening => {
+            for (t, hello) in targets.iter().zip(&observed.hello) {
+                if hello.is_none() {
+                    let logpath = match info.mongod.iter().find(|d| d.pid == t.pid) {
+                        Some(d) => d.logpath.as_deref(),
+                        None => info
+                            .mongos
+                            .iter()
+                            .find(|s| s.pid == t.pid)
+                            .and_then(|s| s.logpath.as_deref()),
+                    };
+                    failures.push(match log_startup_phase(logpath) {
+                        Some(phase) => {
+                            format!("{} on port {} is starting: {}", t.pid, t.port, phase)
+                        }
+                        None => format!("{} does not answer on port {}", t.pid, t.port),
+                    });
+                }
+            }
+        }
+        Check::HasPrimary => {
+            let mut sets: BTreeMap<&str, bool> = BTreeMap::new();
+            for (t, hello) in targets.iter().zip(&observed.hello) {
+                let Some(set) = info
+                    .mongod
+                    .iter()
+                    .find(|d| d.pid == t.pid)
+                    .and_then(|d| d.replica_set_name.as_deref())
+                else {
+                    continue;
+                };
+                let primary = hello.as_ref().is_some_and(|h| {
+                    h.get("isWritablePrimary").and_then(|p| p.as_bool()) == Some(true)
+                });
+                *sets.entry(set).or_default() |= primary;
+            }
+            for (set, _) in sets.iter().filter(|(_, primary)| !**primary) {
+                failures.push(format!("replica set {} has no primary", set));
+            }
+        }
+        Check::NoVersionSkew => {
+            let mut versions: BTreeMap<&str, Vec<i32>> = BTreeMap::new();
+            for (t, version) in targets.iter().zip(&observed.versions) {
+                if let Some(version) = version {
+                    versions.entry(version).or_default().push(t.port);
+                }
+            }
+            if versions.len() > 1 {
+                let found: Vec<String> = versions
+                    .iter()
+                    .map(|(version, ports)| format!("{} on ports {:?}", version, ports))
+                    .collect();
+                failures.push(format!(
+                    "servers run different versions: {}",
+                    found.join(", ")
+                ));
+            }
+        }
+        Check::NoConflicts => {
+            let ports = targets.iter().map(|t| (t.port.to_string(), t.pid));
+            for (port, pids) in duplicates(ports) {
+                failures.push(format!("port {} is used by {:?}", port, pids));
+            }
+            // Another program holding the port makes the server fail with "Address already in use"
+            for (t, listeners) in targets.iter().zip(&observed.listeners) {
+                for (pid, program) in listeners {
+                    if info.proc_info(*pid).is_none() {
+                        failures.push(format!(
+                            "port {} of {} is held by {} {}",
+                            t.port, t.pid, program, pid
+                        ));
+                    }
+                }
+            }
+            // Compare the paths the servers really use, a second mongod may still be waiting
+            // on the lock file
+            let dbpaths = info
+                .mongod
+                .iter()
+                .filter(|d| targets.iter().any(|t| t.pid == d.pid))
+                .map(|d| {
+                    let dbpath = d.dbpath.as_deref().unwrap_or("/data/db");
+                    let canonical = std::fs::canonicalize(dbpath)
+                        .map(|p| p.to_string_lossy().into_owned())
+                        .unwrap_or_else(|_| dbpath.to_owned());
+                    (canonical, d.pid)
+                });
+            for (dbpath, pids) in duplicates(dbpaths) {
+                let users: Vec<String> = pids.iter().map(|pid| started(info, *pid)).collect();
+                failures.push(format!(
+                    "critical: data corruption risk, dbpath {} is used by {}",
+                    dbpath,
+                    users.join(", ")
+                ));
+            }
+        }
+        Check::NoFcvSkew => {
+            for (group, pids) in fcv_groups(info) {
+                let mut versions: BTreeMap<&str, Vec<i32>> = BTreeMap::new();
+                for (t, fcv) in targets.iter().zip(&observed.fcv) {
+                    if let Some(fcv) = fcv.as_deref().filter(|_| pids.contains(&t.pid)) {
+                        versions.entry(fcv).or_default().push(t.port);
+                    }
+                }
+                if versions.len() > 1 {
+                    let found: Vec<String> = versions
+                        .iter()
+                        .map(|(fcv, ports)| format!("{} on ports {:?}", fcv, ports))
+                        .collect();
+                    failures.push(format!(
+                        "{} has different featureCompatibilityVersions: {}",
+                        group,
+                        found.join(", ")
+                    ));
+                }
+            }
+        }
+        Check::NoClockSkew => {
+            let max_skew = max_skew.as_millis() as i64;
+            for ((t, hello), received) in
+                targets.iter().zip(&observed.hello).zip(&observed.received)
+            {
+                let (Some(hello), Some(received)) = (hello, received) else {
+                    continue;
+                };
+                let Some(local_time) = hello.get("localTime").and_then(date_millis) else {
+                    continue;
+                };
+                let offset = local_time - received;
+                if offset.abs() > max_skew {
+                    failures.push(format!(
+                        "clock of {} on port {} is {} the host",
+                        t.pid,
+                        t.port,
+                        describe_offset(offset)
+                    ));
+                }
+                // A last write stamped after the member's own clock came from a member whose
+                // clock runs ahead
+                let last_write = hello
+                    .get("lastWrite")
+                    .and_then(|w| w.get("lastWriteDate"))
+                    .and_then(date_millis);
+                if let Some(last_write) = last_write {
+                    if last_write - local_time > max_skew {
+                        failures.push(format!(
+                            "last write optime of {} on port {} is {}ms ahead of its clock",
+                            t.pid,
+                            t.port,
+                            last_write - local_time
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    CheckResult {
+        name: name(check),
+        ok: failures.is_empty(),
+        failures,
+    }
+}
+
+/// Print the results as JSON, failing when any check failed so scripts can gate on it
+pub fn print_checks(results: &[CheckResult]) -> Result<()> {
+    let failed = results.iter().filter(|r| !r.ok).count();
+    let report = json!({"ok": failed == 0, "checks": results});
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if failed > 0 {
+        bail!("{} of {} check(s) failed", failed, results.len());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_run_check() {
+    let info: MongoPSInfo = serde_json::from_value(json!({
+        "schema_version": 2, "mongod": [], "mongos": [], "shell": [], "mongocryptd": [],
+        "clusters": [{
+            "config_replica_set": "cfg", "config_servers": [1], "mongos": [],
+            "shards": [{"name": "sh1", "replica_set_name": "sh1", "pids": [3]}],
+        }],
+    }))
+    .unwrap();
+    let targets = vec![
+        ServerTarget {
+            pid: 1,
+            port: 20001,
+        },
+        ServerTarget {
+            pid: 2,
+            port: 20001,
+        },
+        ServerTarget {
+            pid: 3,
+            port: 20003,
+        },
+    ];
+    let date = |millis: i64| json!({"$date": millis});
+    let observed = Observed {
+        hello: vec![
+            Some(json!({"localTime": date(1_700_000_000_000)})),
+            None,
+            Some(json!({
+                "localTime": date(1_700_000_005_000),
+                "lastWrite": {"lastWriteDate": date(1_700_000_007_000)},
+            })),
+        ],
+        received: vec![Some(1_700_000_000_200), None, Some(1_700_000_000_000)],
+        versions: vec![Some("7.0.2".to_owned()), None, Some("6.0.1".to_owned())],
+        fcv: vec![Some("8.0".to_owned()), None, Some("7.0".to_owned())],
+        listeners: vec![vec![], vec![(40, "node".to_owned())], vec![]],
+    };
+
+    let max_skew = Duration::from_secs(1);
+
+    let listening = run_check(Check::AllListening, &info, &targets, &observed, max_skew);
+    assert_eq!(listening.name, "all-listening");
+    assert_eq!(listening.failures, vec!["2 does not answer on port 20001"]);
+    let skew = run_check(Check::NoVersionSkew, &info, &targets, &observed, max_skew);
+    assert_eq!(
+        skew.failures,
+        vec!["servers run different versions: 6.0.1 on ports [20003], 7.0.2 on ports [20001]"]
+    );
+    let conflicts = run_check(Check::NoConflicts, &info, &targets, &observed, max_skew);
+    assert_eq!(
+        conflicts.failures,
+        vec![
+            "port 20001 is used by [1, 2]",
+            "port 20001 of 2 is held by node 40"
+        ]
+    );
+    assert!(run_check(Check::HasPrimary, &info, &targets, &observed, max_skew).ok);
+    let fcv = run_check(Check::NoFcvSkew, &info, &targets, &observed, max_skew);
+    assert_eq!(
+        fcv.failures,
+        vec![
+            "cluster cfg has different featureCompatibilityVersions: 7.0 on ports [20003], 8.0 on ports [20001]"
+        ]
+    );
+    let clocks = run_check(Check::NoClockSkew, &info, &targets, &observed, max_skew);
+    assert_eq!(
+        clocks.failures,
+        vec![
+            "clock of 3 on port 20003 is 5000ms ahead of the host",
+            "last write optime of 3 on port 20003 is 2000ms ahead of its clock",
+        ]
+    );
+}
@@ -0,0 +1,323 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Minimal OP_MSG client used to run admin commands against local servers
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::Value;
+
+use super::credentials::Credentials;
+use super::extjson::{decode_document, encode_document};
+use super::probe::DEFAULT_CONCURRENCY;
+use super::scram;
+use super::transport::{TlsSettings, Transport};
+
+const OP_MSG: i32 = 2013;
+const HEADER_LEN: usize = 16;
+const MAX_MESSAGE_LEN: usize = 48 * 1024 * 1024;
+const UNAUTHORIZED: i64 = 13;
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connection settings from the command line, passed to every connection
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// Replaces the connect timeout of each caller
+    pub connect_timeout: Option<Duration>,
+    /// Replaces the operation timeout of each caller
+    pub timeout: Option<Duration>,
+    /// When set every connection uses TLS
+    pub tls: Option<TlsSettings>,
+    /// Used by connections to servers that reply Unauthorized
+    pub credentials: Option<Credentials>,
+    /// Socket files of local servers by port, used instead of TCP when possible
+    pub unix_sockets: HashMap<i32, String>,
+    /// Most servers queried at once
+    pub concurrency: Option<usize>,
+}
+
+impl Settings {
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.unwrap_or(DEFAULT_CONCURRENCY)
+    }
+}
+
+fn timeouts(settings: &Settings, default: Duration) -> (Duration, Duration) {
+    (
+        settings.connect_timeout.unwrap_or(default),
+        settings.timeout.unwrap_or(default),
+    )
+}
+
+pub struct Connection {
+    stream: Transport,
+    request_id: i32,
+    timeout: Duration,
+    credentials: Option<Credentials>,
+    // Authentication is only attempted once, after the first Unauthorized reply
+    tried_auth: bool,
+}
+
+impl Connection {
+    /// Connect with timeout for connecting and for each command, unless overridden by the
+    /// settings. With TLS the handshake has to finish within the connect timeout too.
+    pub fn connect(
+        settings: &Settings,
+        host: &str,
+        port: i32,
+        timeout: Duration,
+    ) -> Result<Connection> {
+        let (connect_timeout, timeout) = timeouts(settings, timeout);
+        let addrs = (host, port as u16)
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve {}:{}", host, port))?;
+
+        let mut last_err = anyhow!("No addresses found for {}:{}", host, port);
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, connect_timeout) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    let stream = match settings.tls {
+                        Some(ref tls) => {
+                            Transport::Tls(Box::new(tls.connect(host, stream, connect_timeout)?))
+                        }
+                        None => Transport::Tcp(stream),
+                    };
+                    stream.set_timeout(timeout)?;
+                    return Ok(Connection {
+                        stream,
+                        request_id: 0,
+                        timeout,
+                        credentials: settings.credentials.clone(),
+                        tried_auth: false,
+                    });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                    last_err = anyhow::Error::new(e).context(format!(
+                        "{} is unreachable, no connection within {:?}",
+                        addr, connect_timeout
+                    ))
+                }
+                Err(e) => last_err = anyhow!("Failed to connect to {}: {}", addr, e),
+            }
+        }
+
+        Err(last_err)
+    }
+
+    /// Connect to a local server, through its socket file when the settings have one,
+    /// falling back to TCP
+    pub fn connect_local(settings: &Settings, port: i32, timeout: Duration) -> Result<Connection> {
+        if let (Some(path), None) = (settings.unix_sockets.get(&port), &settings.tls) {
+            if let Ok(stream) = UnixStream::connect(path) {
+                let (_, timeout) = timeouts(settings, timeout);
+                let stream = Transport::Unix(stream);
+                stream.set_timeout(timeout)?;
+                return Ok(Connection {
+                    stream,
+                    request_id: 0,
+                    timeout,
+                    credentials: settings.credentials.clone(),
+                    tried_auth: false,
+                });
+            }
+        }
+        Connection::connect(settings, "localhost", port, timeout)
+    }
+
+    /// Run a command and return the reply, failing if the reply is not ok
+    pub fn run_command(&mut self, db: &str, cmd: Value) -> Result<Value> {
+        let reply = self.run_command_raw(db, cmd)?;
+        check_ok(&reply)?;
+        Ok(reply)
+    }
+
+    /// Run a command and return the reply as is
+    pub fn run_command_raw(&mut self, db: &str, mut cmd: Value) -> Result<Value> {
+        let Some(map) = cmd.as_object_mut() else {
+            bail!("Commands must be documents");
+        };
+        map.insert("$db".to_owned(), Value::String(db.to_owned()));
+
+        self.send(&cmd)?;
+        let reply = self.receive()?;
+        if reply.get("code").and_then(|c| c.as_i64()) == Some(UNAUTHORIZED) && !self.tried_auth {
+            if let Some(creds) = self.credentials.clone() {
+                self.tried_auth = true;
+                scram::authenticate(self, &creds)
+                    .map_err(|e| anyhow!("Failed to authenticate as {}: {}", creds.username, e))?;
+                self.send(&cmd)?;
+                return self.receive();
+            }
+        }
+        Ok(reply)
+    }
+
+    fn send(&mut self, cmd: &Value) -> Result<()> {
+        let body = encode_document(cmd)?;
+        self.request_id += 1;
+
+        let len = HEADER_LEN + 4 + 1 + body.len();
+        let mut msg = Vec::with_capacity(len);
+        msg.extend_from_slice(&(len as i32).to_le_bytes());
+        msg.extend_from_slice(&self.request_id.to_le_bytes());
+        msg.extend_from_slice(&0i32.to_le_bytes());
+        msg.extend_from_slice(&OP_MSG.to_le_bytes());
+        // flagBits
+        msg.extend_from_slice(&0u32.to_le_bytes());
+        // Section kind 0: body
+        msg.push(0);
+        msg.extend_from_slice(&body);
+
+        self.stream.write_all(&msg).map_err(|e| self.io_error(e))?;
+        Ok(())
+    }
+
+    // Reads and writes time out with WouldBlock on some platforms and TimedOut on others, the
+    // io error stays in the chain so callers can tell a timeout from a failure
+    fn io_error(&self, e: std::io::Error) -> anyhow::Error {
+        match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                let message = format!("No reply within {:?}, the server may be hung", self.timeout);
+                anyhow::Error::new(e).context(message)
+            }
+            _ => e.into(),
+        }
+    }
+
+    fn receive(&mut self) -> Result<Value> {
+        let mut header = [0u8; HEADER_LEN];
+        self.stream
+            .read_exact(&mut header)
+            .map_err(|e| self.io_error(e))?;
+
+        let len = i32::from_le_bytes(header[0..4].try_into()?) as usize;
+        let op_code = i32::from_le_bytes(header[12..16].try_into()?);
+        if !(HEADER_LEN + 5..=MAX_MESSAGE_LEN).contains(&len) {
+            bail!("Invalid reply length {}", len);
+        }
+        if op_code != OP_MSG {
+            bail!("Unexpected reply opcode {}", op_code);
+        }
+
+        let mut payload = vec![0u8; len - HEADER_LEN];
+        self.stream
+            .read_exact(&mut payload)
+            .map_err(|e| self.io_error(e))?;
+
+        parse_reply(&payload)
+    }
+}
+
+/// Body section of an OP_MSG reply after the message header
+fn parse_reply(payload: &[u8]) -> Result<Value> {
+    // Skip flagBits and find the body section
+    let mut pos = 4;
+    while pos < payload.len() {
+        let kind = payload[pos];
+        pos += 1;
+        let Some(len_bytes) = payload.get(pos..pos + 4) else {
+            bail!("Reply truncated in section at offset {}", pos);
+        };
+        let section_len = i32::from_le_bytes(len_bytes.try_into()?);
+        let section = usize::try_from(section_len)
+            .ok()
+            .filter(|len| *len >= 4)
+            .and_then(|len| payload.get(pos..pos + len));
+        let Some(section) = section else {
+            bail!(
+                "Reply section at offset {} has invalid length {}",
+                pos,
+                section_len
+            );
+        };
+        if kind == 0 {
+            return decode_document(section);
+        }
+        pos += section.len();
+    }
+
+    bail!("Reply did not contain a body section")
+}
+
+/// Version of the server on a local port from buildInfo
+pub fn server_version(settings: &Settings, port: i32) -> Result<String> {
+    let mut conn = Connection::connect_local(settings, port, DEFAULT_TIMEOUT)?;
+    let reply = conn.run_command("admin", serde_json::json!({"buildInfo": 1}))?;
+    match reply.get("version").and_then(|v| v.as_str()) {
+        Some(version) => Ok(version.to_owned()),
+        None => bail!("buildInfo on port {} did not return a version", port),
+    }
+}
+
+/// Poll a local port until the server accepts connections and answers hello
+pub fn wait_for_server(settings: &Settings, port: i32, timeout: Duration) -> Result<Value> {
+    let start = Instant::now();
+    loop {
+        let hello = Connection::connect_local(settings, port, Duration::from_secs(1))
+            .and_then(|mut conn| conn.run_command("admin", serde_json::json!({"hello": 1})));
+        match hello {
+            Ok(reply) => return Ok(reply),
+            Err(e) if start.elapsed() > timeout => {
+                bail!(
+                    "Server on port {} not ready after {:?}: {}",
+                    port,
+                    timeout,
+                    e
+                )
+            }
+            Err(_) => std::thread::sleep(Duration::from_millis(250)),
+        }
+    }
+}
+
+pub fn check_ok(reply: &Value) -> Result<()> {
+    let ok = reply.get("ok").and_then(|v| v.as_f64()).unwrap_or(0.0);
+    if ok != 1.0 {
+        let errmsg = reply
+            .get("errmsg")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown error");
+        let code_name = reply.get("codeName").and_then(|v| v.as_str()).unwrap_or("");
+        bail!("Command failed: {} {}", code_name, errmsg);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_reply() {
+    let body = encode_document(&serde_json::json!({"ok": 1})).unwrap();
+    let mut payload = vec![0u8; 4];
+    payload.push(0);
+    payload.extend_from_slice(&body);
+    assert_eq!(parse_reply(&payload).unwrap()["ok"], 1);
+
+    // Cut inside the body, inside the section length and before any section
+    assert!(parse_reply(&payload[..payload.len() - 3]).is_err());
+    assert!(parse_reply(&payload[..7]).is_err());
+    assert!(parse_reply(&payload[..4]).is_err());
+
+    // Section lengths that point past the end or backwards
+    let mut bad = payload.clone();
+    bad[5..9].copy_from_slice(&1000i32.to_le_bytes());
+    assert!(parse_reply(&bad).is_err());
+    bad[5..9].copy_from_slice(&(-8i32).to_le_bytes());
+    assert!(parse_reply(&bad).is_err());
+}
@@ -0,0 +1,60 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+
+// Clipboard tools in order of preference, the first one found wins
+fn clipboard_commands() -> Vec<(&'static str, Vec<&'static str>)> {
+    if cfg!(target_os = "macos") {
+        return vec![("pbcopy", vec![])];
+    }
+
+    let mut commands = Vec::new();
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.push(("wl-copy", vec![]));
+    }
+    commands.push(("xclip", vec!["-selection", "clipboard"]));
+    commands.push(("xsel", vec!["--clipboard", "--input"]));
+    commands
+}
+
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    for (program, args) in clipboard_commands() {
+        let child = Command::new(program)
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let Ok(mut child) = child else {
+            continue;
+        };
+
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(text.as_bytes())
+            .with_context(|| format!("Failed to write to {}", program))?;
+        if !child.wait()?.success() {
+            bail!("{} failed", program);
+        }
+        return Ok(());
+    }
+
+    bail!("No clipboard tool found, install xclip, xsel or wl-clipboard")
+}
@@ -0,0 +1,134 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use super::config;
+use super::launch::LaunchSpec;
+use super::set_cmd_line_option;
+use super::types::CommonProcInfo;
+
+pub struct CloneOptions<'a> {
+    pub port: i32,
+    /// Absolute, the clone runs in the original's working directory
+    pub dbpath: PathBuf,
+    pub repl_set: Option<&'a str>,
+}
+
+// The original's arguments with its own port, dbpath, log and pid files, so the two never share
+// anything on disk. config is the original's effective config, which also covers settings
+// that come from its --config file.
+fn clone_args(
+    pid: i32,
+    args: &[String],
+    config: &Value,
+    options: &CloneOptions,
+) -> Result<Vec<String>> {
+    let dbpath = &options.dbpath;
+    let mut args = args.to_vec();
+    set_cmd_line_option(&mut args, "--port", &options.port.to_string());
+    set_cmd_line_option(&mut args, "--dbpath", &dbpath.to_string_lossy());
+    if let Some(repl_set) = options.repl_set {
+        if config::get_path(config, "replication.replSetName").is_none() {
+            bail!(
+                "{} is not a replica set member, cannot override --replSet",
+                pid
+            );
+        }
+        set_cmd_line_option(&mut args, "--replSet", repl_set);
+    }
+
+    // Two servers appending to the same log file is never what anyone wants, and the clone
+    // would overwrite the original's pid file
+    if config::get_path(config, "systemLog.path").is_some() {
+        let logpath: PathBuf = dbpath.join("mongod.log");
+        set_cmd_line_option(&mut args, "--logpath", &logpath.to_string_lossy());
+    }
+    if config::get_path(config, "processManagement.pidFilePath").is_some() {
+        let pidfile: PathBuf = dbpath.join("mongod.pid");
+        set_cmd_line_option(&mut args, "--pidfilepath", &pidfile.to_string_lossy());
+    }
+    Ok(args)
+}
+
+pub fn clone_server(proc: &CommonProcInfo, options: &CloneOptions) -> Result<u32> {
+    let mut spec = LaunchSpec::from_proc(proc)?;
+    let config = config::local_effective_config(proc)?;
+
+    std::fs::create_dir_all(&options.dbpath)
+        .with_context(|| format!("Failed to create dbpath {}", options.dbpath.display()))?;
+
+    spec.args = clone_args(proc.pid, &spec.args, &config, options)?;
+
+    let log_file = spec.log_file(options.port);
+    let pid = spec.spawn(&log_file)?;
+    println!(
+        "{}: cloned as {} ({} {}), output in {}",
+        proc.pid,
+        pid,
+        spec.binary.display(),
+        spec.args.join(" "),
+        log_file.display()
+    );
+    Ok(pid)
+}
+
+#[test]
+fn test_clone_args() {
+    use serde_json::json;
+
+    let args: Vec<String> = ["--config", "/etc/mongod.conf", "--port", "20001"]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+    let options = |repl_set| CloneOptions {
+        port: 20002,
+        dbpath: PathBuf::from("/tmp/clone"),
+        repl_set,
+    };
+
+    // Log and pid files from the config file are moved next to the clone's data
+    let config = json!({
+        "systemLog": {"destination": "file", "path": "/var/log/mongod.log"},
+        "processManagement": {"pidFilePath": "/run/mongod.pid"},
+        "replication": {"replSetName": "rs0"}
+    });
+    assert_eq!(
+        clone_args(1, &args, &config, &options(Some("rs1"))).unwrap(),
+        [
+            "--config",
+            "/etc/mongod.conf",
+            "--port",
+            "20002",
+            "--dbpath",
+            "/tmp/clone",
+            "--replSet",
+            "rs1",
+            "--logpath",
+            "/tmp/clone/mongod.log",
+            "--pidfilepath",
+            "/tmp/clone/mongod.pid"
+        ]
+    );
+
+    let config = json!({"systemLog": {"destination": "stdout"}});
+    assert_eq!(
+        clone_args(1, &args, &config, &options(None)).unwrap()[4..],
+        ["--dbpath", "/tmp/clone"]
+    );
+    assert!(clone_args(1, &args, &config, &options(Some("rs1"))).is_err());
+}
@@ -0,0 +1,180 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Sharded clusters assembled from the local processes: the routers and config servers of each
+// config server replica set, and the shard replica sets with the pids of their local members
+
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::sharding::{shard_name, Shard};
+use super::{MongoDType, MongoPSInfo};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClusterShard {
+    /// Name the shard is registered as, else its replica set name
+    pub name: String,
+    pub replica_set_name: Option<String>,
+    pub pids: Vec<i32>,
+}
+
+/// One cluster, a cluster without a config server replica set holds the shards that could not
+/// be placed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Cluster {
+    pub config_replica_set: Option<String>,
+    pub config_servers: Vec<i32>,
+    pub mongos: Vec<i32>,
+    pub shards: Vec<ClusterShard>,
+}
+
+impl Cluster {
+    fn new(config_replica_set: Option<String>) -> Cluster {
+        Cluster {
+            config_replica_set,
+            config_servers: Vec::new(),
+            mongos: Vec::new(),
+            shards: Vec::new(),
+        }
+    }
+}
+
+/// Link the local processes into clusters. registered holds the config.shards of each cluster
+/// when it was read, otherwise shards are only placed when there is a single cluster.
+pub fn clusters(info: &MongoPSInfo, registered: &BTreeMap<String, Vec<Shard>>) -> Vec<Cluster> {
+    let mut clusters: BTreeMap<String, Cluster> = BTreeMap::new();
+    for s in info.mongos.iter().filter(|s| !s.configdb.is_empty()) {
+        clusters
+            .entry(s.configdb.clone())
+            .or_insert_with(|| Cluster::new(Some(s.configdb.clone())))
+            .mongos
+            .push(s.pid);
+    }
+    for d in info
+        .mongod
+        .iter()
+        .filter(|d| d.server_type == MongoDType::Config)
+    {
+        let name = d.replica_set_name.clone().unwrap_or_default();
+        clusters
+            .entry(name.clone())
+            .or_insert_with(|| Cluster::new(Some(name)))
+            .config_servers
+            .push(d.pid);
+    }
+
+    // Without config.shards a lone cluster must own every shard
+    let only = (clusters.len() == 1 && registered.is_empty())
+        .then(|| clusters.keys().next().unwrap().clone());
+    let mut unplaced = Cluster::new(None);
+    for d in info
+        .mongod
+        .iter()
+        .filter(|d| d.server_type == MongoDType::Shard)
+    {
+        let set = d.replica_set_name.as_deref();
+        let found = registered.iter().find_map(|(cluster, shards)| {
+            Some((cluster.clone(), shard_name(shards, d.port, set)?))
+        });
+        let (cluster, name) = match found {
+            Some((cluster, name)) => (clusters.get_mut(&cluster), name),
+            None => (
+                only.as_ref().and_then(|c| clusters.get_mut(c)),
+                d.shard_name
+                    .clone()
+                    .or_else(|| d.replica_set_name.clone())
+                    .unwrap_or_else(|| d.port.to_string()),
+            ),
+        };
+        let cluster = cluster.unwrap_or(&mut unplaced);
+        match cluster.shards.iter_mut().find(|s| s.name == name) {
+            Some(shard) => shard.pids.push(d.pid),
+            None => cluster.shards.push(ClusterShard {
+                name,
+                replica_set_name: d.replica_set_name.clone(),
+                pids: vec![d.pid],
+            }),
+        }
+    }
+
+    let mut clusters: Vec<Cluster> = clusters.into_values().collect();
+    if !unplaced.shards.is_empty() {
+        clusters.push(unplaced);
+    }
+    for cluster in clusters.iter_mut() {
+        cluster.shards.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    clusters
+}
+
+#[test]
+fn test_clusters() {
+    use serde_json::json;
+
+    let server = |pid: i32, port: i32, server_type: &str, set: &str| {
+        json!({
+            "pid": pid, "port": port, "server_type": server_type, "replica_set_name": set,
+            "fork": false, "special_modes": [], "storage": {"engine": "wiredTiger"},
+            "set_parameters": {}, "test_instance": false,
+            "auth": {"enabled": false, "mechanisms": [], "transition_to_auth": false},
+        })
+    };
+    let mongos = |pid: i32, configdb: &str| {
+        json!({
+            "pid": pid, "port": 20000 + pid, "configdb": configdb, "fork": false,
+            "set_parameters": {}, "test_instance": false,
+            "auth": {"enabled": false, "mechanisms": [], "transition_to_auth": false},
+        })
+    };
+    let info: MongoPSInfo = serde_json::from_value(json!({
+        "schema_version": 2,
+        "mongod": [
+            server(1, 20001, "Config", "cfgA"),
+            server(2, 20011, "Shard", "rs0"),
+            server(3, 20012, "Shard", "rs0"),
+            server(4, 20021, "Shard", "rs1"),
+        ],
+        "mongos": [mongos(10, "cfgA"), mongos(11, "cfgA")],
+        "shell": [],
+        "mongocryptd": [],
+        "clusters": [],
+    }))
+    .unwrap();
+
+    let one = clusters(&info, &BTreeMap::new());
+    assert_eq!(one.len(), 1);
+    assert_eq!(one[0].config_replica_set.as_deref(), Some("cfgA"));
+    assert_eq!(one[0].config_servers, vec![1]);
+    assert_eq!(one[0].mongos, vec![10, 11]);
+    assert_eq!(one[0].shards[0].name, "rs0");
+    assert_eq!(one[0].shards[0].pids, vec![2, 3]);
+
+    // Registered names replace set names, and shards of other clusters are not placed here
+    let mut registered = BTreeMap::new();
+    registered.insert(
+        "cfgA".to_owned(),
+        vec![Shard {
+            name: "shard01".to_owned(),
+            host: "rs0/localhost:20011".to_owned(),
+        }],
+    );
+    registered.insert("cfgB".to_owned(), vec![]);
+    let two = clusters(&info, &registered);
+    assert_eq!(two.len(), 2);
+    assert_eq!(two[0].shards[0].name, "shard01");
+    assert_eq!(two[0].shards[0].pids, vec![2, 3]);
+    assert_eq!(two[1].config_replica_set, None);
+    assert_eq!(two[1].shards[0].name, "rs1");
+}
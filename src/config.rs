@@ -0,0 +1,662 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Conversion of mongod/mongos command lines into their config file equivalent
+
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+
+use super::client::{Connection, Settings, DEFAULT_TIMEOUT};
+use super::types::CommonProcInfo;
+
+// Command line option to config file setting, options that are flags take a fixed value
+const OPTION_MAP: &[(&str, &str)] = &[
+    ("--port", "net.port"),
+    ("--bind_ip", "net.bindIp"),
+    ("--bind_ip_all", "net.bindIpAll"),
+    ("--ipv6", "net.ipv6"),
+    ("--maxConns", "net.maxIncomingConnections"),
+    ("--unixSocketPrefix", "net.unixDomainSocket.pathPrefix"),
+    ("--filePermissions", "net.unixDomainSocket.filePermissions"),
+    ("--tlsMode", "net.tls.mode"),
+    ("--tlsCertificateKeyFile", "net.tls.certificateKeyFile"),
+    (
+        "--tlsCertificateKeyFilePassword",
+        "net.tls.certificateKeyFilePassword",
+    ),
+    ("--tlsCAFile", "net.tls.CAFile"),
+    ("--tlsCRLFile", "net.tls.CRLFile"),
+    ("--tlsClusterFile", "net.tls.clusterFile"),
+    (
+        "--tlsAllowConnectionsWithoutCertificates",
+        "net.tls.allowConnectionsWithoutCertificates",
+    ),
+    (
+        "--tlsAllowInvalidCertificates",
+        "net.tls.allowInvalidCertificates",
+    ),
+    (
+        "--tlsAllowInvalidHostnames",
+        "net.tls.allowInvalidHostnames",
+    ),
+    ("--sslMode", "net.ssl.mode"),
+    ("--sslPEMKeyFile", "net.ssl.PEMKeyFile"),
+    ("--sslCAFile", "net.ssl.CAFile"),
+    ("--sslClusterFile", "net.ssl.clusterFile"),
+    (
+        "--sslAllowConnectionsWithoutCertificates",
+        "net.ssl.allowConnectionsWithoutCertificates",
+    ),
+    ("--dbpath", "storage.dbPath"),
+    ("--directoryperdb", "storage.directoryPerDB"),
+    ("--syncdelay", "storage.syncPeriodSecs"),
+    ("--storageEngine", "storage.engine"),
+    (
+        "--journalCommitInterval",
+        "storage.journal.commitIntervalMs",
+    ),
+    (
+        "--wiredTigerCacheSizeGB",
+        "storage.wiredTiger.engineConfig.cacheSizeGB",
+    ),
+    (
+        "--wiredTigerJournalCompressor",
+        "storage.wiredTiger.engineConfig.journalCompressor",
+    ),
+    (
+        "--wiredTigerDirectoryForIndexes",
+        "storage.wiredTiger.engineConfig.directoryForIndexes",
+    ),
+    (
+        "--wiredTigerCollectionBlockCompressor",
+        "storage.wiredTiger.collectionConfig.blockCompressor",
+    ),
+    (
+        "--wiredTigerIndexPrefixCompression",
+        "storage.wiredTiger.indexConfig.prefixCompression",
+    ),
+    (
+        "--inMemorySizeGB",
+        "storage.inMemory.engineConfig.inMemorySizeGB",
+    ),
+    ("--logpath", "systemLog.path"),
+    ("--logappend", "systemLog.logAppend"),
+    ("--logRotate", "systemLog.logRotate"),
+    ("--quiet", "systemLog.quiet"),
+    ("--timeStampFormat", "systemLog.timeStampFormat"),
+    ("--fork", "processManagement.fork"),
+    ("--pidfilepath", "processManagement.pidFilePath"),
+    ("--timeZoneInfo", "processManagement.timeZoneInfo"),
+    ("--replSet", "replication.replSetName"),
+    ("--oplogSize", "replication.oplogSizeMB"),
+    (
+        "--enableMajorityReadConcern",
+        "replication.enableMajorityReadConcern",
+    ),
+    ("--configdb", "sharding.configDB"),
+    ("--keyFile", "security.keyFile"),
+    ("--clusterAuthMode", "security.clusterAuthMode"),
+    ("--transitionToAuth", "security.transitionToAuth"),
+    ("--enableEncryption", "security.enableEncryption"),
+    ("--encryptionKeyFile", "security.encryptionKeyFile"),
+    ("--kmipServerName", "security.kmip.serverName"),
+    ("--kmipPort", "security.kmip.port"),
+    ("--kmipKeyIdentifier", "security.kmip.keyIdentifier"),
+    ("--kmipRotateMasterKey", "security.kmip.rotateMasterKey"),
+    ("--kmipServerCAFile", "security.kmip.serverCAFile"),
+    (
+        "--kmipClientCertificateFile",
+        "security.kmip.clientCertificateFile",
+    ),
+    ("--encryptionCipherMode", "security.encryptionCipherMode"),
+    ("--redactClientLogData", "security.redactClientLogData"),
+    ("--ldapServers", "security.ldap.servers"),
+    ("--ldapTransportSecurity", "security.ldap.transportSecurity"),
+    ("--ldapBindMethod", "security.ldap.bind.method"),
+    (
+        "--ldapBindSaslMechanisms",
+        "security.ldap.bind.saslMechanisms",
+    ),
+    ("--ldapQueryUser", "security.ldap.bind.queryUser"),
+    ("--ldapUserToDNMapping", "security.ldap.userToDNMapping"),
+    (
+        "--ldapAuthzQueryTemplate",
+        "security.ldap.authz.queryTemplate",
+    ),
+    ("--saslServiceName", "security.sasl.serviceName"),
+    ("--saslHostName", "security.sasl.hostName"),
+    ("--saslauthdSocketPath", "security.sasl.saslauthdSocketPath"),
+    ("--slowms", "operationProfiling.slowOpThresholdMs"),
+    ("--slowOpSampleRate", "operationProfiling.slowOpSampleRate"),
+    ("--auditDestination", "auditLog.destination"),
+    ("--auditFormat", "auditLog.format"),
+    ("--auditPath", "auditLog.path"),
+];
+
+// Flags whose config file setting is not simply "true"
+const FLAG_MAP: &[(&str, &str, &str)] = &[
+    ("--shardsvr", "sharding.clusterRole", "shardsvr"),
+    ("--configsvr", "sharding.clusterRole", "configsvr"),
+    ("--auth", "security.authorization", "enabled"),
+    ("--noauth", "security.authorization", "disabled"),
+    ("--syslog", "systemLog.destination", "syslog"),
+    ("--nounixsocket", "net.unixDomainSocket.enabled", "false"),
+    ("--noscripting", "security.javascriptEnabled", "false"),
+    ("--nojournal", "storage.journal.enabled", "false"),
+];
+
+/// A command line split into (--name, value) pairs, flags have no value
+pub fn split_options(args: &[String]) -> Vec<(String, Option<String>)> {
+    let mut options = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i];
+        i += 1;
+        if !arg.starts_with('-') {
+            continue;
+        }
+
+        if let Some((name, value)) = arg.split_once('=') {
+            options.push((name.to_owned(), Some(value.to_owned())));
+        } else if i < args.len() && !args[i].starts_with('-') {
+            options.push((arg.to_owned(), Some(args[i].to_owned())));
+            i += 1;
+        } else {
+            options.push((arg.to_owned(), None));
+        }
+    }
+    options
+}
+
+// Command line values are strings, the config file wants the real types
+fn typed_value(value: &str) -> Value {
+    if let Ok(i) = value.parse::<i64>() {
+        Value::from(i)
+    } else if let Ok(d) = value.parse::<f64>() {
+        Value::from(d)
+    } else if value == "true" || value == "false" {
+        Value::Bool(value == "true")
+    } else {
+        Value::String(value.to_owned())
+    }
+}
+
+fn insert_path(config: &mut Map<String, Value>, path: &str, value: Value) {
+    let mut current = config;
+    let mut parts = path.split('.').peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            current.insert(part.to_owned(), value);
+            return;
+        }
+        let entry = current
+            .entry(part.to_owned())
+            .or_insert_with(|| Value::Object(Map::new()));
+        if !entry.is_object() {
+            *entry = Value::Object(Map::new());
+        }
+        current = entry.as_object_mut().unwrap();
+    }
+}
+
+/// Convert a command line (without argv[0]) into a config file document. Options without a
+/// known config file equivalent are returned separately.
+pub fn cmdline_to_config(args: &[String]) -> (Value, Vec<String>) {
+    let mut config = Map::new();
+    let mut unmapped = Vec::new();
+
+    for (name, value) in split_options(args) {
+        if name == "--setParameter" {
+            if let Some((key, param)) = value.as_deref().and_then(|v| v.split_once('=')) {
+                insert_path(
+                    &mut config,
+                    &format!("setParameter.{}", key),
+                    typed_value(param),
+                );
+                continue;
+            }
+        } else if let Some((_, path)) = OPTION_MAP.iter().find(|(opt, _)| *opt == name) {
+            let value = value.as_deref().map_or(Value::Bool(true), typed_value);
+            insert_path(&mut config, path, value);
+            if name == "--logpath" {
+                insert_path(&mut config, "systemLog.destination", Value::from("file"));
+            }
+            continue;
+        } else if let Some((_, path, flag_value)) = FLAG_MAP.iter().find(|(opt, _, _)| *opt == name)
+        {
+            insert_path(&mut config, path, typed_value(flag_value));
+            continue;
+        } else if name == "--profile" {
+            let mode = match value.as_deref() {
+                Some("1") => "slowOp",
+                Some("2") => "all",
+                _ => "off",
+            };
+            insert_path(&mut config, "operationProfiling.mode", Value::from(mode));
+            continue;
+        } else if name.len() > 1 && name[1..].chars().all(|c| c == 'v') {
+            insert_path(
+                &mut config,
+                "systemLog.verbosity",
+                Value::from(name.len() - 1),
+            );
+            continue;
+        }
+
+        unmapped.push(match value {
+            Some(value) => format!("{}={}", name, value),
+            None => name,
+        });
+    }
+
+    (Value::Object(config), unmapped)
+}
+
+// Plain scalars are only used when YAML cannot mistake them for another type
+fn yaml_scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => {
+            let plain = !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_./-,".contains(c))
+                && !matches!(typed_value(s), Value::Number(_) | Value::Bool(_))
+                && !matches!(s.as_str(), "null" | "yes" | "no" | "on" | "off" | "~");
+            if plain {
+                s.clone()
+            } else {
+                Value::String(s.clone()).to_string()
+            }
+        }
+        Value::Null => "null".to_owned(),
+        _ => value.to_string(),
+    }
+}
+
+/// Render a document as block style YAML
+pub fn to_yaml(value: &Value) -> String {
+    let mut out = String::new();
+    write_yaml(&mut out, value, 0);
+    out
+}
+
+fn write_yaml(out: &mut String, value: &Value, indent: usize) {
+    let pad = "  ".repeat(indent);
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map {
+                match v {
+                    Value::Object(m) if !m.is_empty() => {
+                        let _ = writeln!(out, "{}{}:", pad, key);
+                        write_yaml(out, v, indent + 1);
+                    }
+                    Value::Array(a) if !a.is_empty() => {
+                        let _ = writeln!(out, "{}{}:", pad, key);
+                        write_yaml(out, v, indent + 1);
+                    }
+                    _ => {
+                        let _ = writeln!(out, "{}{}: {}", pad, key, yaml_scalar(v));
+                    }
+                }
+            }
+        }
+        Value::Array(values) => {
+            for v in values {
+                match v {
+                    Value::Object(_) | Value::Array(_) => {
+                        let _ = writeln!(out, "{}-", pad);
+                        write_yaml(out, v, indent + 1);
+                    }
+                    _ => {
+                        let _ = writeln!(out, "{}- {}", pad, yaml_scalar(v));
+                    }
+                }
+            }
+        }
+        _ => {
+            let _ = writeln!(out, "{}{}", pad, yaml_scalar(value));
+        }
+    }
+}
+
+// Settings that apply when neither the command line nor the config file mention them
+const DEFAULTS: &[(&str, &str)] = &[
+    ("net.port", "27017"),
+    ("net.bindIp", "localhost"),
+    ("storage.dbPath", "/data/db"),
+    ("storage.engine", "wiredTiger"),
+    ("storage.journal.enabled", "true"),
+    ("systemLog.destination", "stdout"),
+    ("security.authorization", "disabled"),
+];
+
+// Shard and config servers default to their own port, and config servers to their own dbPath
+const CLUSTER_ROLE_DEFAULTS: &[(&str, &str, &str)] = &[
+    ("shardsvr", "net.port", "27018"),
+    ("configsvr", "net.port", "27019"),
+    ("configsvr", "storage.dbPath", "/data/configdb"),
+];
+
+/// Default of a setting the config does not set, which depends on sharding.clusterRole
+pub fn default_setting(config: &Value, path: &str) -> Option<Value> {
+    let role = get_path(config, "sharding.clusterRole").and_then(Value::as_str);
+    CLUSTER_ROLE_DEFAULTS
+        .iter()
+        .find(|(r, p, _)| Some(*r) == role && *p == path)
+        .map(|(_, _, value)| value)
+        .or_else(|| {
+            DEFAULTS
+                .iter()
+                .find(|(p, _)| *p == path)
+                .map(|(_, value)| value)
+        })
+        .map(|value| typed_value(value))
+}
+
+/// Parse a mongod YAML config file, an empty file has no settings
+pub fn parse_yaml(text: &str) -> Result<Value> {
+    match serde_yaml::from_str(text)? {
+        Value::Null => Ok(Value::Object(Map::new())),
+        value => Ok(value),
+    }
+}
+
+fn merge(into: &mut Map<String, Value>, from: &Map<String, Value>) {
+    for (key, value) in from {
+        match (into.get_mut(key), value) {
+            (Some(Value::Object(existing)), Value::Object(nested)) => merge(existing, nested),
+            _ => {
+                into.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Look up a dotted path like net.bindIp
+pub fn get_path<'a>(config: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(config, |value, part| value.get(part))
+}
+
+/// Path of the unix domain socket a server creates, None when disabled with --nounixsocket
+pub fn unix_socket_path(config: &Value, port: i32) -> Option<String> {
+    let enabled = get_path(config, "net.unixDomainSocket.enabled");
+    if enabled.is_some_and(|e| e == &json!(false) || e == &json!("false")) {
+        return None;
+    }
+    let prefix = get_path(config, "net.unixDomainSocket.pathPrefix")
+        .and_then(|p| p.as_str())
+        .unwrap_or("/tmp");
+    Some(format!(
+        "{}/mongodb-{}.sock",
+        prefix.trim_end_matches('/'),
+        port
+    ))
+}
+
+/// Flatten a document into dotted paths
+pub fn flatten(value: &Value) -> Vec<(String, Value)> {
+    let mut out = Vec::new();
+    flatten_into(&mut out, "", value);
+    out
+}
+
+fn flatten_into(out: &mut Vec<(String, Value)>, prefix: &str, value: &Value) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, v) in map {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten_into(out, &path, v);
+            }
+        }
+        _ => out.push((prefix.to_owned(), value.clone())),
+    }
+}
+
+/// Effective configuration computed locally from the command line, config file and defaults
+pub fn local_effective_config(proc: &CommonProcInfo) -> Result<Value> {
+    let mut config = local_config(proc)?;
+    apply_defaults(&mut config);
+    Ok(Value::Object(config))
+}
+
+/// Settings given on the command line or in the config file, without defaults
+pub fn local_config(proc: &CommonProcInfo) -> Result<Map<String, Value>> {
+    let args = proc.cmdline.get(1..).unwrap_or_default();
+    let mut config = Map::new();
+
+    let config_file = split_options(args)
+        .into_iter()
+        .find(|(name, _)| name == "--config" || name == "-f")
+        .and_then(|(_, value)| value);
+    if let Some(config_file) = config_file {
+        let path = match proc.cwd {
+            Some(ref cwd) => cwd.join(&config_file),
+            None => Path::new(&config_file).to_path_buf(),
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        match parse_yaml(&text)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?
+        {
+            Value::Object(file) => merge(&mut config, &file),
+            _ => bail!("Config file {} is not a YAML mapping", path.display()),
+        }
+        insert_path(&mut config, "config", Value::from(config_file));
+    }
+
+    if let Value::Object(cmdline) = cmdline_to_config(args).0 {
+        merge(&mut config, &cmdline);
+    }
+    Ok(config)
+}
+
+fn apply_defaults(config: &mut Map<String, Value>) {
+    let current = Value::Object(config.clone());
+    let present: Vec<String> = flatten(&current).into_iter().map(|(k, _)| k).collect();
+    for (path, _) in DEFAULTS {
+        if !present.iter().any(|p| p == path) {
+            if let Some(value) = default_setting(&current, path) {
+                insert_path(config, path, value);
+            }
+        }
+    }
+}
+
+/// Effective configuration as reported by the server, falling back to computing it locally
+pub fn effective_config(
+    settings: &Settings,
+    proc: &CommonProcInfo,
+    port: Option<i32>,
+) -> Result<Value> {
+    if let Some(port) = port {
+        let parsed = Connection::connect_local(settings, port, DEFAULT_TIMEOUT)
+            .and_then(|mut conn| conn.run_command("admin", json!({"getCmdLineOpts": 1})));
+        if let Ok(Some(Value::Object(parsed))) = parsed.map(|r| r.get("parsed").cloned()) {
+            // The server only reports what was set, fill in the defaults the same way
+            let mut config = parsed;
+            apply_defaults(&mut config);
+            return Ok(Value::Object(config));
+        }
+    }
+
+    local_effective_config(proc)
+}
+
+pub enum ConfigDifference {
+    OnlyLeft(String, Value),
+    OnlyRight(String, Value),
+    Changed(String, Value, Value),
+}
+
+pub fn diff_configs(left: &Value, right: &Value) -> Vec<ConfigDifference> {
+    let left = flatten(left);
+    let right = flatten(right);
+
+    let mut diffs = Vec::new();
+    for (path, value) in &left {
+        match right.iter().find(|(p, _)| p == path) {
+            None => diffs.push(ConfigDifference::OnlyLeft(path.clone(), value.clone())),
+            Some((_, other)) if other != value => diffs.push(ConfigDifference::Changed(
+                path.clone(),
+                value.clone(),
+                other.clone(),
+            )),
+            _ => {}
+        }
+    }
+    for (path, value) in &right {
+        if !left.iter().any(|(p, _)| p == path) {
+            diffs.push(ConfigDifference::OnlyRight(path.clone(), value.clone()));
+        }
+    }
+
+    diffs.sort_by(|a, b| a.path().cmp(b.path()));
+    diffs
+}
+
+impl ConfigDifference {
+    fn path(&self) -> &str {
+        match self {
+            ConfigDifference::OnlyLeft(p, _) => p,
+            ConfigDifference::OnlyRight(p, _) => p,
+            ConfigDifference::Changed(p, _, _) => p,
+        }
+    }
+}
+
+#[test]
+fn test_cmdline_to_config() {
+    let args: Vec<String> = [
+        "--port",
+        "20001",
+        "--replSet=rs0",
+        "--shardsvr",
+        "--setParameter",
+        "enableTestCommands=1",
+        "--logpath",
+        "/tmp/mongod.log",
+        "-vv",
+        "--bogus",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+
+    let (config, unmapped) = cmdline_to_config(&args);
+    assert_eq!(
+        config,
+        serde_json::json!({
+            "net": {"port": 20001},
+            "replication": {"replSetName": "rs0"},
+            "sharding": {"clusterRole": "shardsvr"},
+            "setParameter": {"enableTestCommands": 1},
+            "systemLog": {"path": "/tmp/mongod.log", "destination": "file", "verbosity": 2},
+        })
+    );
+    assert_eq!(unmapped, vec!["--bogus"]);
+
+    assert_eq!(
+        to_yaml(&config),
+        "net:\n  port: 20001\nreplication:\n  replSetName: rs0\nsharding:\n  clusterRole: shardsvr\n\
+         setParameter:\n  enableTestCommands: 1\nsystemLog:\n  path: /tmp/mongod.log\n  \
+         destination: file\n  verbosity: 2\n"
+    );
+
+    assert_eq!(
+        unix_socket_path(&config, 20001).as_deref(),
+        Some("/tmp/mongodb-20001.sock")
+    );
+    let flags = |args: &[&str]| {
+        cmdline_to_config(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>()).0
+    };
+    assert_eq!(unix_socket_path(&flags(&["--nounixsocket"]), 20001), None);
+    assert_eq!(
+        unix_socket_path(&flags(&["--unixSocketPrefix", "/run/mongo/"]), 27017).as_deref(),
+        Some("/run/mongo/mongodb-27017.sock")
+    );
+}
+
+#[test]
+fn test_cluster_role_defaults() {
+    let defaults = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        let Value::Object(mut config) = cmdline_to_config(&args).0 else {
+            unreachable!()
+        };
+        apply_defaults(&mut config);
+        let config = Value::Object(config);
+        (
+            get_path(&config, "net.port").cloned(),
+            get_path(&config, "storage.dbPath").cloned(),
+        )
+    };
+
+    assert_eq!(
+        defaults(&[]),
+        (Some(Value::from(27017)), Some(Value::from("/data/db")))
+    );
+    assert_eq!(
+        defaults(&["--shardsvr"]),
+        (Some(Value::from(27018)), Some(Value::from("/data/db")))
+    );
+    assert_eq!(
+        defaults(&["--configsvr"]),
+        (
+            Some(Value::from(27019)),
+            Some(Value::from("/data/configdb"))
+        )
+    );
+    assert_eq!(
+        defaults(&["--configsvr", "--port", "20001", "--dbpath", "/tmp/cfg"]),
+        (Some(Value::from(20001)), Some(Value::from("/tmp/cfg")))
+    );
+}
+
+#[test]
+fn test_parse_yaml() {
+    let text = "# mongod.conf\n\
+                net:\n  port: 27018  # comment\n  bindIp: \"127.0.0.1,::1\"\n\
+                storage:\n  dbPath: /var/lib/mongo\n  wiredTiger:\n    engineConfig:\n      \
+                cacheSizeGB: 0.5\n\
+                setParameter:\n  enableTestCommands: 1\n";
+    assert_eq!(
+        parse_yaml(text).unwrap(),
+        serde_json::json!({
+            "net": {"port": 27018, "bindIp": "127.0.0.1,::1"},
+            "storage": {"dbPath": "/var/lib/mongo", "wiredTiger": {"engineConfig": {"cacheSizeGB": 0.5}}},
+            "setParameter": {"enableTestCommands": 1},
+        })
+    );
+
+    // Flow mappings, quoted keys and lists
+    let text = "setParameter: {enableTestCommands: 1, 'diagnosticDataCollectionEnabled': false}\n\
+                \"net\":\n  bindIp: localhost\n  compression:\n    compressors: [snappy, zstd]\n";
+    assert_eq!(
+        parse_yaml(text).unwrap(),
+        serde_json::json!({
+            "setParameter": {"enableTestCommands": 1, "diagnosticDataCollectionEnabled": false},
+            "net": {"bindIp": "localhost", "compression": {"compressors": ["snappy", "zstd"]}},
+        })
+    );
+
+    assert_eq!(parse_yaml("# nothing\n").unwrap(), serde_json::json!({}));
+    assert!(parse_yaml("net: [port\n").is_err());
+}
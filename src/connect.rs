@@ -0,0 +1,75 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Open mongosh connected to a server
+
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+
+use super::{uri_host, uri_options, MongoPSInfo, ServerTarget};
+
+/// Connection string for a server, replica set members connect to all local members of their set
+/// so the shell follows elections
+pub fn connection_uri(info: &MongoPSInfo, target: &ServerTarget, direct: bool) -> String {
+    let repl_set = info
+        .mongod
+        .iter()
+        .find(|d| d.pid == target.pid)
+        .and_then(|d| d.replica_set_name.clone());
+
+    let mut options = uri_options(info, target.pid);
+    let hosts = match repl_set {
+        Some(repl_set) if !direct => {
+            options.insert(0, format!("replicaSet={}", repl_set));
+            info.mongod
+                .iter()
+                .filter(|d| d.replica_set_name.as_ref() == Some(&repl_set))
+                .map(|d| uri_host(info, d.port))
+                .collect::<Vec<_>>()
+                .join(",")
+        }
+        _ => {
+            options.insert(0, "directConnection=true".to_owned());
+            uri_host(info, target.port)
+        }
+    };
+
+    format!("mongodb://{}/?{}", hosts, options.join("&"))
+}
+
+/// Path of mongosh on the PATH, falling back to the legacy mongo shell
+pub fn find_shell() -> Result<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    for name in ["mongosh", "mongo"] {
+        if let Some(found) = std::env::split_paths(&path)
+            .map(|dir| dir.join(name))
+            .find(|candidate| candidate.is_file())
+        {
+            return Ok(found);
+        }
+    }
+    bail!("Neither mongosh nor mongo was found on the PATH, use --shell")
+}
+
+/// Replace mpf with the shell, only returns on failure
+pub fn exec_shell(shell: &str, uri: &str, shell_args: &[String]) -> Result<()> {
+    eprintln!("{} {}", shell, uri);
+    let err = std::process::Command::new(shell)
+        .arg(uri)
+        .args(shell_args)
+        .exec();
+    bail!("Failed to run {}: {}", shell, err)
+}
@@ -0,0 +1,122 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Outgoing connection pools of routers and shards from connPoolStats
+
+use std::fmt::Write;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use super::client::{Connection, Settings, DEFAULT_TIMEOUT};
+use super::probe::probe_all;
+use super::ServerTarget;
+
+#[derive(Debug, PartialEq)]
+pub struct HostPool {
+    pub host: String,
+    pub in_use: i64,
+    pub available: i64,
+    pub created: i64,
+    pub refreshing: i64,
+}
+
+fn count(value: &Value, field: &str) -> i64 {
+    value.get(field).and_then(|v| v.as_i64()).unwrap_or(0)
+}
+
+/// Pool usage per remote host, busiest first
+pub fn host_pools(stats: &Value) -> Vec<HostPool> {
+    let mut pools: Vec<HostPool> = stats
+        .get("hosts")
+        .and_then(|h| h.as_object())
+        .map(|hosts| {
+            hosts
+                .iter()
+                .map(|(host, pool)| HostPool {
+                    host: host.clone(),
+                    in_use: count(pool, "inUse"),
+                    available: count(pool, "available"),
+                    created: count(pool, "created"),
+                    refreshing: count(pool, "refreshing"),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    pools.sort_by(|a, b| b.in_use.cmp(&a.in_use).then(a.host.cmp(&b.host)));
+    pools
+}
+
+pub fn render(target: &ServerTarget, stats: &Value) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{} port {}: {} in use, {} available, {} created, {} refreshing",
+        target.pid,
+        target.port,
+        count(stats, "totalInUse"),
+        count(stats, "totalAvailable"),
+        count(stats, "totalCreated"),
+        count(stats, "totalRefreshing")
+    )?;
+    for pool in host_pools(stats) {
+        writeln!(
+            out,
+            "  {:<24} {:>6} in use {:>6} available {:>6} created {:>4} refreshing",
+            pool.host, pool.in_use, pool.available, pool.created, pool.refreshing
+        )?;
+    }
+    Ok(out)
+}
+
+/// Print the connection pools of each server, unreachable servers are reported and skipped
+pub fn print_conn_pools(settings: &Settings, targets: &[ServerTarget]) -> Result<()> {
+    let replies = probe_all(settings, targets, |target| {
+        Connection::connect_local(settings, target.port, DEFAULT_TIMEOUT)
+            .and_then(|mut conn| conn.run_command("admin", json!({"connPoolStats": 1})))
+    });
+    for (target, stats) in targets.iter().zip(replies) {
+        match stats {
+            Ok(stats) => print!("{}", render(target, &stats)?),
+            Err(e) => eprintln!("{} port {}: {}", target.pid, target.port, e),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_host_pools() {
+    let stats = json!({
+        "totalInUse": 7,
+        "hosts": {
+            "localhost:20001": {"inUse": 2, "available": 1, "created": 5, "refreshing": 0},
+            "localhost:20002": {"inUse": 5, "available": 0, "created": 9, "refreshing": 1},
+        },
+    });
+    let pools = host_pools(&stats);
+    assert_eq!(pools[0].host, "localhost:20002");
+    assert_eq!(pools[0].refreshing, 1);
+    assert_eq!(pools[1].in_use, 2);
+
+    let out = render(
+        &ServerTarget {
+            pid: 1,
+            port: 20000,
+        },
+        &stats,
+    )
+    .unwrap();
+    assert!(out.starts_with("1 port 20000: 7 in use, 0 available"));
+    assert!(host_pools(&json!({})).is_empty());
+}
@@ -0,0 +1,163 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// mongocryptd processes grouped by the application that spawned them. Drivers start mongocryptd
+// detached, so once the application exits it is reparented to init and only the clients still
+// connected to its port tell where it came from.
+
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::get_cmd_line_option;
+use super::types::CommonProcInfo;
+
+pub const DEFAULT_PORT: i32 = 27020;
+
+// Parents that only mean the spawner is gone: init, or a subreaper that adopted the process
+const REAPERS: &[&str] = &["init", "systemd", "launchd"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CryptdInstance {
+    pub pid: i32,
+    pub port: i32,
+    /// Processes connected to the port
+    pub clients: Vec<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CryptdParent {
+    pub pid: i32,
+    pub program: String,
+    pub cmdline: String,
+}
+
+/// The mongocryptds of one application, a group without a parent holds the stray ones
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CryptdGroup {
+    pub parent: Option<CryptdParent>,
+    pub instances: Vec<CryptdInstance>,
+}
+
+// The live parent process, else the first client that is not another mongocryptd
+fn spawner(
+    proc: &CommonProcInfo,
+    instance: &CryptdInstance,
+    procs: &HashMap<i32, &CommonProcInfo>,
+) -> Option<i32> {
+    let parent = proc
+        .ppid
+        .filter(|ppid| *ppid > 1)
+        .and_then(|ppid| procs.get(&ppid))
+        .filter(|p| !REAPERS.contains(&p.program.as_str()));
+    if let Some(parent) = parent {
+        return Some(parent.pid);
+    }
+    instance
+        .clients
+        .iter()
+        .copied()
+        .find(|pid| procs.get(pid).is_some_and(|p| p.program != "mongocryptd"))
+}
+
+/// Group mongocryptd processes by their spawner. procs holds every process on the machine and
+/// clients gives the pids connected to a port.
+pub fn group_by_parent<F>(
+    cryptds: &[&CommonProcInfo],
+    procs: &[CommonProcInfo],
+    clients: F,
+) -> Vec<CryptdGroup>
+where
+    F: Fn(i32) -> Vec<i32>,
+{
+    let by_pid: HashMap<i32, &CommonProcInfo> = procs.iter().map(|p| (p.pid, p)).collect();
+    let mut groups: Vec<CryptdGroup> = Vec::new();
+
+    for proc in cryptds {
+        let port = get_cmd_line_option("--port", &proc.cmdline)
+            .and_then(|p| p.parse().ok())
+            .unwrap_or(DEFAULT_PORT);
+        let mut instance = CryptdInstance {
+            pid: proc.pid,
+            port,
+            clients: clients(port),
+        };
+        instance.clients.retain(|pid| *pid != proc.pid);
+
+        let parent = spawner(proc, &instance, &by_pid).map(|pid| {
+            let p = by_pid[&pid];
+            CryptdParent {
+                pid,
+                program: p.program.clone(),
+                cmdline: p.cmdline.join(" "),
+            }
+        });
+        let parent_pid = parent.as_ref().map(|p| p.pid);
+        match groups
+            .iter_mut()
+            .find(|g| g.parent.as_ref().map(|p| p.pid) == parent_pid)
+        {
+            Some(group) => group.instances.push(instance),
+            None => groups.push(CryptdGroup {
+                parent,
+                instances: vec![instance],
+            }),
+        }
+    }
+
+    // Stray instances last
+    groups.sort_by_key(|g| (g.parent.is_none(), g.parent.as_ref().map(|p| p.pid)));
+    groups
+}
+
+#[test]
+fn test_group_by_parent() {
+    let proc = |pid: i32, ppid: i32, program: &str, cmdline: &[&str]| CommonProcInfo {
+        pid,
+        ppid: Some(ppid),
+        program: program.to_owned(),
+        cmdline: cmdline.iter().map(|s| s.to_string()).collect(),
+        env: Default::default(),
+        cwd: None,
+        exe: None,
+        uid: None,
+        start_time: None,
+        rss: None,
+    };
+    let procs = vec![
+        proc(1, 0, "systemd", &[]),
+        proc(100, 1, "node", &["node", "test.js"]),
+        proc(101, 100, "mongocryptd", &["mongocryptd"]),
+        proc(102, 1, "mongocryptd", &["mongocryptd", "--port", "27021"]),
+        proc(103, 1, "mongocryptd", &["mongocryptd", "--port=27022"]),
+        proc(200, 1, "python3", &["python3", "app.py"]),
+    ];
+    let cryptds: Vec<&CommonProcInfo> = procs
+        .iter()
+        .filter(|p| p.program == "mongocryptd")
+        .collect();
+
+    // 102 was orphaned but python3 is still connected to it, nothing uses 103
+    let groups = group_by_parent(&cryptds, &procs, |port| match port {
+        27021 => vec![200, 102],
+        _ => vec![],
+    });
+    assert_eq!(groups.len(), 3);
+    assert_eq!(groups[0].parent.as_ref().unwrap().program, "node");
+    assert_eq!(groups[0].instances[0].port, DEFAULT_PORT);
+    assert_eq!(groups[1].parent.as_ref().unwrap().pid, 200);
+    assert_eq!(groups[1].instances[0].clients, vec![200]);
+    assert_eq!(groups[2].parent, None);
+    assert_eq!(groups[2].instances[0].pid, 103);
+}
@@ -0,0 +1,195 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Everything known about a single process
+
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use super::client::{Connection, Settings};
+use super::config::{get_path, local_effective_config};
+use super::fork::check_pidfile;
+use super::startup::log_startup_phase;
+use super::MongoPSInfo;
+
+/// Role of a server from hello, e.g. PRIMARY or mongos
+pub fn server_role(settings: &Settings, port: i32) -> Result<String> {
+    let mut conn = Connection::connect_local(settings, port, Duration::from_secs(1))?;
+    let hello = conn.run_command("admin", json!({"hello": 1}))?;
+    let is = |field: &str| hello.get(field).and_then(|v| v.as_bool()) == Some(true);
+
+    let role = if hello.get("msg").and_then(|v| v.as_str()) == Some("isdbgrid") {
+        "mongos"
+    } else if is("arbiterOnly") {
+        "ARBITER"
+    } else if hello.get("setName").is_none() {
+        "standalone"
+    } else if is("isWritablePrimary") || is("ismaster") {
+        "PRIMARY"
+    } else if is("secondary") {
+        "SECONDARY"
+    } else {
+        "OTHER"
+    };
+    Ok(role.to_owned())
+}
+
+pub fn format_age(secs: u64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m {}s", s / 60, s % 60),
+        s if s < 86400 => format!("{}h {}m", s / 3600, s % 3600 / 60),
+        s => format!("{}d {}h", s / 86400, s % 86400 / 3600),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+pub fn describe(info: &MongoPSInfo, pid: i32) -> Result<String> {
+    let Some(proc) = info.proc_info(pid) else {
+        bail!("{} is not a mongo process", pid);
+    };
+
+    let mut out = String::new();
+    let mut line = |name: &str, value: String| {
+        writeln!(out, "{:<14}{}", format!("{}:", name), value).unwrap();
+    };
+
+    let mongod = info.mongod.iter().find(|d| d.pid == pid);
+    let mongos = info.mongos.iter().find(|s| s.pid == pid);
+    line("pid", pid.to_string());
+    let kind = match (mongod, mongos) {
+        (Some(d), _) => format!("mongod ({:?})", d.server_type),
+        (_, Some(_)) => "mongos".to_owned(),
+        _ if info.cryptd(pid).is_some() => "mongocryptd".to_owned(),
+        _ => "legacy shell".to_owned(),
+    };
+    line("type", kind);
+    if let Some((group, c)) = info.cryptd(pid) {
+        line("port", c.port.to_string());
+        line(
+            "spawned by",
+            group
+                .parent
+                .as_ref()
+                .map_or("nothing, stray".to_owned(), |p| {
+                    format!("{} {}", p.pid, p.cmdline)
+                }),
+        );
+    }
+
+    if let Some(port) = info.server_target(pid).map(|t| t.port) {
+        line("port", port.to_string());
+        let logpath = mongod
+            .and_then(|d| d.logpath.as_deref())
+            .or(mongos.and_then(|s| s.logpath.as_deref()));
+        let role = server_role(&info.settings, port).unwrap_or_else(|e| {
+            match log_startup_phase(logpath) {
+                Some(phase) => format!("starting: {}", phase),
+                None => format!("unreachable ({})", e),
+            }
+        });
+        line("role", role);
+    }
+    if let Some(d) = mongod {
+        if let Some(ref name) = d.replica_set_name {
+            line("replica set", name.clone());
+        }
+    }
+    if let Some(s) = mongos {
+        line("config db", s.configdb.clone());
+    }
+    let start_dir = mongod
+        .and_then(|d| d.start_dir.as_ref())
+        .or(mongos.and_then(|s| s.start_dir.as_ref()));
+    if let Some(dir) = start_dir {
+        line("started by", format!("mpf start in {}", dir));
+    }
+
+    if let Ok(config) = local_effective_config(proc) {
+        if mongod.is_some() {
+            if let Some(dbpath) = get_path(&config, "storage.dbPath") {
+                line("dbpath", scalar(dbpath));
+            }
+        }
+        let destination = get_path(&config, "systemLog.destination").map(scalar);
+        let log = match get_path(&config, "systemLog.path") {
+            Some(path) => scalar(path),
+            None => destination.unwrap_or_else(|| "stdout".to_owned()),
+        };
+        line("log", log);
+    }
+
+    let fork = mongod
+        .map(|d| (d.fork, &d.pidfile))
+        .or(mongos.map(|s| (s.fork, &s.pidfile)));
+    if let Some((forked, pidfile)) = fork {
+        if forked {
+            line("forked", "yes, daemonized with --fork".to_owned());
+        }
+        if let Some(pidfile) = pidfile {
+            line("pidfile", pidfile.clone());
+            if let Some((_, problem)) = check_pidfile(info, pid, pidfile) {
+                line("WARNING", problem);
+            }
+        }
+    }
+
+    if let Some(d) = mongod {
+        if !d.special_modes.is_empty() {
+            line("special modes", d.special_modes.join(", "));
+        }
+    }
+
+    if let Some(exe) = proc.exe.as_ref() {
+        line("binary", exe.display().to_string());
+    }
+    if let Some(cwd) = proc.cwd.as_ref() {
+        line("cwd", cwd.display().to_string());
+    }
+    if let Some(start) = proc.start_time {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        line(
+            "started",
+            format!("{} ago", format_age(now.saturating_sub(start))),
+        );
+    }
+    if let Some(uid) = proc.uid {
+        line(
+            "user",
+            super::user_name(uid).unwrap_or_else(|| uid.to_string()),
+        );
+    }
+    line("command line", proc.cmdline.join(" "));
+
+    Ok(out)
+}
+
+#[test]
+fn test_format_age() {
+    assert_eq!(format_age(59), "59s");
+    assert_eq!(format_age(125), "2m 5s");
+    assert_eq!(format_age(7500), "2h 5m");
+    assert_eq!(format_age(90000), "1d 1h");
+}
@@ -0,0 +1,146 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Free space on the filesystems holding the dbpaths and logs of local servers
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use super::report::format_bytes;
+use super::MongoPSInfo;
+
+/// Free space below which doctor warns when --warn-disk-free is not given
+pub const DEFAULT_MIN_FREE_PERCENT: f64 = 10.0;
+
+/// A filesystem short on space and the servers keeping files on it
+#[derive(Debug)]
+pub struct LowVolume {
+    pub paths: Vec<String>,
+    pub free_bytes: u64,
+    pub free_percent: f64,
+    pub pids: Vec<i32>,
+}
+
+impl LowVolume {
+    pub fn message(&self) -> String {
+        format!(
+            "filesystem of {} has {:.1}% free ({})",
+            self.paths.join(", "),
+            self.free_percent,
+            format_bytes(self.free_bytes)
+        )
+    }
+}
+
+/// Parse a percentage like 10% or 10
+pub fn parse_percent(s: &str) -> Result<f64, String> {
+    let value = s
+        .trim()
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid percentage {}", s))?;
+    if !(0.0..=100.0).contains(&value) {
+        return Err(format!("Percentage {} is not between 0 and 100", s));
+    }
+    Ok(value)
+}
+
+/// Bytes available to unprivileged users and total bytes of the filesystem holding path
+fn free_space(path: &Path) -> Result<(u64, u64)> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        bail!(
+            "Failed to statvfs {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        );
+    }
+    let block = stat.f_frsize as u64;
+    Ok((stat.f_bavail as u64 * block, stat.f_blocks as u64 * block))
+}
+
+fn percent(free: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+    free as f64 * 100.0 / total as f64
+}
+
+/// The dbpath and log file of every server, with the pid using them
+pub fn server_paths(info: &MongoPSInfo) -> Vec<(i32, String)> {
+    let mut paths = Vec::new();
+    for d in &info.mongod {
+        paths.push((
+            d.pid,
+            d.dbpath.clone().unwrap_or_else(|| "/data/db".to_owned()),
+        ));
+        paths.extend(d.logpath.clone().map(|l| (d.pid, l)));
+    }
+    for s in &info.mongos {
+        paths.extend(s.logpath.clone().map(|l| (s.pid, l)));
+    }
+    paths
+}
+
+/// Filesystems holding any of the paths with less than min_percent free, paths that do not exist
+/// are skipped
+pub fn low_volumes(paths: &[(i32, String)], min_percent: f64) -> Vec<LowVolume> {
+    // Device to paths and pids on it
+    let mut devices: BTreeMap<u64, (Vec<String>, Vec<i32>)> = BTreeMap::new();
+    for (pid, path) in paths {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            continue;
+        };
+        let (paths, pids) = devices.entry(metadata.dev()).or_default();
+        if !paths.contains(path) {
+            paths.push(path.clone());
+        }
+        if !pids.contains(pid) {
+            pids.push(*pid);
+        }
+    }
+
+    let mut low = Vec::new();
+    for (paths, pids) in devices.into_values() {
+        let Ok((free, total)) = free_space(Path::new(&paths[0])) else {
+            continue;
+        };
+        let free_percent = percent(free, total);
+        if free_percent < min_percent {
+            low.push(LowVolume {
+                paths,
+                free_bytes: free,
+                free_percent,
+                pids,
+            });
+        }
+    }
+    low
+}
+
+#[test]
+fn test_parse_percent() {
+    assert_eq!(parse_percent("10%"), Ok(10.0));
+    assert_eq!(parse_percent("2.5"), Ok(2.5));
+    assert!(parse_percent("150%").is_err());
+    assert!(parse_percent("lots").is_err());
+    assert_eq!(percent(5, 50), 10.0);
+    assert_eq!(percent(0, 0), 100.0);
+}
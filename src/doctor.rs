@@ -0,0 +1,256 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Host settings mongod warns about at startup, checked once for all local mongods
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::client::{server_version, Settings};
+use super::disk::low_volumes;
+#[cfg(target_os = "linux")]
+use super::linux::{filesystem_type, soft_limits};
+#[cfg(target_os = "macos")]
+use super::macos::{filesystem_type, soft_limits};
+use super::probe::probe_all;
+use super::ServerTarget;
+
+/// Lowest open file and process limits mongod does not warn about
+const MIN_LIMIT: u64 = 64000;
+
+/// Lowest vm.max_map_count mongod does not warn about
+const MIN_MAX_MAP_COUNT: u64 = 262144;
+
+/// A problem with the host and the servers it affects
+#[derive(Debug)]
+pub struct Warning {
+    pub message: String,
+    pub advice: String,
+    pub servers: Vec<ServerTarget>,
+}
+
+/// A mongod to check, with the dbpath and log file it uses
+pub struct Server {
+    pub target: ServerTarget,
+    pub dbpath: String,
+    pub logpath: Option<String>,
+}
+
+fn read_setting(path: &str) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_owned())
+}
+
+/// The selected value of a kernel setting like "always [madvise] never"
+fn selected(setting: &str) -> Option<&str> {
+    let start = setting.find('[')?;
+    let end = setting[start..].find(']')?;
+    Some(&setting[start + 1..start + end])
+}
+
+/// Transparent hugepages setting wanted by a version: 8.0 uses them, earlier versions do not
+fn wanted_hugepages(version: &str) -> &'static str {
+    let major = version
+        .split('.')
+        .next()
+        .and_then(|m| m.parse::<u32>().ok())
+        .unwrap_or(0);
+    if major >= 8 {
+        "always"
+    } else {
+        "never"
+    }
+}
+
+fn check_hugepages(
+    servers: &[Server],
+    versions: &[Option<String>],
+    found: &mut Vec<(String, String, ServerTarget)>,
+) {
+    for name in ["enabled", "defrag"] {
+        let path = format!("/sys/kernel/mm/transparent_hugepage/{}", name);
+        let Some(setting) = read_setting(&path) else {
+            continue;
+        };
+        let Some(value) = selected(&setting) else {
+            continue;
+        };
+        for (server, version) in servers.iter().zip(versions) {
+            let Some(version) = version else {
+                continue;
+            };
+            let wanted = wanted_hugepages(version);
+            // defrag only matters when hugepages are not wanted
+            if value != wanted && (name == "enabled" || wanted == "never") {
+                found.push((
+                    format!("{} is '{}'", path, value),
+                    format!(
+                        "We suggest setting it to '{}' for mongod {}",
+                        wanted, version
+                    ),
+                    server.target.clone(),
+                ));
+            }
+        }
+    }
+}
+
+fn check_limits(servers: &[Server], found: &mut Vec<(String, String, ServerTarget)>) {
+    for server in servers {
+        let Ok((files, processes)) = soft_limits(server.target.pid) else {
+            continue;
+        };
+        for (what, limit) in [("open files", files), ("processes", processes)] {
+            if let Some(limit) = limit.filter(|l| *l < MIN_LIMIT) {
+                found.push((
+                    format!("soft rlimit for {} is {}", what, limit),
+                    format!("We suggest raising it to at least {}", MIN_LIMIT),
+                    server.target.clone(),
+                ));
+            }
+        }
+    }
+}
+
+fn check_host(servers: &[Server], found: &mut Vec<(String, String, ServerTarget)>) {
+    let mut host = Vec::new();
+    if let Some(count) =
+        read_setting("/proc/sys/vm/max_map_count").and_then(|c| c.parse::<u64>().ok())
+    {
+        if count < MIN_MAX_MAP_COUNT {
+            host.push((
+                format!("vm.max_map_count is {}", count),
+                format!("We suggest setting it to at least {}", MIN_MAX_MAP_COUNT),
+            ));
+        }
+    }
+    if let Some(source) =
+        read_setting("/sys/devices/system/clocksource/clocksource0/current_clocksource")
+    {
+        if matches!(source.as_str(), "xen" | "hpet" | "acpi_pm") {
+            host.push((
+                format!("the clock source is {}", source),
+                "Timestamps are slow to read, we suggest using tsc".to_owned(),
+            ));
+        }
+    }
+
+    for (message, advice) in host {
+        for server in servers {
+            found.push((message.clone(), advice.clone(), server.target.clone()));
+        }
+    }
+}
+
+fn check_filesystems(servers: &[Server], found: &mut Vec<(String, String, ServerTarget)>) {
+    for server in servers {
+        let Ok(fs_type) = filesystem_type(server.target.pid, Path::new(&server.dbpath)) else {
+            continue;
+        };
+        let advice = match fs_type.as_str() {
+            "ext4" => "XFS is strongly recommended with the WiredTiger storage engine",
+            "nfs" | "nfs4" => "NFS is not recommended, use local storage",
+            "tmpfs" => "Data in tmpfs is lost when the host restarts",
+            _ => continue,
+        };
+        found.push((
+            format!("dbpath {} is on {}", server.dbpath, fs_type),
+            advice.to_owned(),
+            server.target.clone(),
+        ));
+    }
+}
+
+fn check_disk_free(
+    servers: &[Server],
+    min_free_percent: f64,
+    found: &mut Vec<(String, String, ServerTarget)>,
+) {
+    let mut paths = Vec::new();
+    for server in servers {
+        paths.push((server.target.pid, server.dbpath.clone()));
+        paths.extend(server.logpath.clone().map(|l| (server.target.pid, l)));
+    }
+    for volume in low_volumes(&paths, min_free_percent) {
+        for server in servers
+            .iter()
+            .filter(|s| volume.pids.contains(&s.target.pid))
+        {
+            found.push((
+                volume.message(),
+                format!(
+                    "Servers fail when the disk fills up, keep at least {}% free",
+                    min_free_percent
+                ),
+                server.target.clone(),
+            ));
+        }
+    }
+}
+
+/// Check the host for every server, grouping servers that share a warning
+pub fn doctor(settings: &Settings, servers: &[Server], min_free_percent: f64) -> Vec<Warning> {
+    let versions = probe_all(settings, servers, |s| {
+        server_version(settings, s.target.port).ok()
+    });
+
+    let mut found = Vec::new();
+    check_hugepages(servers, &versions, &mut found);
+    check_limits(servers, &mut found);
+    check_host(servers, &mut found);
+    check_filesystems(servers, &mut found);
+    check_disk_free(servers, min_free_percent, &mut found);
+
+    let mut grouped: BTreeMap<(String, String), Vec<ServerTarget>> = BTreeMap::new();
+    for (message, advice, target) in found {
+        grouped.entry((message, advice)).or_default().push(target);
+    }
+    grouped
+        .into_iter()
+        .map(|((message, advice), servers)| Warning {
+            message,
+            advice,
+            servers,
+        })
+        .collect()
+}
+
+pub fn print_doctor(warnings: &[Warning]) {
+    if warnings.is_empty() {
+        println!("No host problems found");
+        return;
+    }
+
+    for warning in warnings {
+        let servers: Vec<String> = warning
+            .servers
+            .iter()
+            .map(|t| format!("{} (port {})", t.pid, t.port))
+            .collect();
+        println!("** WARNING: {}", warning.message);
+        println!("**          {}", warning.advice);
+        println!("**          Affects {}", servers.join(", "));
+        println!();
+    }
+}
+
+#[test]
+fn test_selected() {
+    assert_eq!(selected("always [madvise] never"), Some("madvise"));
+    assert_eq!(selected("[always] madvise never"), Some("always"));
+    assert_eq!(selected("never"), None);
+    assert_eq!(wanted_hugepages("7.0.12"), "never");
+    assert_eq!(wanted_hugepages("8.0.0"), "always");
+}
@@ -0,0 +1,144 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Follow which member of each replica set is primary
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use serde_json::json;
+
+use super::client::{Connection, Settings};
+use super::probe::probe_all;
+use super::watch::timestamp;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ElectionState {
+    pub primary: Option<String>,
+    pub term: Option<i64>,
+}
+
+impl std::fmt::Display for ElectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.primary.as_deref().unwrap_or("no primary"))?;
+        if let Some(term) = self.term {
+            write!(f, " (term {})", term)?;
+        }
+        Ok(())
+    }
+}
+
+// What a single member thinks, the term only comes from replSetGetStatus
+fn member_view(settings: &Settings, port: i32) -> Option<ElectionState> {
+    let mut conn = Connection::connect_local(settings, port, Duration::from_secs(1)).ok()?;
+    let hello = conn.run_command("admin", json!({"hello": 1})).ok()?;
+    let term = conn
+        .run_command("admin", json!({"replSetGetStatus": 1}))
+        .ok()
+        .and_then(|status| status.get("term").and_then(|t| t.as_i64()));
+    Some(ElectionState {
+        primary: hello
+            .get("primary")
+            .and_then(|p| p.as_str())
+            .map(|p| p.to_owned()),
+        term,
+    })
+}
+
+/// Combine the views of the members, trusting the member that saw the latest term since members
+/// that missed an election still report the old primary
+pub fn merge_views(views: &[ElectionState]) -> ElectionState {
+    views
+        .iter()
+        .filter(|v| v.primary.is_some())
+        .max_by_key(|v| v.term)
+        .or_else(|| views.iter().max_by_key(|v| v.term))
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub fn change_line(set: &str, old: &ElectionState, new: &ElectionState) -> Option<String> {
+    if old == new {
+        return None;
+    }
+    Some(format!("{} {} -> {}", set, old, new))
+}
+
+fn poll(settings: &Settings, sets: &BTreeMap<String, Vec<i32>>) -> BTreeMap<String, ElectionState> {
+    sets.iter()
+        .map(|(set, ports)| {
+            let views: Vec<ElectionState> =
+                probe_all(settings, ports, |p| member_view(settings, *p))
+                    .into_iter()
+                    .flatten()
+                    .collect();
+            (set.clone(), merge_views(&views))
+        })
+        .collect()
+}
+
+/// Print the primary of each replica set, with follow keep polling and print every change
+pub fn elections(
+    settings: &Settings,
+    sets: &BTreeMap<String, Vec<i32>>,
+    follow: bool,
+    interval: Duration,
+) -> Result<()> {
+    if sets.is_empty() {
+        bail!("No replica set members matched");
+    }
+
+    let mut previous = poll(settings, sets);
+    for (set, state) in &previous {
+        println!("{} {} {}", timestamp(), set, state);
+    }
+
+    if !follow {
+        return Ok(());
+    }
+    loop {
+        std::thread::sleep(interval);
+        let current = poll(settings, sets);
+        for (set, state) in &current {
+            let old = previous.get(set).cloned().unwrap_or_default();
+            if let Some(line) = change_line(set, &old, state) {
+                println!("{} {}", timestamp(), line);
+            }
+        }
+        previous = current;
+    }
+}
+
+#[test]
+fn test_merge_views() {
+    let view = |primary: Option<&str>, term| ElectionState {
+        primary: primary.map(|p| p.to_owned()),
+        term,
+    };
+    let merged = merge_views(&[
+        view(Some("localhost:20001"), Some(1)),
+        view(Some("localhost:20002"), Some(2)),
+        view(None, Some(3)),
+    ]);
+    assert_eq!(merged, view(Some("localhost:20002"), Some(2)));
+    assert_eq!(merge_views(&[view(None, Some(3))]), view(None, Some(3)));
+    assert_eq!(merge_views(&[]), ElectionState::default());
+
+    assert_eq!(
+        change_line("rs0", &merged, &view(Some("localhost:20003"), Some(3))).unwrap(),
+        "rs0 localhost:20002 (term 2) -> localhost:20003 (term 3)"
+    );
+    assert!(change_line("rs0", &merged, &merged).is_none());
+}
@@ -0,0 +1,123 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Encryption at rest settings of a mongod
+
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::audit::{Finding, Severity};
+use super::config::get_path;
+use super::MongoPSInfo;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EncryptionInfo {
+    /// Where the master key comes from: keyfile or kmip
+    pub key_source: String,
+    pub key_file: Option<String>,
+    pub kmip_server: Option<String>,
+    pub kmip_port: Option<i64>,
+    pub cipher_mode: Option<String>,
+}
+
+/// Encryption settings of a mongod, None when encryption at rest is off
+pub fn encryption_info(config: &Value) -> Option<EncryptionInfo> {
+    let enabled = get_path(config, "security.enableEncryption")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    if !enabled {
+        return None;
+    }
+
+    let as_string =
+        |path: &str| -> Option<String> { get_path(config, path)?.as_str().map(|s| s.to_owned()) };
+    let kmip_server = match get_path(config, "security.kmip.serverName") {
+        Some(Value::Array(servers)) => Some(
+            servers
+                .iter()
+                .filter_map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        Some(Value::String(server)) => Some(server.clone()),
+        _ => None,
+    };
+    let key_file = as_string("security.encryptionKeyFile");
+
+    Some(EncryptionInfo {
+        key_source: if kmip_server.is_some() {
+            "kmip"
+        } else {
+            "keyfile"
+        }
+        .to_owned(),
+        key_file,
+        kmip_server,
+        kmip_port: get_path(config, "security.kmip.port").and_then(|v| v.as_i64()),
+        cipher_mode: as_string("security.encryptionCipherMode"),
+    })
+}
+
+/// Replica sets where only some of the members encrypt their data
+pub fn check_mixed_encryption(info: &MongoPSInfo, pids: &[i32]) -> Vec<Finding> {
+    let mut sets: BTreeMap<&str, Vec<(i32, bool)>> = BTreeMap::new();
+    for d in info.mongod.iter().filter(|d| pids.contains(&d.pid)) {
+        if let Some(ref repl_set) = d.replica_set_name {
+            sets.entry(repl_set)
+                .or_default()
+                .push((d.pid, d.encryption.is_some()));
+        }
+    }
+
+    let mut findings = Vec::new();
+    for (repl_set, members) in sets {
+        let encrypted = members.iter().filter(|(_, e)| *e).count();
+        if encrypted == 0 || encrypted == members.len() {
+            continue;
+        }
+        for (pid, _) in members.iter().filter(|(_, e)| !*e) {
+            findings.push(Finding {
+                pid: *pid,
+                severity: Severity::Medium,
+                message: format!(
+                    "not encrypted while {} of {} members of replica set {} are",
+                    encrypted,
+                    members.len(),
+                    repl_set
+                ),
+            });
+        }
+    }
+    findings
+}
+
+#[test]
+fn test_encryption_info() {
+    use serde_json::json;
+
+    assert_eq!(encryption_info(&json!({"security": {}})), None);
+
+    let config = json!({"security": {
+        "enableEncryption": true,
+        "kmip": {"serverName": "kmip.example.com", "port": 5696}
+    }});
+    let encryption = encryption_info(&config).unwrap();
+    assert_eq!(encryption.key_source, "kmip");
+    assert_eq!(encryption.kmip_port, Some(5696));
+
+    let config = json!({"security": {"enableEncryption": true, "encryptionKeyFile": "/key"}});
+    assert_eq!(encryption_info(&config).unwrap().key_source, "keyfile");
+}
@@ -0,0 +1,67 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Evaluate a JavaScript snippet with mongosh on every matched server and label the output
+
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use super::connect::connection_uri;
+use super::fanout::{fan_out, Report};
+use super::{MongoPSInfo, ServerTarget};
+
+/// What the shell printed for one server
+pub struct EvalOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+fn run_shell(shell: &Path, uri: &str, snippet: &str) -> Result<EvalOutput> {
+    let output = Command::new(shell)
+        .arg(uri)
+        .arg("--quiet")
+        .arg("--eval")
+        .arg(snippet)
+        .output()
+        .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", shell.display(), e))?;
+    Ok(EvalOutput {
+        success: output.status.success(),
+        stdout: String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr)
+            .trim_end()
+            .to_owned(),
+    })
+}
+
+/// Run the snippet on every target concurrently, each connected directly to its server
+pub fn eval(info: &MongoPSInfo, targets: &[ServerTarget], shell: &Path, snippet: &str) -> Report {
+    fan_out(targets, info.settings.concurrency(), |t| {
+        let output = run_shell(shell, &connection_uri(info, t, true), snippet)?;
+        if !output.success {
+            let text = [output.stdout, output.stderr]
+                .into_iter()
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<String>>()
+                .join("\n");
+            bail!(text);
+        }
+        Ok(Value::from(output.stdout))
+    })
+}
@@ -0,0 +1,311 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Export the discovered processes in formats other tools understand
+
+use std::fmt::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use super::client::server_version;
+use super::config::{cmdline_to_config, to_yaml};
+use super::probe::probe_all;
+use super::types::CommonProcInfo;
+use super::{MongoDType, MongoPSInfo};
+
+fn mongod_service(port: i32) -> String {
+    format!("mongod-{}", port)
+}
+
+fn mongos_service(port: i32) -> String {
+    format!("mongos-{}", port)
+}
+
+/// Render the topology as a docker-compose file. `version_of` maps a port to the server
+/// version used for the image tag.
+pub fn docker_compose<F>(info: &MongoPSInfo, version_of: F) -> Result<String>
+where
+    F: Fn(i32) -> Option<String>,
+{
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Generated by mpf from the mongo processes running locally"
+    )?;
+    writeln!(
+        out,
+        "# Replica sets must be initiated with the service names as member hosts, i.e. mongod-27017:27017"
+    )?;
+    writeln!(out, "services:")?;
+
+    for d in &info.mongod {
+        let mut command = vec![
+            "mongod".to_owned(),
+            "--bind_ip_all".to_owned(),
+            "--port".to_owned(),
+            d.port.to_string(),
+        ];
+        if let Some(ref repl_set) = d.replica_set_name {
+            command.push("--replSet".to_owned());
+            command.push(repl_set.clone());
+        }
+        match d.server_type {
+            MongoDType::Config => command.push("--configsvr".to_owned()),
+            MongoDType::Shard => command.push("--shardsvr".to_owned()),
+            _ => {}
+        }
+
+        write_service(
+            &mut out,
+            &mongod_service(d.port),
+            version_of(d.port),
+            d.port,
+            &command,
+            &[],
+        )?;
+    }
+
+    for s in &info.mongos {
+        // Point the router at the config server containers rather than localhost
+        let config_hosts: Vec<String> = info
+            .mongod
+            .iter()
+            .filter(|d| {
+                d.server_type == MongoDType::Config
+                    && d.replica_set_name.as_deref() == Some(s.configdb.as_str())
+            })
+            .map(|d| format!("{}:{}", mongod_service(d.port), d.port))
+            .collect();
+        let depends_on: Vec<String> = info
+            .mongod
+            .iter()
+            .filter(|d| d.server_type == MongoDType::Config || d.server_type == MongoDType::Shard)
+            .map(|d| mongod_service(d.port))
+            .collect();
+
+        let command = vec![
+            "mongos".to_owned(),
+            "--bind_ip_all".to_owned(),
+            "--port".to_owned(),
+            s.port.to_string(),
+            "--configdb".to_owned(),
+            format!("{}/{}", s.configdb, config_hosts.join(",")),
+        ];
+
+        write_service(
+            &mut out,
+            &mongos_service(s.port),
+            version_of(s.port),
+            s.port,
+            &command,
+            &depends_on,
+        )?;
+    }
+
+    Ok(out)
+}
+
+fn write_service(
+    out: &mut String,
+    name: &str,
+    version: Option<String>,
+    port: i32,
+    command: &[String],
+    depends_on: &[String],
+) -> Result<()> {
+    writeln!(out, "  {}:", name)?;
+    writeln!(
+        out,
+        "    image: mongo:{}",
+        version.unwrap_or_else(|| "latest".to_owned())
+    )?;
+    writeln!(out, "    hostname: {}", name)?;
+    writeln!(out, "    ports:")?;
+    writeln!(out, "      - \"{}:{}\"", port, port)?;
+    writeln!(out, "    command:")?;
+    for arg in command {
+        writeln!(out, "      - \"{}\"", arg)?;
+    }
+    if !depends_on.is_empty() {
+        writeln!(out, "    depends_on:")?;
+        for dep in depends_on {
+            writeln!(out, "      - {}", dep)?;
+        }
+    }
+    Ok(())
+}
+
+/// Render a process's command line as an equivalent config file
+pub fn config_file(proc: &CommonProcInfo) -> Result<String> {
+    let args = proc.cmdline.get(1..).unwrap_or_default();
+    let (config, unmapped) = cmdline_to_config(args);
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "# Generated by mpf from the command line of {} ({})",
+        proc.pid, proc.program
+    )?;
+    for option in unmapped {
+        writeln!(out, "# No config file equivalent: {}", option)?;
+    }
+    out.push_str(&to_yaml(&config));
+    Ok(out)
+}
+
+#[derive(Debug, ValueEnum, Clone, PartialEq)]
+pub enum Debugger {
+    /// CodeLLDB extension
+    Lldb,
+    /// Microsoft C/C++ extension
+    Cppdbg,
+}
+
+/// VS Code attach configuration for a process
+pub fn vscode_attach_config(
+    proc: &CommonProcInfo,
+    port: Option<i32>,
+    debugger: &Debugger,
+) -> Value {
+    let name = match port {
+        Some(port) => format!("Attach {} {} (port {})", proc.program, proc.pid, port),
+        None => format!("Attach {} {}", proc.program, proc.pid),
+    };
+    let program = proc
+        .exe
+        .as_ref()
+        .map_or(String::new(), |p| p.to_string_lossy().into_owned());
+
+    match debugger {
+        Debugger::Lldb => json!({
+            "name": name,
+            "type": "lldb",
+            "request": "attach",
+            "pid": proc.pid,
+            "program": program,
+        }),
+        Debugger::Cppdbg => json!({
+            "name": name,
+            "type": "cppdbg",
+            "request": "attach",
+            "program": program,
+            "processId": proc.pid.to_string(),
+            "MIMode": if cfg!(target_os = "macos") { "lldb" } else { "gdb" },
+        }),
+    }
+}
+
+/// Add a configuration to a launch.json, replacing any configuration with the same name
+pub fn write_launch_json(path: &Path, config: Value) -> Result<()> {
+    let mut launch = if path.exists() {
+        let text = std::fs::read_to_string(path)?;
+        serde_json::from_str::<Value>(&text).with_context(|| {
+            format!(
+                "Cannot parse {}, comments are not supported, print the configuration instead",
+                path.display()
+            )
+        })?
+    } else {
+        json!({"version": "0.2.0", "configurations": []})
+    };
+
+    if !launch.get("configurations").is_some_and(|c| c.is_array()) {
+        launch["configurations"] = json!([]);
+    }
+    let configurations = launch["configurations"].as_array_mut().unwrap();
+    configurations.retain(|c| c.get("name") != config.get("name"));
+    configurations.push(config);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&launch)? + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+pub fn docker_compose_local(info: &MongoPSInfo) -> Result<String> {
+    let ports: Vec<i32> = info.server_targets().iter().map(|t| t.port).collect();
+    let versions = probe_all(&info.settings, &ports, |port| {
+        server_version(&info.settings, *port).ok()
+    });
+    docker_compose(info, |port| {
+        let i = ports.iter().position(|p| *p == port)?;
+        versions[i].clone()
+    })
+}
+
+#[test]
+fn test_docker_compose() {
+    use super::{MongoDServerInfo, MongoSServerInfo};
+
+    let info = MongoPSInfo {
+        schema_version: super::schema::SCHEMA_VERSION,
+        mongod: vec![MongoDServerInfo {
+            pid: 1,
+            port: 20001,
+            server_type: MongoDType::Config,
+            replica_set_name: Some("configRS".to_owned()),
+            dbpath: None,
+            logpath: None,
+            fork: false,
+            pidfile: None,
+            user: None,
+            cwd: None,
+            listening_ports: None,
+            special_modes: vec![],
+            storage: Default::default(),
+            encryption: None,
+            set_parameters: Default::default(),
+            test_instance: false,
+            tls: None,
+            auth: Default::default(),
+            unix_socket: None,
+            replication: None,
+            shard_name: None,
+            start_dir: None,
+        }],
+        mongos: vec![MongoSServerInfo {
+            pid: 2,
+            port: 20000,
+            configdb: "configRS".to_owned(),
+            logpath: None,
+            fork: false,
+            pidfile: None,
+            user: None,
+            cwd: None,
+            listening_ports: None,
+            set_parameters: Default::default(),
+            test_instance: false,
+            tls: None,
+            auth: Default::default(),
+            unix_socket: None,
+            start_dir: None,
+        }],
+        shell: vec![],
+        mongocryptd: vec![],
+        clusters: vec![],
+        plugin_processes: vec![],
+        procs: vec![],
+        settings: Default::default(),
+    };
+
+    let compose = docker_compose(&info, |_| Some("7.0.2".to_owned())).unwrap();
+    assert!(compose.contains("  mongod-20001:\n    image: mongo:7.0.2\n"));
+    assert!(compose.contains("      - \"--configsvr\"\n"));
+    assert!(compose.contains("      - \"configRS/mongod-20001:20001\"\n"));
+    assert!(compose.contains("    depends_on:\n      - mongod-20001\n"));
+}
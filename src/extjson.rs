@@ -0,0 +1,118 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// BSON documents as serde_json values, encoded and decoded with the bson crate.
+//
+// Numbers are plain JSON numbers, BSON types without a JSON equivalent use the Extended JSON
+// wrappers (i.e. {"$oid": "..."}) so they survive a round trip. Dates are always
+// {"$date": {"$numberLong": "..."}} so callers only deal with one form.
+
+use anyhow::{bail, Result};
+use bson::raw::RawDocument;
+use bson::{Bson, Document, Utf8Lossy};
+use serde_json::{json, Value};
+
+pub fn encode_document(doc: &Value) -> Result<Vec<u8>> {
+    let Value::Object(map) = doc else {
+        bail!("Only objects can be encoded as BSON documents");
+    };
+    Ok(Document::try_from(map.clone())?.to_vec()?)
+}
+
+/// Decode the document at the start of buf, strings that are not valid UTF-8 are replaced
+/// lossily since server replies can carry arbitrary bytes from logs and user data
+pub fn decode_document(buf: &[u8]) -> Result<Value> {
+    let len = buf
+        .get(..4)
+        .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .and_then(|len| usize::try_from(len).ok());
+    let Some(bytes) = len.and_then(|len| buf.get(..len)) else {
+        bail!("Truncated BSON document");
+    };
+    let Utf8Lossy(doc) = Utf8Lossy::<Document>::try_from(RawDocument::from_bytes(bytes)?)?;
+    Ok(to_json(Bson::Document(doc)))
+}
+
+fn to_json(value: Bson) -> Value {
+    match value {
+        Bson::Document(doc) => {
+            Value::Object(doc.into_iter().map(|(k, v)| (k, to_json(v))).collect())
+        }
+        Bson::Array(values) => Value::Array(values.into_iter().map(to_json).collect()),
+        Bson::DateTime(date) => {
+            json!({"$date": {"$numberLong": date.timestamp_millis().to_string()}})
+        }
+        Bson::Symbol(s) => Value::String(s),
+        Bson::Undefined => Value::Null,
+        v => v.into_relaxed_extjson(),
+    }
+}
+
+/// Canonical Extended JSON keeps the BSON type of every number
+pub fn to_canonical_extended_json(value: &Value) -> Result<Value> {
+    Ok(Bson::try_from(value.clone())?.into_canonical_extjson())
+}
+
+#[test]
+fn test_bson_round_trip() {
+    let doc = serde_json::json!({
+        "hello": 1,
+        "big": 5_000_000_000i64,
+        "ratio": 0.5,
+        "name": "rs0",
+        "ok": true,
+        "none": null,
+        "hosts": ["localhost:20000", "localhost:20001"],
+        "nested": {"a": {"b": "c"}},
+        "electionId": {"$oid": "7fffffff0000000000000001"},
+        "ts": {"$timestamp": {"t": 1700000000, "i": 3}},
+        "when": {"$date": {"$numberLong": "1700000000000"}},
+        "bin": {"$binary": {"base64": "AQIDBA==", "subType": "04"}},
+        "price": {"$numberDecimal": "1.50"},
+    });
+
+    let mut bytes = encode_document(&doc).unwrap();
+    assert_eq!(decode_document(&bytes).unwrap(), doc);
+
+    // Keys keep their insertion order since the first key of a command is its name
+    let keys: Vec<&String> = doc.as_object().unwrap().keys().collect();
+    assert_eq!(keys[0], "hello");
+
+    // Anything after the document is not part of it
+    bytes.extend_from_slice(&[1, 2, 3]);
+    assert_eq!(decode_document(&bytes).unwrap(), doc);
+    assert!(decode_document(&bytes[..bytes.len() - 10]).is_err());
+    assert!(decode_document(&[1, 2]).is_err());
+}
+
+#[test]
+fn test_canonical_extended_json() {
+    let doc = serde_json::json!({
+        "port": 20001,
+        "big": 5_000_000_000i64,
+        "cache": 0.5,
+        "ports": [1],
+        "when": {"$date": {"$numberLong": "1700000000000"}},
+    });
+    assert_eq!(
+        to_canonical_extended_json(&doc).unwrap(),
+        serde_json::json!({
+            "port": {"$numberInt": "20001"},
+            "big": {"$numberLong": "5000000000"},
+            "cache": {"$numberDouble": "0.5"},
+            "ports": [{"$numberInt": "1"}],
+            "when": {"$date": {"$numberLong": "1700000000000"}},
+        })
+    );
+}
@@ -0,0 +1,245 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Run one action on many matched targets concurrently and report every outcome together, so
+// run-cmd, eval, signal and shutdown share how results, failures and timeouts are shown
+
+use std::io::ErrorKind;
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+
+use super::probe::probe_limited;
+use super::ServerTarget;
+
+/// Something an action runs against, identified by its pid and port if it has one
+pub trait Target: Sync {
+    fn pid(&self) -> i32;
+    fn port(&self) -> Option<i32>;
+}
+
+impl Target for ServerTarget {
+    fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    fn port(&self) -> Option<i32> {
+        Some(self.port)
+    }
+}
+
+impl Target for i32 {
+    fn pid(&self) -> i32 {
+        *self
+    }
+
+    fn port(&self) -> Option<i32> {
+        None
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Failed,
+    Timeout,
+}
+
+/// The outcome of the action on one target
+#[derive(Serialize, Debug)]
+pub struct TargetResult {
+    pub pid: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<i32>,
+    pub status: Status,
+    pub elapsed_ms: u128,
+    /// What the action returned, or the error message
+    pub output: Value,
+}
+
+/// The outcomes on all targets, in the order of the targets
+pub struct Report {
+    pub results: Vec<TargetResult>,
+}
+
+/// Run action on every target with at most concurrency running at once
+pub fn fan_out<T, F>(targets: &[T], concurrency: usize, action: F) -> Report
+where
+    T: Target,
+    F: Fn(&T) -> Result<Value> + Sync,
+{
+    let results = probe_limited(targets, concurrency, |t| {
+        let start = Instant::now();
+        let result = action(t);
+        let elapsed_ms = start.elapsed().as_millis();
+        let (status, output) = match result {
+            Ok(output) => (Status::Ok, output),
+            Err(e) => (classify(&e), Value::from(e.to_string())),
+        };
+        TargetResult {
+            pid: t.pid(),
+            port: t.port(),
+            status,
+            elapsed_ms,
+            output,
+        }
+    });
+    Report { results }
+}
+
+// Timeouts keep their io error in the chain, see Connection::io_error
+fn classify(e: &anyhow::Error) -> Status {
+    let timed_out = e.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|io| matches!(io.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock))
+    });
+    if timed_out {
+        Status::Timeout
+    } else {
+        Status::Failed
+    }
+}
+
+impl TargetResult {
+    fn label(&self) -> String {
+        match self.port {
+            Some(port) => format!("{} port {}:", self.pid, port),
+            None => format!("{}:", self.pid),
+        }
+    }
+
+    /// The output after the label, indented on the following lines when it spans several
+    fn labeled(&self) -> String {
+        let text = match &self.output {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        if text.contains('\n') {
+            let lines: Vec<String> = text.lines().map(|l| format!("  {}", l)).collect();
+            format!("{}\n{}", self.label(), lines.join("\n"))
+        } else {
+            format!("{} {}", self.label(), text)
+        }
+    }
+}
+
+impl Report {
+    pub fn count(&self, status: Status) -> usize {
+        self.results.iter().filter(|r| r.status == status).count()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "ok": self.count(Status::Ok),
+            "failed": self.count(Status::Failed),
+            "timeout": self.count(Status::Timeout),
+            "results": self.results,
+        })
+    }
+
+    /// One line per target followed by a table of the outcomes when there are several, or the
+    /// whole report as JSON, failing when any target failed or timed out
+    pub fn print(&self, as_json: bool) -> Result<()> {
+        if as_json {
+            println!("{}", serde_json::to_string_pretty(&self.to_json())?);
+        } else {
+            for result in &self.results {
+                if result.status == Status::Ok {
+                    println!("{}", result.labeled());
+                } else {
+                    eprintln!("{}", result.labeled());
+                }
+            }
+            if self.results.len() > 1 {
+                println!();
+                print!("{}", self.summary_table());
+            }
+        }
+
+        let unsuccessful = self.results.len() - self.count(Status::Ok);
+        if unsuccessful > 0 {
+            bail!(
+                "Failed on {} of {} target(s)",
+                unsuccessful,
+                self.results.len()
+            );
+        }
+        Ok(())
+    }
+
+    fn summary_table(&self) -> String {
+        let mut out = format!("{:<8} {:<6} {:<8} {:>8}\n", "PID", "PORT", "STATUS", "TIME");
+        for result in &self.results {
+            let port = result.port.map_or("-".to_owned(), |p| p.to_string());
+            let status = serde_json::to_value(result.status).unwrap_or_default();
+            out += &format!(
+                "{:<8} {:<6} {:<8} {:>6}ms\n",
+                result.pid,
+                port,
+                status.as_str().unwrap_or_default(),
+                result.elapsed_ms
+            );
+        }
+        out += &format!(
+            "{} ok, {} failed, {} timed out\n",
+            self.count(Status::Ok),
+            self.count(Status::Failed),
+            self.count(Status::Timeout)
+        );
+        out
+    }
+}
+
+#[test]
+fn test_fan_out() {
+    let report = fan_out(&[1, 2, 3], 2, |pid| match pid {
+        1 => Ok(Value::from("sent SIGTERM")),
+        2 => Err(
+            anyhow::Error::new(std::io::Error::from(ErrorKind::WouldBlock))
+                .context("No reply within 1s, the server may be hung"),
+        ),
+        _ => bail!("No such process"),
+    });
+    let statuses: Vec<Status> = report.results.iter().map(|r| r.status).collect();
+    assert_eq!(statuses, [Status::Ok, Status::Timeout, Status::Failed]);
+    assert_eq!(report.results[0].labeled(), "1: sent SIGTERM");
+    let multiline = TargetResult {
+        pid: 10,
+        port: Some(20001),
+        status: Status::Ok,
+        elapsed_ms: 5,
+        output: Value::from("{\n  current: 5\n}"),
+    };
+    assert_eq!(
+        multiline.labeled(),
+        "10 port 20001:\n  {\n    current: 5\n  }"
+    );
+    assert_eq!(
+        report.results[1].labeled(),
+        "2: No reply within 1s, the server may be hung"
+    );
+
+    let summary = report.to_json();
+    assert_eq!(
+        (&summary["ok"], &summary["failed"], &summary["timeout"]),
+        (&json!(1), &json!(1), &json!(1))
+    );
+    assert!(report
+        .summary_table()
+        .ends_with("1 ok, 1 failed, 1 timed out\n"));
+}
@@ -0,0 +1,156 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// C API so test harnesses in C and C++ can embed the discovery instead of running mpf and
+// parsing its output. Results are JSON strings owned by the caller and released with
+// mpf_free, failures return NULL and leave a message for mpf_last_error. cbindgen generates
+// include/mpf.h from the doc comments and signatures here, see cbindgen.toml.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, UnwindSafe};
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+
+use super::{connection_settings, filter_args, scan, schema, select_pids, server_uri, watch_entry};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: Option<String>) {
+    let message = message.map(|m| CString::new(m.replace('\0', " ")).unwrap_or_default());
+    LAST_ERROR.with(|e| *e.borrow_mut() = message);
+}
+
+// Run f for a C caller: a panic must not unwind into C, and strings cannot hold a NUL
+fn to_c<F: FnOnce() -> Result<String> + UnwindSafe>(f: F) -> *mut c_char {
+    let result = match catch_unwind(f) {
+        Ok(result) => result.and_then(|s| Ok(CString::new(s)?)),
+        Err(_) => Err(anyhow!("mpf panicked")),
+    };
+    match result {
+        Ok(s) => {
+            set_last_error(None);
+            s.into_raw()
+        }
+        Err(e) => {
+            set_last_error(Some(format!("{:#}", e)));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+fn summary() -> Result<String> {
+    let args = filter_args(&[])?;
+    let info = scan(&args, &connection_settings(&args)?)?;
+    let summary = schema::to_output_version(serde_json::to_value(&info)?, args.output_version)?;
+    Ok(summary.to_string())
+}
+
+fn find(args: &[String]) -> Result<String> {
+    let args = filter_args(args)?;
+    let info = scan(&args, &connection_settings(&args)?)?;
+    let matches: Vec<Value> = select_pids(&args, &info)
+        .into_iter()
+        .map(|pid| {
+            let entry = watch_entry(&info, pid);
+            json!({
+                "pid": pid,
+                "type": entry.kind,
+                "port": entry.port,
+                "uri": info.server_target(pid).map(|t| server_uri(&info, &t)),
+            })
+        })
+        .collect();
+    Ok(Value::Array(matches).to_string())
+}
+
+/// The JSON summary printed by mpf without arguments, without running classifier plugins.
+/// NULL on failure.
+#[no_mangle]
+pub extern "C" fn mpf_scan() -> *mut c_char {
+    to_c(summary)
+}
+
+/// The processes matched by command line filters such as {"-p", "20000-20010", "--replset",
+/// "rs0"}, as a JSON array of {pid, type, port, uri}. NULL on failure, including for arguments
+/// mpf would reject. Connection options among the arguments, such as --timeout, --tlsCAFile
+/// or --username, only apply to this call.
+///
+/// # Safety
+///
+/// argv must point to argc valid NUL terminated strings, or be NULL when argc is 0.
+#[no_mangle]
+pub unsafe extern "C" fn mpf_find(argv: *const *const c_char, argc: usize) -> *mut c_char {
+    let mut args = Vec::with_capacity(argc);
+    for i in 0..argc {
+        let arg = unsafe { *argv.add(i) };
+        if arg.is_null() {
+            set_last_error(Some(format!("Argument {} is NULL", i)));
+            return std::ptr::null_mut();
+        }
+        args.push(
+            unsafe { CStr::from_ptr(arg) }
+                .to_string_lossy()
+                .into_owned(),
+        );
+    }
+    to_c(move || find(&args))
+}
+
+/// Release a string returned by mpf_scan or mpf_find, NULL is ignored
+///
+/// # Safety
+///
+/// s must come from mpf_scan or mpf_find and not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn mpf_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+/// Why the last call on this thread returned NULL, or NULL after a success. The string stays
+/// valid until the next mpf call on the thread.
+#[no_mangle]
+pub extern "C" fn mpf_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(std::ptr::null(), |s| s.as_ptr()))
+}
+
+/// Version of the library, a static string
+#[no_mangle]
+pub extern "C" fn mpf_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+#[test]
+fn test_ffi() {
+    let last_error = || unsafe { CStr::from_ptr(mpf_last_error()) }.to_string_lossy();
+
+    let bad = CString::new("--no-such-flag").unwrap();
+    let argv = [bad.as_ptr()];
+    assert!(unsafe { mpf_find(argv.as_ptr(), argv.len()) }.is_null());
+    assert!(last_error().contains("--no-such-flag"));
+
+    let command = CString::new("report").unwrap();
+    let argv = [command.as_ptr()];
+    assert!(unsafe { mpf_find(argv.as_ptr(), argv.len()) }.is_null());
+    assert_eq!(last_error(), "Only filters are supported, not commands");
+
+    let version = unsafe { CStr::from_ptr(mpf_version()) };
+    assert_eq!(version.to_str().unwrap(), env!("CARGO_PKG_VERSION"));
+    unsafe { mpf_free(std::ptr::null_mut()) };
+}
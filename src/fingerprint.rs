@@ -0,0 +1,180 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Which exact build each server runs: the binaries of the running processes hashed with SHA-256,
+// compared with the file now at the same path to spot servers left on a replaced binary
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde_derive::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::describe::format_age;
+use super::MongoPSInfo;
+
+/// One build and the processes running it
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Binary {
+    pub path: String,
+    /// Seconds since the epoch the running binary was last modified
+    pub mtime: Option<u64>,
+    pub sha256: String,
+    /// The file at path was deleted or replaced since the processes started
+    pub stale: bool,
+    pub pids: Vec<i32>,
+}
+
+/// SHA-256 of a file in hex, read in chunks
+pub fn file_sha256(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// The kernel keeps the binary a process runs open even after it was replaced on disk
+fn running_binary(pid: i32, exe: &Path) -> PathBuf {
+    let proc_exe = PathBuf::from(format!("/proc/{}/exe", pid));
+    if proc_exe.exists() {
+        proc_exe
+    } else {
+        exe.to_path_buf()
+    }
+}
+
+// The path of a binary without the marker Linux appends once it is deleted
+fn binary_path(exe: &Path) -> (String, bool) {
+    let path = exe.to_string_lossy();
+    match path.strip_suffix(" (deleted)") {
+        Some(path) => (path.to_owned(), true),
+        None => (path.into_owned(), false),
+    }
+}
+
+/// Fingerprint the binaries of the mongod and mongos processes among pids, hashing each file
+/// once
+pub fn fingerprints(info: &MongoPSInfo, pids: &[i32]) -> Vec<Binary> {
+    let mut binaries: Vec<Binary> = Vec::new();
+    // (device, inode) of each running binary to its sha256 and mtime
+    let mut hashed: HashMap<(u64, u64), (String, Option<u64>)> = HashMap::new();
+    // On disk hash of each path, to compare with the running binaries
+    let mut on_disk: HashMap<String, Option<String>> = HashMap::new();
+
+    let servers = info
+        .mongod
+        .iter()
+        .map(|d| d.pid)
+        .chain(info.mongos.iter().map(|s| s.pid))
+        .filter(|pid| pids.contains(pid));
+    for pid in servers {
+        let Some(exe) = info.proc_info(pid).and_then(|p| p.exe.as_ref()) else {
+            continue;
+        };
+        let running = running_binary(pid, exe);
+        let Ok(metadata) = std::fs::metadata(&running) else {
+            eprintln!("{}: cannot read {}", pid, running.display());
+            continue;
+        };
+        let key = (metadata.dev(), metadata.ino());
+        if let Entry::Vacant(entry) = hashed.entry(key) {
+            let Ok(sha256) = file_sha256(&running) else {
+                eprintln!("{}: cannot read {}", pid, running.display());
+                continue;
+            };
+            entry.insert((sha256, u64::try_from(metadata.mtime()).ok()));
+        }
+        let (sha256, mtime) = hashed[&key].clone();
+
+        let (path, deleted) = binary_path(exe);
+        let current = on_disk
+            .entry(path.clone())
+            .or_insert_with(|| file_sha256(Path::new(&path)).ok());
+        let stale = deleted || current.as_ref() != Some(&sha256);
+
+        match binaries
+            .iter_mut()
+            .find(|b| b.path == path && b.sha256 == sha256)
+        {
+            Some(binary) => binary.pids.push(pid),
+            None => binaries.push(Binary {
+                path,
+                mtime,
+                sha256,
+                stale,
+                pids: vec![pid],
+            }),
+        }
+    }
+    binaries.sort_by(|a, b| (&a.path, a.mtime).cmp(&(&b.path, b.mtime)));
+    binaries
+}
+
+pub fn print_fingerprints(binaries: &[Binary]) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    println!(
+        "{:<12}  {:<12}  {:<5}  {:<40}  pids",
+        "sha256", "modified", "", "path"
+    );
+    for binary in binaries {
+        let modified = match binary.mtime {
+            Some(mtime) => format!("{} ago", format_age(now.saturating_sub(mtime))),
+            None => "-".to_owned(),
+        };
+        let pids: Vec<String> = binary.pids.iter().map(|p| p.to_string()).collect();
+        println!(
+            "{:<12}  {:<12}  {:<5}  {:<40}  {}",
+            &binary.sha256[..12],
+            modified,
+            if binary.stale { "STALE" } else { "" },
+            binary.path,
+            pids.join(",")
+        );
+    }
+}
+
+#[test]
+fn test_binary_path() {
+    assert_eq!(
+        binary_path(Path::new("/opt/mongo/bin/mongod (deleted)")),
+        ("/opt/mongo/bin/mongod".to_owned(), true)
+    );
+    assert_eq!(
+        binary_path(Path::new("/opt/mongo/bin/mongod")),
+        ("/opt/mongo/bin/mongod".to_owned(), false)
+    );
+
+    let path = std::env::temp_dir().join(format!("mpf-fingerprint-{}", std::process::id()));
+    std::fs::write(&path, b"abc").unwrap();
+    assert_eq!(
+        file_sha256(&path).unwrap(),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,176 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Servers started with --fork. The launching process waits in the foreground until the daemon it
+// forked is ready, so for a while both exist with the same command line and only the daemon is
+// the server.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::audit::{Finding, Severity};
+use super::signal::process_exists;
+use super::types::CommonProcInfo;
+use super::MongoPSInfo;
+
+/// Pid written to a --pidfilepath file, None if it is missing or garbled
+pub fn read_pidfile(path: &Path) -> Option<i32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[derive(Debug, PartialEq)]
+enum PidfileState {
+    Current,
+    Missing,
+    /// Holds a pid that is not running
+    Stale(i32),
+    /// Holds the pid of another live process
+    Other(i32),
+}
+
+fn pidfile_state<F>(pid: i32, held: Option<i32>, exists: F) -> PidfileState
+where
+    F: Fn(i32) -> bool,
+{
+    match held {
+        None => PidfileState::Missing,
+        Some(held) if held == pid => PidfileState::Current,
+        Some(held) if exists(held) => PidfileState::Other(held),
+        Some(held) => PidfileState::Stale(held),
+    }
+}
+
+/// Compare the pidfile of a server with the running processes, init scripts trust it to stop
+/// the right one
+pub fn check_pidfile(info: &MongoPSInfo, pid: i32, pidfile: &str) -> Option<(Severity, String)> {
+    match pidfile_state(pid, read_pidfile(Path::new(pidfile)), process_exists) {
+        PidfileState::Current => None,
+        PidfileState::Missing => Some((
+            Severity::Medium,
+            format!("pidfile {} is missing or unreadable", pidfile),
+        )),
+        PidfileState::Stale(held) => Some((
+            Severity::Medium,
+            format!("pidfile {} holds {} which is not running", pidfile, held),
+        )),
+        PidfileState::Other(held) => {
+            let other = match (info.server_target(held), info.proc_info(held)) {
+                (Some(t), Some(p)) => format!("{} on port {}", p.program, t.port),
+                (None, Some(p)) => p.program.clone(),
+                _ => "another process".to_owned(),
+            };
+            Some((
+                Severity::High,
+                format!("pidfile {} holds {}, which is {}", pidfile, held, other),
+            ))
+        }
+    }
+}
+
+/// Pidfile mismatches of the servers among pids
+pub fn check_pidfiles(info: &MongoPSInfo, pids: &[i32]) -> Vec<Finding> {
+    let mongod = info.mongod.iter().map(|d| (d.pid, &d.pidfile));
+    let mongos = info.mongos.iter().map(|s| (s.pid, &s.pidfile));
+    mongod
+        .chain(mongos)
+        .filter(|(pid, _)| pids.contains(pid))
+        .filter_map(|(pid, pidfile)| {
+            let (severity, message) = check_pidfile(info, pid, pidfile.as_ref()?)?;
+            Some(Finding {
+                pid,
+                severity,
+                message,
+            })
+        })
+        .collect()
+}
+
+/// Pids of launchers among forking servers. In each group of processes running the same command
+/// line the daemon is the pid in its pidfile, else the newest process.
+pub fn launchers<F>(procs: &[&CommonProcInfo], pidfile_pid: F) -> Vec<i32>
+where
+    F: Fn(&CommonProcInfo) -> Option<i32>,
+{
+    let mut groups: HashMap<(&str, &[String]), Vec<&CommonProcInfo>> = HashMap::new();
+    for proc in procs {
+        groups
+            .entry((proc.program.as_str(), proc.cmdline.as_slice()))
+            .or_default()
+            .push(proc);
+    }
+
+    let mut launchers = Vec::new();
+    for group in groups.into_values().filter(|g| g.len() > 1) {
+        let daemon = group
+            .iter()
+            .find_map(|p| pidfile_pid(p).filter(|pid| group.iter().any(|g| g.pid == *pid)))
+            .or_else(|| {
+                group
+                    .iter()
+                    .max_by_key(|p| (p.start_time, p.pid))
+                    .map(|p| p.pid)
+            });
+        launchers.extend(
+            group
+                .iter()
+                .map(|p| p.pid)
+                .filter(|pid| Some(*pid) != daemon),
+        );
+    }
+    launchers.sort();
+    launchers
+}
+
+#[test]
+fn test_pidfile_state() {
+    let exists = |pid| pid == 42;
+    assert_eq!(pidfile_state(7, Some(7), exists), PidfileState::Current);
+    assert_eq!(pidfile_state(7, None, exists), PidfileState::Missing);
+    assert_eq!(pidfile_state(7, Some(42), exists), PidfileState::Other(42));
+    assert_eq!(pidfile_state(7, Some(8), exists), PidfileState::Stale(8));
+}
+
+#[test]
+fn test_launchers() {
+    let proc = |pid: i32, start_time: u64, port: &str| CommonProcInfo {
+        pid,
+        ppid: Some(1),
+        program: "mongod".to_owned(),
+        cmdline: vec!["mongod".to_owned(), "--fork".to_owned(), port.to_owned()],
+        env: Default::default(),
+        cwd: None,
+        exe: None,
+        uid: None,
+        start_time: Some(start_time),
+        rss: None,
+    };
+    let procs = [
+        proc(10, 100, "--port=1"),
+        proc(12, 101, "--port=1"),
+        proc(20, 100, "--port=2"),
+        proc(21, 100, "--port=2"),
+        proc(30, 100, "--port=3"),
+    ];
+    let refs: Vec<&CommonProcInfo> = procs.iter().collect();
+
+    assert_eq!(launchers(&refs, |_| None), vec![10, 20]);
+    // A pidfile wins over start times, but only if it names one of the group
+    assert_eq!(
+        launchers(&refs, |p| match p.cmdline[2].as_str() {
+            "--port=1" => Some(10),
+            _ => Some(99),
+        }),
+        vec![12, 20]
+    );
+}
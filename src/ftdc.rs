@@ -0,0 +1,563 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Decode the full time diagnostic data capture (FTDC) files of a server's diagnostic.data into
+// timestamped series of chosen metrics for plotting.
+//
+// Each metrics file is a sequence of BSON documents. Those with type 1 hold a chunk: a zlib
+// compressed reference sample followed by the varint encoded deltas of every later sample, with
+// runs of zero deltas collapsed.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::ValueEnum;
+use flate2::read::ZlibDecoder;
+use serde_json::{Map, Value};
+
+use super::extjson::decode_document;
+use super::tls::civil_from_days;
+
+// Documents of a metrics file holding a chunk of samples
+const TYPE_METRIC_CHUNK: i64 = 1;
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum FtdcFormat {
+    Csv,
+    Json,
+}
+
+/// Metric names and the values of every sample of one chunk, in the order they were taken
+#[derive(Debug, PartialEq)]
+pub struct Chunk {
+    pub names: Vec<String>,
+    pub samples: Vec<Vec<u64>>,
+}
+
+fn slice(buf: &[u8], start: usize, len: usize) -> Result<&[u8]> {
+    match start.checked_add(len).and_then(|end| buf.get(start..end)) {
+        Some(bytes) => Ok(bytes),
+        None => bail!("FTDC data ends early"),
+    }
+}
+
+fn read_i32(buf: &[u8], pos: usize) -> Result<i32> {
+    Ok(i32::from_le_bytes(slice(buf, pos, 4)?.try_into()?))
+}
+
+// A length or count, which cannot be negative
+fn read_len(buf: &[u8], pos: usize) -> Result<usize> {
+    let len = read_i32(buf, pos)?;
+    usize::try_from(len).map_err(|_| anyhow!("Invalid length {} in FTDC data", len))
+}
+
+fn read_u64(buf: &[u8], pos: usize) -> Result<u64> {
+    Ok(u64::from_le_bytes(slice(buf, pos, 8)?.try_into()?))
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let Some(byte) = buf.get(*pos) else {
+            bail!("FTDC data ends early");
+        };
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("Invalid varint in FTDC data")
+}
+
+/// The numbers of a BSON document in order, the way the server turns a sample into metrics:
+/// numbers, bools and dates are one metric each, timestamps two and nested documents and
+/// arrays are walked
+fn extract_metrics(
+    doc: &[u8],
+    prefix: &str,
+    names: &mut Vec<String>,
+    values: &mut Vec<u64>,
+) -> Result<()> {
+    let end = read_len(doc, 0)?
+        .checked_sub(1)
+        .context("Invalid BSON document length in FTDC data")?;
+    let mut pos = 4;
+    while pos < end {
+        let Some(&element_type) = doc.get(pos) else {
+            bail!("FTDC data ends early");
+        };
+        let name_len = doc
+            .get(pos + 1..)
+            .and_then(|rest| rest.iter().position(|b| *b == 0))
+            .context("Unterminated BSON field name in FTDC data")?;
+        let name = String::from_utf8_lossy(&doc[pos + 1..pos + 1 + name_len]);
+        let path = if prefix.is_empty() {
+            name.into_owned()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        pos += 2 + name_len;
+
+        let mut metric = |value: u64, name: String| {
+            names.push(name);
+            values.push(value);
+        };
+        pos += match element_type {
+            0x01 => {
+                let double = f64::from_le_bytes(slice(doc, pos, 8)?.try_into()?);
+                metric(double as i64 as u64, path);
+                8
+            }
+            0x10 => {
+                metric(read_i32(doc, pos)? as i64 as u64, path);
+                4
+            }
+            0x09 | 0x12 => {
+                metric(read_u64(doc, pos)?, path);
+                8
+            }
+            0x08 => {
+                metric(slice(doc, pos, 1)?[0] as u64, path);
+                1
+            }
+            0x11 => {
+                let timestamp = read_u64(doc, pos)?;
+                metric(timestamp >> 32, format!("{}.t", path));
+                metric(timestamp & 0xffff_ffff, format!("{}.i", path));
+                8
+            }
+            // Servers convert decimals to integers, which is not worth implementing here but
+            // must still take a metric to keep the others in place
+            0x13 => {
+                metric(0, path);
+                16
+            }
+            0x03 | 0x04 => {
+                let len = read_len(doc, pos)?;
+                extract_metrics(slice(doc, pos, len)?, &path, names, values)?;
+                len
+            }
+            0x02 | 0x0D | 0x0E => 4 + read_len(doc, pos)?,
+            0x05 => 5 + read_len(doc, pos)?,
+            0x07 => 12,
+            0x0C => 16 + read_len(doc, pos)?,
+            0x0F => read_len(doc, pos)?,
+            // The pattern and the options, two C strings
+            0x0B => {
+                let mut nuls = doc
+                    .get(pos..)
+                    .unwrap_or_default()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, b)| **b == 0);
+                let (options_end, _) = nuls
+                    .nth(1)
+                    .context("Unterminated regular expression in FTDC data")?;
+                options_end + 1
+            }
+            0x06 | 0x0A | 0x7F | 0xFF => 0,
+            _ => bail!("Unsupported BSON type 0x{:02x} in FTDC data", element_type),
+        };
+    }
+    Ok(())
+}
+
+/// Decode the data of a chunk document: the uncompressed length, then the zlib stream
+pub fn decode_chunk(data: &[u8]) -> Result<Chunk> {
+    let mut buf = Vec::new();
+    ZlibDecoder::new(slice(data, 4, data.len().saturating_sub(4))?)
+        .read_to_end(&mut buf)
+        .context("Failed to decompress FTDC chunk")?;
+
+    let ref_len = read_len(&buf, 0)?;
+    let mut names = Vec::new();
+    let mut reference = Vec::new();
+    extract_metrics(slice(&buf, 0, ref_len)?, "", &mut names, &mut reference)?;
+
+    let mut pos = ref_len;
+    let metric_count = read_len(&buf, pos)?;
+    let delta_count = read_len(&buf, pos + 4)?;
+    pos += 8;
+    if metric_count != reference.len() {
+        bail!(
+            "FTDC chunk has {} metrics but its reference sample has {}",
+            metric_count,
+            reference.len()
+        );
+    }
+
+    // Deltas are stored metric by metric, each with one delta per later sample
+    let delta_len = metric_count
+        .checked_mul(delta_count)
+        .context("Too many deltas in FTDC chunk")?;
+    let mut deltas = Vec::new();
+    deltas
+        .try_reserve_exact(delta_len)
+        .context("Too many deltas in FTDC chunk")?;
+    deltas.resize(delta_len, 0u64);
+    let mut zeros = 0;
+    for delta in deltas.iter_mut() {
+        if zeros > 0 {
+            zeros -= 1;
+            continue;
+        }
+        *delta = read_varint(&buf, &mut pos)?;
+        if *delta == 0 {
+            zeros = read_varint(&buf, &mut pos)?;
+        }
+    }
+
+    let mut samples = vec![reference];
+    for j in 0..delta_count {
+        let sample = samples[j]
+            .iter()
+            .enumerate()
+            .map(|(i, value)| value.wrapping_add(deltas[i * delta_count + j]))
+            .collect();
+        samples.push(sample);
+    }
+    Ok(Chunk { names, samples })
+}
+
+/// The chunks of one metrics file
+pub fn read_chunks(path: &Path) -> Result<Vec<Chunk>> {
+    let buf = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut chunks = Vec::new();
+    let mut pos = 0;
+    while pos + 4 <= buf.len() {
+        let len = read_i32(&buf, pos)? as usize;
+        // The interim file can end with a document that is still being written
+        let Some(bytes) = buf.get(pos..pos + len) else {
+            break;
+        };
+        pos += len;
+        let doc = decode_document(bytes)?;
+        if doc.get("type").and_then(Value::as_i64) != Some(TYPE_METRIC_CHUNK) {
+            continue;
+        }
+        let Some(data) = doc
+            .get("data")
+            .and_then(|d| d.get("$binary"))
+            .and_then(|b| b.get("base64"))
+            .and_then(Value::as_str)
+        else {
+            bail!("FTDC chunk in {} has no data", path.display());
+        };
+        chunks.push(
+            decode_chunk(&BASE64.decode(data)?)
+                .with_context(|| format!("Failed to decode a chunk of {}", path.display()))?,
+        );
+    }
+    Ok(chunks)
+}
+
+/// The metrics files of a diagnostic.data directory, oldest first with metrics.interim last
+pub fn metrics_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("metrics."))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Whether a metric name contains the pattern, ignoring case. wt is short for wiredTiger, e.g.
+/// wt.cache.bytes matches serverStatus.wiredTiger.cache.bytes currently in the cache.
+pub fn metric_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<&str> = pattern
+        .split('.')
+        .map(|part| if part == "wt" { "wiredtiger" } else { part })
+        .collect();
+    name.to_lowercase()
+        .contains(&pattern.join(".").to_lowercase())
+}
+
+/// Values of the matching metrics at each sample time, None where a chunk lacks a metric
+#[derive(Debug, Default)]
+pub struct Series {
+    pub columns: Vec<String>,
+    /// Milliseconds since the epoch and the value of each column
+    pub rows: Vec<(i64, Vec<Option<i64>>)>,
+}
+
+/// The series of the metrics matching any pattern, from since on
+pub fn series(chunks: &[Chunk], patterns: &[String], since: Option<i64>) -> Result<Series> {
+    let mut series = Series::default();
+    let mut columns: HashMap<String, usize> = HashMap::new();
+
+    for chunk in chunks {
+        // Every sample records when it was taken
+        let Some(start) = chunk.names.iter().position(|n| n == "start") else {
+            bail!("FTDC chunk has no start time");
+        };
+        let mut selected = Vec::new();
+        for (i, name) in chunk.names.iter().enumerate() {
+            if i != start && patterns.iter().any(|p| metric_matches(p, name)) {
+                let next = columns.len();
+                let column = *columns.entry(name.clone()).or_insert(next);
+                if column == series.columns.len() {
+                    series.columns.push(name.clone());
+                }
+                selected.push((i, column));
+            }
+        }
+        for sample in &chunk.samples {
+            let time = sample[start] as i64;
+            if since.is_some_and(|since| time < since) {
+                continue;
+            }
+            let mut values = vec![None; series.columns.len()];
+            for (i, column) in &selected {
+                values[*column] = Some(sample[*i] as i64);
+            }
+            series.rows.push((time, values));
+        }
+    }
+
+    let width = series.columns.len();
+    for (_, values) in &mut series.rows {
+        values.resize(width, None);
+    }
+    Ok(series)
+}
+
+/// ISO 8601 UTC time of milliseconds since the epoch
+pub fn format_time(millis: i64) -> String {
+    let secs = millis.div_euclid(1000);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time = secs.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        millis.rem_euclid(1000)
+    )
+}
+
+// Quote a CSV field when it has a separator, quote or line break
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+/// A header of time and the metric names, then a line per sample
+pub fn write_csv(series: &Series, out: &mut dyn Write) -> Result<()> {
+    let header: Vec<String> = series.columns.iter().map(|c| csv_field(c)).collect();
+    writeln!(out, "time,{}", header.join(","))?;
+    for (time, values) in &series.rows {
+        let values: Vec<String> = values
+            .iter()
+            .map(|v| v.map_or(String::new(), |v| v.to_string()))
+            .collect();
+        writeln!(out, "{},{}", format_time(*time), values.join(","))?;
+    }
+    Ok(())
+}
+
+/// An object of each metric name to its [time, value] pairs
+pub fn to_json(series: &Series) -> Value {
+    let mut metrics = Map::new();
+    for (column, name) in series.columns.iter().enumerate() {
+        let points: Vec<Value> = series
+            .rows
+            .iter()
+            .filter_map(|(time, values)| {
+                let value = values[column]?;
+                Some(Value::Array(vec![
+                    Value::from(format_time(*time)),
+                    Value::from(value),
+                ]))
+            })
+            .collect();
+        metrics.insert(name.clone(), Value::Array(points));
+    }
+    Value::Object(metrics)
+}
+
+/// Where a server writes FTDC: diagnostic.data in the dbpath of a mongod, next to the log of a
+/// mongos, which has no dbpath
+pub fn diagnostic_dir(dbpath: Option<&str>, logpath: Option<&str>) -> Option<PathBuf> {
+    match (dbpath, logpath) {
+        (Some(dbpath), _) => Some(Path::new(dbpath).join("diagnostic.data")),
+        (None, Some(logpath)) => Some(Path::new(logpath).with_extension("diagnostic.data")),
+        (None, None) => None,
+    }
+}
+
+/// Decode every metrics file of dir and write the series of the metrics matching any pattern
+/// to output, or stdout
+pub fn export(
+    dir: &Path,
+    patterns: &[String],
+    since: Option<i64>,
+    format: &FtdcFormat,
+    output: Option<&Path>,
+) -> Result<()> {
+    let files = metrics_files(dir)?;
+    if files.is_empty() {
+        bail!("No FTDC metrics files in {}", dir.display());
+    }
+    let mut chunks = Vec::new();
+    for file in &files {
+        chunks.extend(read_chunks(file)?);
+    }
+    let series = series(&chunks, patterns, since)?;
+    if series.columns.is_empty() {
+        bail!(
+            "No metric in {} matches {}",
+            dir.display(),
+            patterns.join(", ")
+        );
+    }
+
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(
+            std::fs::File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match format {
+        FtdcFormat::Csv => write_csv(&series, &mut out)?,
+        FtdcFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(&to_json(&series))?)?,
+    }
+    if let Some(path) = output {
+        eprintln!(
+            "Wrote {} samples of {} metrics to {}",
+            series.rows.len(),
+            series.columns.len(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn zlib(data: &[u8]) -> Vec<u8> {
+    use flate2::write::ZlibEncoder;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn test_decode_chunk() {
+    use super::extjson::encode_document;
+    use serde_json::json;
+
+    let reference = json!({
+        "start": {"$date": {"$numberLong": "1700000000000"}},
+        "serverStatus": {
+            "host": "localhost",
+            "wiredTiger": {"cache": {"bytes currently in the cache": 1000, "dirty": 5.0}},
+            "ok": true
+        }
+    });
+    let mut payload = encode_document(&reference).unwrap();
+    // 4 metrics with 3 deltas each: start advances 1s per sample, the cache grows by 10 then
+    // stays, dirty never changes and ok stays true
+    payload.extend_from_slice(&4u32.to_le_bytes());
+    payload.extend_from_slice(&3u32.to_le_bytes());
+    payload.extend_from_slice(&[0xe8, 0x07, 0xe8, 0x07, 0xe8, 0x07]);
+    payload.extend_from_slice(&[10, 0, 7]);
+    let mut data = (payload.len() as u32).to_le_bytes().to_vec();
+    data.extend_from_slice(&zlib(&payload));
+
+    let chunk = decode_chunk(&data).unwrap();
+    assert_eq!(
+        chunk.names,
+        [
+            "start",
+            "serverStatus.wiredTiger.cache.bytes currently in the cache",
+            "serverStatus.wiredTiger.cache.dirty",
+            "serverStatus.ok"
+        ]
+    );
+    assert_eq!(chunk.samples.len(), 4);
+    assert_eq!(chunk.samples[1], [1700000001000, 1010, 5, 1]);
+    assert_eq!(chunk.samples[3], [1700000003000, 1010, 5, 1]);
+
+    let series = series(
+        &[chunk],
+        &["wt.cache.bytes".to_owned()],
+        Some(1700000002000),
+    )
+    .unwrap();
+    assert_eq!(
+        series.columns,
+        ["serverStatus.wiredTiger.cache.bytes currently in the cache"]
+    );
+    let mut csv = Vec::new();
+    write_csv(&series, &mut csv).unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "time,serverStatus.wiredTiger.cache.bytes currently in the cache\n\
+         2023-11-14T22:13:22.000Z,1010\n\
+         2023-11-14T22:13:23.000Z,1010\n"
+    );
+}
+
+#[test]
+fn test_decode_truncated_chunk() {
+    use super::extjson::encode_document;
+    use serde_json::json;
+
+    let reference = json!({
+        "start": {"$date": {"$numberLong": "1700000000000"}},
+        "serverStatus": {"host": "localhost", "version": "7.0.0", "uptime": 5, "ok": true}
+    });
+    let mut payload = encode_document(&reference).unwrap();
+    payload.extend_from_slice(&3u32.to_le_bytes());
+    payload.extend_from_slice(&1u32.to_le_bytes());
+    payload.extend_from_slice(&[0xe8, 0x07, 1, 0, 0]);
+    let chunk = |payload: &[u8]| {
+        let mut data = (payload.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&zlib(payload));
+        decode_chunk(&data)
+    };
+    assert_eq!(chunk(&payload).unwrap().samples.len(), 2);
+
+    // Cut anywhere, the chunk fails to decode instead of panicking
+    for len in 0..payload.len() - 1 {
+        assert!(chunk(&payload[..len]).is_err(), "decoded {} bytes", len);
+    }
+
+    // Negative and zero document lengths, and a regular expression without its options
+    let mut bad = payload.clone();
+    bad[0..4].copy_from_slice(&(-1i32).to_le_bytes());
+    assert!(chunk(&bad).is_err());
+    bad[0..4].copy_from_slice(&0i32.to_le_bytes());
+    assert!(chunk(&bad).is_err());
+    let regex = [16, 0, 0, 0, 0x0B, b'r', 0, b'a', b'b', 0, b'i'];
+    assert!(extract_metrics(&regex, "", &mut Vec::new(), &mut Vec::new()).is_err());
+}
@@ -0,0 +1,224 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The local topology as a graph of servers, with replica sets as groups and edges from routers
+// to config servers to shards, rendered for Graphviz and Mermaid
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::{MongoDServerInfo, MongoDType, MongoPSInfo};
+
+pub struct Node {
+    pub id: String,
+    pub label: String,
+}
+
+/// Servers drawn inside one box, a replica set
+pub struct Group {
+    pub id: String,
+    pub label: String,
+    pub nodes: Vec<Node>,
+}
+
+/// Edges run between node or group ids
+pub struct Graph {
+    pub groups: Vec<Group>,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<(String, String)>,
+}
+
+fn node_id(pid: i32) -> String {
+    format!("p{}", pid)
+}
+
+// Ids only keep characters every renderer accepts
+fn group_id(set: &str) -> String {
+    let name: String = set
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("cluster_{}", name)
+}
+
+fn mongod_node(d: &MongoDServerInfo) -> Node {
+    let role = match d.replication {
+        Some(ref r) => r.state.clone(),
+        None => format!("{:?}", d.server_type),
+    };
+    Node {
+        id: node_id(d.pid),
+        label: format!("mongod :{}\npid {}\n{}", d.port, d.pid, role),
+    }
+}
+
+impl Graph {
+    pub fn new(info: &MongoPSInfo) -> Graph {
+        let mut sets: BTreeMap<&str, Vec<&MongoDServerInfo>> = BTreeMap::new();
+        let mut nodes = Vec::new();
+        for d in &info.mongod {
+            match d.replica_set_name {
+                Some(ref name) => sets.entry(name).or_default().push(d),
+                None => nodes.push(mongod_node(d)),
+            }
+        }
+        nodes.extend(info.mongos.iter().map(|s| Node {
+            id: node_id(s.pid),
+            label: format!("mongos :{}\npid {}", s.port, s.pid),
+        }));
+
+        let groups = sets
+            .into_iter()
+            .map(|(name, members)| {
+                let label = match members[0].server_type {
+                    MongoDType::Config => format!("config servers {}", name),
+                    MongoDType::Shard => match members[0].shard_name {
+                        Some(ref shard) if shard != name => format!("shard {} ({})", shard, name),
+                        _ => format!("shard {}", name),
+                    },
+                    _ => format!("replica set {}", name),
+                };
+                Group {
+                    id: group_id(name),
+                    label,
+                    nodes: members.into_iter().map(mongod_node).collect(),
+                }
+            })
+            .collect();
+
+        // A set name or a standalone shard server
+        let target = |pid: i32| match info.mongod.iter().find(|d| d.pid == pid) {
+            Some(d) => d.replica_set_name.as_deref().map_or(node_id(pid), group_id),
+            None => node_id(pid),
+        };
+        let mut edges = Vec::new();
+        for cluster in &info.clusters {
+            let config = cluster.config_replica_set.as_deref().map(group_id);
+            for mongos in &cluster.mongos {
+                if let Some(ref config) = config {
+                    edges.push((node_id(*mongos), config.clone()));
+                }
+            }
+            for shard in &cluster.shards {
+                let to = target(shard.pids[0]);
+                match config {
+                    Some(ref config) => edges.push((config.clone(), to)),
+                    // Without config servers the routers are the only link
+                    None => edges.extend(cluster.mongos.iter().map(|m| (node_id(*m), to.clone()))),
+                }
+            }
+        }
+
+        Graph {
+            groups,
+            nodes,
+            edges,
+        }
+    }
+
+    fn group(&self, id: &str) -> Option<&Group> {
+        self.groups.iter().find(|g| g.id == id)
+    }
+
+    /// Graphviz DOT, e.g. for `dot -Tpng`
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        let label = |s: &str| s.replace('"', "\\\"").replace('\n', "\\n");
+        writeln!(out, "digraph mongo {{").unwrap();
+        writeln!(out, "  compound=true;").unwrap();
+        writeln!(out, "  node [shape=box];").unwrap();
+        for group in &self.groups {
+            writeln!(out, "  subgraph {} {{", group.id).unwrap();
+            writeln!(out, "    label=\"{}\";", label(&group.label)).unwrap();
+            for node in &group.nodes {
+                writeln!(out, "    {} [label=\"{}\"];", node.id, label(&node.label)).unwrap();
+            }
+            writeln!(out, "  }}").unwrap();
+        }
+        for node in &self.nodes {
+            writeln!(out, "  {} [label=\"{}\"];", node.id, label(&node.label)).unwrap();
+        }
+        // DOT edges join nodes, so a group is entered at its first member and clipped to its box
+        for (from, to) in &self.edges {
+            let mut attributes = Vec::new();
+            let mut end = |id: &str, attribute: &str| match self.group(id) {
+                Some(group) => {
+                    attributes.push(format!("{}={}", attribute, id));
+                    group.nodes[0].id.clone()
+                }
+                None => id.to_owned(),
+            };
+            let from = end(from, "ltail");
+            let to = end(to, "lhead");
+            if attributes.is_empty() {
+                writeln!(out, "  {} -> {};", from, to).unwrap();
+            } else {
+                writeln!(out, "  {} -> {} [{}];", from, to, attributes.join(",")).unwrap();
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+
+    /// Mermaid flowchart, which GitHub renders inside ```mermaid blocks
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::new();
+        let label = |s: &str| s.replace('"', "#quot;").replace('\n', "<br/>");
+        writeln!(out, "flowchart LR").unwrap();
+        for group in &self.groups {
+            writeln!(out, "  subgraph {}[\"{}\"]", group.id, label(&group.label)).unwrap();
+            for node in &group.nodes {
+                writeln!(out, "    {}[\"{}\"]", node.id, label(&node.label)).unwrap();
+            }
+            writeln!(out, "  end").unwrap();
+        }
+        for node in &self.nodes {
+            writeln!(out, "  {}[\"{}\"]", node.id, label(&node.label)).unwrap();
+        }
+        for (from, to) in &self.edges {
+            writeln!(out, "  {} --> {}", from, to).unwrap();
+        }
+        out
+    }
+}
+
+#[test]
+fn test_render() {
+    let node = |pid: i32, label: &str| Node {
+        id: node_id(pid),
+        label: label.to_owned(),
+    };
+    let graph = Graph {
+        groups: vec![Group {
+            id: group_id("config-rs"),
+            label: "config servers config-rs".to_owned(),
+            nodes: vec![node(1, "mongod :20001\npid 1")],
+        }],
+        nodes: vec![node(2, "mongos \"a\"")],
+        edges: vec![(node_id(2), group_id("config-rs"))],
+    };
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph mongo {\n"));
+    assert!(dot.contains("  subgraph cluster_config_rs {\n"));
+    assert!(dot.contains("    p1 [label=\"mongod :20001\\npid 1\"];\n"));
+    assert!(dot.contains("  p2 [label=\"mongos \\\"a\\\"\"];\n"));
+    assert!(dot.contains("  p2 -> p1 [lhead=cluster_config_rs];\n"));
+
+    let mermaid = graph.to_mermaid();
+    assert!(mermaid.starts_with("flowchart LR\n"));
+    assert!(mermaid.contains("  subgraph cluster_config_rs[\"config servers config-rs\"]\n"));
+    assert!(mermaid.contains("    p1[\"mongod :20001<br/>pid 1\"]\n  end\n"));
+    assert!(mermaid.contains("  p2[\"mongos #quot;a#quot;\"]\n"));
+    assert!(mermaid.contains("  p2 --> cluster_config_rs\n"));
+}
@@ -0,0 +1,323 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// gRPC service with the inventory, actions and change stream of mpf rpc, for tooling in Go or
+// Python that wants typed topology data. The service is defined in proto/mpf.proto and
+// generated by build.rs. Scans block, so they run off the async runtime.
+
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+
+use super::client::Settings;
+use super::signal::{parse_signal, send_signal, signal_name};
+use super::watch::{diff, Change, Entry, Snapshot};
+use super::{
+    connection_settings_over, filter_args, scan, select_pids, server_uri, watch_entry, Args,
+    MongoPSInfo,
+};
+
+pub mod proto {
+    tonic::include_proto!("mpf.v1");
+}
+
+use proto::mpf_server::{Mpf, MpfServer};
+use proto::{
+    KillReply, KillRequest, Process, ScanReply, ScanRequest, UriReply, UriRequest, WatchEvent,
+    WatchRequest,
+};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+
+fn failed(e: anyhow::Error) -> Status {
+    Status::internal(format!("{:#}", e))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn process(pid: i32, entry: Entry, uri: Option<String>) -> Process {
+    Process {
+        pid,
+        r#type: entry.kind,
+        port: entry.port,
+        summary: entry.line,
+        uri,
+    }
+}
+
+fn watch_event(change: Change, first: bool) -> WatchEvent {
+    let (event, pid, entry, previous) = match change {
+        Change::Added(pid, entry) => ("started", pid, entry, None),
+        Change::Removed(pid, entry) => ("stopped", pid, entry, None),
+        Change::Changed(pid, old, new) => ("changed", pid, new, Some(old.line)),
+    };
+    WatchEvent {
+        event: if first { "running" } else { event }.to_owned(),
+        process: Some(process(pid, entry, None)),
+        previous,
+        time: now_secs(),
+        message: String::new(),
+    }
+}
+
+// Send the changes between scans until the client cancels the stream
+fn watch<F>(mut snapshot: F, interval: Duration, tx: &mpsc::Sender<Result<WatchEvent, Status>>)
+where
+    F: FnMut() -> Result<Snapshot>,
+{
+    let mut previous = Snapshot::new();
+    let mut first = true;
+    while !tx.is_closed() {
+        match snapshot() {
+            Ok(current) => {
+                for change in diff(&previous, &current) {
+                    if tx.blocking_send(Ok(watch_event(change, first))).is_err() {
+                        return;
+                    }
+                }
+                previous = current;
+                first = false;
+            }
+            Err(e) => {
+                let event = WatchEvent {
+                    event: "error".to_owned(),
+                    time: now_secs(),
+                    message: format!("{:#}", e),
+                    ..WatchEvent::default()
+                };
+                if tx.blocking_send(Ok(event)).is_err() {
+                    return;
+                }
+            }
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+pub struct Service {
+    /// Connection settings from the command line that started the server
+    settings: Settings,
+}
+
+impl Service {
+    // Filters as on the command line, with connection options among them replacing those of
+    // the server for this request only
+    fn args(&self, args: &[String]) -> Result<(Args, Settings), Status> {
+        let args = filter_args(args).map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+        let settings = connection_settings_over(&args, &self.settings)
+            .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+        Ok((args, settings))
+    }
+
+    async fn scan(&self, args: &[String]) -> Result<(Args, MongoPSInfo), Status> {
+        let (args, settings) = self.args(args)?;
+        tokio::task::spawn_blocking(move || {
+            let info = scan(&args, &settings)?;
+            Ok((args, info))
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(failed)
+    }
+}
+
+#[tonic::async_trait]
+impl Mpf for Service {
+    async fn scan(&self, request: Request<ScanRequest>) -> Result<Response<ScanReply>, Status> {
+        let (args, info) = Service::scan(self, &request.into_inner().args).await?;
+        let processes = select_pids(&args, &info)
+            .into_iter()
+            .map(|pid| {
+                let uri = info.server_target(pid).map(|t| server_uri(&info, &t));
+                process(pid, watch_entry(&info, pid), uri)
+            })
+            .collect();
+        Ok(Response::new(ScanReply { processes }))
+    }
+
+    async fn uri(&self, request: Request<UriRequest>) -> Result<Response<UriReply>, Status> {
+        let pid = request.into_inner().pid;
+        let (_, info) = Service::scan(self, &[]).await?;
+        match info.server_target(pid) {
+            Some(target) => Ok(Response::new(UriReply {
+                uri: server_uri(&info, &target),
+            })),
+            None => Err(Status::not_found(format!(
+                "{} is not a mongod or mongos",
+                pid
+            ))),
+        }
+    }
+
+    async fn kill(&self, request: Request<KillRequest>) -> Result<Response<KillReply>, Status> {
+        let request = request.into_inner();
+        let signal = if request.signal.is_empty() {
+            libc::SIGTERM
+        } else {
+            parse_signal(&request.signal).map_err(Status::invalid_argument)?
+        };
+        // Only processes mpf found, the service is not a general kill
+        let (_, info) = Service::scan(self, &[]).await?;
+        if info.proc_info(request.pid).is_none() {
+            return Err(Status::not_found(format!(
+                "{} is not a mongo process",
+                request.pid
+            )));
+        }
+        send_signal(request.pid, signal).map_err(failed)?;
+        Ok(Response::new(KillReply {
+            pid: request.pid,
+            signal: signal_name(signal),
+        }))
+    }
+
+    type WatchStream = ReceiverStream<Result<WatchEvent, Status>>;
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let request = request.into_inner();
+        let (args, settings) = self.args(&request.args)?;
+        let interval = match request.interval_ms {
+            0 => DEFAULT_INTERVAL,
+            ms => Duration::from_millis(ms),
+        };
+        let (tx, rx) = mpsc::channel(16);
+        let snapshot = move || -> Result<Snapshot> {
+            let info = scan(&args, &settings)?;
+            Ok(select_pids(&args, &info)
+                .into_iter()
+                .map(|pid| (pid, watch_entry(&info, pid)))
+                .collect())
+        };
+        std::thread::spawn(move || watch(snapshot, interval, &tx));
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Serve on listen until interrupted
+pub fn serve(settings: Settings, listen: SocketAddr) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let incoming =
+            TcpIncoming::bind(listen).with_context(|| format!("Failed to listen on {}", listen))?;
+        eprintln!("Serving mpf.v1.Mpf on {}", incoming.local_addr()?);
+        tonic::transport::Server::builder()
+            .add_service(MpfServer::new(Service { settings }))
+            .serve_with_incoming(incoming)
+            .await
+            .context("gRPC server failed")
+    })
+}
+
+#[test]
+fn test_service() {
+    use proto::mpf_client::MpfClient;
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let incoming = TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = incoming.local_addr().unwrap();
+        let service = MpfServer::new(Service {
+            settings: Settings::default(),
+        });
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(incoming),
+        );
+        let mut client = MpfClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+
+        let scan = |args: &[&str]| ScanRequest {
+            args: args.iter().map(|a| a.to_string()).collect(),
+        };
+        let status = client.scan(scan(&["--bogus"])).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert!(status.message().contains("--bogus"));
+
+        // Port 1 is not a mongo port
+        let reply = client.scan(scan(&["-p", "1"])).await.unwrap().into_inner();
+        assert_eq!(reply.processes, []);
+
+        let kill = KillRequest {
+            pid: std::process::id() as i32,
+            signal: "SIGNOPE".to_owned(),
+        };
+        let status = client.kill(kill).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = client
+            .uri(UriRequest {
+                pid: std::process::id() as i32,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    });
+}
+
+#[test]
+fn test_watch_events() {
+    let entry = |port: i32| Entry {
+        kind: "mongod".to_owned(),
+        port: Some(port),
+        line: format!("mongod {}", port),
+    };
+    let mut scans = vec![
+        Snapshot::from([(1, entry(20001)), (2, entry(20002))]),
+        Snapshot::from([(2, entry(20002))]),
+        Snapshot::from([(2, entry(20002))]),
+    ]
+    .into_iter();
+    let (tx, rx) = mpsc::channel(16);
+    let rx = std::cell::RefCell::new(rx);
+    watch(
+        || {
+            let snapshot = scans.next().unwrap();
+            if scans.len() == 0 {
+                // Like a client cancelling, what was sent can still be read
+                rx.borrow_mut().close();
+            }
+            Ok(snapshot)
+        },
+        Duration::ZERO,
+        &tx,
+    );
+
+    let mut events = Vec::new();
+    while let Ok(event) = rx.borrow_mut().try_recv() {
+        let event = event.unwrap();
+        events.push((event.event, event.process.unwrap().pid));
+    }
+    assert_eq!(
+        events,
+        [
+            ("running".to_owned(), 1),
+            ("running".to_owned(), 2),
+            ("stopped".to_owned(), 1)
+        ]
+    );
+}
@@ -0,0 +1,141 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// In progress index builds from currentOp
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use super::client::{Connection, Settings, DEFAULT_TIMEOUT};
+use super::probe::probe_all;
+use super::ServerTarget;
+
+#[derive(Debug, PartialEq)]
+pub struct IndexBuild {
+    pub ns: String,
+    pub indexes: Vec<String>,
+    /// e.g. scanning collection, or draining writes received during build
+    pub phase: String,
+    pub percent: Option<f64>,
+    pub secs_running: i64,
+}
+
+// The msg looks like "Index Build: scanning collection Index Build: scanning collection: 12/100 12%"
+fn phase(msg: &str) -> String {
+    let last = msg.rsplit("Index Build: ").next().unwrap_or(msg);
+    let phase = match last.rsplit_once(':') {
+        Some((phase, progress)) if progress.trim().contains('/') => phase,
+        _ => last,
+    };
+    phase.trim().to_owned()
+}
+
+/// Index builds in a currentOp reply
+pub fn index_builds(reply: &Value) -> Vec<IndexBuild> {
+    reply
+        .get("inprog")
+        .and_then(|i| i.as_array())
+        .map_or(&[][..], |i| i.as_slice())
+        .iter()
+        .filter_map(|op| {
+            let command = op.get("command")?;
+            command.get("createIndexes")?;
+            let indexes =
+                command
+                    .get("indexes")
+                    .and_then(|i| i.as_array())
+                    .map_or(vec![], |indexes| {
+                        indexes
+                            .iter()
+                            .filter_map(|i| Some(i.get("name")?.as_str()?.to_owned()))
+                            .collect()
+                    });
+            let percent = op.get("progress").and_then(|p| {
+                let done = p.get("done")?.as_f64()?;
+                let total = p.get("total")?.as_f64()?;
+                (total > 0.0).then(|| done * 100.0 / total)
+            });
+            Some(IndexBuild {
+                ns: op
+                    .get("ns")
+                    .and_then(|n| n.as_str())
+                    .unwrap_or("")
+                    .to_owned(),
+                indexes,
+                phase: phase(op.get("msg").and_then(|m| m.as_str()).unwrap_or("starting")),
+                percent,
+                secs_running: op.get("secs_running").and_then(|s| s.as_i64()).unwrap_or(0),
+            })
+        })
+        .collect()
+}
+
+/// Print the index builds in progress on each server
+pub fn print_index_builds(settings: &Settings, targets: &[ServerTarget]) -> Result<()> {
+    let replies = probe_all(settings, targets, |target| {
+        Connection::connect_local(settings, target.port, DEFAULT_TIMEOUT)
+            .and_then(|mut conn| conn.run_command("admin", json!({"currentOp": 1, "$all": true})))
+    });
+    for (target, reply) in targets.iter().zip(replies) {
+        let builds = match reply {
+            Ok(reply) => index_builds(&reply),
+            Err(e) => {
+                eprintln!("{} port {}: {}", target.pid, target.port, e);
+                continue;
+            }
+        };
+
+        println!(
+            "{} port {}: {} index build(s)",
+            target.pid,
+            target.port,
+            builds.len()
+        );
+        for build in builds {
+            println!(
+                "  {} {} - {}{} for {}s",
+                build.ns,
+                build.indexes.join(","),
+                build.phase,
+                build
+                    .percent
+                    .map_or(String::new(), |p| format!(" {:.1}%", p)),
+                build.secs_running
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_index_builds() {
+    let reply = json!({
+        "inprog": [
+            {"ns": "test.c", "secs_running": 30,
+             "command": {"createIndexes": "c", "indexes": [{"key": {"x": 1}, "name": "x_1"}]},
+             "msg": "Index Build: scanning collection Index Build: scanning collection: 250/1000 25%",
+             "progress": {"done": 250, "total": 1000}},
+            {"ns": "test.d", "command": {"createIndexes": "d", "indexes": []},
+             "msg": "Index Build: draining writes received during build"},
+            {"ns": "test.e", "command": {"find": "e"}},
+        ],
+    });
+    let builds = index_builds(&reply);
+    assert_eq!(builds.len(), 2);
+    assert_eq!(builds[0].phase, "scanning collection");
+    assert_eq!(builds[0].percent, Some(25.0));
+    assert_eq!(builds[0].indexes, vec!["x_1"]);
+    assert_eq!(builds[1].phase, "draining writes received during build");
+    assert_eq!(builds[1].percent, None);
+}
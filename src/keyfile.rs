@@ -0,0 +1,125 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Keyfile checks, members of a replica set must share the same key to authenticate each other
+
+use std::collections::BTreeMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use super::audit::{Finding, Severity};
+use super::config::get_path;
+use super::types::CommonProcInfo;
+use super::MongoPSInfo;
+
+/// Keyfile of a server resolved against its working directory
+pub fn key_file_path(proc: &CommonProcInfo, config: &Value) -> Option<PathBuf> {
+    let key_file = get_path(config, "security.keyFile")?.as_str()?;
+    Some(match proc.cwd {
+        Some(ref cwd) => cwd.join(key_file),
+        None => key_file.into(),
+    })
+}
+
+/// The key itself, mongod ignores whitespace in keyfiles
+pub fn read_key(path: &PathBuf) -> std::io::Result<String> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(text.chars().filter(|c| !c.is_whitespace()).collect())
+}
+
+fn finding(pid: i32, severity: Severity, message: String) -> Finding {
+    Finding {
+        pid,
+        severity,
+        message,
+    }
+}
+
+/// Check that keyfiles exist, are only readable by their owner and match within each replica set
+pub fn check_key_files(info: &MongoPSInfo, configs: &[(&CommonProcInfo, Value)]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    // Replica set name to (pid, keyfile, key) of each member
+    let mut sets: BTreeMap<String, Vec<(i32, PathBuf, String)>> = BTreeMap::new();
+
+    for (proc, config) in configs {
+        let Some(path) = key_file_path(proc, config) else {
+            continue;
+        };
+
+        let metadata = match std::fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                findings.push(finding(
+                    proc.pid,
+                    Severity::High,
+                    format!("keyfile {} cannot be read: {}", path.display(), e),
+                ));
+                continue;
+            }
+        };
+
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o004 != 0 {
+            findings.push(finding(
+                proc.pid,
+                Severity::High,
+                format!("keyfile {} is world readable ({:o})", path.display(), mode),
+            ));
+        } else if mode & 0o077 != 0 {
+            findings.push(finding(
+                proc.pid,
+                Severity::Medium,
+                format!(
+                    "keyfile {} should only be readable by its owner ({:o}, expected 600)",
+                    path.display(),
+                    mode
+                ),
+            ));
+        }
+
+        let repl_set = info
+            .mongod
+            .iter()
+            .find(|d| d.pid == proc.pid)
+            .and_then(|d| d.replica_set_name.clone());
+        if let (Some(repl_set), Ok(key)) = (repl_set, read_key(&path)) {
+            sets.entry(repl_set)
+                .or_default()
+                .push((proc.pid, path, key));
+        }
+    }
+
+    for (repl_set, members) in sets {
+        let (first_pid, first_path, first_key) = &members[0];
+        for (pid, path, key) in &members[1..] {
+            if key != first_key {
+                findings.push(finding(
+                    *pid,
+                    Severity::High,
+                    format!(
+                        "keyfile {} differs from {} used by {} in replica set {}, members will fail to authenticate",
+                        path.display(),
+                        first_path.display(),
+                        first_pid,
+                        repl_set
+                    ),
+                ));
+            }
+        }
+    }
+
+    findings
+}
@@ -0,0 +1,121 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+
+use super::types::CommonProcInfo;
+
+/// Everything needed to launch a process again the way it was originally started
+#[derive(Debug, Clone)]
+pub struct LaunchSpec {
+    pub binary: PathBuf,
+    pub args: Vec<String>,
+    pub env: HashMap<OsString, OsString>,
+    pub cwd: PathBuf,
+}
+
+// The executable the kernel ran, argv0 can be anything the parent chose. A binary replaced by an
+// upgrade reads as "(deleted)" and argv0 is the best guess at the new one.
+fn binary(exe: Option<&Path>, argv0: &str, cwd: &Path) -> PathBuf {
+    if let Some(exe) = exe.filter(|e| !e.to_string_lossy().ends_with(" (deleted)")) {
+        return exe.to_path_buf();
+    }
+
+    // Relative binaries were resolved against the original working directory
+    if argv0.contains('/') {
+        cwd.join(argv0)
+    } else {
+        PathBuf::from(argv0)
+    }
+}
+
+impl LaunchSpec {
+    pub fn from_proc(proc: &CommonProcInfo) -> Result<LaunchSpec> {
+        let Some(argv0) = proc.cmdline.first() else {
+            bail!("Cannot read the command line of {}", proc.pid);
+        };
+        let Some(ref cwd) = proc.cwd else {
+            bail!("Cannot read the working directory of {}", proc.pid);
+        };
+
+        Ok(LaunchSpec {
+            binary: binary(proc.exe.as_deref(), argv0, cwd),
+            args: proc.cmdline[1..].to_vec(),
+            env: proc.env.clone(),
+            cwd: cwd.clone(),
+        })
+    }
+
+    /// Where output goes for a process listening on port, since there is no terminal to
+    /// return it to
+    pub fn log_file(&self, port: i32) -> PathBuf {
+        let name = self
+            .binary
+            .file_name()
+            .map_or("mongo".into(), |n| n.to_string_lossy());
+        std::env::temp_dir().join(format!("mpf-{}-{}.log", name, port))
+    }
+
+    /// Start the process in its own process group so it outlives the terminal's Ctrl-C
+    pub fn spawn(&self, log_file: &Path) -> Result<u32> {
+        let log = File::options()
+            .create(true)
+            .append(true)
+            .open(log_file)
+            .with_context(|| format!("Failed to open {}", log_file.display()))?;
+
+        let child = Command::new(&self.binary)
+            .args(&self.args)
+            .env_clear()
+            .envs(&self.env)
+            .current_dir(&self.cwd)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .process_group(0)
+            .spawn()
+            .with_context(|| format!("Failed to start {}", self.binary.display()))?;
+
+        Ok(child.id())
+    }
+}
+
+#[test]
+fn test_binary() {
+    let cwd = Path::new("/home/me");
+    assert_eq!(
+        binary(Some(Path::new("/opt/mongo/bin/mongod")), "mongod", cwd),
+        Path::new("/opt/mongo/bin/mongod")
+    );
+    assert_eq!(
+        binary(None, "./bin/mongod", cwd),
+        Path::new("/home/me/./bin/mongod")
+    );
+    assert_eq!(binary(None, "mongod", cwd), Path::new("mongod"));
+    assert_eq!(
+        binary(
+            Some(Path::new("/opt/mongo/bin/mongod (deleted)")),
+            "/opt/mongo/bin/mongod",
+            cwd
+        ),
+        Path::new("/opt/mongo/bin/mongod")
+    );
+}
@@ -0,0 +1,3179 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// use std::collections::HashMap;
+// use std::ffi::OsString;
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+mod audit;
+mod auth;
+mod check;
+mod client;
+mod clipboard;
+mod clone;
+mod cluster;
+mod config;
+mod connect;
+mod connpool;
+mod credentials;
+mod cryptd;
+mod describe;
+mod disk;
+mod doctor;
+mod elections;
+mod encryption;
+mod eval;
+mod export;
+mod extjson;
+mod fanout;
+mod ffi;
+mod fingerprint;
+mod fork;
+mod ftdc;
+mod graph;
+mod grpc;
+mod index_builds;
+mod keyfile;
+mod launch;
+mod loglevel;
+mod logq;
+mod man;
+mod msgpack;
+mod nuke;
+mod ops;
+mod output;
+mod plugins;
+mod priority;
+mod probe;
+mod profiler;
+mod replication;
+mod report;
+mod restart;
+mod rpc;
+mod run_cmd;
+mod schema;
+mod scram;
+mod script;
+mod self_update;
+mod set_param;
+mod sharding;
+mod shell_init;
+mod shutdown;
+mod signal;
+mod slow;
+mod start;
+mod startup;
+mod stat;
+mod statsd;
+mod storage;
+mod tls;
+mod tmux;
+mod top;
+mod top_ns;
+mod transport;
+mod types;
+mod wait;
+mod watch;
+use types::CommonProcInfo;
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+use linux::{connected_pids, cpu_time, get_procs, listening_ports};
+
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "macos")]
+use macos::{connected_pids, cpu_time, get_procs, listening_ports};
+
+// If we derive our own ArgEnum, we can get better case
+// Because ArgEnum default case conversion converts "_" to "-" and CamelCase to "camel-case"
+#[derive(Debug, PartialEq, Clone, ValueEnum)]
+enum MongoProcess {
+    Legacyshell,
+    Mongod,
+    Mongos,
+    Mongocryptd,
+    // Mongoqd,
+    // TODO
+}
+
+fn is_mongo_process(proc: &CommonProcInfo) -> Option<MongoProcess> {
+    if !proc.program.starts_with("mongo") {
+        return Option::None;
+    }
+
+    if proc.program == "mongod" {
+        Some(MongoProcess::Mongod)
+    } else if proc.program == "mongos" {
+        Some(MongoProcess::Mongos)
+    } else if proc.program == "mongo" {
+        Some(MongoProcess::Legacyshell)
+    } else if proc.program == "mongocryptd" {
+        Some(MongoProcess::Mongocryptd)
+    } else {
+        eprintln!(
+            "Unexpected mongo like process found: {} {:?} {:?}",
+            proc.pid, proc.program, proc.cmdline
+        );
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ValueEnum, Clone, PartialEq)]
+enum MongoDType {
+    Standalone,
+    ReplicaSet,
+    Config,
+    Shard,
+}
+
+#[derive(Debug, ValueEnum, Clone, PartialEq)]
+enum ReplicaSetType {
+    Primary,
+    Secondary,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MongoSServerInfo {
+    pid: i32,
+    port: i32,
+    configdb: String,
+    logpath: Option<String>,
+    fork: bool,
+    pidfile: Option<String>,
+    user: Option<String>,
+    cwd: Option<String>,
+    listening_ports: Option<Vec<i32>>,
+    set_parameters: Map<String, Value>,
+    test_instance: bool,
+    tls: Option<tls::TlsInfo>,
+    auth: auth::AuthInfo,
+    unix_socket: Option<String>,
+    /// Directory of the mpf start that launched the router
+    start_dir: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MongoDServerInfo {
+    pid: i32,
+    port: i32,
+    server_type: MongoDType,
+    replica_set_name: Option<String>,
+    dbpath: Option<String>,
+    logpath: Option<String>,
+    fork: bool,
+    pidfile: Option<String>,
+    user: Option<String>,
+    cwd: Option<String>,
+    listening_ports: Option<Vec<i32>>,
+    special_modes: Vec<String>,
+    storage: storage::StorageInfo,
+    encryption: Option<encryption::EncryptionInfo>,
+    set_parameters: Map<String, Value>,
+    test_instance: bool,
+    tls: Option<tls::TlsInfo>,
+    auth: auth::AuthInfo,
+    unix_socket: Option<String>,
+    replication: Option<replication::ReplicationInfo>,
+    /// Name the shard is registered as in its cluster, read from the config server
+    shard_name: Option<String>,
+    /// Directory of the mpf start that launched the server
+    start_dir: Option<String>,
+}
+
+// Started with --fork or processManagement.fork
+fn forks(config: Option<&Value>) -> bool {
+    config
+        .and_then(|c| config::get_path(c, "processManagement.fork"))
+        .is_some_and(|fork| fork.as_bool() == Some(true) || fork.as_str() == Some("true"))
+}
+
+// The socket file of a local server, if it created one
+fn unix_socket(config: Option<&Value>, port: i32) -> Option<String> {
+    config::unix_socket_path(config?, port).filter(|path| std::path::Path::new(path).exists())
+}
+
+fn get_cmd_line_option(option: &str, options: &[String]) -> Option<String> {
+    for (i, opt) in options.iter().enumerate() {
+        if opt == option {
+            if i + 1 < options.len() {
+                // NOTE: assume options are generally correct
+                return Some(options[i + 1].to_owned());
+            } else {
+                return None;
+            }
+        } else if opt.starts_with(option) {
+            let split = opt.split('=');
+            let splits: Vec<&str> = split.collect();
+            if splits.len() == 2 && splits[0] == option {
+                return Some(splits[1].to_owned());
+            }
+        }
+    }
+
+    None
+}
+
+fn has_cmd_line_flag(flag: &str, options: &[String]) -> bool {
+    options.iter().any(|opt| {
+        opt == flag
+            || opt
+                .split_once('=')
+                .is_some_and(|(name, value)| name == flag && value != "false" && value != "0")
+    })
+}
+
+/// Replace the value of option in either form, or append it if missing
+fn set_cmd_line_option(options: &mut Vec<String>, option: &str, value: &str) {
+    for i in 0..options.len() {
+        if options[i] == option && i + 1 < options.len() {
+            options[i + 1] = value.to_owned();
+            return;
+        } else if options[i].split_once('=').map(|s| s.0) == Some(option) {
+            options[i] = format!("{}={}", option, value);
+            return;
+        }
+    }
+
+    options.push(option.to_owned());
+    options.push(value.to_owned());
+}
+
+// Name of a user from the password database
+fn user_name(uid: u32) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let ret = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if ret != 0 || result.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(pwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+// Parameters only set by test harnesses like resmoke, failpoints are matched by prefix
+const TEST_PARAMETERS: &[&str] = &[
+    "enableTestCommands",
+    "testingDiagnosticsEnabled",
+    "disableLogicalSessionCacheRefresh",
+    "failpoint.",
+];
+
+fn is_test_instance(params: &Map<String, Value>) -> bool {
+    params.iter().any(|(name, value)| {
+        let enabled = !matches!(value, Value::Bool(false))
+            && value.as_i64() != Some(0)
+            && value.as_str() != Some("false");
+        enabled
+            && TEST_PARAMETERS.iter().any(|p| {
+                if p.ends_with('.') {
+                    name.starts_with(p)
+                } else {
+                    name == p
+                }
+            })
+    })
+}
+
+// Modes where a mongod is not a normal cluster member, as flags or parameters
+const SPECIAL_MODE_FLAGS: &[&str] = &["--queryableBackupMode", "--repair", "--readOnly"];
+const SPECIAL_MODE_PARAMETERS: &[&str] =
+    &["recoverFromOplogAsStandalone", "startupRecoveryForRestore"];
+
+fn get_special_modes(cmdline: &[String], params: &Map<String, Value>) -> Vec<String> {
+    let mut modes: Vec<String> = SPECIAL_MODE_FLAGS
+        .iter()
+        .filter(|flag| has_cmd_line_flag(flag, cmdline))
+        .map(|flag| flag.trim_start_matches('-').to_owned())
+        .collect();
+    modes.extend(
+        SPECIAL_MODE_PARAMETERS
+            .iter()
+            .filter(|p| has_parameter(params, &format!("{}=1", p)))
+            .map(|p| p.to_string()),
+    );
+    modes
+}
+
+// Every --setParameter, repeated on the command line or in the config file
+fn get_set_parameters(config: Option<&Value>) -> Map<String, Value> {
+    match config.and_then(|c| c.get("setParameter")) {
+        Some(Value::Object(params)) => params.clone(),
+        _ => Map::new(),
+    }
+}
+
+// Match name or name=value against the parameters, 1 and true are the same for flags
+fn has_parameter(params: &Map<String, Value>, spec: &str) -> bool {
+    let (name, expected) = match spec.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (spec, None),
+    };
+    let Some(value) = params.get(name) else {
+        return false;
+    };
+    let Some(expected) = expected else {
+        return true;
+    };
+
+    let actual = match value {
+        Value::String(s) => s.clone(),
+        Value::Bool(true) => "1".to_owned(),
+        Value::Bool(false) => "0".to_owned(),
+        v => v.to_string(),
+    };
+    let expected = match expected {
+        "true" => "1",
+        "false" => "0",
+        e => e,
+    };
+    actual == expected
+}
+
+/// Port from net.port of the effective config, or the command line when the config file cannot be read
+fn server_port(proc: &CommonProcInfo, config: Option<&Value>) -> Result<i32> {
+    let port = match config {
+        Some(c) => config::get_path(c, "net.port").cloned(),
+        None => get_cmd_line_option("--port", &proc.cmdline)
+            .map(Value::String)
+            .or_else(|| {
+                let args = proc.cmdline.get(1..).unwrap_or_default();
+                config::default_setting(&config::cmdline_to_config(args).0, "net.port")
+            }),
+    };
+    let port = match port {
+        None => return Ok(27017),
+        Some(Value::Number(n)) => n.as_i64().and_then(|n| i32::try_from(n).ok()),
+        Some(Value::String(s)) => s.parse::<i32>().ok(),
+        Some(_) => None,
+    };
+    port.filter(|p| (0..=65535).contains(p))
+        .ok_or_else(|| anyhow!("Bad port number for process {}", proc.pid))
+}
+
+fn get_mongod_info(proc: &CommonProcInfo) -> Result<MongoDServerInfo> {
+    assert_eq!(is_mongo_process(proc), Some(MongoProcess::Mongod));
+
+    let cmdline = &proc.cmdline;
+
+    let shardsvr = has_cmd_line_flag("--shardsvr", cmdline);
+    let configsvr = has_cmd_line_flag("--configsvr", cmdline);
+
+    let repl_set = get_cmd_line_option("--replSet", cmdline);
+
+    let mut server_type = MongoDType::Standalone;
+    if configsvr {
+        server_type = MongoDType::Config;
+    } else if shardsvr {
+        server_type = MongoDType::Shard;
+    } else if repl_set.is_some() {
+        server_type = MongoDType::ReplicaSet;
+    }
+
+    // Settings that may also come from a config file
+    let config = config::local_effective_config(proc).ok();
+    let port = server_port(proc, config.as_ref())?;
+    let dbpath = match config {
+        Some(ref c) => config_path(proc, c, "storage.dbPath"),
+        None => get_cmd_line_option("--dbpath", cmdline).map(|p| resolve_path(proc, &p)),
+    };
+
+    let set_parameters = get_set_parameters(config.as_ref());
+    let tls = config.as_ref().and_then(|c| tls::tls_info(proc, c));
+    let auth = config.as_ref().map_or_else(Default::default, |c| {
+        auth::auth_info(proc, c, tls.is_some())
+    });
+
+    Ok(MongoDServerInfo {
+        pid: proc.pid,
+        port,
+        server_type,
+        replica_set_name: repl_set,
+        dbpath,
+        logpath: config
+            .as_ref()
+            .and_then(|c| config_path(proc, c, "systemLog.path")),
+        fork: forks(config.as_ref()),
+        pidfile: config
+            .as_ref()
+            .and_then(|c| config_path(proc, c, "processManagement.pidFilePath")),
+        user: proc.uid.and_then(user_name),
+        cwd: proc.cwd.as_ref().map(|c| c.display().to_string()),
+        listening_ports: None,
+        special_modes: get_special_modes(cmdline, &set_parameters),
+        storage: config
+            .as_ref()
+            .map_or_else(Default::default, storage::storage_info),
+        encryption: config.as_ref().and_then(encryption::encryption_info),
+        test_instance: is_test_instance(&set_parameters),
+        set_parameters,
+        tls,
+        auth,
+        unix_socket: unix_socket(config.as_ref(), port),
+        replication: None,
+        shard_name: None,
+        start_dir: None,
+    })
+}
+
+fn get_mongos_info(proc: &CommonProcInfo) -> Result<MongoSServerInfo> {
+    assert_eq!(is_mongo_process(proc), Some(MongoProcess::Mongos));
+
+    let cmdline = &proc.cmdline;
+    let configdb_opt = get_cmd_line_option("--configdb", cmdline);
+
+    let configdb = configdb_opt.map_or(String::new(), |c| {
+        let parts = c.split_once('/');
+        parts.map_or(String::new(), |v| v.0.to_owned())
+    });
+
+    let config = config::local_effective_config(proc).ok();
+    let port = server_port(proc, config.as_ref())?;
+
+    let set_parameters = get_set_parameters(config.as_ref());
+    let tls = config.as_ref().and_then(|c| tls::tls_info(proc, c));
+    let auth = config.as_ref().map_or_else(Default::default, |c| {
+        auth::auth_info(proc, c, tls.is_some())
+    });
+
+    Ok(MongoSServerInfo {
+        pid: proc.pid,
+        port,
+        configdb,
+        logpath: config
+            .as_ref()
+            .and_then(|c| config_path(proc, c, "systemLog.path")),
+        fork: forks(config.as_ref()),
+        pidfile: config
+            .as_ref()
+            .and_then(|c| config_path(proc, c, "processManagement.pidFilePath")),
+        user: proc.uid.and_then(user_name),
+        cwd: proc.cwd.as_ref().map(|c| c.display().to_string()),
+        listening_ports: None,
+        test_instance: is_test_instance(&set_parameters),
+        set_parameters,
+        tls,
+        auth,
+        unix_socket: unix_socket(config.as_ref(), port),
+        start_dir: None,
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct MongoPSInfo {
+    schema_version: u32,
+    mongod: Vec<MongoDServerInfo>,
+    mongos: Vec<MongoSServerInfo>,
+    shell: Vec<i32>,
+    mongocryptd: Vec<cryptd::CryptdGroup>,
+    clusters: Vec<cluster::Cluster>,
+    /// Other processes recognized by classifier plugins
+    #[serde(default)]
+    plugin_processes: Vec<plugins::Classified>,
+    #[serde(skip)]
+    procs: Vec<CommonProcInfo>,
+    /// Connection settings for the servers found, used by everything that talks to them
+    #[serde(skip)]
+    settings: client::Settings,
+}
+
+fn listens_on(listening_ports: &Option<Vec<i32>>, ports: &PortSet) -> bool {
+    listening_ports
+        .as_ref()
+        .is_some_and(|p| p.iter().any(|port| ports.contains(*port)))
+}
+
+// Real listening ports from the socket table, None if the process could not be inspected
+fn read_listening_ports(pid: i32, port: i32) -> Option<Vec<i32>> {
+    let ports: Vec<i32> = listening_ports(pid)
+        .ok()?
+        .into_iter()
+        .map(|p| p as i32)
+        .collect();
+    if !ports.is_empty() && !ports.contains(&port) {
+        eprintln!(
+            "WARNING: {} should be on port {} but is listening on {:?}",
+            pid, port, ports
+        );
+    }
+    Some(ports)
+}
+
+impl MongoPSInfo {
+    fn read_listening_ports(&mut self) {
+        for d in self.mongod.iter_mut() {
+            d.listening_ports = read_listening_ports(d.pid, d.port);
+        }
+        for s in self.mongos.iter_mut() {
+            s.listening_ports = read_listening_ports(s.pid, s.port);
+        }
+    }
+
+    fn unix_sockets(&self) -> HashMap<i32, String> {
+        let mongod = self.mongod.iter().map(|d| (d.port, &d.unix_socket));
+        let mongos = self.mongos.iter().map(|s| (s.port, &s.unix_socket));
+        mongod
+            .chain(mongos)
+            .filter_map(|(port, socket)| Some((port, socket.clone()?)))
+            .collect()
+    }
+
+    fn read_replication(&mut self) {
+        let members: Vec<Option<i32>> = self
+            .mongod
+            .iter()
+            .map(|d| d.replica_set_name.as_ref().map(|_| d.port))
+            .collect();
+        let replication = probe::probe_all(&self.settings, &members, |port| {
+            port.and_then(|port| replication::replication_info(&self.settings, port))
+        });
+        for (d, r) in self.mongod.iter_mut().zip(replication) {
+            d.replication = r;
+        }
+    }
+
+    fn read_shard_names(&mut self) {
+        if !self
+            .mongod
+            .iter()
+            .any(|d| d.server_type == MongoDType::Shard)
+        {
+            return;
+        }
+        let registered = sharding::registered_shards(self);
+        let shards: Vec<sharding::Shard> = registered.values().flatten().cloned().collect();
+        for d in self
+            .mongod
+            .iter_mut()
+            .filter(|d| d.server_type == MongoDType::Shard)
+        {
+            d.shard_name = sharding::shard_name(&shards, d.port, d.replica_set_name.as_deref());
+        }
+        self.clusters = cluster::clusters(self, &registered);
+    }
+
+    fn is_type(&self, pid: i32, t: &TypeExclusion) -> bool {
+        match t {
+            TypeExclusion::Process(MongoProcess::Legacyshell) => self.shell.contains(&pid),
+            TypeExclusion::Process(MongoProcess::Mongod) => {
+                self.mongod.iter().any(|d| d.pid == pid)
+            }
+            TypeExclusion::Process(MongoProcess::Mongos) => {
+                self.mongos.iter().any(|s| s.pid == pid)
+            }
+            TypeExclusion::Process(MongoProcess::Mongocryptd) => self.cryptd(pid).is_some(),
+            TypeExclusion::Server(server_type) => self
+                .mongod
+                .iter()
+                .any(|d| d.pid == pid && d.server_type == *server_type),
+        }
+    }
+
+    fn cryptd(&self, pid: i32) -> Option<(&cryptd::CryptdGroup, &cryptd::CryptdInstance)> {
+        self.mongocryptd
+            .iter()
+            .find_map(|g| Some((g, g.instances.iter().find(|i| i.pid == pid)?)))
+    }
+
+    fn cryptd_instances(&self) -> impl Iterator<Item = &cryptd::CryptdInstance> {
+        self.mongocryptd.iter().flat_map(|g| g.instances.iter())
+    }
+
+    fn is_test_instance(&self, pid: i32) -> bool {
+        self.mongod.iter().any(|d| d.pid == pid && d.test_instance)
+            || self.mongos.iter().any(|s| s.pid == pid && s.test_instance)
+    }
+
+    fn set_parameters(&self, pid: i32) -> Option<&Map<String, Value>> {
+        match self.mongod.iter().find(|d| d.pid == pid) {
+            Some(d) => Some(&d.set_parameters),
+            None => Some(&self.mongos.iter().find(|s| s.pid == pid)?.set_parameters),
+        }
+    }
+
+    fn auth_info(&self, pid: i32) -> Option<&auth::AuthInfo> {
+        match self.mongod.iter().find(|d| d.pid == pid) {
+            Some(d) => Some(&d.auth),
+            None => Some(&self.mongos.iter().find(|s| s.pid == pid)?.auth),
+        }
+    }
+
+    fn tls_info(&self, pid: i32) -> Option<&tls::TlsInfo> {
+        match self.mongod.iter().find(|d| d.pid == pid) {
+            Some(d) => d.tls.as_ref(),
+            None => self.mongos.iter().find(|s| s.pid == pid)?.tls.as_ref(),
+        }
+    }
+
+    // What a filter script sees of a process: its summary entry with its type and command line,
+    // and for replica set members their position among the local members by port
+    fn script_record(&self, pid: i32) -> Value {
+        let mut record = if let Some(d) = self.mongod.iter().find(|d| d.pid == pid) {
+            let mut ports: Vec<i32> = self
+                .mongod
+                .iter()
+                .filter(|m| {
+                    m.replica_set_name.is_some() && m.replica_set_name == d.replica_set_name
+                })
+                .map(|m| m.port)
+                .collect();
+            ports.sort();
+            let mut record = serde_json::to_value(d).unwrap_or_default();
+            record["type"] = json!("mongod");
+            record["replica_set_index"] = json!(ports.iter().position(|p| *p == d.port));
+            record
+        } else if let Some(s) = self.mongos.iter().find(|s| s.pid == pid) {
+            let mut record = serde_json::to_value(s).unwrap_or_default();
+            record["type"] = json!("mongos");
+            record
+        } else if let Some((_, c)) = self.cryptd(pid) {
+            json!({"type": "mongocryptd", "pid": pid, "port": c.port})
+        } else {
+            json!({"type": "shell", "pid": pid})
+        };
+        if let Some(proc) = self.proc_info(pid) {
+            record["cmdline"] = json!(proc.cmdline);
+        }
+        record
+    }
+
+    fn proc_info(&self, pid: i32) -> Option<&CommonProcInfo> {
+        self.procs.iter().find(|p| p.pid == pid)
+    }
+
+    // Only servers have ports, shells are never targets
+    fn server_target(&self, pid: i32) -> Option<ServerTarget> {
+        if let Some(d) = self.mongod.iter().find(|d| d.pid == pid) {
+            return Some(ServerTarget { pid, port: d.port });
+        }
+        self.mongos
+            .iter()
+            .find(|s| s.pid == pid)
+            .map(|s| ServerTarget { pid, port: s.port })
+    }
+
+    fn server_targets(&self) -> Vec<ServerTarget> {
+        self.mongod
+            .iter()
+            .map(|d| d.pid)
+            .chain(self.mongos.iter().map(|s| s.pid))
+            .filter_map(|pid| self.server_target(pid))
+            .collect()
+    }
+}
+
+/// A mongod or mongos selected by the filters
+#[derive(Debug, Clone)]
+struct ServerTarget {
+    pid: i32,
+    port: i32,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum Field {
+    Port,
+    Dbpath,
+    Log,
+}
+
+// A field of a server for --field, None if it does not have one
+fn server_field(info: &MongoPSInfo, pid: i32, field: &Field) -> Option<String> {
+    let target = info.server_target(pid)?;
+    let mongod = info.mongod.iter().find(|d| d.pid == pid);
+    match field {
+        Field::Port => Some(target.port.to_string()),
+        Field::Dbpath => mongod?.dbpath.clone(),
+        Field::Log => match mongod {
+            Some(d) => d.logpath.clone(),
+            None => info.mongos.iter().find(|s| s.pid == pid)?.logpath.clone(),
+        },
+    }
+}
+
+/// Absolute path of a path given to a process, relative paths are relative to its working
+/// directory. Symlinks are resolved when the path exists.
+fn resolve_path(proc: &CommonProcInfo, path: &str) -> String {
+    let path = match proc.cwd {
+        Some(ref cwd) => cwd.join(path),
+        None => PathBuf::from(path),
+    };
+    std::fs::canonicalize(&path)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+// A path setting of the effective config, resolved against the working directory
+fn config_path(proc: &CommonProcInfo, config: &Value, setting: &str) -> Option<String> {
+    let value = config::get_path(config, setting)?.as_str()?;
+    Some(resolve_path(proc, value))
+}
+
+/// A process type or mongod server type to exclude
+#[derive(Debug, Clone, PartialEq)]
+enum TypeExclusion {
+    Process(MongoProcess),
+    Server(MongoDType),
+}
+
+fn parse_type_exclusion(s: &str) -> Result<TypeExclusion, String> {
+    MongoProcess::from_str(s, true)
+        .map(TypeExclusion::Process)
+        .or_else(|_| MongoDType::from_str(s, true).map(TypeExclusion::Server))
+        .map_err(|_| {
+            format!(
+                "Unknown type {}, expected a process type (legacyshell, mongod, mongos, mongocryptd) or \
+                 server type (standalone, replica-set, config, shard)",
+                s
+            )
+        })
+}
+
+/// Parse a duration like 90, 500ms, 30s, 5m, 2h or 1d
+fn parse_age(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration {}", s))?;
+    let secs = match unit {
+        "ms" => return Ok(Duration::from_millis(number)),
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Invalid duration {}, use ms, s, m, h or d", s)),
+    };
+    Ok(Duration::from_secs(number * secs))
+}
+
+/// Ports and inclusive port ranges
+#[derive(Debug, Clone, PartialEq)]
+struct PortSet(Vec<(i32, i32)>);
+
+impl PortSet {
+    fn contains(&self, port: i32) -> bool {
+        self.0
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&port))
+    }
+}
+
+fn parse_port_set(s: &str) -> Result<PortSet, String> {
+    let parse_port = |p: &str| {
+        p.trim()
+            .parse::<i32>()
+            .map_err(|_| format!("Invalid port {}", p))
+    };
+
+    let mut ranges = Vec::new();
+    for part in s.split(',').filter(|p| !p.trim().is_empty()) {
+        let range = match part.split_once('-') {
+            Some((start, end)) => (parse_port(start)?, parse_port(end)?),
+            None => (parse_port(part)?, parse_port(part)?),
+        };
+        if range.0 > range.1 {
+            return Err(format!("Invalid port range {}", part));
+        }
+        ranges.push(range);
+    }
+
+    if ranges.is_empty() {
+        return Err("No ports given".to_owned());
+    }
+    Ok(PortSet(ranges))
+}
+
+// Simple process picker for mongodb development
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    /// Process Type, repeat or separate with commas to match several
+    #[clap(
+        short = 't',
+        long = "type",
+        value_enum,
+        value_parser,
+        value_delimiter = ','
+    )]
+    process_type: Vec<MongoProcess>,
+
+    /// ServerType
+    #[clap(long, value_enum, value_parser)]
+    server_type: Option<MongoDType>,
+
+    /// Port of mongo daemon to search for, or a list of ports and ranges like 20020-20030,27017
+    #[clap(short, long, value_parser = parse_port_set)]
+    port: Option<PortSet>,
+
+    /// Verbose
+    #[clap(short, long)]
+    verbose: bool,
+
+    /// Read the ports servers really listen on from the socket table, --port also matches them
+    #[clap(long)]
+    sockets: bool,
+
+    /// Skip a process type or mongod server type, e.g. config
+    #[clap(long, value_parser = parse_type_exclusion, value_delimiter = ',')]
+    exclude_type: Vec<TypeExclusion>,
+
+    /// Skip servers on these ports and port ranges
+    #[clap(long, value_parser = parse_port_set)]
+    exclude_port: Option<PortSet>,
+
+    /// Only match members of these replica sets
+    #[clap(long, value_delimiter = ',')]
+    replset: Vec<String>,
+
+    /// Skip members of these replica sets
+    #[clap(long, value_delimiter = ',')]
+    exclude_replset: Vec<String>,
+
+    /// Only match processes owned by this user name or uid
+    #[clap(long)]
+    user: Option<String>,
+
+    /// Only match processes owned by the current user
+    #[clap(long, conflicts_with = "user")]
+    mine: bool,
+
+    /// Only match mongods using this dbpath, relative to the current directory
+    #[clap(long)]
+    dbpath: Option<PathBuf>,
+
+    /// Only match processes started less than this long ago, e.g. 5m
+    #[clap(long, value_parser = parse_age)]
+    younger_than: Option<Duration>,
+
+    /// Only match processes started more than this long ago, e.g. 2h
+    #[clap(long, value_parser = parse_age)]
+    older_than: Option<Duration>,
+
+    /// Only match pids read from stdin, e.g. from pgrep, and print what they are
+    #[clap(long)]
+    stdin_pids: bool,
+
+    // Pids read from stdin for --stdin-pids
+    #[clap(skip)]
+    candidate_pids: Option<Vec<i32>>,
+
+    /// Query each replica set member over the wire for its state and replication lag
+    #[clap(long)]
+    driver: bool,
+
+    /// Only match secondaries lagging behind the primary by more than this, e.g. 10s, implies
+    /// --driver
+    // --max-lag is the old, misleading name, still accepted so existing scripts keep working
+    #[clap(long, alias = "max-lag", value_parser = parse_age)]
+    lagging_more_than: Option<Duration>,
+
+    /// Only match replica set members in one of these roles, implies --driver
+    #[clap(long, value_enum, value_delimiter = ',')]
+    role: Vec<replication::MemberRole>,
+
+    /// Only match replica set members with this tag in the replica set config, given as
+    /// name=value, repeat to require several, implies --driver
+    #[clap(long, value_parser = replication::parse_member_tag)]
+    member_tag: Vec<(String, String)>,
+
+    /// Connect to servers through their unix domain socket files instead of localhost TCP, also
+    /// in the connection strings of --uri and connect
+    #[clap(long)]
+    prefer_socket: bool,
+
+    /// Time to wait for each reply from a server, e.g. 10s or 500ms
+    #[clap(long, value_parser = parse_age)]
+    timeout: Option<Duration>,
+
+    /// Time to wait for a connection to a server before reporting it unreachable
+    #[clap(long, value_parser = parse_age)]
+    connect_timeout: Option<Duration>,
+
+    /// Most servers to query or run a command on at the same time [default: 16]
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..))]
+    concurrency: Option<u16>,
+
+    /// User to authenticate as on servers started with --auth, also read from MPF_MONGODB_URI
+    #[clap(long)]
+    username: Option<String>,
+
+    /// Password for --username, asked for on a terminal when missing
+    #[clap(long)]
+    password: Option<String>,
+
+    /// Database the user is defined in, defaults to admin
+    #[clap(long = "authenticationDatabase")]
+    authentication_database: Option<String>,
+
+    /// Credentials profile of ~/.config/mpf/credentials.json, defaults to "default"
+    #[clap(long)]
+    profile: Option<String>,
+
+    /// Authentication mechanism, negotiated with the server by default
+    #[clap(long = "authenticationMechanism", value_enum)]
+    authentication_mechanism: Option<scram::Mechanism>,
+
+    /// Connect to servers with TLS, verifying them against this CA file
+    #[clap(long = "tlsCAFile")]
+    tls_ca_file: Option<String>,
+
+    /// Client certificate and key PEM file for servers that require one
+    #[clap(long = "tlsCertificateKeyFile")]
+    tls_certificate_key_file: Option<String>,
+
+    /// Connect with TLS without verifying the server certificate
+    #[clap(long = "tlsAllowInvalidCertificates")]
+    tls_allow_invalid_certificates: bool,
+
+    /// Only match servers with TLS enabled
+    #[clap(long)]
+    tls_enabled: bool,
+
+    /// Only match servers that require authentication
+    #[clap(long)]
+    auth_enabled: bool,
+
+    /// Only match shard mongods registered under these shard names, implies --driver for shards
+    #[clap(long, value_delimiter = ',')]
+    shard_name: Vec<String>,
+
+    /// Only match the mongos at this position, counting from 0, among the matched routers of each
+    /// cluster sorted by port
+    #[clap(long)]
+    router_index: Option<usize>,
+
+    /// Only match one mongos per cluster, the first by port that answers
+    #[clap(long, conflicts_with = "router_index")]
+    any_router: bool,
+
+    /// Only match servers started with a --setParameter, given as name or name=value
+    #[clap(long)]
+    has_parameter: Vec<String>,
+
+    /// Only match test instances, started with enableTestCommands or similar parameters
+    #[clap(long, conflicts_with = "exclude_test")]
+    test_only: bool,
+
+    /// Skip test instances
+    #[clap(long)]
+    exclude_test: bool,
+
+    /// Only match processes a Rhai script keeps, it sees each process as `process` and ends
+    /// with true, false or #{keep: .., name: annotation, ...}
+    #[clap(long, value_parser = script::parse_file)]
+    filter_script: Option<script::Script>,
+
+    /// Warn about filesystems holding a dbpath or log with less than this free, e.g. 10%, and
+    /// exit nonzero after running the command
+    #[clap(long, value_parser = disk::parse_percent)]
+    warn_disk_free: Option<f64>,
+
+    /// Print connection strings for matched servers instead of pids
+    #[clap(long)]
+    uri: bool,
+
+    /// Print this field of each matched server instead of pids
+    #[clap(long, value_enum)]
+    field: Option<Field>,
+
+    /// Format of the summary printed when there are no filters
+    #[clap(long, value_enum, default_value = "json")]
+    output: output::OutputFormat,
+
+    /// Write the summary to this file instead of stdout, replacing it atomically
+    #[clap(long)]
+    output_file: Option<PathBuf>,
+
+    /// Shape of the JSON output, for scripts written against an older version
+    #[clap(long, default_value_t = schema::SCHEMA_VERSION)]
+    output_version: u32,
+
+    /// Copy the single match to the clipboard
+    #[clap(long)]
+    copy: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Shut down matched servers with the shutdown command, sending SIGTERM to unreachable ones
+    Shutdown {
+        /// Shut down even if a primary has no electable secondary caught up
+        #[clap(long)]
+        force: bool,
+
+        /// Seconds a primary waits for a secondary to catch up before stepping down
+        #[clap(long)]
+        timeout_secs: Option<u32>,
+
+        /// Print the outcome on each server as JSON
+        #[clap(long)]
+        json: bool,
+
+        /// Do not ask for confirmation when more than one process matches
+        #[clap(short, long)]
+        yes: bool,
+    },
+
+    /// Stop matched servers and start them again with the same arguments, environment and
+    /// working directory
+    Restart {
+        /// Binary to start instead of the original one
+        #[clap(long)]
+        binary: Option<PathBuf>,
+
+        /// Do not ask for confirmation when more than one process matches
+        #[clap(short, long)]
+        yes: bool,
+    },
+
+    /// Start a copy of the matched mongod on a different port and dbpath
+    Clone {
+        /// Port for the new server
+        #[clap(long)]
+        port: i32,
+
+        /// Data directory for the new server, created if it does not exist
+        #[clap(long)]
+        dbpath: PathBuf,
+
+        /// Replica set name for the new server
+        #[clap(long = "replSet")]
+        repl_set: Option<String>,
+    },
+
+    /// Start a standalone, replica set or sharded cluster for development
+    Start {
+        #[clap(value_enum)]
+        topology: start::Topology,
+
+        /// Directory containing the mongod and mongos binaries, defaults to the PATH
+        #[clap(long)]
+        bin_dir: Option<PathBuf>,
+
+        /// Directory for data files and logs
+        #[clap(long, default_value = "data")]
+        dir: PathBuf,
+
+        /// First port, the rest of the processes use the following ports
+        #[clap(long = "base-port", default_value_t = 27017)]
+        port: i32,
+
+        /// Replica set name
+        #[clap(long = "replSet", default_value = "rs0")]
+        repl_set: String,
+
+        /// Number of members in each replica set
+        #[clap(long, default_value_t = 3)]
+        nodes: i32,
+
+        /// Number of shards
+        #[clap(long, default_value_t = 2)]
+        shards: i32,
+    },
+
+    /// Shut down what start launched in a directory and is still running
+    Stop {
+        /// Directory given to start
+        #[clap(long, default_value = "data")]
+        dir: PathBuf,
+    },
+
+    /// Export the discovered processes in another format, or push metrics with --statsd
+    Export {
+        #[clap(subcommand)]
+        format: Option<ExportFormat>,
+
+        /// Push process counts and per-server RSS, CPU and health gauges to this statsd address
+        /// every interval, e.g. localhost:8125
+        #[clap(long)]
+        statsd: Option<String>,
+
+        /// Time between statsd pushes
+        #[clap(long, value_parser = parse_age, default_value = "10s")]
+        interval: Duration,
+
+        /// Prefix of the statsd metric names
+        #[clap(long, default_value = "mpf")]
+        prefix: String,
+    },
+
+    /// Compare the effective configuration of two servers
+    ConfigDiff {
+        /// Process id of the first server
+        pid_a: i32,
+
+        /// Process id of the second server
+        pid_b: i32,
+    },
+
+    /// Open a tmux pane per matched server
+    Tmux {
+        /// What to run in each pane
+        #[clap(long, value_enum, default_value = "log")]
+        mode: tmux::PaneMode,
+    },
+
+    /// Freeze matched processes with SIGSTOP
+    Pause {
+        /// Do not ask for confirmation
+        #[clap(short, long)]
+        yes: bool,
+    },
+
+    /// Continue processes frozen by pause with SIGCONT
+    Resume,
+
+    /// Send a signal to matched processes
+    Signal {
+        /// Signal name (SIGUSR2, USR2) or number
+        #[clap(short, long, value_parser = signal::parse_signal)]
+        signal: i32,
+
+        /// Only list the processes that would be signaled
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Print the outcome for each process as JSON
+        #[clap(long)]
+        json: bool,
+
+        /// Do not ask for confirmation when more than one process matches
+        #[clap(short, long)]
+        yes: bool,
+    },
+
+    /// Render a report of the matched servers with versions, resource usage and warnings
+    Report {
+        #[clap(long, value_enum, default_value = "markdown")]
+        format: report::ReportFormat,
+    },
+
+    /// List the binaries matched servers run with their SHA-256, marking binaries replaced on disk
+    /// since the servers started
+    Fingerprint {
+        /// Print JSON instead of a table
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Print the JSON Schema of the output for --output-version
+    Schema,
+
+    /// Print shell functions mcd (cd to the dbpath), mlog (tail the log) and mport (print the
+    /// port) that take mpf's filters, e.g. eval "$(mpf shell-init bash)"
+    ShellInit {
+        #[clap(value_enum)]
+        shell: shell_init::Shell,
+    },
+
+    /// Run mongosh connected to the matched server, asking which one if several match
+    Connect {
+        /// Connect to the server itself instead of its replica set
+        #[clap(long)]
+        direct: bool,
+
+        /// Shell to run
+        #[clap(long, default_value = "mongosh")]
+        shell: String,
+
+        /// Extra arguments for the shell, after --
+        #[clap(last = true)]
+        shell_args: Vec<String>,
+    },
+
+    /// Evaluate JavaScript with mongosh on every matched server and print each output labeled
+    /// with its server, e.g. mpf eval 'db.serverStatus().connections'
+    Eval {
+        /// JavaScript to evaluate
+        snippet: String,
+
+        /// Shell to run, mongosh or else mongo from the PATH by default
+        #[clap(long)]
+        shell: Option<PathBuf>,
+
+        /// Print the outputs and errors as JSON
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Describe a process: type, port, role, replica set, dbpath, log and command line
+    Info {
+        /// Process id, e.g. from top
+        pid: i32,
+    },
+
+    /// Report risky security settings, exits with an error when there are findings
+    Audit,
+
+    /// Check that the matched servers form a healthy fixture, printing a JSON report and failing
+    /// when a check fails
+    Check {
+        /// Checks to run, all of them by default
+        #[clap(value_enum, value_delimiter = ',')]
+        checks: Vec<check::Check>,
+
+        /// Largest difference between a server clock and the host clock no-clock-skew accepts
+        #[clap(long, value_parser = parse_age, default_value = "1s")]
+        max_skew: Duration,
+    },
+
+    /// Check host settings mongod warns about at startup, like transparent hugepages, rlimits,
+    /// vm.max_map_count, the clock source and the filesystem of each dbpath
+    Doctor,
+
+    /// Change the scheduling priority of matched processes
+    Renice {
+        /// Nice value, from -20 (highest priority) to 19 (lowest)
+        #[clap(long, allow_hyphen_values = true)]
+        nice: i32,
+
+        /// Do not ask for confirmation when more than one process matches
+        #[clap(short, long)]
+        yes: bool,
+    },
+
+    /// Restrict matched processes to a set of CPUs (Linux only)
+    Affinity {
+        /// CPU list like 0-3,6
+        #[clap(long)]
+        cpus: String,
+
+        /// Do not ask for confirmation when more than one process matches
+        #[clap(short, long)]
+        yes: bool,
+    },
+
+    /// Print the shards of the cluster behind the matched mongos with their local pids, and the
+    /// balancer state and migrations in progress
+    ShStatus,
+
+    /// Count the chunks of a sharded collection on each shard through the matched mongos
+    Chunks {
+        /// Namespace of the collection, db.collection
+        #[clap(long)]
+        ns: String,
+    },
+
+    /// Show the outgoing connection pools of matched servers per remote host from connPoolStats
+    Connpool,
+
+    /// Read or set the database profiler level on matched servers
+    ProfileLevel {
+        #[clap(subcommand)]
+        action: ProfileAction,
+    },
+
+    /// Run a command document on every matched server concurrently and print each reply, e.g.
+    /// mpf run-cmd '{"setParameter": 1, "logLevel": 2}'
+    RunCmd {
+        /// Command as a JSON document, the first field names the command
+        command: String,
+
+        /// Database to run the command on
+        #[clap(long, default_value = "admin")]
+        db: String,
+
+        /// Print the replies and errors as JSON
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Set runtime parameters on every matched server, e.g. mpf set-param logLevel=2, and read
+    /// them back to confirm they took
+    SetParam {
+        /// Parameters as name=value, values are typed as JSON when they parse
+        #[clap(required = true, value_parser = set_param::parse_assignment)]
+        params: Vec<(String, Value)>,
+    },
+
+    /// Raise the log verbosity of matched servers, with --for restoring the previous level when
+    /// the time is up or on Ctrl-C
+    Loglevel {
+        /// Component like replication or replication.election, the global logLevel without one
+        #[clap(long)]
+        component: Option<String>,
+
+        /// Verbosity from 0 to 5
+        #[clap(long, value_parser = clap::value_parser!(i64).range(0..=5))]
+        level: i64,
+
+        /// Restore the previous level after this long, e.g. 5m
+        #[clap(long = "for", value_parser = parse_age)]
+        duration: Option<Duration>,
+    },
+
+    /// Show index builds in progress on matched servers with their phase and progress
+    IndexBuilds,
+
+    /// Summarize long running operations from currentOp on matched servers
+    Ops {
+        /// Only show operations running for at least this many seconds
+        #[clap(long, default_value_t = 1)]
+        secs: i64,
+
+        /// Kill this operation on the matched server instead
+        #[clap(long)]
+        kill_op: Option<String>,
+    },
+
+    /// Print the primary and term of each matched replica set
+    Elections {
+        /// Keep polling and print every change of primary
+        #[clap(long)]
+        follow: bool,
+
+        /// Seconds between polls with --follow
+        #[clap(long, default_value_t = 1)]
+        interval_secs: u64,
+    },
+
+    /// Full screen live view of matched servers grouped by replica set, with keys to kill,
+    /// attach a debugger, tail the log or open a shell
+    Top {
+        /// Seconds between refreshes
+        #[clap(long, default_value_t = 2)]
+        interval_secs: u64,
+    },
+
+    /// Print rows of operations per second, cache usage and connections of every matched server
+    /// like mongostat, e.g. mpf stat --interval 1s
+    Stat {
+        /// Time between polls
+        #[clap(long, value_parser = parse_age, default_value = "1s")]
+        interval: Duration,
+
+        /// Stop after this many polls
+        #[clap(long)]
+        count: Option<u64>,
+    },
+
+    /// Print the time each namespace of the matched mongod spent reading and writing per
+    /// interval, like mongotop
+    TopNs {
+        /// Time between polls
+        #[clap(long, value_parser = parse_age, default_value = "1s")]
+        interval: Duration,
+
+        /// Stop after this many polls
+        #[clap(long)]
+        count: Option<u64>,
+
+        /// Most namespaces to show per poll, busiest first
+        #[clap(long, default_value_t = 10)]
+        limit: usize,
+    },
+
+    /// Group the "Slow query" entries of the matched servers' logs by namespace and query shape
+    /// and print the ones taking the most time
+    Slow {
+        /// Skip operations faster than this
+        #[clap(long, value_parser = parse_age, default_value = "0ms")]
+        threshold: Duration,
+
+        /// Most query shapes to print
+        #[clap(long, default_value_t = 10)]
+        limit: usize,
+
+        /// Print the groups as JSON
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Print the entries of the matched servers' JSON logs that match all the filters, e.g.
+    /// mpf logq --component COMMAND --severity W --since 10m
+    Logq {
+        /// Components to keep, e.g. REPL,ELECTION
+        #[clap(long, value_delimiter = ',')]
+        component: Vec<String>,
+
+        /// Keep this severity and more severe ones: F, E, W, I or D1-D5
+        #[clap(long, value_parser = logq::parse_severity)]
+        severity: Option<usize>,
+
+        /// Keep entries logged within this long, e.g. 10m
+        #[clap(long, value_parser = parse_age)]
+        since: Option<Duration>,
+
+        /// Log message ids to keep, e.g. 21216
+        #[clap(long, value_delimiter = ',')]
+        id: Vec<i64>,
+
+        /// Print the entries as JSON, one per line, with the pid and port of their server
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Print the JSON logs of the matched servers with the node of each line, e.g.
+    /// mpf --replset rs0 logs --merge --since 5m
+    Logs {
+        /// Interleave the logs ordered by time instead of one after another
+        #[clap(long)]
+        merge: bool,
+
+        /// Only print entries logged within this long, e.g. 10m
+        #[clap(long, value_parser = parse_age)]
+        since: Option<Duration>,
+    },
+
+    /// Read the full time diagnostic data capture (FTDC) of a server
+    Ftdc {
+        #[clap(subcommand)]
+        action: FtdcAction,
+    },
+
+    /// Block until the matched servers are ready, e.g. at the top of a test script
+    Wait {
+        /// Wait until every server accepts connections and replica set members are PRIMARY,
+        /// SECONDARY or ARBITER, printing startup milestones from the log on the way
+        #[clap(long, conflicts_with = "cluster")]
+        until_ready: bool,
+
+        /// Wait for the replica set with this name instead, as seen by any of its members
+        #[clap(long, requires = "until")]
+        cluster: Option<String>,
+
+        /// What the replica set of --cluster has to reach
+        #[clap(long, value_enum, requires = "cluster")]
+        until: Option<wait::Until>,
+
+        /// Give up and fail after this long
+        #[clap(long, value_parser = parse_age, default_value = "5m")]
+        timeout: Duration,
+    },
+
+    /// Print the man page of mpf or one of its commands, or write them all for packaging
+    Man {
+        /// Command to print the page of, e.g. ftdc export
+        command: Vec<String>,
+
+        /// Write mpf.1 and a page for every command into this directory instead
+        #[clap(long, conflicts_with = "command")]
+        dir: Option<PathBuf>,
+    },
+
+    /// Replace this binary with the latest release for the platform, after checking its signature
+    SelfUpdate {
+        /// Only report whether a newer release exists
+        #[clap(long)]
+        check: bool,
+
+        /// Install the latest release even when it is not newer
+        #[clap(long)]
+        force: bool,
+
+        /// Do not ask for confirmation
+        #[clap(short, long)]
+        yes: bool,
+    },
+
+    /// Speak JSON-RPC 2.0 on stdin and stdout, one message per line, for editor integrations
+    /// that keep one mpf running: scan, uri, kill, watch-subscribe and watch-unsubscribe
+    Rpc,
+
+    /// Serve the inventory, actions and change stream of rpc over gRPC, see proto/mpf.proto
+    Grpc {
+        /// Address to listen on, port 0 picks a free port
+        #[clap(long, default_value = "127.0.0.1:50051")]
+        listen: std::net::SocketAddr,
+    },
+
+    /// Rescan periodically and print the processes that started, stopped or changed
+    Watch {
+        /// Seconds between scans
+        #[clap(long, default_value_t = 2)]
+        interval_secs: u64,
+
+        /// Only print when something changed
+        #[clap(long)]
+        changes_only: bool,
+
+        /// Print colored text, or one JSON event per line for other programs
+        #[clap(long, value_enum, default_value = "text")]
+        output: watch::WatchOutput,
+    },
+
+    /// Stop all matched processes, routers first and config servers last
+    Nuke {
+        /// Do not ask for confirmation
+        #[clap(short, long)]
+        yes: bool,
+
+        /// Delete the dbpath of each stopped mongod
+        #[clap(long)]
+        delete_data: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum FtdcAction {
+    /// Decode the diagnostic.data of the matched server into timestamped series of metrics, e.g.
+    /// mpf ftdc export --metric wt.cache.bytes --since 1h -o out.csv
+    Export {
+        /// Metrics to export, matching any part of their names ignoring case, wt is short for
+        /// wiredTiger
+        #[clap(long, required = true, value_delimiter = ',')]
+        metric: Vec<String>,
+
+        /// Only samples taken within this long, e.g. 1h
+        #[clap(long, value_parser = parse_age)]
+        since: Option<Duration>,
+
+        /// File to write, standard output by default
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+
+        /// Output format, json when the output file ends in .json and csv otherwise
+        #[clap(long, value_enum)]
+        format: Option<ftdc::FtdcFormat>,
+
+        /// diagnostic.data directory to read instead of the matched server's, e.g. a copy from
+        /// another host
+        #[clap(long)]
+        dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfileAction {
+    /// Print the profiler level and slowms of each database
+    Get {
+        /// Only this database instead of every database
+        #[clap(long)]
+        db: Option<String>,
+    },
+
+    /// Set the profiler level: 0 off, 1 slow operations, 2 all operations
+    Set {
+        #[clap(value_parser = clap::value_parser!(i32).range(0..=2))]
+        level: i32,
+
+        /// Threshold in milliseconds for slow operations
+        #[clap(long)]
+        slow_ms: Option<i64>,
+
+        /// Only this database instead of every database
+        #[clap(long)]
+        db: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ExportFormat {
+    /// docker-compose.yml reproducing the local topology in containers
+    DockerCompose,
+
+    /// Graphviz DOT graph of the local topology, e.g. for `mpf export dot | dot -Tpng`
+    Dot,
+
+    /// Mermaid flowchart of the local topology, for GitHub issues and pull requests
+    Mermaid,
+
+    /// YAML config file equivalent to a server's command line
+    Config {
+        /// Process id of the mongod or mongos
+        #[clap(long)]
+        pid: i32,
+    },
+
+    /// VS Code launch.json configuration to attach a debugger to a process
+    Vscode {
+        /// Process id to attach to, defaults to the single process matched by the filters
+        #[clap(long)]
+        pid: Option<i32>,
+
+        /// Debugger extension to generate the configuration for
+        #[clap(long, value_enum, default_value = "lldb")]
+        debugger: export::Debugger,
+
+        /// Add the configuration to this launch.json instead of printing it
+        #[clap(long)]
+        write: Option<PathBuf>,
+    },
+}
+
+fn scan_mongo_processes(verbose: bool, settings: client::Settings) -> Result<MongoPSInfo> {
+    // Get a list of processes
+    let procs = get_procs()?;
+
+    let mut shells: Vec<i32> = Vec::new();
+
+    let mut mongod_servers: Vec<MongoDServerInfo> = Vec::new();
+    let mut mongos_servers: Vec<MongoSServerInfo> = Vec::new();
+
+    let mut mongo_procs: Vec<CommonProcInfo> = Vec::new();
+
+    // Spawners of mongocryptd may be any process, so look them up before keeping only mongo ones
+    let cryptds: Vec<&CommonProcInfo> = procs
+        .iter()
+        .filter(|p| p.program == "mongocryptd")
+        .collect();
+    let mongocryptd = cryptd::group_by_parent(&cryptds, &procs, |port| {
+        connected_pids(port as u16).unwrap_or_default()
+    });
+
+    // Drop --fork launchers still waiting on their daemon so only the live server is a target
+    let mut pidfiles: HashMap<i32, Option<String>> = HashMap::new();
+    let forking: Vec<&CommonProcInfo> = procs
+        .iter()
+        .filter(|p| p.program == "mongod" || p.program == "mongos")
+        .filter(|p| {
+            let config = config::local_effective_config(p).ok();
+            let pidfile = config
+                .as_ref()
+                .and_then(|c| config_path(p, c, "processManagement.pidFilePath"));
+            pidfiles.insert(p.pid, pidfile);
+            forks(config.as_ref())
+        })
+        .collect();
+    let launchers = fork::launchers(&forking, |p| {
+        fork::read_pidfile(Path::new(pidfiles.get(&p.pid)?.as_ref()?))
+    });
+
+    // Get a list of mongodb information
+    for p in procs {
+        if launchers.contains(&p.pid) {
+            if verbose {
+                println!("Skipping {} still launching a forked server", p.pid);
+            }
+            continue;
+        }
+
+        let mp = is_mongo_process(&p);
+        if mp.is_some() && verbose {
+            println!("{:?} -{:?} -{:?} -{:?}", p.pid, mp, p.program, p.cmdline);
+        }
+
+        if let Some(mpt) = mp {
+            match mpt {
+                MongoProcess::Legacyshell => {
+                    shells.push(p.pid);
+                }
+                MongoProcess::Mongod => match get_mongod_info(&p) {
+                    Ok(info) => mongod_servers.push(info),
+                    Err(e) => {
+                        eprintln!("WARNING: Skipping {}: {:#}", p.pid, e);
+                        continue;
+                    }
+                },
+                MongoProcess::Mongos => match get_mongos_info(&p) {
+                    Ok(info) => mongos_servers.push(info),
+                    Err(e) => {
+                        eprintln!("WARNING: Skipping {}: {:#}", p.pid, e);
+                        continue;
+                    }
+                },
+                MongoProcess::Mongocryptd => {}
+            }
+            mongo_procs.push(p);
+        }
+    }
+
+    // Dump Process Info
+    if verbose {
+        for s in shells.as_slice() {
+            println!("Shell: {:?}", s);
+        }
+        for d in mongod_servers.as_slice() {
+            println!("{:?}", d);
+        }
+        for s in mongos_servers.as_slice() {
+            println!("{:?}", s);
+        }
+    }
+
+    let mut info = MongoPSInfo {
+        schema_version: schema::SCHEMA_VERSION,
+        shell: shells,
+        mongod: mongod_servers,
+        mongos: mongos_servers,
+        mongocryptd,
+        clusters: Vec::new(),
+        plugin_processes: Vec::new(),
+        procs: mongo_procs,
+        settings,
+    };
+    info.clusters = cluster::clusters(&info, &BTreeMap::new());
+    start::label_started(&mut info);
+    Ok(info)
+}
+
+// Narrow down the matched pids, starting from every process if nothing matched yet
+fn narrow_pids<F>(pids: Option<Vec<i32>>, info: &MongoPSInfo, keep: F) -> Option<Vec<i32>>
+where
+    F: Fn(i32) -> bool,
+{
+    let pids = pids.unwrap_or_else(|| info.procs.iter().map(|p| p.pid).collect());
+    Some(pids.into_iter().filter(|pid| keep(*pid)).collect())
+}
+
+// A process the script fails on is dropped with a warning
+fn script_verdict(
+    info: &MongoPSInfo,
+    script: &script::Script,
+    pid: i32,
+) -> Option<script::Verdict> {
+    match script.run(&info.script_record(pid)) {
+        Ok(verdict) => Some(verdict),
+        Err(e) => {
+            eprintln!("WARNING: Filter script failed on {}: {:#}", pid, e);
+            None
+        }
+    }
+}
+
+// Returns None if no filters were specified
+fn filter_pids(args: &Args, info: &MongoPSInfo) -> Option<Vec<i32>> {
+    let mut pids = match_pids(args, info);
+
+    if let Some(ref candidates) = args.candidate_pids {
+        pids = narrow_pids(pids, info, |pid| candidates.contains(&pid));
+    }
+
+    if let Some(ref user) = args.user {
+        pids = narrow_pids(pids, info, |pid| {
+            info.proc_info(pid).and_then(|p| p.uid).is_some_and(|uid| {
+                uid.to_string() == *user || user_name(uid).as_ref() == Some(user)
+            })
+        });
+    }
+    if args.mine {
+        let me = unsafe { libc::getuid() };
+        pids = narrow_pids(pids, info, |pid| {
+            info.proc_info(pid).and_then(|p| p.uid) == Some(me)
+        });
+    }
+    if let Some(ref dbpath) = args.dbpath {
+        let dbpath = std::fs::canonicalize(dbpath)
+            .unwrap_or_else(|_| dbpath.clone())
+            .display()
+            .to_string();
+        pids = narrow_pids(pids, info, |pid| {
+            info.mongod
+                .iter()
+                .any(|d| d.pid == pid && d.dbpath.as_ref() == Some(&dbpath))
+        });
+    }
+    if args.younger_than.is_some() || args.older_than.is_some() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        pids = narrow_pids(pids, info, |pid| {
+            let Some(start) = info.proc_info(pid).and_then(|p| p.start_time) else {
+                return false;
+            };
+            let age = now.saturating_sub(start);
+            args.younger_than.is_none_or(|d| age < d.as_secs())
+                && args.older_than.is_none_or(|d| age > d.as_secs())
+        });
+    }
+    if !args.exclude_type.is_empty() {
+        pids = narrow_pids(pids, info, |pid| {
+            !args.exclude_type.iter().any(|t| info.is_type(pid, t))
+        });
+    }
+    if let Some(ref ports) = args.exclude_port {
+        pids = narrow_pids(pids, info, |pid| {
+            !info
+                .server_target(pid)
+                .is_some_and(|t| ports.contains(t.port))
+        });
+    }
+    if !args.replset.is_empty() {
+        pids = narrow_pids(pids, info, |pid| {
+            info.mongod.iter().any(|d| {
+                d.pid == pid
+                    && d.replica_set_name
+                        .as_ref()
+                        .is_some_and(|name| args.replset.contains(name))
+            })
+        });
+    }
+    if !args.exclude_replset.is_empty() {
+        pids = narrow_pids(pids, info, |pid| {
+            !info.mongod.iter().any(|d| {
+                d.pid == pid
+                    && d.replica_set_name
+                        .as_ref()
+                        .is_some_and(|name| args.exclude_replset.contains(name))
+            })
+        });
+    }
+    if let Some(threshold) = args.lagging_more_than {
+        pids = narrow_pids(pids, info, |pid| {
+            info.mongod.iter().any(|d| {
+                d.pid == pid
+                    && d.replication
+                        .as_ref()
+                        .and_then(|r| r.lag_secs)
+                        .is_some_and(|lag| lag > threshold.as_secs_f64())
+            })
+        });
+    }
+    if !args.role.is_empty() {
+        pids = narrow_pids(pids, info, |pid| {
+            info.mongod.iter().any(|d| {
+                d.pid == pid
+                    && d.replication
+                        .as_ref()
+                        .is_some_and(|r| args.role.iter().any(|role| role.matches(r)))
+            })
+        });
+    }
+    if !args.member_tag.is_empty() {
+        pids = narrow_pids(pids, info, |pid| {
+            info.mongod.iter().any(|d| {
+                d.pid == pid
+                    && d.replication.as_ref().is_some_and(|r| {
+                        args.member_tag
+                            .iter()
+                            .all(|(name, value)| r.tags.get(name) == Some(value))
+                    })
+            })
+        });
+    }
+    if args.tls_enabled {
+        pids = narrow_pids(pids, info, |pid| info.tls_info(pid).is_some());
+    }
+    if args.auth_enabled {
+        pids = narrow_pids(pids, info, |pid| {
+            info.auth_info(pid).is_some_and(|auth| auth.enabled)
+        });
+    }
+    if args.test_only {
+        pids = narrow_pids(pids, info, |pid| info.is_test_instance(pid));
+    }
+    if args.exclude_test {
+        pids = narrow_pids(pids, info, |pid| !info.is_test_instance(pid));
+    }
+    for spec in &args.has_parameter {
+        pids = narrow_pids(pids, info, |pid| {
+            info.set_parameters(pid)
+                .is_some_and(|params| has_parameter(params, spec))
+        });
+    }
+    if !args.shard_name.is_empty() {
+        pids = narrow_pids(pids, info, |pid| {
+            info.mongod.iter().any(|d| {
+                d.pid == pid
+                    && d.shard_name
+                        .as_ref()
+                        .is_some_and(|name| args.shard_name.contains(name))
+            })
+        });
+    }
+    if let Some(ref script) = args.filter_script {
+        pids = narrow_pids(pids, info, |pid| {
+            script_verdict(info, script, pid).is_some_and(|v| v.keep)
+        });
+    }
+    if args.router_index.is_some() || args.any_router {
+        let all = pids.unwrap_or_else(|| info.procs.iter().map(|p| p.pid).collect());
+        let clusters = sharding::routers(info, &all);
+        pids = Some(sharding::pick_routers(&clusters, args.router_index, |t| {
+            describe::server_role(&info.settings, t.port).is_ok()
+        }));
+    }
+
+    pids
+}
+
+fn match_pids(args: &Args, info: &MongoPSInfo) -> Option<Vec<i32>> {
+    let mongod_servers = &info.mongod;
+    let mongos_servers = &info.mongos;
+
+    // Find servers by port
+    let mut pids: Vec<i32> = Vec::new();
+    if let Some(ref port) = args.port {
+        // TODO - nightly's iter_collect_into would be nice here
+        pids.extend_from_slice(
+            mongod_servers
+                .as_slice()
+                .iter()
+                .filter(|d| port.contains(d.port) || listens_on(&d.listening_ports, port))
+                .map(|d| d.pid)
+                .collect::<Vec<i32>>()
+                .as_slice(),
+        );
+        pids.extend_from_slice(
+            mongos_servers
+                .as_slice()
+                .iter()
+                .filter(|d| port.contains(d.port) || listens_on(&d.listening_ports, port))
+                .map(|d| d.pid)
+                .collect::<Vec<i32>>()
+                .as_slice(),
+        );
+        pids.extend(
+            info.cryptd_instances()
+                .filter(|c| port.contains(c.port))
+                .map(|c| c.pid),
+        );
+    } else if let Some(ref server_type) = args.server_type {
+        pids.extend_from_slice(
+            mongod_servers
+                .as_slice()
+                .iter()
+                .filter(|d| d.server_type == *server_type)
+                .map(|d| d.pid)
+                .collect::<Vec<i32>>()
+                .as_slice(),
+        );
+    } else if !args.process_type.is_empty() {
+        for process_type in &args.process_type {
+            match process_type {
+                MongoProcess::Legacyshell => {
+                    pids.extend_from_slice(info.shell.as_slice());
+                }
+                MongoProcess::Mongod => {
+                    pids.extend_from_slice(
+                        mongod_servers
+                            .as_slice()
+                            .iter()
+                            .map(|d| d.pid)
+                            .collect::<Vec<i32>>()
+                            .as_slice(),
+                    );
+                }
+                MongoProcess::Mongos => {
+                    pids.extend_from_slice(
+                        mongos_servers
+                            .as_slice()
+                            .iter()
+                            .map(|d| d.pid)
+                            .collect::<Vec<i32>>()
+                            .as_slice(),
+                    );
+                }
+                MongoProcess::Mongocryptd => {
+                    pids.extend(info.cryptd_instances().map(|c| c.pid));
+                }
+            }
+        }
+    } else {
+        return None;
+    }
+
+    Some(pids)
+}
+
+// Servers matched by the filters, or all servers if there are no filters
+fn select_servers(args: &Args, info: &MongoPSInfo) -> Vec<ServerTarget> {
+    match filter_pids(args, info) {
+        Some(pids) => pids
+            .into_iter()
+            .filter_map(|pid| info.server_target(pid))
+            .collect(),
+        None => info.server_targets(),
+    }
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    print!("{} [y/N] ", prompt);
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+    Ok(matches!(line.trim(), "y" | "Y" | "yes"))
+}
+
+// Without filters every mongod and mongos matches, so acting on more than one process asks
+// first unless --yes was given
+fn confirm_many(info: &MongoPSInfo, pids: &[i32], action: &str, yes: bool) -> Result<()> {
+    if pids.len() > 1 && !yes {
+        list_processes(info, pids)?;
+        if !confirm(&format!("{} {} processes?", action, pids.len()))? {
+            bail!("Aborted");
+        }
+    }
+    Ok(())
+}
+
+// Every integer in the input is a candidate pid, so the output of pgrep or ps can be piped in as
+// is, other numbers are dropped when intersecting with the mongo processes
+fn read_pids<R: BufRead>(input: R) -> Result<Vec<i32>> {
+    let mut pids = Vec::new();
+    for line in input.lines() {
+        pids.extend(
+            line?
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter_map(|token| token.parse::<i32>().ok()),
+        );
+    }
+    Ok(pids)
+}
+
+// One line summary of a process: pid, type, port and replica set
+fn summary_line(info: &MongoPSInfo, pid: i32) -> String {
+    if let Some(d) = info.mongod.iter().find(|d| d.pid == pid) {
+        let mut line = format!(
+            "{} mongod {:?} {} {}",
+            pid,
+            d.server_type,
+            d.port,
+            d.replica_set_name.as_deref().unwrap_or("-")
+        );
+        if let Some(ref name) = d.shard_name {
+            line.push_str(&format!(" shard {}", name));
+        }
+        let engine = storage::engine_name(&d.storage, &d.special_modes);
+        if engine != "wiredTiger" {
+            line.push_str(&format!(" {}", engine));
+        }
+        if let Some(ref r) = d.replication {
+            line.push_str(&format!(" {}", r.state));
+            if let Some(lag) = r.lag_secs {
+                line.push_str(&format!(" lag {:.1}s", lag));
+            }
+            if !r.roles.is_empty() {
+                line.push_str(&format!(" {}", r.roles.join(",")));
+            }
+        }
+        // Only known with --sockets
+        if d.listening_ports.as_ref().is_some_and(|p| p.is_empty()) {
+            line.push_str(&format!(
+                " {}",
+                startup::describe_not_listening(d.logpath.as_deref())
+            ));
+        }
+        line
+    } else if let Some(s) = info.mongos.iter().find(|s| s.pid == pid) {
+        format!("{} mongos {} {}", pid, s.port, s.configdb)
+    } else if let Some((group, c)) = info.cryptd(pid) {
+        match group.parent {
+            Some(ref parent) => format!(
+                "{} mongocryptd {} spawned by {} {}",
+                pid, c.port, parent.pid, parent.program
+            ),
+            None => format!("{} mongocryptd {} stray", pid, c.port),
+        }
+    } else {
+        format!("{} shell", pid)
+    }
+}
+
+fn watch_entry(info: &MongoPSInfo, pid: i32) -> watch::Entry {
+    let (kind, port) = if let Some(target) = info.server_target(pid) {
+        let kind = if info.mongos.iter().any(|s| s.pid == pid) {
+            "mongos"
+        } else {
+            "mongod"
+        };
+        (kind, Some(target.port))
+    } else if let Some((_, c)) = info.cryptd(pid) {
+        ("mongocryptd", Some(c.port))
+    } else {
+        ("shell", None)
+    };
+    watch::Entry {
+        kind: kind.to_owned(),
+        port,
+        line: summary_line(info, pid),
+    }
+}
+
+fn list_processes(info: &MongoPSInfo, pids: &[i32]) -> Result<()> {
+    for pid in pids {
+        let proc = info
+            .proc_info(*pid)
+            .with_context(|| format!("Process {} exited", pid))?;
+        println!("{} {} {}", pid, proc.program, proc.cmdline.join(" "));
+    }
+    Ok(())
+}
+
+// Processes matched by the filters, or every mongo process if there are no filters
+fn select_pids(args: &Args, info: &MongoPSInfo) -> Vec<i32> {
+    filter_pids(args, info).unwrap_or_else(|| info.procs.iter().map(|p| p.pid).collect())
+}
+
+// Nodes in a special mode behave very differently, make sure they are not missed
+fn warn_special_modes(info: &MongoPSInfo, pids: &[i32]) {
+    for d in info.mongod.iter().filter(|d| pids.contains(&d.pid)) {
+        if !d.special_modes.is_empty() {
+            eprintln!(
+                "WARNING: {} on port {} is running in {} mode and is not a normal cluster member",
+                d.pid,
+                d.port,
+                d.special_modes.join(", ")
+            );
+        }
+    }
+}
+
+// Connection string for a single server, bypassing replica set discovery
+fn server_uri(info: &MongoPSInfo, target: &ServerTarget) -> String {
+    let mut options = vec!["directConnection=true".to_owned()];
+    options.extend(uri_options(info, target.pid));
+    format!(
+        "mongodb://{}/?{}",
+        uri_host(info, target.port),
+        options.join("&")
+    )
+}
+
+// Host of a local server in a connection string, the escaped socket path with --prefer-socket
+fn uri_host(info: &MongoPSInfo, port: i32) -> String {
+    match info.settings.unix_sockets.get(&port) {
+        Some(path) => path.replace('/', "%2F"),
+        None => format!("localhost:{}", port),
+    }
+}
+
+// TLS and authentication options a server needs in its connection string
+fn uri_options(info: &MongoPSInfo, pid: i32) -> Vec<String> {
+    let mut options = Vec::new();
+    if let Some(ref tls) = info.settings.tls {
+        options.extend(tls.options.uri_options());
+    } else if info.tls_info(pid).is_some() {
+        options.push("tls=true".to_owned());
+    }
+    if let Some(auth) = info.auth_info(pid) {
+        options.extend(auth.uri_options());
+    }
+    options
+}
+
+// A single server from the filters, asking which one when several match on a terminal
+fn pick_server(args: &Args, info: &MongoPSInfo) -> Result<ServerTarget> {
+    let targets = select_servers(args, info);
+    match targets.as_slice() {
+        [] => bail!("No servers matched"),
+        [target] => return Ok(target.clone()),
+        _ => {}
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!(
+            "{} servers matched, use the filters to pick one",
+            targets.len()
+        );
+    }
+
+    for (i, target) in targets.iter().enumerate() {
+        println!("{:>3}) {}", i + 1, summary_line(info, target.pid));
+    }
+    print!("Server [1-{}]: ", targets.len());
+    std::io::stdout().flush()?;
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+    match line.trim().parse::<usize>() {
+        Ok(n) if (1..=targets.len()).contains(&n) => Ok(targets[n - 1].clone()),
+        _ => bail!("No server picked"),
+    }
+}
+
+// Any matched mongos, they all see the same cluster
+fn first_mongos(args: &Args, info: &MongoPSInfo) -> Result<ServerTarget> {
+    select_servers(args, info)
+        .into_iter()
+        .find(|t| info.mongos.iter().any(|s| s.pid == t.pid))
+        .ok_or_else(|| anyhow!("No mongos matched"))
+}
+
+// The explicitly requested pid, or the only process matched by the filters
+fn single_pid(args: &Args, info: &MongoPSInfo, pid: Option<i32>) -> Result<i32> {
+    if let Some(pid) = pid {
+        if info.proc_info(pid).is_none() {
+            bail!("{} is not a mongo process", pid);
+        }
+        return Ok(pid);
+    }
+
+    let pids = select_pids(args, info);
+    match pids.as_slice() {
+        [pid] => Ok(*pid),
+        [] => bail!("No processes matched"),
+        _ => bail!(
+            "{} processes matched, use the filters or --pid to pick one",
+            pids.len()
+        ),
+    }
+}
+
+// Filters given as command line arguments without the program name, for callers embedding mpf;
+// commands are not run
+fn filter_args(args: &[String]) -> Result<Args> {
+    let args = Args::try_parse_from(std::iter::once("mpf".to_owned()).chain(args.iter().cloned()))
+        .map_err(|e| anyhow!("{}", e.to_string().trim()))?;
+    if args.command.is_some() {
+        bail!("Only filters are supported, not commands");
+    }
+    Ok(args)
+}
+
+// Connection settings from the arguments. This resolves the credentials, which may ask for a
+// password, so it happens once per command and not on every scan.
+fn connection_settings(args: &Args) -> Result<client::Settings> {
+    connection_settings_over(args, &client::Settings::default())
+}
+
+// Connection settings from the arguments, keeping those of base the arguments do not set
+fn connection_settings_over(args: &Args, base: &client::Settings) -> Result<client::Settings> {
+    let tls = (args.tls_ca_file.is_some()
+        || args.tls_certificate_key_file.is_some()
+        || args.tls_allow_invalid_certificates)
+        .then(|| transport::TlsOptions {
+            ca_file: args.tls_ca_file.clone(),
+            certificate_key_file: args.tls_certificate_key_file.clone(),
+            allow_invalid_certificates: args.tls_allow_invalid_certificates,
+        })
+        .map(transport::TlsSettings::new)
+        .transpose()?;
+    let credentials = credentials::resolve(
+        args.username.as_deref(),
+        args.password.as_deref(),
+        args.authentication_database.as_deref(),
+        args.profile.as_deref(),
+        args.authentication_mechanism,
+    )?;
+    Ok(client::Settings {
+        connect_timeout: args.connect_timeout.or(base.connect_timeout),
+        timeout: args.timeout.or(base.timeout),
+        tls: tls.or_else(|| base.tls.clone()),
+        credentials: credentials.or_else(|| base.credentials.clone()),
+        unix_sockets: HashMap::new(),
+        concurrency: args.concurrency.map(usize::from).or(base.concurrency),
+    })
+}
+
+// The processes with the details the arguments ask for, carrying the connection settings
+// every later connection to them uses
+fn scan(args: &Args, settings: &client::Settings) -> Result<MongoPSInfo> {
+    let mut info = scan_mongo_processes(args.verbose, settings.clone())?;
+    if args.sockets {
+        info.read_listening_ports();
+    }
+    if args.prefer_socket {
+        info.settings.unix_sockets = info.unix_sockets();
+    }
+    // Filters on the replication state imply --driver
+    let replication_filters =
+        args.lagging_more_than.is_some() || !args.role.is_empty() || !args.member_tag.is_empty();
+    if args.driver || replication_filters {
+        info.read_replication();
+    }
+    if args.driver || !args.shard_name.is_empty() {
+        info.read_shard_names();
+    }
+    Ok(info)
+}
+
+/// Run the command line, the mpf binary is just this
+pub fn run() -> Result<()> {
+    let mut args = Args::parse();
+    if args.stdin_pids {
+        args.candidate_pids = Some(read_pids(std::io::stdin().lock())?);
+    }
+
+    // println!("args: {:?}", args);
+
+    // Man pages only need the argument definitions and updates only the network, neither
+    // looks at the processes
+    if let Some(Command::Man { command, dir }) = &args.command {
+        return man::man(Args::command(), command, dir.as_deref());
+    }
+    if let Some(Command::SelfUpdate { check, force, yes }) = &args.command {
+        let current = env!("CARGO_PKG_VERSION");
+        let Some(update) = self_update::check(*force)? else {
+            println!("mpf {} is the latest release", current);
+            return Ok(());
+        };
+        println!("mpf {} is available, running {}", update.version, current);
+        if *check || !(*yes || confirm(&format!("Install {}?", update.archive))?) {
+            return Ok(());
+        }
+        let exe = self_update::install(&update)?;
+        println!("Installed mpf {} to {}", update.version, exe.display());
+        return Ok(());
+    }
+
+    if args.process_type.contains(&MongoProcess::Legacyshell) && args.port.is_some() {
+        eprintln!("ERROR: Cannot use port with legacy shell");
+        std::process::exit(1);
+    }
+
+    let settings = connection_settings(&args)?;
+    let mut info = scan(&args, &settings)?;
+    let low_disks = match args.warn_disk_free {
+        Some(min_free) => disk::low_volumes(&disk::server_paths(&info), min_free),
+        None => Vec::new(),
+    };
+    for volume in &low_disks {
+        let pids: Vec<String> = volume.pids.iter().map(|p| p.to_string()).collect();
+        eprintln!("WARNING: {}, used by {}", volume.message(), pids.join(", "));
+    }
+
+    if let Some(ref command) = args.command {
+        match command {
+            Command::Shutdown {
+                force,
+                timeout_secs,
+                json,
+                yes,
+            } => {
+                let targets = select_servers(&args, &info);
+                let pids: Vec<i32> = targets.iter().map(|t| t.pid).collect();
+                confirm_many(&info, &pids, "Shut down", *yes)?;
+                shutdown::shutdown_servers(&info.settings, &targets, *force, *timeout_secs, *json)?;
+            }
+            Command::Restart { binary, yes } => {
+                let targets = select_servers(&args, &info);
+                if targets.is_empty() {
+                    bail!("No servers matched");
+                }
+                let pids: Vec<i32> = targets.iter().map(|t| t.pid).collect();
+                confirm_many(&info, &pids, "Restart", *yes)?;
+                for target in targets {
+                    let proc = info
+                        .proc_info(target.pid)
+                        .with_context(|| format!("Process {} exited", target.pid))?;
+                    restart::restart_server(&info.settings, &target, proc, binary.as_deref())?;
+                }
+            }
+            Command::Clone {
+                port,
+                dbpath,
+                repl_set,
+            } => {
+                let pids = filter_pids(&args, &info)
+                    .unwrap_or_else(|| info.mongod.iter().map(|d| d.pid).collect::<Vec<i32>>());
+                let mongods: Vec<i32> = pids
+                    .into_iter()
+                    .filter(|pid| info.mongod.iter().any(|d| d.pid == *pid))
+                    .collect();
+                if mongods.len() != 1 {
+                    bail!(
+                        "Clone needs exactly one matching mongod, found {}",
+                        mongods.len()
+                    );
+                }
+
+                let proc = info
+                    .proc_info(mongods[0])
+                    .with_context(|| format!("Process {} exited", mongods[0]))?;
+                let options = clone::CloneOptions {
+                    port: *port,
+                    // Relative to where mpf runs, not the original's working directory
+                    dbpath: std::env::current_dir()?.join(dbpath),
+                    repl_set: repl_set.as_deref(),
+                };
+                clone::clone_server(proc, &options)?;
+            }
+            Command::Export {
+                format,
+                statsd,
+                interval,
+                prefix,
+            } => match (format, statsd) {
+                (Some(_), Some(_)) => bail!("--statsd pushes metrics and takes no export format"),
+                (None, None) => bail!("Pick an export format, or push metrics with --statsd"),
+                (None, Some(address)) => {
+                    let rescan = || {
+                        let info = scan(&args, &settings)?;
+                        let targets = select_servers(&args, &info);
+                        Ok((info, targets))
+                    };
+                    statsd::push(rescan, address, prefix, *interval)?;
+                }
+                (Some(format), None) => match format {
+                    ExportFormat::DockerCompose => {
+                        print!("{}", export::docker_compose_local(&info)?);
+                    }
+                    ExportFormat::Dot => {
+                        print!("{}", graph::Graph::new(&info).to_dot());
+                    }
+                    ExportFormat::Mermaid => {
+                        print!("{}", graph::Graph::new(&info).to_mermaid());
+                    }
+                    ExportFormat::Vscode {
+                        pid,
+                        debugger,
+                        write,
+                    } => {
+                        let pid = single_pid(&args, &info, *pid)?;
+                        let proc = info
+                            .proc_info(pid)
+                            .with_context(|| format!("Process {} exited", pid))?;
+                        let port = info.server_target(pid).map(|t| t.port);
+                        let config = export::vscode_attach_config(proc, port, debugger);
+                        match write {
+                            Some(path) => {
+                                export::write_launch_json(path, config)?;
+                                println!(
+                                    "Added attach configuration for {} to {}",
+                                    pid,
+                                    path.display()
+                                );
+                            }
+                            None => println!("{}", serde_json::to_string_pretty(&config)?),
+                        }
+                    }
+                    ExportFormat::Config { pid } => {
+                        let Some(proc) = info.proc_info(*pid) else {
+                            bail!("{} is not a mongo process", pid);
+                        };
+                        print!("{}", export::config_file(proc)?);
+                    }
+                },
+            },
+            Command::ConfigDiff { pid_a, pid_b } => {
+                let load = |pid: i32| {
+                    let Some(proc) = info.proc_info(pid) else {
+                        bail!("{} is not a mongo process", pid);
+                    };
+                    let port = info.server_target(pid).map(|t| t.port);
+                    config::effective_config(&info.settings, proc, port)
+                };
+                let diffs = config::diff_configs(&load(*pid_a)?, &load(*pid_b)?);
+                if diffs.is_empty() {
+                    println!("No differences");
+                }
+                for diff in diffs {
+                    match diff {
+                        config::ConfigDifference::OnlyLeft(path, v) => {
+                            println!("- {}: {} (only in {})", path, v, pid_a)
+                        }
+                        config::ConfigDifference::OnlyRight(path, v) => {
+                            println!("+ {}: {} (only in {})", path, v, pid_b)
+                        }
+                        config::ConfigDifference::Changed(path, a, b) => {
+                            println!("~ {}: {} -> {}", path, a, b)
+                        }
+                    }
+                }
+            }
+            Command::Tmux { mode } => {
+                let targets = select_servers(&args, &info);
+                tmux::open_panes(&info, &targets, mode)?;
+            }
+            Command::Pause { yes } => {
+                let pids = select_pids(&args, &info);
+                if pids.is_empty() {
+                    bail!("No processes matched");
+                }
+                list_processes(&info, &pids)?;
+                if !*yes && !confirm(&format!("Freeze {} processes?", pids.len()))? {
+                    bail!("Aborted");
+                }
+                signal::signal_all(&pids, libc::SIGSTOP, false)?;
+            }
+            Command::Resume => {
+                signal::signal_all(&select_pids(&args, &info), libc::SIGCONT, false)?;
+            }
+            Command::Signal {
+                signal,
+                dry_run,
+                json,
+                yes,
+            } => {
+                let pids = select_pids(&args, &info);
+                if *dry_run {
+                    list_processes(&info, &pids)?;
+                } else {
+                    confirm_many(&info, &pids, "Signal", *yes)?;
+                    signal::signal_all(&pids, *signal, *json)?;
+                }
+            }
+            Command::Report { format } => {
+                let pids = select_pids(&args, &info);
+                let sections = report::sections(&info, &pids);
+                let warnings = report::warnings(&info, &pids);
+                match format {
+                    report::ReportFormat::Markdown => {
+                        print!("{}", report::render_markdown(&sections, &warnings))
+                    }
+                    report::ReportFormat::Html => {
+                        print!("{}", report::render_html(&sections, &warnings))
+                    }
+                }
+            }
+            Command::Fingerprint { json } => {
+                let binaries = fingerprint::fingerprints(&info, &select_pids(&args, &info));
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(&binaries)?);
+                } else {
+                    fingerprint::print_fingerprints(&binaries);
+                }
+            }
+            Command::Schema => {
+                let schema = schema::json_schema(args.output_version)?;
+                println!("{}", serde_json::to_string_pretty(&schema)?);
+            }
+            Command::ShellInit { shell } => {
+                print!("{}", shell_init::shell_init(shell));
+            }
+            Command::Connect {
+                direct,
+                shell,
+                shell_args,
+            } => {
+                let target = pick_server(&args, &info)?;
+                let uri = connect::connection_uri(&info, &target, *direct);
+                connect::exec_shell(shell, &uri, shell_args)?;
+            }
+            Command::Eval {
+                snippet,
+                shell,
+                json,
+            } => {
+                let shell = match shell {
+                    Some(shell) => shell.clone(),
+                    None => connect::find_shell()?,
+                };
+                eval::eval(&info, &select_servers(&args, &info), &shell, snippet).print(*json)?;
+            }
+            Command::Info { pid } => {
+                print!("{}", describe::describe(&info, *pid)?);
+            }
+            Command::Audit => {
+                audit::print_audit(&audit::audit(&info, &select_pids(&args, &info)))?;
+            }
+            Command::Check { checks, max_skew } => {
+                let checks = if checks.is_empty() {
+                    check::Check::value_variants().to_vec()
+                } else {
+                    checks.clone()
+                };
+                // Shards are only placed in their cluster once their names are known
+                if checks.contains(&check::Check::NoFcvSkew) && !args.driver {
+                    info.read_shard_names();
+                }
+                let targets = select_servers(&args, &info);
+                let observed = check::observe(&info, &targets);
+                let results: Vec<check::CheckResult> = checks
+                    .into_iter()
+                    .map(|c| check::run_check(c, &info, &targets, &observed, *max_skew))
+                    .collect();
+                check::print_checks(&results)?;
+            }
+            Command::Doctor => {
+                let servers: Vec<doctor::Server> = select_servers(&args, &info)
+                    .into_iter()
+                    .filter_map(|target| {
+                        let d = info.mongod.iter().find(|d| d.pid == target.pid)?;
+                        Some(doctor::Server {
+                            target,
+                            dbpath: d.dbpath.clone().unwrap_or_else(|| "/data/db".to_owned()),
+                            logpath: d.logpath.clone(),
+                        })
+                    })
+                    .collect();
+                let min_free = args
+                    .warn_disk_free
+                    .unwrap_or(disk::DEFAULT_MIN_FREE_PERCENT);
+                doctor::print_doctor(&doctor::doctor(&info.settings, &servers, min_free));
+            }
+            Command::Renice { nice, yes } => {
+                let pids = select_pids(&args, &info);
+                confirm_many(&info, &pids, "Renice", *yes)?;
+                priority::renice_all(&pids, *nice)?;
+            }
+            Command::Affinity { cpus, yes } => {
+                let cpus = priority::parse_cpu_list(cpus).map_err(|e| anyhow!(e))?;
+                let pids = select_pids(&args, &info);
+                confirm_many(&info, &pids, "Pin", *yes)?;
+                priority::set_affinity_all(&pids, &cpus)?;
+            }
+            Command::ShStatus => {
+                let target = first_mongos(&args, &info)?;
+                print!("{}", sharding::sh_status(&info, &target)?);
+            }
+            Command::Chunks { ns } => {
+                let target = first_mongos(&args, &info)?;
+                print!("{}", sharding::chunks(&info, &target, ns)?);
+            }
+            Command::Connpool => {
+                connpool::print_conn_pools(&info.settings, &select_servers(&args, &info))?;
+            }
+            Command::ProfileLevel { action } => {
+                let targets = select_servers(&args, &info);
+                match action {
+                    ProfileAction::Get { db } => profiler::profile_level(
+                        &info.settings,
+                        &targets,
+                        db.as_deref(),
+                        None,
+                        None,
+                    )?,
+                    ProfileAction::Set { level, slow_ms, db } => profiler::profile_level(
+                        &info.settings,
+                        &targets,
+                        db.as_deref(),
+                        Some(*level),
+                        *slow_ms,
+                    )?,
+                }
+            }
+            Command::RunCmd { command, db, json } => {
+                let cmd = run_cmd::parse_command(command)?;
+                let targets = select_servers(&args, &info);
+                run_cmd::run_cmd(&info.settings, &targets, db, &cmd).print(*json)?;
+            }
+            Command::SetParam { params } => {
+                set_param::set_parameters(&info.settings, &select_servers(&args, &info), params)?;
+            }
+            Command::Loglevel {
+                component,
+                level,
+                duration,
+            } => {
+                let targets = select_servers(&args, &info);
+                loglevel::bump(
+                    &info.settings,
+                    &targets,
+                    component.as_deref(),
+                    *level,
+                    *duration,
+                )?;
+            }
+            Command::IndexBuilds => {
+                index_builds::print_index_builds(&info.settings, &select_servers(&args, &info))?;
+            }
+            Command::Ops { secs, kill_op } => match kill_op {
+                Some(opid) => ops::kill_op(&info.settings, &pick_server(&args, &info)?, opid)?,
+                None => ops::print_ops(&info.settings, &select_servers(&args, &info), *secs)?,
+            },
+            Command::Elections {
+                follow,
+                interval_secs,
+            } => {
+                let mut sets: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+                for target in select_servers(&args, &info) {
+                    let Some(d) = info.mongod.iter().find(|d| d.pid == target.pid) else {
+                        continue;
+                    };
+                    if let Some(ref set) = d.replica_set_name {
+                        sets.entry(set.clone()).or_default().push(target.port);
+                    }
+                }
+                elections::elections(
+                    &info.settings,
+                    &sets,
+                    *follow,
+                    Duration::from_secs(*interval_secs),
+                )?;
+            }
+            Command::Top { interval_secs } => {
+                let rescan = || {
+                    let info = scan(&args, &settings)?;
+                    let targets = select_servers(&args, &info);
+                    Ok((info, targets))
+                };
+                top::top(rescan, Duration::from_secs(*interval_secs))?;
+            }
+            Command::Stat { interval, count } => {
+                let rescan = || {
+                    let info = scan(&args, &settings)?;
+                    let targets = select_servers(&args, &info);
+                    Ok((info, targets))
+                };
+                stat::stat(rescan, *interval, *count)?;
+            }
+            Command::TopNs {
+                interval,
+                count,
+                limit,
+            } => {
+                let target = pick_server(&args, &info)?;
+                if info.mongos.iter().any(|s| s.pid == target.pid) {
+                    bail!("top-ns needs a mongod, mongos has no top command");
+                }
+                top_ns::top_ns(&info.settings, &target, *interval, *count, *limit)?;
+            }
+            Command::Slow {
+                threshold,
+                limit,
+                json,
+            } => {
+                let mut logs = Vec::new();
+                for target in select_servers(&args, &info) {
+                    match server_field(&info, target.pid, &Field::Log) {
+                        Some(log) => logs.push((target.pid, log)),
+                        None => eprintln!("{} has no log file, skipping it", target.pid),
+                    }
+                }
+                let mut queries = slow::slow_queries(&logs, *threshold)?;
+                queries.truncate(*limit);
+                if *json {
+                    println!("{}", serde_json::to_string_pretty(&queries)?);
+                } else {
+                    slow::print_slow_queries(&queries);
+                }
+            }
+            Command::Logq {
+                component,
+                severity,
+                since,
+                id,
+                json,
+            } => {
+                let filter = logq::LogFilter::new(component, *severity, *since, id);
+                for target in select_servers(&args, &info) {
+                    let Some(log) = server_field(&info, target.pid, &Field::Log) else {
+                        eprintln!("{} has no log file, skipping it", target.pid);
+                        continue;
+                    };
+                    for record in logq::read_records(&log, &filter)? {
+                        if *json {
+                            println!("{}", logq::record_json(&record, target.pid, target.port));
+                        } else {
+                            println!("{} {}", target.port, logq::render_record(&record));
+                        }
+                    }
+                }
+            }
+            Command::Logs { merge, since } => {
+                let filter = logq::LogFilter::new(&[], None, *since, &[]);
+                let mut logs = Vec::new();
+                for target in select_servers(&args, &info) {
+                    let Some(log) = server_field(&info, target.pid, &Field::Log) else {
+                        eprintln!("{} has no log file, skipping it", target.pid);
+                        continue;
+                    };
+                    let records = logq::read_records(&log, &filter)?;
+                    logs.push((logq::node_label(&info, &target), records));
+                }
+                logq::print_logs(logs, *merge)?;
+            }
+            Command::Ftdc {
+                action:
+                    FtdcAction::Export {
+                        metric,
+                        since,
+                        output,
+                        format,
+                        dir,
+                    },
+            } => {
+                let dir = match dir {
+                    Some(dir) => dir.clone(),
+                    None => {
+                        let target = pick_server(&args, &info)?;
+                        let logpath = server_field(&info, target.pid, &Field::Log);
+                        let dbpath = match info.mongod.iter().find(|d| d.pid == target.pid) {
+                            Some(d) => Some(d.dbpath.as_deref().unwrap_or("/data/db")),
+                            None => None,
+                        };
+                        ftdc::diagnostic_dir(dbpath, logpath.as_deref()).ok_or_else(|| {
+                            anyhow!("Cannot tell where {} writes FTDC, use --dir", target.pid)
+                        })?
+                    }
+                };
+                let format = format.clone().unwrap_or_else(|| {
+                    match output.as_ref().and_then(|o| o.extension()) {
+                        Some(ext) if ext == "json" => ftdc::FtdcFormat::Json,
+                        _ => ftdc::FtdcFormat::Csv,
+                    }
+                });
+                let since = since.map(|since| {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |d| d.as_millis() as i64);
+                    now - since.as_millis() as i64
+                });
+                ftdc::export(&dir, metric, since, &format, output.as_deref())?;
+            }
+            Command::Wait {
+                until_ready,
+                cluster,
+                until,
+                timeout,
+            } => {
+                if let (Some(cluster), Some(until)) = (cluster, until) {
+                    wait::until_cluster(&info, cluster, *until, *timeout)?;
+                } else if *until_ready {
+                    wait::until_ready(&info, &select_servers(&args, &info), *timeout)?;
+                } else {
+                    bail!("Pick what to wait for, e.g. --until-ready or --cluster rs0 --until primary");
+                }
+            }
+            // Handled before scanning, see above
+            Command::Man { .. } | Command::SelfUpdate { .. } => {}
+            Command::Rpc => rpc::serve(settings)?,
+            Command::Grpc { listen } => grpc::serve(settings, *listen)?,
+            Command::Watch {
+                interval_secs,
+                changes_only,
+                output,
+            } => {
+                let rescan = || {
+                    let info = scan(&args, &settings)?;
+                    Ok(select_pids(&args, &info)
+                        .into_iter()
+                        .map(|pid| (pid, watch_entry(&info, pid)))
+                        .collect())
+                };
+                watch::watch(
+                    rescan,
+                    Duration::from_secs(*interval_secs),
+                    *changes_only,
+                    output,
+                )?;
+            }
+            Command::Nuke { yes, delete_data } => {
+                nuke::nuke(&info, &select_pids(&args, &info), *yes, *delete_data)?;
+            }
+            Command::Start {
+                topology,
+                bin_dir,
+                dir,
+                port,
+                repl_set,
+                nodes,
+                shards,
+            } => {
+                let options = start::StartOptions {
+                    topology: topology.clone(),
+                    bin_dir: bin_dir.clone(),
+                    dir: dir.clone(),
+                    port: *port,
+                    repl_set: repl_set.clone(),
+                    nodes: *nodes,
+                    shards: *shards,
+                };
+                let state = start::start_topology(&settings, &options)?;
+                println!(
+                    "Started {} processes, recorded in {}",
+                    state.processes.len(),
+                    dir.join(start::STATE_FILE).display()
+                );
+            }
+            Command::Stop { dir } => start::stop_topology(&info, dir)?,
+        }
+        return check_disk_free(&low_disks);
+    }
+
+    let pids = filter_pids(&args, &info);
+    if args.uri || args.copy || args.field.is_some() {
+        let pids = select_pids(&args, &info);
+        let lines: Vec<String> = if let Some(ref field) = args.field {
+            pids.iter()
+                .filter_map(|pid| server_field(&info, *pid, field))
+                .collect()
+        } else if args.uri {
+            pids.iter()
+                .filter_map(|pid| info.server_target(*pid))
+                .map(|t| server_uri(&info, &t))
+                .collect()
+        } else {
+            pids.iter().map(|pid| pid.to_string()).collect()
+        };
+
+        for line in &lines {
+            println!("{}", line);
+        }
+        if args.copy {
+            match lines.as_slice() {
+                [line] => clipboard::copy_to_clipboard(line)?,
+                _ => bail!("--copy needs exactly one match, found {}", lines.len()),
+            }
+        }
+    } else if let Some(pids) = pids {
+        for pid in &pids {
+            let mut line = if args.stdin_pids {
+                summary_line(&info, *pid)
+            } else {
+                pid.to_string()
+            };
+            if let Some(verdict) = args
+                .filter_script
+                .as_ref()
+                .and_then(|script| script_verdict(&info, script, *pid))
+            {
+                for (name, value) in &verdict.annotations {
+                    let value = if value.is_null() {
+                        "-".to_owned()
+                    } else {
+                        script::display(value)
+                    };
+                    line.push_str(&format!(" {}={}", name, value));
+                }
+            }
+            println!("{}", line);
+        }
+        warn_special_modes(&info, &pids);
+    } else {
+        // If there were no filters, dump all the process info as json. Classifier plugins only
+        // run here, not on every scan.
+        info.plugin_processes = plugins::classify(
+            get_procs()?
+                .iter()
+                .filter(|p| is_mongo_process(p).is_none()),
+        );
+        let summary = schema::to_output_version(serde_json::to_value(&info)?, args.output_version)?;
+        let bytes = output::encode(&summary, &args.output)?;
+        match args.output_file {
+            Some(ref path) => output::write_atomic(path, &bytes)?,
+            None => {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(&bytes)?;
+                stdout.flush()?;
+            }
+        }
+        if let Some(warning) = storage::cache_warning(&info) {
+            eprintln!("{}", warning);
+        }
+        warn_special_modes(
+            &info,
+            &info.mongod.iter().map(|d| d.pid).collect::<Vec<i32>>(),
+        );
+    }
+
+    check_disk_free(&low_disks)
+}
+
+// Fail after the command ran when --warn-disk-free found a filesystem short on space
+fn check_disk_free(low_disks: &[disk::LowVolume]) -> Result<()> {
+    if !low_disks.is_empty() {
+        bail!(
+            "{} filesystem(s) below the free space threshold",
+            low_disks.len()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_cmd_opts() {
+    let opts1 = vec!["foo".to_owned()];
+    assert_eq!(get_cmd_line_option("--port", &opts1), None);
+
+    let opts2 = vec!["--port".to_owned(), "20000".to_owned()];
+    assert_eq!(
+        get_cmd_line_option("--port", &opts2),
+        Some("20000".to_owned())
+    );
+
+    let opts2a = vec!["--port".to_owned()];
+    assert_eq!(get_cmd_line_option("--port", &opts2a), None);
+
+    let opts3 = vec!["--port=20000".to_owned()];
+    assert_eq!(
+        get_cmd_line_option("--port", &opts3),
+        Some("20000".to_owned())
+    );
+
+    let opts3a = vec!["--ports=20000".to_owned()];
+    assert_eq!(get_cmd_line_option("--port", &opts3a), None);
+}
+
+#[test]
+fn test_cmd_flags() {
+    let opts = vec![
+        "--replSet".to_owned(),
+        "rs0".to_owned(),
+        "--configsvr".to_owned(),
+    ];
+    assert!(has_cmd_line_flag("--configsvr", &opts));
+    assert!(!has_cmd_line_flag("--shardsvr", &opts));
+
+    let opts2 = vec!["--shardsvr=false".to_owned()];
+    assert!(!has_cmd_line_flag("--shardsvr", &opts2));
+}
+
+#[test]
+fn test_set_cmd_opts() {
+    let mut opts1 = vec!["--port".to_owned(), "20000".to_owned()];
+    set_cmd_line_option(&mut opts1, "--port", "20001");
+    assert_eq!(opts1, vec!["--port", "20001"]);
+
+    let mut opts2 = vec!["--port=20000".to_owned(), "--ports=1".to_owned()];
+    set_cmd_line_option(&mut opts2, "--port", "20001");
+    assert_eq!(opts2, vec!["--port=20001", "--ports=1"]);
+
+    let mut opts3 = vec!["--ports=1".to_owned()];
+    set_cmd_line_option(&mut opts3, "--port", "20001");
+    assert_eq!(opts3, vec!["--ports=1", "--port", "20001"]);
+}
+
+#[test]
+fn test_has_parameter() {
+    let (config, _) = config::cmdline_to_config(&[
+        "--setParameter".to_owned(),
+        "enableTestCommands=1".to_owned(),
+        "--setParameter=featureFlagFoo=true".to_owned(),
+        "--setParameter".to_owned(),
+        "logComponentVerbosity={replication: 2}".to_owned(),
+    ]);
+    let params = get_set_parameters(Some(&config));
+    assert_eq!(params.len(), 3);
+
+    assert!(has_parameter(&params, "enableTestCommands"));
+    assert!(has_parameter(&params, "enableTestCommands=1"));
+    assert!(has_parameter(&params, "enableTestCommands=true"));
+    assert!(has_parameter(&params, "featureFlagFoo=1"));
+    assert!(!has_parameter(&params, "featureFlagFoo=false"));
+    assert!(has_parameter(
+        &params,
+        "logComponentVerbosity={replication: 2}"
+    ));
+    assert!(!has_parameter(&params, "bogus"));
+    assert!(is_test_instance(&params));
+
+    let (config, _) = config::cmdline_to_config(&[
+        "--setParameter".to_owned(),
+        "enableTestCommands=0".to_owned(),
+        "--setParameter".to_owned(),
+        "failpointsEnabled=1".to_owned(),
+    ]);
+    assert!(!is_test_instance(&get_set_parameters(Some(&config))));
+}
+
+#[test]
+fn test_special_modes() {
+    let cmdline: Vec<String> = ["mongod", "--queryableBackupMode", "--repair=false"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let mut params = Map::new();
+    params.insert("recoverFromOplogAsStandalone".to_owned(), Value::Bool(true));
+    assert_eq!(
+        get_special_modes(&cmdline, &params),
+        vec!["queryableBackupMode", "recoverFromOplogAsStandalone"]
+    );
+}
+
+#[test]
+fn test_parse_type_exclusion() {
+    assert_eq!(
+        parse_type_exclusion("mongos"),
+        Ok(TypeExclusion::Process(MongoProcess::Mongos))
+    );
+    assert_eq!(
+        parse_type_exclusion("config"),
+        Ok(TypeExclusion::Server(MongoDType::Config))
+    );
+    assert!(parse_type_exclusion("bogus").is_err());
+}
+
+#[test]
+fn test_parse_age() {
+    assert_eq!(parse_age("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_age("5m"), Ok(Duration::from_secs(300)));
+    assert_eq!(parse_age("2h"), Ok(Duration::from_secs(7200)));
+    assert_eq!(parse_age("1d"), Ok(Duration::from_secs(86400)));
+    assert_eq!(parse_age("250ms"), Ok(Duration::from_millis(250)));
+    assert!(parse_age("5w").is_err());
+    assert!(parse_age("m").is_err());
+}
+
+#[test]
+fn test_lagging_more_than() {
+    let lag = |flag: &str| {
+        filter_args(&[flag.to_owned(), "10s".to_owned()])
+            .unwrap()
+            .lagging_more_than
+    };
+    assert_eq!(lag("--lagging-more-than"), Some(Duration::from_secs(10)));
+    assert_eq!(lag("--max-lag"), Some(Duration::from_secs(10)));
+}
+
+#[test]
+fn test_parse_port_set() {
+    let ports = parse_port_set("20020-20030,27017").unwrap();
+    assert!(ports.contains(20020));
+    assert!(ports.contains(20025));
+    assert!(ports.contains(27017));
+    assert!(!ports.contains(20031));
+
+    assert_eq!(parse_port_set("20000"), Ok(PortSet(vec![(20000, 20000)])));
+    assert!(parse_port_set("20030-20020").is_err());
+    assert!(parse_port_set("abc").is_err());
+}
+
+#[test]
+fn test_read_pids() {
+    let input = "  PID TTY\n 1234 pts/0\n5678\n91,92\n";
+    assert_eq!(
+        read_pids(input.as_bytes()).unwrap(),
+        vec![1234, 5678, 91, 92]
+    );
+}
+
+#[test]
+fn test_server_port() {
+    let dir = std::env::temp_dir().join(format!("mpf-port-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("mongod.conf"), "net:\n  port: 28017\n").unwrap();
+
+    let proc = |args: &[&str]| CommonProcInfo {
+        pid: 1,
+        ppid: None,
+        program: "mongod".to_owned(),
+        cmdline: std::iter::once("mongod")
+            .chain(args.iter().copied())
+            .map(str::to_owned)
+            .collect(),
+        env: Default::default(),
+        cwd: Some(dir.clone()),
+        exe: None,
+        uid: None,
+        start_time: None,
+        rss: None,
+    };
+    let port = |args: &[&str]| {
+        let p = proc(args);
+        server_port(&p, config::local_effective_config(&p).ok().as_ref())
+    };
+
+    assert_eq!(port(&[]).unwrap(), 27017);
+    assert_eq!(port(&["--port", "20020"]).unwrap(), 20020);
+    assert_eq!(port(&["--config", "mongod.conf"]).unwrap(), 28017);
+    assert_eq!(
+        port(&["--config", "mongod.conf", "--port=20021"]).unwrap(),
+        20021
+    );
+    assert!(port(&["--port", "abc"]).is_err());
+    assert!(port(&["--port", "70000"]).is_err());
+    // Without a readable config file the command line still counts
+    assert_eq!(
+        port(&["-f", "missing.conf", "--port", "20022"]).unwrap(),
+        20022
+    );
+    // Shard and config servers started without --port listen on their own default
+    assert_eq!(port(&["--shardsvr", "--replSet", "sh0"]).unwrap(), 27018);
+    assert_eq!(port(&["--configsvr", "--replSet", "cfg"]).unwrap(), 27019);
+    assert_eq!(port(&["-f", "missing.conf", "--configsvr"]).unwrap(), 27019);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_readme_usage() {
+    // Regenerate with: mpf --help, which wraps at 100 columns when not on a terminal
+    let usage = Args::command()
+        .term_width(100)
+        .render_long_help()
+        .to_string();
+    let readme = include_str!("../README.md");
+    assert!(
+        readme.contains(&format!("```\n{}```", usage)),
+        "The usage block of README.md is not the output of mpf --help"
+    );
+}
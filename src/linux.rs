@@ -12,7 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use anyhow::Result;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use procfs::net::TcpState;
+use procfs::process::FDTarget;
 
 use super::types::CommonProcInfo;
 
@@ -20,12 +26,27 @@ pub fn get_procs() -> Result<Vec<CommonProcInfo>> {
     let mut procs = Vec::<CommonProcInfo>::new();
 
     for prc in procfs::process::all_processes()? {
-        let prc = prc?;
+        // Processes can exit while we are enumerating them
+        let Ok(prc) = prc else {
+            continue;
+        };
+        let Ok(stat) = prc.stat() else {
+            continue;
+        };
+        let start_time = procfs::boot_time_secs()
+            .ok()
+            .map(|boot| boot + stat.starttime / procfs::ticks_per_second());
         let cp = CommonProcInfo {
             pid: prc.pid,
-            program: prc.stat().unwrap().comm.clone(), // TODO - avoid unwrap
+            ppid: Some(stat.ppid),
+            program: stat.comm,
             cmdline: prc.cmdline().unwrap_or_default(),
-            // env : prc.environ().unwrap_or_default(),
+            env: prc.environ().unwrap_or_default(),
+            cwd: prc.cwd().ok(),
+            exe: prc.exe().ok(),
+            uid: prc.uid().ok(),
+            start_time,
+            rss: Some(stat.rss * procfs::page_size()),
         };
 
         procs.push(cp);
@@ -33,3 +54,157 @@ pub fn get_procs() -> Result<Vec<CommonProcInfo>> {
 
     Ok(procs)
 }
+
+// Priority and affinity are per thread on Linux so every thread of the process is updated
+
+fn thread_ids(pid: i32) -> Result<Vec<i32>> {
+    let prc = procfs::process::Process::new(pid)?;
+    let mut tids = Vec::new();
+    for task in prc.tasks()? {
+        tids.push(task?.tid);
+    }
+    Ok(tids)
+}
+
+pub fn set_nice(pid: i32, nice: i32) -> Result<()> {
+    for tid in thread_ids(pid)? {
+        let ret = unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, nice) };
+        if ret != 0 {
+            bail!(
+                "Failed to set priority of thread {}: {}",
+                tid,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn set_affinity(pid: i32, cpus: &[usize]) -> Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for cpu in cpus {
+        unsafe { libc::CPU_SET(*cpu, &mut set) };
+    }
+
+    for tid in thread_ids(pid)? {
+        let ret =
+            unsafe { libc::sched_setaffinity(tid, std::mem::size_of::<libc::cpu_set_t>(), &set) };
+        if ret != 0 {
+            bail!(
+                "Failed to set affinity of thread {}: {}",
+                tid,
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// User plus system CPU time the process has used
+pub fn cpu_time(pid: i32) -> Result<Duration> {
+    let stat = procfs::process::Process::new(pid)?.stat()?;
+    let ticks = stat.utime + stat.stime;
+    Ok(Duration::from_millis(
+        ticks * 1000 / procfs::ticks_per_second(),
+    ))
+}
+
+/// Processes with a TCP connection to a local port, from the socket table and their file
+/// descriptors
+pub fn connected_pids(port: u16) -> Result<Vec<i32>> {
+    let inodes: Vec<u64> = procfs::net::tcp()?
+        .into_iter()
+        .chain(procfs::net::tcp6().unwrap_or_default())
+        .filter(|entry| entry.state == TcpState::Established && entry.remote_address.port() == port)
+        .map(|entry| entry.inode)
+        .collect();
+    if inodes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut pids = Vec::new();
+    for prc in procfs::process::all_processes()?.flatten() {
+        let Ok(mut fds) = prc.fd() else {
+            continue;
+        };
+        if fds.any(|fd| {
+            matches!(fd.map(|fd| fd.target), Ok(FDTarget::Socket(inode)) if inodes.contains(&inode))
+        }) {
+            pids.push(prc.pid);
+        }
+    }
+    Ok(pids)
+}
+
+/// Processes listening on a local TCP port with their program names, from the socket table and
+/// their file descriptors
+pub fn listening_processes(port: u16) -> Result<Vec<(i32, String)>> {
+    let inodes: Vec<u64> = procfs::net::tcp()?
+        .into_iter()
+        .chain(procfs::net::tcp6().unwrap_or_default())
+        .filter(|entry| entry.state == TcpState::Listen && entry.local_address.port() == port)
+        .map(|entry| entry.inode)
+        .collect();
+    if inodes.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut found = Vec::new();
+    for prc in procfs::process::all_processes()?.flatten() {
+        let Ok(mut fds) = prc.fd() else {
+            continue;
+        };
+        if fds.any(|fd| {
+            matches!(fd.map(|fd| fd.target), Ok(FDTarget::Socket(inode)) if inodes.contains(&inode))
+        }) {
+            let program = prc.stat().map(|s| s.comm).unwrap_or_default();
+            found.push((prc.pid, program));
+        }
+    }
+    Ok(found)
+}
+
+/// Ports of the TCP sockets the process is listening on, from the socket table
+pub fn listening_ports(pid: i32) -> Result<Vec<u16>> {
+    let prc = procfs::process::Process::new(pid)?;
+    let inodes: Vec<u64> = prc
+        .fd()?
+        .filter_map(|fd| match fd.ok()?.target {
+            FDTarget::Socket(inode) => Some(inode),
+            _ => None,
+        })
+        .collect();
+
+    let mut ports: Vec<u16> = procfs::net::tcp()?
+        .into_iter()
+        .chain(procfs::net::tcp6().unwrap_or_default())
+        .filter(|entry| entry.state == TcpState::Listen && inodes.contains(&entry.inode))
+        .map(|entry| entry.local_address.port())
+        .collect();
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+/// Soft limits on open files and processes, None when unlimited
+pub fn soft_limits(pid: i32) -> Result<(Option<u64>, Option<u64>)> {
+    let limits = procfs::process::Process::new(pid)?.limits()?;
+    let soft = |limit: procfs::process::Limit| match limit.soft_limit {
+        procfs::process::LimitValue::Value(v) => Some(v),
+        procfs::process::LimitValue::Unlimited => None,
+    };
+    Ok((soft(limits.max_open_files), soft(limits.max_processes)))
+}
+
+/// Type of the filesystem holding a path, from the mount table the process sees
+pub fn filesystem_type(pid: i32, path: &Path) -> Result<String> {
+    let mounts = procfs::process::Process::new(pid)?.mountinfo()?;
+    match mounts
+        .into_iter()
+        .filter(|m| path.starts_with(&m.mount_point))
+        .max_by_key(|m| m.mount_point.as_os_str().len())
+    {
+        Some(mount) => Ok(mount.fs_type),
+        None => bail!("No mount point found for {}", path.display()),
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Raise the log verbosity of servers for a while and put back what they had before
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use serde_json::{json, Map, Value};
+
+use super::client::Settings;
+use super::run_cmd::run_all;
+use super::ServerTarget;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// setParameter command setting the verbosity of a component like replication.election, or the
+/// global logLevel without one
+pub fn set_command(component: Option<&str>, level: i64) -> Value {
+    let Some(component) = component else {
+        return json!({"setParameter": 1, "logLevel": level});
+    };
+    let mut verbosity = json!({ "verbosity": level });
+    for part in component.rsplit('.') {
+        let mut map = Map::new();
+        map.insert(part.to_owned(), verbosity);
+        verbosity = Value::Object(map);
+    }
+    json!({"setParameter": 1, "logComponentVerbosity": verbosity})
+}
+
+fn get_command(component: Option<&str>) -> Value {
+    match component {
+        Some(_) => json!({"getParameter": 1, "logComponentVerbosity": 1}),
+        None => json!({"getParameter": 1, "logLevel": 1}),
+    }
+}
+
+/// The verbosity in a getParameter reply, -1 for a component inheriting its parent's
+pub fn current_level(reply: &Value, component: Option<&str>) -> Option<i64> {
+    let Some(component) = component else {
+        return reply.get("logLevel")?.as_i64();
+    };
+    let mut value = reply.get("logComponentVerbosity")?;
+    for part in component.split('.') {
+        value = value.get(part)?;
+    }
+    value.get("verbosity")?.as_i64()
+}
+
+fn describe(component: Option<&str>) -> String {
+    match component {
+        Some(component) => format!("{} verbosity", component),
+        None => "logLevel".to_owned(),
+    }
+}
+
+// Wait until the time is up or Ctrl-C, returning true when interrupted
+fn wait(duration: Duration) -> bool {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    let handler = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    let previous_int = unsafe { libc::signal(libc::SIGINT, handler) };
+    let previous_term = unsafe { libc::signal(libc::SIGTERM, handler) };
+
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline && !INTERRUPTED.load(Ordering::SeqCst) {
+        std::thread::sleep(Duration::from_millis(100).min(deadline - Instant::now()));
+    }
+
+    unsafe {
+        libc::signal(libc::SIGINT, previous_int);
+        libc::signal(libc::SIGTERM, previous_term);
+    }
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Set the verbosity on every target, and with a duration wait and restore the previous level
+pub fn bump(
+    settings: &Settings,
+    targets: &[ServerTarget],
+    component: Option<&str>,
+    level: i64,
+    duration: Option<Duration>,
+) -> Result<()> {
+    let what = describe(component);
+    let previous: Vec<Option<i64>> = run_all(settings, targets, "admin", &get_command(component))
+        .into_iter()
+        .map(|reply| current_level(&reply.ok()?, component))
+        .collect();
+
+    let mut changed = Vec::new();
+    let mut failures = 0;
+    let set = run_all(settings, targets, "admin", &set_command(component, level));
+    for ((t, result), was) in targets.iter().zip(set).zip(&previous) {
+        match (result, was) {
+            (Ok(_), Some(was)) => {
+                println!(
+                    "{} port {}: {} {} (was {})",
+                    t.pid, t.port, what, level, was
+                );
+                changed.push((t.clone(), *was));
+            }
+            (Ok(_), None) => {
+                eprintln!(
+                    "{} port {}: {} {}, previous level unknown and will not be restored",
+                    t.pid, t.port, what, level
+                );
+                failures += 1;
+            }
+            (Err(e), _) => {
+                eprintln!("{} port {}: {}", t.pid, t.port, e);
+                failures += 1;
+            }
+        }
+    }
+
+    if let Some(duration) = duration.filter(|_| !changed.is_empty()) {
+        if wait(duration) {
+            println!("Interrupted, restoring");
+        }
+        for (t, was) in &changed {
+            let restore = std::slice::from_ref(t);
+            match run_all(settings, restore, "admin", &set_command(component, *was)).remove(0) {
+                Ok(_) => println!("{} port {}: restored {} {}", t.pid, t.port, what, was),
+                Err(e) => {
+                    eprintln!(
+                        "{} port {}: failed to restore {}: {}",
+                        t.pid, t.port, what, e
+                    );
+                    failures += 1;
+                }
+            }
+        }
+    }
+
+    if failures > 0 {
+        bail!("Failed on {} of {} server(s)", failures, targets.len());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_set_command() {
+    assert_eq!(
+        set_command(None, 2),
+        json!({"setParameter": 1, "logLevel": 2})
+    );
+    assert_eq!(
+        set_command(Some("replication.election"), 3),
+        json!({"setParameter": 1, "logComponentVerbosity": {"replication": {"election": {"verbosity": 3}}}})
+    );
+
+    let reply = json!({"logComponentVerbosity": {
+        "verbosity": 0,
+        "replication": {"verbosity": -1, "election": {"verbosity": 2}},
+    }});
+    assert_eq!(current_level(&reply, Some("replication")), Some(-1));
+    assert_eq!(current_level(&reply, Some("replication.election")), Some(2));
+    assert_eq!(current_level(&json!({"logLevel": 1}), None), Some(1));
+    assert_eq!(current_level(&reply, Some("storage")), None);
+}
@@ -0,0 +1,305 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Query the structured JSON logs of servers (4.4 and later) by component, severity, message id
+// and age instead of grepping mongod.log
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, IsTerminal, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde_json::{Map, Value};
+
+use super::tls::days_from_civil;
+use super::watch::paint;
+use super::{MongoPSInfo, ServerTarget};
+
+// Most severe first, debug levels D1 to D5 follow I
+const SEVERITIES: &[&str] = &["F", "E", "W", "I", "D1", "D2", "D3", "D4", "D5"];
+
+// ANSI colors of the node labels in merged logs, cycled through
+const NODE_COLORS: &[&str] = &["36", "33", "35", "32", "34", "31"];
+
+/// Rank of a severity, lower is more severe. D alone is D1, as older servers write it.
+pub fn parse_severity(s: &str) -> Result<usize, String> {
+    let upper = s.to_ascii_uppercase();
+    let name = if upper == "D" { "D1" } else { upper.as_str() };
+    SEVERITIES
+        .iter()
+        .position(|n| *n == name)
+        .ok_or_else(|| format!("Unknown severity {}, use F, E, W, I or D1-D5", s))
+}
+
+/// Milliseconds since the epoch of a log timestamp like 2024-05-01T12:00:00.123+02:00
+pub fn parse_log_date(text: &str) -> Option<i64> {
+    let field = |range: std::ops::Range<usize>| -> Option<i64> { text.get(range)?.parse().ok() };
+    let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+    let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+
+    let mut rest = text.get(19..)?;
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(fraction.len());
+        let padded = format!("{:0<3}", &fraction[..digits]);
+        millis = padded.get(0..3)?.parse().ok()?;
+        rest = &fraction[digits..];
+    }
+    let offset_minutes = match rest {
+        "" | "Z" => 0,
+        _ => {
+            let sign = match rest.chars().next()? {
+                '+' => 1,
+                '-' => -1,
+                _ => return None,
+            };
+            let zone = rest[1..].replace(':', "");
+            let hours: i64 = zone.get(0..2)?.parse().ok()?;
+            let minutes: i64 = zone.get(2..4).map_or(Some(0), |m| m.parse().ok())?;
+            sign * (hours * 60 + minutes)
+        }
+    };
+
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second
+        - offset_minutes * 60;
+    Some(secs * 1000 + millis)
+}
+
+/// One line of a structured log
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    /// Milliseconds since the epoch
+    pub time: i64,
+    pub severity: usize,
+    pub component: String,
+    pub id: Option<i64>,
+    /// The whole entry as the server wrote it
+    pub entry: Value,
+}
+
+/// Parse a log line, None for lines of the old text format or anything else that is not JSON
+pub fn parse_record(line: &str) -> Option<LogRecord> {
+    if !line.starts_with('{') {
+        return None;
+    }
+    let entry: Value = serde_json::from_str(line).ok()?;
+    let time = parse_log_date(entry.get("t")?.get("$date")?.as_str()?)?;
+    let severity = parse_severity(entry.get("s")?.as_str()?).ok()?;
+    let component = entry.get("c")?.as_str()?.to_owned();
+    Some(LogRecord {
+        time,
+        severity,
+        component,
+        id: entry.get("id").and_then(Value::as_i64),
+        entry,
+    })
+}
+
+/// What to keep, an empty list keeps everything
+#[derive(Debug, Default)]
+pub struct LogFilter {
+    pub components: Vec<String>,
+    /// Keep this severity and the more severe ones
+    pub severity: Option<usize>,
+    /// Keep records from this time on, in milliseconds since the epoch
+    pub since: Option<i64>,
+    pub ids: Vec<i64>,
+}
+
+impl LogFilter {
+    /// since is counted back from now
+    pub fn new(
+        components: &[String],
+        severity: Option<usize>,
+        since: Option<Duration>,
+        ids: &[i64],
+    ) -> LogFilter {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64);
+        LogFilter {
+            components: components.to_vec(),
+            severity,
+            since: since.map(|since| now - since.as_millis() as i64),
+            ids: ids.to_vec(),
+        }
+    }
+
+    pub fn matches(&self, record: &LogRecord) -> bool {
+        (self.components.is_empty()
+            || self
+                .components
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(&record.component)))
+            && self.severity.is_none_or(|s| record.severity <= s)
+            && self.since.is_none_or(|since| record.time >= since)
+            && (self.ids.is_empty() || record.id.is_some_and(|id| self.ids.contains(&id)))
+    }
+}
+
+/// The records of a log file that match the filter, in the order they were written
+pub fn read_records(path: &str, filter: &LogFilter) -> Result<Vec<LogRecord>> {
+    let file =
+        File::open(path).map_err(|e| anyhow::anyhow!("Failed to open log {}: {}", path, e))?;
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Some(record) = parse_record(&line?) {
+            if filter.matches(&record) {
+                records.push(record);
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// A record on one line: time, severity, component, id, context, message and attributes
+pub fn render_record(record: &LogRecord) -> String {
+    let field = |name: &str| record.entry.get(name).and_then(Value::as_str).unwrap_or("");
+    let time = record
+        .entry
+        .get("t")
+        .and_then(|t| t.get("$date"))
+        .and_then(Value::as_str)
+        .unwrap_or("");
+    let mut line = format!(
+        "{} {:<2} {:<8} {:<8} [{}] {}",
+        time,
+        SEVERITIES[record.severity],
+        record.component,
+        record.id.map_or("-".to_owned(), |id| id.to_string()),
+        field("ctx"),
+        field("msg")
+    );
+    if let Some(attr) = record.entry.get("attr") {
+        line.push(' ');
+        line.push_str(&attr.to_string());
+    }
+    line
+}
+
+/// The records of several logs ordered by time, each with the index of its log. Records with
+/// the same time keep the order they were written in.
+pub fn merge(logs: Vec<Vec<LogRecord>>) -> Vec<(usize, LogRecord)> {
+    let mut merged: Vec<(usize, LogRecord)> = logs
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, records)| records.into_iter().map(move |r| (i, r)))
+        .collect();
+    merged.sort_by_key(|(_, r)| r.time);
+    merged
+}
+
+/// Replica set name or program with the port, to tell the nodes of a merged log apart
+pub fn node_label(info: &MongoPSInfo, target: &ServerTarget) -> String {
+    let name = match info.mongod.iter().find(|d| d.pid == target.pid) {
+        Some(d) => d.replica_set_name.as_deref().unwrap_or("mongod"),
+        None => "mongos",
+    };
+    format!("{}:{}", name, target.port)
+}
+
+/// Print the logs one after another, or interleaved by time with merge, each line prefixed
+/// with the label of its node in a color of its own on a terminal
+pub fn print_logs(logs: Vec<(String, Vec<LogRecord>)>, merge_logs: bool) -> Result<()> {
+    let color = std::io::stdout().is_terminal();
+    let width = logs.iter().map(|(label, _)| label.len()).max().unwrap_or(0);
+    let (labels, records): (Vec<String>, Vec<Vec<LogRecord>>) = logs.into_iter().unzip();
+    let lines: Vec<(usize, LogRecord)> = if merge_logs {
+        merge(records)
+    } else {
+        records
+            .into_iter()
+            .enumerate()
+            .flat_map(|(i, records)| records.into_iter().map(move |r| (i, r)))
+            .collect()
+    };
+    let mut out = std::io::stdout().lock();
+    for (i, record) in lines {
+        let label = format!("{:<width$}", labels[i], width = width);
+        let label = paint(label, NODE_COLORS[i % NODE_COLORS.len()], color);
+        // Stop quietly when piped into head or a pager that quit
+        match writeln!(out, "{} | {}", label, render_record(&record)) {
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+/// The entry with the pid and port of its server in front
+pub fn record_json(record: &LogRecord, pid: i32, port: i32) -> Value {
+    let mut entry = Map::new();
+    entry.insert("pid".to_owned(), Value::from(pid));
+    entry.insert("port".to_owned(), Value::from(port));
+    if let Value::Object(fields) = &record.entry {
+        entry.extend(fields.clone());
+    }
+    Value::Object(entry)
+}
+
+#[test]
+fn test_log_filter() {
+    assert_eq!(
+        parse_log_date("2024-05-01T12:00:00.123+02:00"),
+        parse_log_date("2024-05-01T10:00:00.123Z")
+    );
+    assert_eq!(parse_log_date("1970-01-01T00:00:01.5Z"), Some(1500));
+    assert_eq!(parse_log_date("2024-05-01 garbage"), None);
+    assert_eq!(parse_severity("w"), Ok(2));
+    assert_eq!(parse_severity("D"), parse_severity("D1"));
+
+    let line = r#"{"t":{"$date":"2024-05-01T12:00:00.000+00:00"},"s":"W","c":"COMMAND","id":20525,"ctx":"conn3","msg":"Failed to gather storage statistics","attr":{"error":"Interrupted"}}"#;
+    let record = parse_record(line).unwrap();
+    assert_eq!(
+        render_record(&record),
+        r#"2024-05-01T12:00:00.000+00:00 W  COMMAND  20525    [conn3] Failed to gather storage statistics {"error":"Interrupted"}"#
+    );
+    assert!(
+        parse_record("2019-01-01T00:00:00.000+0000 I NETWORK  [conn1] end connection").is_none()
+    );
+
+    let filter = |components: &[&str], severity: &str, ids: &[i64]| LogFilter {
+        components: components.iter().map(|c| c.to_string()).collect(),
+        severity: Some(parse_severity(severity).unwrap()),
+        since: None,
+        ids: ids.to_vec(),
+    };
+    assert!(filter(&["command"], "W", &[]).matches(&record));
+    assert!(!filter(&[], "E", &[]).matches(&record));
+    assert!(!filter(&["REPL"], "I", &[]).matches(&record));
+    assert!(filter(&[], "I", &[20525]).matches(&record));
+    assert!(!filter(&[], "I", &[1]).matches(&record));
+    let since = LogFilter {
+        since: Some(record.time + 1),
+        ..LogFilter::default()
+    };
+    assert!(!since.matches(&record));
+
+    let at = |time: i64, msg: &str| LogRecord {
+        time,
+        entry: serde_json::json!({ "msg": msg }),
+        ..record.clone()
+    };
+    let merged = merge(vec![
+        vec![at(1, "a1"), at(5, "a5")],
+        vec![at(3, "b3"), at(5, "b5")],
+    ]);
+    let order: Vec<(usize, &str)> = merged
+        .iter()
+        .map(|(i, r)| (*i, r.entry["msg"].as_str().unwrap()))
+        .collect();
+    assert_eq!(order, [(0, "a1"), (1, "b3"), (0, "a5"), (1, "b5")]);
+}
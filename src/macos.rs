@@ -1,11 +1,17 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 extern crate libproc;
-use libproc::libproc::proc_pid::{self};
+use libproc::libproc::bsd_info::BSDInfo;
+use libproc::libproc::file_info::{pidfdinfo, ListFDs, ProcFDType};
+use libproc::libproc::net_info::{SocketFDInfo, SocketInfoKind, TcpSIState};
+use libproc::libproc::proc_pid::{self, listpidinfo, pidinfo};
+use libproc::libproc::task_info::TaskInfo;
 
 use libc::{c_int, c_void, size_t};
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use super::types::CommonProcInfo;
 
@@ -18,11 +24,25 @@ pub fn get_procs() -> Result<Vec<CommonProcInfo>> {
         for p in pids {
             let pio = get_path_info(p as i32, arg_max);
             if let Some(pi) = pio {
+                let bsd = pidinfo::<BSDInfo>(p as i32, 0).ok();
                 let cp = CommonProcInfo {
                     pid: p as i32,
+                    ppid: bsd.as_ref().map(|b| b.pbi_ppid as i32),
                     program: pi.name,
                     cmdline: pi.cmd,
-                    // env : prc.environ().unwrap_or_default(),
+                    env: pi
+                        .env
+                        .iter()
+                        .filter_map(|e| e.split_once('='))
+                        .map(|(k, v)| (OsString::from(k), OsString::from(v)))
+                        .collect(),
+                    cwd: proc_pid::pidcwd(p as i32).ok(),
+                    exe: Some(pi.exe),
+                    uid: bsd.as_ref().map(|b| b.pbi_uid),
+                    start_time: bsd.map(|b| b.pbi_start_tvsec),
+                    rss: pidinfo::<TaskInfo>(p as i32, 0)
+                        .ok()
+                        .map(|t| t.pti_resident_size),
                 };
 
                 procs.push(cp);
@@ -33,6 +53,119 @@ pub fn get_procs() -> Result<Vec<CommonProcInfo>> {
     Ok(procs)
 }
 
+/// Ports of the TCP sockets the process is listening on, from its file descriptors
+pub fn listening_ports(pid: i32) -> Result<Vec<u16>> {
+    let info = pidinfo::<BSDInfo>(pid, 0).map_err(anyhow::Error::msg)?;
+    let fds = listpidinfo::<ListFDs>(pid, info.pbi_nfiles as usize).map_err(anyhow::Error::msg)?;
+
+    let mut ports = Vec::new();
+    for fd in fds {
+        if !matches!(fd.proc_fdtype.into(), ProcFDType::Socket) {
+            continue;
+        }
+        let Ok(socket) = pidfdinfo::<SocketFDInfo>(pid, fd.proc_fd) else {
+            continue;
+        };
+        if !matches!(socket.psi.soi_kind.into(), SocketInfoKind::Tcp) {
+            continue;
+        }
+        let tcp = unsafe { socket.psi.soi_proto.pri_tcp };
+        if matches!(tcp.tcpsi_state.into(), TcpSIState::Listen) {
+            ports.push(u16::from_be(tcp.tcpsi_ini.insi_lport as u16));
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+/// Processes listening on a local TCP port with their program names, from the file descriptors of
+/// every process
+pub fn listening_processes(port: u16) -> Result<Vec<(i32, String)>> {
+    let pids = proc_pid::listpids(proc_pid::ProcType::ProcAllPIDS).map_err(anyhow::Error::msg)?;
+    let mut found = Vec::new();
+    for pid in pids.into_iter().map(|p| p as i32) {
+        if listening_ports(pid).is_ok_and(|ports| ports.contains(&port)) {
+            found.push((pid, proc_pid::name(pid).unwrap_or_default()));
+        }
+    }
+    Ok(found)
+}
+
+/// Processes with a TCP connection to a local port, from the file descriptors of every process
+pub fn connected_pids(port: u16) -> Result<Vec<i32>> {
+    let pids = proc_pid::listpids(proc_pid::ProcType::ProcAllPIDS).map_err(anyhow::Error::msg)?;
+    let mut connected = Vec::new();
+    for pid in pids.into_iter().map(|p| p as i32) {
+        let Ok(info) = pidinfo::<BSDInfo>(pid, 0) else {
+            continue;
+        };
+        let Ok(fds) = listpidinfo::<ListFDs>(pid, info.pbi_nfiles as usize) else {
+            continue;
+        };
+        let uses_port = fds.iter().any(|fd| {
+            if !matches!(fd.proc_fdtype.into(), ProcFDType::Socket) {
+                return false;
+            }
+            let Ok(socket) = pidfdinfo::<SocketFDInfo>(pid, fd.proc_fd) else {
+                return false;
+            };
+            if !matches!(socket.psi.soi_kind.into(), SocketInfoKind::Tcp) {
+                return false;
+            }
+            let tcp = unsafe { socket.psi.soi_proto.pri_tcp };
+            matches!(tcp.tcpsi_state.into(), TcpSIState::Established)
+                && u16::from_be(tcp.tcpsi_ini.insi_fport as u16) == port
+        });
+        if uses_port {
+            connected.push(pid);
+        }
+    }
+    Ok(connected)
+}
+
+/// User plus system CPU time the process has used
+pub fn cpu_time(pid: i32) -> Result<Duration> {
+    let task = pidinfo::<TaskInfo>(pid, 0).map_err(anyhow::Error::msg)?;
+    Ok(Duration::from_nanos(
+        task.pti_total_user + task.pti_total_system,
+    ))
+}
+
+pub fn set_nice(pid: i32, nice: i32) -> Result<()> {
+    let ret = unsafe { libc::setpriority(libc::PRIO_PROCESS, pid as libc::id_t, nice) };
+    if ret != 0 {
+        bail!(
+            "Failed to set priority of {}: {}",
+            pid,
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+pub fn set_affinity(_pid: i32, _cpus: &[usize]) -> Result<()> {
+    bail!("macOS does not support setting CPU affinity")
+}
+
+pub fn soft_limits(_pid: i32) -> Result<(Option<u64>, Option<u64>)> {
+    bail!("macOS does not support reading the limits of another process")
+}
+
+pub fn filesystem_type(_pid: i32, path: &Path) -> Result<String> {
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        bail!(
+            "Failed to statfs {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        );
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    Ok(name.to_string_lossy().into_owned())
+}
+
 // FROM https://github.com/dalance/procs
 // Specifically  https://github.com/dalance/procs/blob/7874ae2e3ab85c3569abece491063f9709cdc4db/src/process/macos.rs
 // MIT License
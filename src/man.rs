@@ -0,0 +1,106 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Man pages rendered from the clap definitions with clap_mangen, one for mpf and one for each
+// subcommand, so the documentation always matches the arguments the binary takes
+
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Command;
+use clap_mangen::Man;
+
+// Building the command names the subcommands mpf-wait, mpf-ftdc-export and so on, which is
+// what clap_mangen titles their pages and files with
+fn build(cmd: Command) -> Command {
+    let mut cmd = cmd.disable_help_subcommand(true);
+    cmd.build();
+    cmd
+}
+
+/// Write the page of cmd and of every subcommand under it into dir, returning the files
+pub fn write_pages(cmd: &Command, dir: &Path) -> Result<Vec<PathBuf>> {
+    let file = Man::new(cmd.clone())
+        .generate_to(dir)
+        .with_context(|| format!("Failed to write a man page into {}", dir.display()))?;
+    let mut files = vec![file];
+    for sub in cmd.get_subcommands().filter(|s| !s.is_hide_set()) {
+        files.extend(write_pages(sub, dir)?);
+    }
+    Ok(files)
+}
+
+/// Write every page into dir, or print the page of the subcommand named by path, e.g.
+/// ["ftdc", "export"], with an empty path for the top-level page
+pub fn man(cmd: Command, path: &[String], dir: Option<&Path>) -> Result<()> {
+    let cmd = build(cmd);
+    match dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+            for file in write_pages(&cmd, dir)? {
+                println!("{}", file.display());
+            }
+        }
+        None => {
+            let mut page = &cmd;
+            for word in path {
+                page = match page.find_subcommand(word) {
+                    Some(sub) => sub,
+                    None => bail!(
+                        "No command {} {}",
+                        page.get_bin_name().unwrap_or(page.get_name()),
+                        word
+                    ),
+                };
+            }
+            let mut out = Vec::new();
+            Man::new(page.clone()).render(&mut out)?;
+            // Stop quietly when piped into a pager that quit
+            match std::io::stdout().lock().write_all(&out) {
+                Err(e) if e.kind() == ErrorKind::BrokenPipe => {}
+                result => result?,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_write_pages() {
+    use clap::{Arg, ArgAction};
+
+    let cmd = Command::new("mpf")
+        .about("Simple process picker")
+        .subcommand(
+            Command::new("wait").about("Block until ready").arg(
+                Arg::new("until_ready")
+                    .long("until-ready")
+                    .action(ArgAction::SetTrue)
+                    .help("Wait for hello"),
+            ),
+        );
+    let dir = std::env::temp_dir().join(format!("mpf-man-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let files = write_pages(&build(cmd), &dir).unwrap();
+    let names: Vec<_> = files.iter().map(|f| f.file_name().unwrap()).collect();
+    assert_eq!(names, ["mpf.1", "mpf-wait.1"]);
+    let wait = std::fs::read_to_string(dir.join("mpf-wait.1")).unwrap();
+    assert!(wait.contains("mpf\\-wait"), "{}", wait);
+    assert!(wait.contains("\\-\\-until\\-ready"), "{}", wait);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
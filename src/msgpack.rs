@@ -0,0 +1,115 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// MessagePack encoding of JSON values
+
+use serde_json::Value;
+
+fn write_len(buf: &mut Vec<u8>, len: usize, fix: u8, fix_max: usize, markers: [u8; 3]) {
+    if len <= fix_max {
+        buf.push(fix | len as u8);
+    } else if len <= u8::MAX as usize && markers[0] != 0 {
+        buf.push(markers[0]);
+        buf.push(len as u8);
+    } else if len <= u16::MAX as usize {
+        buf.push(markers[1]);
+        buf.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        buf.push(markers[2]);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+fn write_int(buf: &mut Vec<u8>, i: i64) {
+    match i {
+        0..=0x7f => buf.push(i as u8),
+        -32..=-1 => buf.push(i as i8 as u8),
+        0x80..=0xff => buf.extend_from_slice(&[0xcc, i as u8]),
+        0x100..=0xffff => {
+            buf.push(0xcd);
+            buf.extend_from_slice(&(i as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            buf.push(0xce);
+            buf.extend_from_slice(&(i as u32).to_be_bytes());
+        }
+        -0x80..=-33 => buf.extend_from_slice(&[0xd0, i as i8 as u8]),
+        -0x8000..=-0x81 => {
+            buf.push(0xd1);
+            buf.extend_from_slice(&(i as i16).to_be_bytes());
+        }
+        -0x8000_0000..=-0x8001 => {
+            buf.push(0xd2);
+            buf.extend_from_slice(&(i as i32).to_be_bytes());
+        }
+        _ => {
+            buf.push(0xd3);
+            buf.extend_from_slice(&i.to_be_bytes());
+        }
+    }
+}
+
+fn write_value(buf: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => buf.push(0xc0),
+        Value::Bool(b) => buf.push(if *b { 0xc3 } else { 0xc2 }),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                write_int(buf, i);
+            } else if let Some(u) = n.as_u64() {
+                buf.push(0xcf);
+                buf.extend_from_slice(&u.to_be_bytes());
+            } else {
+                buf.push(0xcb);
+                buf.extend_from_slice(&n.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Value::String(s) => {
+            write_len(buf, s.len(), 0xa0, 31, [0xd9, 0xda, 0xdb]);
+            buf.extend_from_slice(s.as_bytes());
+        }
+        Value::Array(values) => {
+            write_len(buf, values.len(), 0x90, 15, [0, 0xdc, 0xdd]);
+            for v in values {
+                write_value(buf, v);
+            }
+        }
+        Value::Object(map) => {
+            write_len(buf, map.len(), 0x80, 15, [0, 0xde, 0xdf]);
+            for (k, v) in map {
+                write_value(buf, &Value::String(k.clone()));
+                write_value(buf, v);
+            }
+        }
+    }
+}
+
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_value(&mut buf, value);
+    buf
+}
+
+#[test]
+fn test_encode() {
+    use serde_json::json;
+
+    assert_eq!(encode(&json!({"a": 1})), [0x81, 0xa1, b'a', 0x01]);
+    assert_eq!(encode(&json!([null, true, -1])), [0x93, 0xc0, 0xc3, 0xff]);
+    assert_eq!(encode(&json!(20001)), [0xcd, 0x4e, 0x21]);
+    assert_eq!(encode(&json!(-200)), [0xd1, 0xff, 0x38]);
+    assert_eq!(encode(&json!(0.5)), [0xcb, 0x3f, 0xe0, 0, 0, 0, 0, 0, 0]);
+    let long = "x".repeat(40);
+    assert_eq!(&encode(&json!(long))[..2], [0xd9, 40]);
+}
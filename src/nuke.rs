@@ -0,0 +1,238 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use super::shutdown::shutdown_server;
+use super::signal::{send_signal, wait_for_exit};
+use super::{config, confirm, list_processes, MongoDType, MongoPSInfo};
+
+const EXIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Clients first, then routers which depend on the config servers and shards, then shards which
+// depend on the config servers
+fn stop_order(info: &MongoPSInfo, pids: &[i32]) -> Vec<Vec<i32>> {
+    let selected = |pid: &i32| pids.contains(pid);
+
+    let cryptd = info
+        .mongocryptd
+        .iter()
+        .flat_map(|g| &g.instances)
+        .map(|i| i.pid);
+    let clients: Vec<i32> = info
+        .shell
+        .iter()
+        .copied()
+        .chain(cryptd)
+        .filter(selected)
+        .collect();
+    let routers: Vec<i32> = info.mongos.iter().map(|s| s.pid).filter(selected).collect();
+    let data: Vec<i32> = info
+        .mongod
+        .iter()
+        .filter(|d| d.server_type != MongoDType::Config)
+        .map(|d| d.pid)
+        .filter(selected)
+        .collect();
+    let config: Vec<i32> = info
+        .mongod
+        .iter()
+        .filter(|d| d.server_type == MongoDType::Config)
+        .map(|d| d.pid)
+        .filter(selected)
+        .collect();
+
+    vec![clients, routers, data, config]
+}
+
+fn stop_process(info: &MongoPSInfo, pid: i32) -> Result<()> {
+    // Shells and mongocryptd are not servers mpf talks to, they only get a signal
+    match info.server_target(pid) {
+        Some(target) => {
+            shutdown_server(&info.settings, &target, true, None)?;
+        }
+        None => send_signal(pid, libc::SIGTERM)?,
+    }
+    Ok(())
+}
+
+// The dbpath from the scan, but only when it was given on the command line or in the config
+// file, a mongod left on the default /data/db may share it with anything else on the host
+fn explicit_dbpath(info: &MongoPSInfo, pid: i32) -> Option<PathBuf> {
+    let dbpath = info.mongod.iter().find(|d| d.pid == pid)?.dbpath.as_ref()?;
+    let config = config::local_config(info.proc_info(pid)?).ok()?;
+    config.get("storage")?.get("dbPath")?;
+    Some(PathBuf::from(dbpath))
+}
+
+pub fn nuke(info: &MongoPSInfo, pids: &[i32], yes: bool, delete_data: bool) -> Result<()> {
+    let tiers = stop_order(info, pids);
+    let all: Vec<i32> = tiers.iter().flatten().copied().collect();
+    if all.is_empty() {
+        println!("Nothing to stop");
+        return Ok(());
+    }
+
+    // Resolve the data directories before the processes are gone
+    let dbpaths: Vec<(i32, Option<PathBuf>)> = if delete_data {
+        all.iter()
+            .map(|pid| (*pid, explicit_dbpath(info, *pid)))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    if !yes {
+        list_processes(info, &all)?;
+        for (_, dbpath) in &dbpaths {
+            if let Some(dbpath) = dbpath {
+                println!("delete {}", dbpath.display());
+            }
+        }
+        if !confirm(&format!("Stop {} processes?", all.len()))? {
+            bail!("Aborted");
+        }
+    }
+
+    // Never delete the data of a mongod that is still running
+    let mut survivors = Vec::new();
+    for tier in tiers {
+        for pid in &tier {
+            if let Err(e) = stop_process(info, *pid) {
+                eprintln!("{}: {}", pid, e);
+            }
+        }
+        // Anything still alive after the timeout is wedged
+        for pid in &tier {
+            if !wait_for_exit(*pid, EXIT_TIMEOUT) {
+                eprintln!(
+                    "{}: still running after {:?}, sending SIGKILL",
+                    pid, EXIT_TIMEOUT
+                );
+                send_signal(*pid, libc::SIGKILL)?;
+                if !wait_for_exit(*pid, EXIT_TIMEOUT) {
+                    eprintln!("{}: still running after SIGKILL", pid);
+                    survivors.push(*pid);
+                    continue;
+                }
+            }
+            println!("{}: stopped", pid);
+        }
+    }
+
+    // One directory that cannot be deleted does not keep the others around
+    let mut failed = 0;
+    for (pid, dbpath) in dbpaths {
+        match dbpath {
+            _ if survivors.contains(&pid) => {
+                eprintln!("{}: still running, not deleting data", pid)
+            }
+            Some(dbpath) => match std::fs::remove_dir_all(&dbpath) {
+                Ok(()) => println!("{}: deleted {}", pid, dbpath.display()),
+                Err(e) => {
+                    eprintln!("{}: failed to delete {}: {}", pid, dbpath.display(), e);
+                    failed += 1;
+                }
+            },
+            None => println!("{}: no --dbpath or storage.dbPath, not deleting data", pid),
+        }
+    }
+    if failed > 0 {
+        bail!("Failed to delete {} data directories", failed);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_stop_order() {
+    use super::cryptd::{CryptdGroup, CryptdInstance};
+    use super::{MongoDServerInfo, MongoSServerInfo};
+
+    let mongod = |pid: i32, server_type: MongoDType| MongoDServerInfo {
+        pid,
+        port: 20000 + pid,
+        server_type,
+        replica_set_name: None,
+        dbpath: None,
+        logpath: None,
+        fork: false,
+        pidfile: None,
+        user: None,
+        cwd: None,
+        listening_ports: None,
+        special_modes: vec![],
+        storage: Default::default(),
+        encryption: None,
+        set_parameters: Default::default(),
+        test_instance: false,
+        tls: None,
+        auth: Default::default(),
+        unix_socket: None,
+        replication: None,
+        shard_name: None,
+        start_dir: None,
+    };
+    let info = MongoPSInfo {
+        schema_version: super::schema::SCHEMA_VERSION,
+        mongod: vec![
+            mongod(1, MongoDType::Config),
+            mongod(2, MongoDType::Shard),
+            mongod(3, MongoDType::Standalone),
+        ],
+        mongos: vec![MongoSServerInfo {
+            pid: 4,
+            port: 20004,
+            configdb: "configRS".to_owned(),
+            logpath: None,
+            fork: false,
+            pidfile: None,
+            user: None,
+            cwd: None,
+            listening_ports: None,
+            set_parameters: Default::default(),
+            test_instance: false,
+            tls: None,
+            auth: Default::default(),
+            unix_socket: None,
+            start_dir: None,
+        }],
+        shell: vec![5],
+        mongocryptd: vec![CryptdGroup {
+            parent: None,
+            instances: vec![CryptdInstance {
+                pid: 6,
+                port: 27020,
+                clients: vec![],
+            }],
+        }],
+        clusters: vec![],
+        plugin_processes: vec![],
+        procs: vec![],
+        settings: Default::default(),
+    };
+
+    assert_eq!(
+        stop_order(&info, &[1, 2, 3, 4, 5, 6]),
+        [vec![5, 6], vec![4], vec![2, 3], vec![1]]
+    );
+    // Only the selected processes, keeping the tiers
+    assert_eq!(
+        stop_order(&info, &[1, 6]),
+        [vec![6], vec![], vec![], vec![1]]
+    );
+}
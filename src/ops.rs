@@ -0,0 +1,129 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Long running operations from currentOp
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use super::client::{Connection, Settings, DEFAULT_TIMEOUT};
+use super::probe::probe_all;
+use super::ServerTarget;
+
+#[derive(Debug, PartialEq)]
+pub struct Operation {
+    /// A number on mongod, shard:number on mongos
+    pub opid: String,
+    pub op: String,
+    pub ns: String,
+    pub secs_running: i64,
+    pub desc: String,
+}
+
+fn scalar(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(s)) => s.clone(),
+        Some(v) => v.to_string(),
+        None => String::new(),
+    }
+}
+
+/// Operations in a currentOp reply running for at least min_secs, longest first
+pub fn long_running(reply: &Value, min_secs: i64) -> Vec<Operation> {
+    let mut ops: Vec<Operation> = reply
+        .get("inprog")
+        .and_then(|i| i.as_array())
+        .map_or(&[][..], |i| i.as_slice())
+        .iter()
+        .filter_map(|op| {
+            let secs_running = op.get("secs_running")?.as_i64()?;
+            (secs_running >= min_secs).then(|| Operation {
+                opid: scalar(op.get("opid")),
+                op: scalar(op.get("op")),
+                ns: scalar(op.get("ns")),
+                secs_running,
+                desc: scalar(op.get("desc")),
+            })
+        })
+        .collect();
+    ops.sort_by_key(|op| std::cmp::Reverse(op.secs_running));
+    ops
+}
+
+/// Print the operations running for at least min_secs on each server
+pub fn print_ops(settings: &Settings, targets: &[ServerTarget], min_secs: i64) -> Result<()> {
+    let replies = probe_all(settings, targets, |target| {
+        Connection::connect_local(settings, target.port, DEFAULT_TIMEOUT)
+            .and_then(|mut conn| conn.run_command("admin", json!({"currentOp": 1, "active": true})))
+    });
+    for (target, reply) in targets.iter().zip(replies) {
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(e) => {
+                eprintln!("{} port {}: {}", target.pid, target.port, e);
+                continue;
+            }
+        };
+
+        let ops = long_running(&reply, min_secs);
+        println!(
+            "{} port {}: {} operation(s)",
+            target.pid,
+            target.port,
+            ops.len()
+        );
+        for op in ops {
+            let line = format!(
+                "  {:>12} {:>6}s {:<10} {:<30} {}",
+                op.opid, op.secs_running, op.op, op.ns, op.desc
+            );
+            println!("{}", line.trim_end());
+        }
+    }
+    Ok(())
+}
+
+/// Kill an operation, the opid is a number on mongod and shard:number on mongos
+pub fn kill_op(settings: &Settings, target: &ServerTarget, opid: &str) -> Result<()> {
+    let op = match opid.parse::<i64>() {
+        Ok(n) => json!(n),
+        Err(_) => json!(opid),
+    };
+    let mut conn = Connection::connect_local(settings, target.port, DEFAULT_TIMEOUT)?;
+    conn.run_command("admin", json!({"killOp": 1, "op": op}))?;
+    println!(
+        "{} port {}: killed operation {}",
+        target.pid, target.port, opid
+    );
+    Ok(())
+}
+
+#[test]
+fn test_long_running() {
+    let reply = json!({
+        "inprog": [
+            {"opid": 12, "op": "query", "ns": "test.c", "secs_running": 5, "desc": "conn1"},
+            {"opid": "shard01:7", "op": "command", "ns": "admin.$cmd", "secs_running": 120},
+            {"opid": 13, "op": "none", "desc": "ReplBatcher"},
+            {"opid": 14, "op": "update", "ns": "test.d", "secs_running": 0},
+        ],
+    });
+    let ops = long_running(&reply, 1);
+    assert_eq!(ops.len(), 2);
+    assert_eq!(ops[0].opid, "shard01:7");
+    assert_eq!(ops[1].opid, "12");
+    assert_eq!(ops[1].desc, "conn1");
+    assert_eq!(long_running(&reply, 0).len(), 3);
+    assert!(long_running(&json!({}), 0).is_empty());
+}
@@ -0,0 +1,91 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Encoding of the summary and writing it out
+
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde_json::Value;
+
+use super::extjson::{encode_document, to_canonical_extended_json};
+use super::msgpack;
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum OutputFormat {
+    Json,
+    /// Canonical Extended JSON on one line, e.g. for mongoimport
+    Ejson,
+    /// A single BSON document
+    Bson,
+    /// MessagePack, compact and quick to parse for frequent polling
+    Msgpack,
+}
+
+pub fn encode(summary: &Value, format: &OutputFormat) -> Result<Vec<u8>> {
+    Ok(match format {
+        OutputFormat::Json => {
+            let mut bytes = serde_json::to_vec_pretty(summary)?;
+            bytes.push(b'\n');
+            bytes
+        }
+        OutputFormat::Ejson => {
+            let mut bytes = serde_json::to_vec(&to_canonical_extended_json(summary)?)?;
+            bytes.push(b'\n');
+            bytes
+        }
+        OutputFormat::Bson => encode_document(summary)?,
+        OutputFormat::Msgpack => msgpack::encode(summary),
+    })
+}
+
+/// Write to a temporary file next to the target and rename it into place, so readers see either
+/// the old or the new contents and never a partial file
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file name", path.display()))?;
+    let tmp = dir.join(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = std::fs::File::create(&tmp)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&tmp, path));
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_write_atomic() {
+    let path = std::env::temp_dir().join(format!("mpf-test-{}.json", std::process::id()));
+    write_atomic(&path, b"{}").unwrap();
+    write_atomic(&path, b"{\"a\": 1}").unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"a\": 1}");
+    std::fs::remove_file(&path).unwrap();
+}
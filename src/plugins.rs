@@ -0,0 +1,225 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Classifier plugins: executables listed in ~/.config/mpf/plugins.json teach mpf about other
+// daemons in the JSON summary. Each one gets the processes that are not mongo processes as a
+// JSON array on stdin and answers with the ones it recognizes, e.g.
+// [{"pid": 123, "type": "mongot", "fields": {"port": 27028}}]. Nothing runs unless it is listed,
+// and environments are not sent since they may hold secrets.
+
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use super::probe::{probe_limited, DEFAULT_CONCURRENCY};
+use super::types::CommonProcInfo;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A process a plugin recognized
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Classified {
+    pub pid: i32,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// File name of the plugin that recognized it
+    pub plugin: String,
+    pub fields: Map<String, Value>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct PluginsFile {
+    #[serde(default)]
+    classifiers: Vec<PathBuf>,
+}
+
+fn config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("mpf").join("plugins.json"))
+}
+
+/// The classifiers listed in the plugins file, none when there is no file
+pub fn classifiers() -> Vec<PathBuf> {
+    let Some(path) = config_path() else {
+        return Vec::new();
+    };
+    let Ok(text) = std::fs::read_to_string(&path) else {
+        return Vec::new();
+    };
+    match serde_json::from_str::<PluginsFile>(&text) {
+        Ok(file) => file.classifiers,
+        Err(e) => {
+            eprintln!("WARNING: Failed to parse {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+/// What a plugin is told about a candidate process
+pub fn candidate_json(p: &CommonProcInfo) -> Value {
+    json!({
+        "pid": p.pid,
+        "ppid": p.ppid,
+        "program": p.program,
+        "cmdline": p.cmdline,
+        "cwd": p.cwd,
+        "exe": p.exe,
+        "uid": p.uid,
+        "start_time": p.start_time,
+        "rss": p.rss,
+    })
+}
+
+/// The processes in a plugin reply, keeping only pids that were offered to it
+pub fn parse_reply(
+    plugin: &str,
+    reply: &[u8],
+    candidates: &HashSet<i32>,
+) -> Result<Vec<Classified>> {
+    let reply: Value = serde_json::from_slice(reply).context("Reply is not JSON")?;
+    let Some(entries) = reply.as_array() else {
+        bail!("Reply is not an array");
+    };
+    let mut out = Vec::new();
+    for entry in entries {
+        let pid = entry
+            .get("pid")
+            .and_then(Value::as_i64)
+            .with_context(|| format!("Entry without a pid: {}", entry))?;
+        let kind = entry
+            .get("type")
+            .and_then(Value::as_str)
+            .with_context(|| format!("Entry without a type: {}", entry))?;
+        if !candidates.contains(&(pid as i32)) {
+            continue;
+        }
+        out.push(Classified {
+            pid: pid as i32,
+            kind: kind.to_owned(),
+            plugin: plugin.to_owned(),
+            fields: entry
+                .get("fields")
+                .and_then(Value::as_object)
+                .cloned()
+                .unwrap_or_default(),
+        });
+    }
+    Ok(out)
+}
+
+// Run a plugin with input on stdin, killing it if it takes longer than TIMEOUT
+fn run(plugin: &Path, input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new(plugin)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .context("Failed to start")?;
+
+    // Feed stdin and drain stdout on their own threads so a large exchange cannot deadlock
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut out = Vec::new();
+        stdout.read_to_end(&mut out).map(|_| out)
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("Timed out after {:?}", TIMEOUT);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    // A plugin may exit without reading everything, that is its choice
+    let _ = writer.join();
+    let out = reader.join().unwrap()?;
+    if !status.success() {
+        bail!("Exited with {}", status);
+    }
+    Ok(out)
+}
+
+/// Ask every classifier about procs. Failing plugins are reported and skipped so they cannot
+/// break the scan.
+pub fn classify<'a>(procs: impl Iterator<Item = &'a CommonProcInfo>) -> Vec<Classified> {
+    let plugins = classifiers();
+    if plugins.is_empty() {
+        return Vec::new();
+    }
+    let procs: Vec<&CommonProcInfo> = procs.collect();
+    let candidates: HashSet<i32> = procs.iter().map(|p| p.pid).collect();
+    let input = Value::Array(procs.iter().map(|p| candidate_json(p)).collect()).to_string();
+
+    probe_limited(&plugins, DEFAULT_CONCURRENCY, |plugin| {
+        let name = plugin.file_name().unwrap_or_default().to_string_lossy();
+        match run(plugin, input.as_bytes())
+            .and_then(|reply| parse_reply(&name, &reply, &candidates))
+        {
+            Ok(classified) => classified,
+            Err(e) => {
+                eprintln!("WARNING: Classifier {} failed: {:#}", plugin.display(), e);
+                Vec::new()
+            }
+        }
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+#[test]
+fn test_parse_reply() {
+    let candidates: HashSet<i32> = [10, 11].into_iter().collect();
+    let reply = br#"[
+        {"pid": 10, "type": "mongot", "fields": {"port": 27028}},
+        {"pid": 11, "type": "agent"},
+        {"pid": 99, "type": "not offered"}
+    ]"#;
+    assert_eq!(
+        parse_reply("mpf-classify-search", reply, &candidates).unwrap(),
+        vec![
+            Classified {
+                pid: 10,
+                kind: "mongot".to_owned(),
+                plugin: "mpf-classify-search".to_owned(),
+                fields: json!({"port": 27028}).as_object().unwrap().clone(),
+            },
+            Classified {
+                pid: 11,
+                kind: "agent".to_owned(),
+                plugin: "mpf-classify-search".to_owned(),
+                fields: Map::new(),
+            },
+        ]
+    );
+    assert!(parse_reply("p", b"{}", &candidates).is_err());
+    assert!(parse_reply("p", br#"[{"type": "x"}]"#, &candidates).is_err());
+}
@@ -0,0 +1,88 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::{bail, Result};
+
+#[cfg(target_os = "linux")]
+use super::linux::{set_affinity, set_nice};
+#[cfg(target_os = "macos")]
+use super::macos::{set_affinity, set_nice};
+
+/// Parse a CPU list like 0-3,6
+pub fn parse_cpu_list(s: &str) -> Result<Vec<usize>, String> {
+    let mut cpus = Vec::new();
+    for part in s.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let bad = |_| format!("Invalid CPU list {}", s);
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let start = start.parse::<usize>().map_err(bad)?;
+                let end = end.parse::<usize>().map_err(bad)?;
+                if start > end {
+                    return Err(format!("Invalid CPU range {}", part));
+                }
+                cpus.extend(start..=end);
+            }
+            None => cpus.push(part.parse::<usize>().map_err(bad)?),
+        }
+    }
+
+    if cpus.is_empty() {
+        return Err("No CPUs given".to_owned());
+    }
+    Ok(cpus)
+}
+
+fn apply_all<F>(pids: &[i32], what: &str, f: F) -> Result<()>
+where
+    F: Fn(i32) -> Result<()>,
+{
+    let mut failures = 0;
+    for pid in pids {
+        match f(*pid) {
+            Ok(()) => println!("{}: set {}", pid, what),
+            Err(e) => {
+                eprintln!("{}: {}", pid, e);
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        bail!("Failed to set {} on {} process(es)", what, failures);
+    }
+    Ok(())
+}
+
+pub fn renice_all(pids: &[i32], nice: i32) -> Result<()> {
+    apply_all(pids, &format!("nice {}", nice), |pid| set_nice(pid, nice))
+}
+
+pub fn set_affinity_all(pids: &[i32], cpus: &[usize]) -> Result<()> {
+    let what = format!(
+        "affinity {}",
+        cpus.iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    apply_all(pids, &what, |pid| set_affinity(pid, cpus))
+}
+
+#[test]
+fn test_parse_cpu_list() {
+    assert_eq!(parse_cpu_list("0-3"), Ok(vec![0, 1, 2, 3]));
+    assert_eq!(parse_cpu_list("1,4-5"), Ok(vec![1, 4, 5]));
+    assert!(parse_cpu_list("3-1").is_err());
+    assert!(parse_cpu_list("a").is_err());
+}
@@ -0,0 +1,96 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Query many servers at once so a large local cluster costs about one round trip. Each probe is
+// a blocking connection with its own timeout, run on a pool of scoped threads no larger than
+// --concurrency.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::client::Settings;
+
+/// Most probes running at the same time when --concurrency is not given
+pub const DEFAULT_CONCURRENCY: usize = 16;
+
+/// Run probe on every item concurrently, at most the concurrency of the settings at once,
+/// returning the results in the order of the items
+pub fn probe_all<T, R, F>(settings: &Settings, items: &[T], probe: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    probe_limited(items, settings.concurrency(), probe)
+}
+
+/// Like probe_all with at most limit probes running at once, a limit of 1 runs them in order
+pub fn probe_limited<T, R, F>(items: &[T], limit: usize, probe: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    let next = AtomicUsize::new(0);
+    let workers = items.len().min(limit.max(1));
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                let result = probe(item);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every item is probed"))
+        .collect()
+}
+
+#[test]
+fn test_probe_all() {
+    use std::time::{Duration, Instant};
+
+    let items: Vec<u64> = (0..40).collect();
+    let start = Instant::now();
+    let results = probe_all(&Settings::default(), &items, |i| {
+        std::thread::sleep(Duration::from_millis(50));
+        i * 2
+    });
+    assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<u64>>());
+    // 40 probes on 16 threads take three rounds, not forty
+    assert!(start.elapsed() < Duration::from_millis(40 * 50 / 2));
+
+    assert!(probe_all(&Settings::default(), &Vec::<u64>::new(), |i| *i).is_empty());
+
+    // With --concurrency 1 they run one after the other
+    let serial = Settings {
+        concurrency: Some(1),
+        ..Settings::default()
+    };
+    let start = Instant::now();
+    probe_all(&serial, &items[..4], |_| {
+        std::thread::sleep(Duration::from_millis(50))
+    });
+    assert!(start.elapsed() >= Duration::from_millis(4 * 50));
+}
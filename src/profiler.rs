@@ -0,0 +1,123 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Read and set the database profiler level
+
+use anyhow::{bail, Result};
+use serde_json::{json, Map, Value};
+
+use super::client::{Connection, Settings, DEFAULT_TIMEOUT};
+use super::ServerTarget;
+
+// The profiler cannot be enabled on local
+const SKIPPED_DATABASES: &[&str] = &["local"];
+
+fn database_names(conn: &mut Connection) -> Result<Vec<String>> {
+    let reply = conn.run_command("admin", json!({"listDatabases": 1, "nameOnly": true}))?;
+    Ok(reply
+        .get("databases")
+        .and_then(|d| d.as_array())
+        .map_or(&[][..], |d| d.as_slice())
+        .iter()
+        .filter_map(|d| d.get("name")?.as_str())
+        .filter(|name| !SKIPPED_DATABASES.contains(name))
+        .map(|name| name.to_owned())
+        .collect())
+}
+
+/// The profile command, -1 only reads the current settings
+pub fn profile_command(level: i32, slow_ms: Option<i64>) -> Value {
+    let mut cmd = Map::new();
+    cmd.insert("profile".to_owned(), json!(level));
+    if let Some(slow_ms) = slow_ms {
+        cmd.insert("slowms".to_owned(), json!(slow_ms));
+    }
+    Value::Object(cmd)
+}
+
+pub fn describe_level(reply: &Value) -> String {
+    format!(
+        "level {}, slowms {}",
+        reply.get("was").map_or("?".to_owned(), |v| v.to_string()),
+        reply
+            .get("slowms")
+            .map_or("?".to_owned(), |v| v.to_string())
+    )
+}
+
+/// Read the profiler level of a database, or of every database, on each server. With a level
+/// the profiler is set first and the previous setting is printed.
+pub fn profile_level(
+    settings: &Settings,
+    targets: &[ServerTarget],
+    db: Option<&str>,
+    level: Option<i32>,
+    slow_ms: Option<i64>,
+) -> Result<()> {
+    let mut failures = 0;
+    for target in targets {
+        let result = Connection::connect_local(settings, target.port, DEFAULT_TIMEOUT).and_then(
+            |mut conn| {
+                let dbs = match db {
+                    Some(db) => vec![db.to_owned()],
+                    None => database_names(&mut conn)?,
+                };
+                for db in dbs {
+                    let cmd = profile_command(level.unwrap_or(-1), slow_ms);
+                    let reply = conn.run_command(&db, cmd)?;
+                    match level {
+                        Some(level) => println!(
+                            "{} port {} {}: set level {} (was {})",
+                            target.pid,
+                            target.port,
+                            db,
+                            level,
+                            describe_level(&reply)
+                        ),
+                        None => println!(
+                            "{} port {} {}: {}",
+                            target.pid,
+                            target.port,
+                            db,
+                            describe_level(&reply)
+                        ),
+                    }
+                }
+                Ok(())
+            },
+        );
+        if let Err(e) = result {
+            eprintln!("{} port {}: {}", target.pid, target.port, e);
+            failures += 1;
+        }
+    }
+
+    if failures > 0 {
+        bail!("Failed on {} server(s)", failures);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_profile_command() {
+    assert_eq!(profile_command(-1, None), json!({"profile": -1}));
+    assert_eq!(
+        profile_command(1, Some(50)),
+        json!({"profile": 1, "slowms": 50})
+    );
+    assert_eq!(
+        describe_level(&json!({"was": 0, "slowms": 100, "ok": 1})),
+        "level 0, slowms 100"
+    );
+}
@@ -0,0 +1,226 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Live replication state of a replica set member
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::client::{Connection, Settings};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReplicationInfo {
+    /// Member state, e.g. PRIMARY or SECONDARY
+    pub state: String,
+    /// Seconds the member's last applied write is behind the primary's, secondaries only
+    pub lag_secs: Option<f64>,
+    /// Roles from the replica set config: arbiter, hidden, delayed, priority-zero and votes-zero
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// Tags from the replica set config
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Role of a member for --role
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum MemberRole {
+    Primary,
+    Secondary,
+    Arbiter,
+    Hidden,
+}
+
+impl MemberRole {
+    pub fn matches(&self, replication: &ReplicationInfo) -> bool {
+        let has_role = |role: &str| replication.roles.iter().any(|r| r == role);
+        match self {
+            MemberRole::Primary => replication.state == "PRIMARY",
+            MemberRole::Secondary => replication.state == "SECONDARY",
+            MemberRole::Arbiter => replication.state == "ARBITER" || has_role("arbiter"),
+            MemberRole::Hidden => has_role("hidden"),
+        }
+    }
+}
+
+/// Milliseconds since the epoch of a decoded BSON date
+pub fn date_millis(value: &Value) -> Option<i64> {
+    let date = value.get("$date")?;
+    match date.get("$numberLong") {
+        Some(millis) => millis.as_str()?.parse().ok(),
+        None => date.as_i64(),
+    }
+}
+
+fn optime_millis(member: &Value) -> Option<i64> {
+    member.get("optimeDate").and_then(date_millis).or_else(|| {
+        let t = member
+            .get("optime")?
+            .get("ts")?
+            .get("$timestamp")?
+            .get("t")?;
+        Some(t.as_i64()? * 1000)
+    })
+}
+
+fn number(member: &Value, field: &str) -> Option<f64> {
+    member.get(field)?.as_f64()
+}
+
+/// Roles of a member of a replSetGetConfig reply
+pub fn member_roles(member: &Value) -> Vec<String> {
+    let flag = |field: &str| member.get(field).and_then(|v| v.as_bool()) == Some(true);
+    let mut roles = Vec::new();
+    if flag("arbiterOnly") {
+        roles.push("arbiter");
+    }
+    if flag("hidden") {
+        roles.push("hidden");
+    }
+    // secondaryDelaySecs replaced slaveDelay in 5.0
+    let delay = number(member, "secondaryDelaySecs").or_else(|| number(member, "slaveDelay"));
+    if delay.is_some_and(|d| d > 0.0) {
+        roles.push("delayed");
+    }
+    if !flag("arbiterOnly") && number(member, "priority") == Some(0.0) {
+        roles.push("priority-zero");
+    }
+    if number(member, "votes") == Some(0.0) {
+        roles.push("votes-zero");
+    }
+    roles.into_iter().map(|r| r.to_owned()).collect()
+}
+
+/// Tags of a member of a replSetGetConfig reply
+pub fn member_tags(member: &Value) -> BTreeMap<String, String> {
+    let Some(tags) = member.get("tags").and_then(|t| t.as_object()) else {
+        return BTreeMap::new();
+    };
+    tags.iter()
+        .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_owned())))
+        .collect()
+}
+
+/// Parse a member tag filter like region=us-east
+pub fn parse_member_tag(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(format!("Invalid member tag {}, expected name=value", s)),
+    }
+}
+
+/// State and lag of the member reporting a replSetGetStatus reply, with its roles from the
+/// replSetGetConfig reply when there is one
+pub fn from_status(status: &Value, config: Option<&Value>) -> Option<ReplicationInfo> {
+    let members = status.get("members")?.as_array()?;
+    let me = members
+        .iter()
+        .find(|m| m.get("self").and_then(|s| s.as_bool()) == Some(true))?;
+    let state = me.get("stateStr")?.as_str()?.to_owned();
+
+    let primary = members
+        .iter()
+        .find(|m| m.get("stateStr").and_then(|s| s.as_str()) == Some("PRIMARY"));
+    let lag_secs = match (state.as_str(), primary) {
+        ("SECONDARY", Some(primary)) => {
+            let behind = optime_millis(primary)? - optime_millis(me)?;
+            Some(behind.max(0) as f64 / 1000.0)
+        }
+        _ => None,
+    };
+
+    let name = me.get("name").and_then(|n| n.as_str());
+    let member = config
+        .and_then(|c| c.get("config")?.get("members")?.as_array())
+        .and_then(|members| {
+            members
+                .iter()
+                .find(|m| m.get("host").and_then(|h| h.as_str()) == name)
+        });
+    Some(ReplicationInfo {
+        state,
+        lag_secs,
+        roles: member.map(member_roles).unwrap_or_default(),
+        tags: member.map(member_tags).unwrap_or_default(),
+    })
+}
+
+/// None for servers that are not replica set members or cannot be reached
+pub fn replication_info(settings: &Settings, port: i32) -> Option<ReplicationInfo> {
+    let mut conn = Connection::connect_local(settings, port, Duration::from_secs(1)).ok()?;
+    let status = conn
+        .run_command("admin", json!({"replSetGetStatus": 1}))
+        .ok()?;
+    let config = conn
+        .run_command("admin", json!({"replSetGetConfig": 1}))
+        .ok();
+    from_status(&status, config.as_ref())
+}
+
+#[test]
+fn test_from_status() {
+    let date = |millis: i64| json!({"$date": {"$numberLong": millis.to_string()}});
+    let status = json!({
+        "set": "rs0",
+        "members": [
+            {"name": "localhost:20001", "stateStr": "PRIMARY", "optimeDate": date(1_700_000_030_000)},
+            {"name": "localhost:20002", "stateStr": "SECONDARY", "optimeDate": date(1_700_000_000_500),
+             "self": true},
+            {"name": "localhost:20003", "stateStr": "ARBITER"},
+        ],
+    });
+    let config = json!({"config": {"members": [
+        {"host": "localhost:20001", "priority": 1, "votes": 1},
+        {"host": "localhost:20002", "priority": 0, "votes": 1, "hidden": true,
+         "secondaryDelaySecs": 0, "tags": {"region": "us-east"}},
+        {"host": "localhost:20003", "arbiterOnly": true, "priority": 0, "votes": 1},
+    ]}});
+    assert_eq!(
+        from_status(&status, Some(&config)),
+        Some(ReplicationInfo {
+            state: "SECONDARY".to_owned(),
+            lag_secs: Some(29.5),
+            roles: vec!["hidden".to_owned(), "priority-zero".to_owned()],
+            tags: BTreeMap::from([("region".to_owned(), "us-east".to_owned())]),
+        })
+    );
+    assert_eq!(
+        parse_member_tag("region=us-east"),
+        Ok(("region".to_owned(), "us-east".to_owned()))
+    );
+    assert!(parse_member_tag("region").is_err());
+    let secondary = from_status(&status, Some(&config)).unwrap();
+    assert!(MemberRole::Secondary.matches(&secondary));
+    assert!(MemberRole::Hidden.matches(&secondary));
+    assert!(!MemberRole::Primary.matches(&secondary));
+
+    assert_eq!(
+        member_roles(&json!({"arbiterOnly": true, "priority": 0, "votes": 1})),
+        vec!["arbiter"]
+    );
+    assert_eq!(
+        member_roles(&json!({"slaveDelay": 3600, "priority": 0, "votes": 0})),
+        vec!["delayed", "priority-zero", "votes-zero"]
+    );
+
+    let mut status = status;
+    status["members"][1]["self"] = json!(false);
+    status["members"][0]["self"] = json!(true);
+    assert_eq!(from_status(&status, None).unwrap().lag_secs, None);
+    assert_eq!(from_status(&json!({"ok": 1}), None), None);
+}
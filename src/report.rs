@@ -0,0 +1,287 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Shareable report of the local topology for pasting into tickets
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+
+use super::audit::audit;
+use super::client::server_version;
+use super::describe::format_age;
+use super::probe::probe_all;
+use super::sharding::routers;
+use super::storage::cache_warning;
+use super::MongoPSInfo;
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+pub struct Section {
+    pub title: String,
+    pub rows: Vec<Vec<String>>,
+}
+
+const HEADER: &[&str] = &[
+    "pid", "port", "type", "version", "dbpath", "memory", "user", "age",
+];
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn row(
+    info: &MongoPSInfo,
+    versions: &HashMap<i32, String>,
+    pid: i32,
+    port: i32,
+    kind: String,
+    dbpath: Option<&str>,
+) -> Vec<String> {
+    let proc = info.proc_info(pid);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    vec![
+        pid.to_string(),
+        port.to_string(),
+        kind,
+        versions
+            .get(&port)
+            .cloned()
+            .unwrap_or_else(|| "unreachable".to_owned()),
+        dbpath.unwrap_or("-").to_owned(),
+        proc.and_then(|p| p.rss)
+            .map_or("-".to_owned(), format_bytes),
+        proc.and_then(|p| p.uid)
+            .and_then(super::user_name)
+            .unwrap_or_else(|| "-".to_owned()),
+        proc.and_then(|p| p.start_time)
+            .map_or("-".to_owned(), |start| {
+                format_age(now.saturating_sub(start))
+            }),
+    ]
+}
+
+/// Tables per replica set, then standalone servers and routers
+pub fn sections(info: &MongoPSInfo, pids: &[i32]) -> Vec<Section> {
+    let cryptds = info.cryptd_instances().filter(|c| pids.contains(&c.pid));
+    let ports: Vec<i32> = info
+        .server_targets()
+        .into_iter()
+        .filter(|t| pids.contains(&t.pid))
+        .map(|t| t.port)
+        .chain(cryptds.map(|c| c.port))
+        .collect();
+    let versions: HashMap<i32, String> = ports
+        .iter()
+        .zip(probe_all(&info.settings, &ports, |port| {
+            server_version(&info.settings, *port).ok()
+        }))
+        .filter_map(|(port, version)| Some((*port, version?)))
+        .collect();
+
+    let mut sets: BTreeMap<String, Vec<Vec<String>>> = BTreeMap::new();
+    let mut standalone = Vec::new();
+    for d in info.mongod.iter().filter(|d| pids.contains(&d.pid)) {
+        let mut kind = format!("{:?}", d.server_type);
+        if !d.special_modes.is_empty() {
+            kind = format!("{} ({})", kind, d.special_modes.join(", "));
+        }
+        let r = row(info, &versions, d.pid, d.port, kind, d.dbpath.as_deref());
+        match d.replica_set_name {
+            Some(ref name) => sets.entry(name.clone()).or_default().push(r),
+            None => standalone.push(r),
+        }
+    }
+
+    let mut sections: Vec<Section> = sets
+        .into_iter()
+        .map(|(name, rows)| Section {
+            title: format!("Replica set {}", name),
+            rows,
+        })
+        .collect();
+    if !standalone.is_empty() {
+        sections.push(Section {
+            title: "Standalone servers".to_owned(),
+            rows: standalone,
+        });
+    }
+
+    // Routers of the same cluster are interchangeable, so show each cluster once
+    for (cluster, routers) in routers(info, pids) {
+        let rows = routers
+            .iter()
+            .map(|t| row(info, &versions, t.pid, t.port, "mongos".to_owned(), None))
+            .collect();
+        let title = match cluster.as_str() {
+            "" => "Routers".to_owned(),
+            name => format!("Routers of cluster {}", name),
+        };
+        sections.push(Section { title, rows });
+    }
+
+    for group in &info.mongocryptd {
+        let rows: Vec<Vec<String>> = group
+            .instances
+            .iter()
+            .filter(|c| pids.contains(&c.pid))
+            .map(|c| {
+                row(
+                    info,
+                    &versions,
+                    c.pid,
+                    c.port,
+                    "mongocryptd".to_owned(),
+                    None,
+                )
+            })
+            .collect();
+        if rows.is_empty() {
+            continue;
+        }
+        let title = match group.parent {
+            Some(ref parent) => {
+                format!("mongocryptd spawned by {} ({})", parent.program, parent.pid)
+            }
+            None => "Stray mongocryptd".to_owned(),
+        };
+        sections.push(Section { title, rows });
+    }
+    sections
+}
+
+pub fn warnings(info: &MongoPSInfo, pids: &[i32]) -> Vec<String> {
+    let mut warnings: Vec<String> = audit(info, pids)
+        .into_iter()
+        .map(|f| format!("{} {}: {}", f.severity, f.pid, f.message))
+        .collect();
+    warnings.extend(cache_warning(info).map(|w| w.trim_start_matches("WARNING: ").to_owned()));
+    let stray = info
+        .mongocryptd
+        .iter()
+        .filter(|g| g.parent.is_none())
+        .flat_map(|g| g.instances.iter());
+    warnings.extend(stray.filter(|c| pids.contains(&c.pid)).map(|c| {
+        format!(
+            "mongocryptd {} on port {} has no parent or clients and can be killed",
+            c.pid, c.port
+        )
+    }));
+    warnings
+}
+
+fn escape_markdown(s: &str) -> String {
+    s.replace('|', "\\|")
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn render_markdown(sections: &[Section], warnings: &[String]) -> String {
+    let mut out = String::from("# MongoDB processes\n");
+    for section in sections {
+        writeln!(out, "\n## {}\n", escape_markdown(&section.title)).unwrap();
+        writeln!(out, "| {} |", HEADER.join(" | ")).unwrap();
+        writeln!(out, "|{}", "---|".repeat(HEADER.len())).unwrap();
+        for row in &section.rows {
+            let cells: Vec<String> = row.iter().map(|c| escape_markdown(c)).collect();
+            writeln!(out, "| {} |", cells.join(" | ")).unwrap();
+        }
+    }
+
+    out.push_str("\n## Warnings\n\n");
+    if warnings.is_empty() {
+        out.push_str("None\n");
+    }
+    for warning in warnings {
+        writeln!(out, "- {}", escape_markdown(warning)).unwrap();
+    }
+    out
+}
+
+pub fn render_html(sections: &[Section], warnings: &[String]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>MongoDB processes</title></head>\n<body>\n<h1>MongoDB processes</h1>\n",
+    );
+    for section in sections {
+        writeln!(
+            out,
+            "<h2>{}</h2>\n<table border=\"1\">",
+            escape_html(&section.title)
+        )
+        .unwrap();
+        let header: Vec<String> = HEADER.iter().map(|h| format!("<th>{}</th>", h)).collect();
+        writeln!(out, "<tr>{}</tr>", header.concat()).unwrap();
+        for row in &section.rows {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|c| format!("<td>{}</td>", escape_html(c)))
+                .collect();
+            writeln!(out, "<tr>{}</tr>", cells.concat()).unwrap();
+        }
+        out.push_str("</table>\n");
+    }
+
+    out.push_str("<h2>Warnings</h2>\n");
+    if warnings.is_empty() {
+        out.push_str("<p>None</p>\n");
+    } else {
+        out.push_str("<ul>\n");
+        for warning in warnings {
+            writeln!(out, "<li>{}</li>", escape_html(warning)).unwrap();
+        }
+        out.push_str("</ul>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+#[test]
+fn test_render_markdown() {
+    let sections = vec![Section {
+        title: "Replica set rs0".to_owned(),
+        rows: vec![HEADER.iter().map(|h| h.to_string()).collect()],
+    }];
+    let markdown = render_markdown(&sections, &["a|b".to_owned()]);
+    assert!(markdown.contains("## Replica set rs0"));
+    assert!(markdown.contains("|---|---|"));
+    assert!(markdown.contains("- a\\|b"));
+
+    let html = render_html(&sections, &["<b>".to_owned()]);
+    assert!(html.contains("<li>&lt;b&gt;</li>"));
+    assert_eq!(format_bytes(1536), "1.5 KB");
+}
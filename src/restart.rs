@@ -0,0 +1,57 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use super::client::Settings;
+use super::launch::LaunchSpec;
+use super::shutdown::shutdown_server;
+use super::signal::wait_for_exit;
+use super::types::CommonProcInfo;
+use super::ServerTarget;
+
+const EXIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub fn restart_server(
+    settings: &Settings,
+    target: &ServerTarget,
+    proc: &CommonProcInfo,
+    binary: Option<&Path>,
+) -> Result<u32> {
+    let mut spec = LaunchSpec::from_proc(proc)?;
+    if let Some(binary) = binary {
+        spec.binary = std::fs::canonicalize(binary)
+            .with_context(|| format!("Cannot find binary {}", binary.display()))?;
+    }
+
+    shutdown_server(settings, target, false, None)?;
+    if !wait_for_exit(target.pid, EXIT_TIMEOUT) {
+        bail!("{} did not exit within {:?}", target.pid, EXIT_TIMEOUT);
+    }
+
+    let log_file = spec.log_file(target.port);
+    let pid = spec.spawn(&log_file)?;
+    println!(
+        "{}: restarted as {} ({} {}), output in {}",
+        target.pid,
+        pid,
+        spec.binary.display(),
+        spec.args.join(" "),
+        log_file.display()
+    );
+    Ok(pid)
+}
@@ -0,0 +1,438 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// JSON-RPC 2.0 on stdin and stdout, one message per line, so an editor plugin can keep one mpf
+// running instead of polling the command line. Methods:
+//   scan {args?}                         matched processes as [{pid, type, port, summary, uri}]
+//   uri {pid}                            connection string of a server
+//   kill {pid, signal?}                  signal a mongo process, SIGTERM by default
+//   watch-subscribe {args?, interval_ms?} then watch-event notifications, first a running event
+//                                        for every process, then started, stopped and changed
+//   watch-unsubscribe {subscription}
+// args are filters as on the command line, e.g. ["-p", "20000-20010"].
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use super::client::Settings;
+use super::signal::{parse_signal, send_signal, signal_name};
+use super::watch::{change_event, diff, Snapshot};
+use super::{
+    connection_settings_over, filter_args, scan, select_pids, server_uri, watch_entry, Args,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// Reserved for implementation defined server errors
+const FAILED: i64 = -32000;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, PartialEq)]
+pub struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        RpcError {
+            code: FAILED,
+            message: format!("{:#}", e),
+        }
+    }
+}
+
+fn invalid_params(message: &str) -> RpcError {
+    RpcError {
+        code: INVALID_PARAMS,
+        message: message.to_owned(),
+    }
+}
+
+fn filters(params: &Value) -> Result<Vec<String>, RpcError> {
+    match params.get("args") {
+        None | Some(Value::Null) => Ok(Vec::new()),
+        Some(Value::Array(args)) => args
+            .iter()
+            .map(|a| {
+                a.as_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| invalid_params("args must be strings"))
+            })
+            .collect(),
+        Some(_) => Err(invalid_params("args must be an array of strings")),
+    }
+}
+
+fn pid_param(params: &Value) -> Result<i32, RpcError> {
+    params
+        .get("pid")
+        .and_then(Value::as_i64)
+        .and_then(|pid| i32::try_from(pid).ok())
+        .ok_or_else(|| invalid_params("pid must be a process id"))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Writes whole lines, shared by the responses and the watch threads
+type Output = Arc<Mutex<Box<dyn Write + Send>>>;
+
+fn send(out: &Output, message: &Value) {
+    let mut out = out.lock().unwrap();
+    // The client going away ends the session through stdin, nothing to do here
+    let _ = writeln!(out, "{}", message).and_then(|_| out.flush());
+}
+
+pub struct Server {
+    out: Output,
+    /// Connection settings from the command line that started the server
+    settings: Settings,
+    subscriptions: HashMap<u64, Arc<AtomicBool>>,
+    next_subscription: u64,
+}
+
+impl Server {
+    pub fn new(out: Box<dyn Write + Send>, settings: Settings) -> Self {
+        Server {
+            out: Arc::new(Mutex::new(out)),
+            settings,
+            subscriptions: HashMap::new(),
+            next_subscription: 1,
+        }
+    }
+
+    // Connection options among the arguments of a request replace those of the server for that
+    // request only
+    fn settings(&self, args: &Args) -> Result<Settings> {
+        connection_settings_over(args, &self.settings)
+    }
+
+    fn call(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "scan" => {
+                let args = filter_args(&filters(params)?)?;
+                let info = scan(&args, &self.settings(&args)?)?;
+                let processes: Vec<Value> = select_pids(&args, &info)
+                    .into_iter()
+                    .map(|pid| {
+                        let entry = watch_entry(&info, pid);
+                        json!({
+                            "pid": pid,
+                            "type": entry.kind,
+                            "port": entry.port,
+                            "summary": entry.line,
+                            "uri": info.server_target(pid).map(|t| server_uri(&info, &t)),
+                        })
+                    })
+                    .collect();
+                Ok(Value::Array(processes))
+            }
+            "uri" => {
+                let pid = pid_param(params)?;
+                let args = filter_args(&[])?;
+                let info = scan(&args, &self.settings(&args)?)?;
+                match info.server_target(pid) {
+                    Some(target) => Ok(json!(server_uri(&info, &target))),
+                    None => Err(invalid_params(&format!(
+                        "{} is not a mongod or mongos",
+                        pid
+                    ))),
+                }
+            }
+            "kill" => {
+                let pid = pid_param(params)?;
+                let signal = match params.get("signal") {
+                    None | Some(Value::Null) => libc::SIGTERM,
+                    Some(Value::String(name)) => {
+                        parse_signal(name).map_err(|e| invalid_params(&e))?
+                    }
+                    Some(Value::Number(n)) => n
+                        .as_i64()
+                        .and_then(|n| i32::try_from(n).ok())
+                        .ok_or_else(|| invalid_params("Bad signal number"))?,
+                    Some(_) => return Err(invalid_params("signal must be a name or number")),
+                };
+                // Only processes mpf found, the protocol is not a general kill
+                let args = filter_args(&[])?;
+                let info = scan(&args, &self.settings(&args)?)?;
+                if info.proc_info(pid).is_none() {
+                    return Err(invalid_params(&format!("{} is not a mongo process", pid)));
+                }
+                send_signal(pid, signal)?;
+                Ok(json!({"pid": pid, "signal": signal_name(signal)}))
+            }
+            "watch-subscribe" => {
+                let args = filter_args(&filters(params)?)?;
+                let interval = match params.get("interval_ms") {
+                    None | Some(Value::Null) => DEFAULT_INTERVAL,
+                    Some(ms) => Duration::from_millis(
+                        ms.as_u64()
+                            .filter(|ms| *ms > 0)
+                            .ok_or_else(|| invalid_params("interval_ms must be positive"))?,
+                    ),
+                };
+                let id = self.next_subscription;
+                self.next_subscription += 1;
+                let active = Arc::new(AtomicBool::new(true));
+                self.subscriptions.insert(id, active.clone());
+
+                let settings = self.settings(&args)?;
+                let out = self.out.clone();
+                let snapshot = move || -> Result<Snapshot> {
+                    let info = scan(&args, &settings)?;
+                    Ok(select_pids(&args, &info)
+                        .into_iter()
+                        .map(|pid| (pid, watch_entry(&info, pid)))
+                        .collect())
+                };
+                std::thread::spawn(move || watch(id, snapshot, interval, &active, &out));
+                Ok(json!({"subscription": id}))
+            }
+            "watch-unsubscribe" => {
+                let id = params
+                    .get("subscription")
+                    .and_then(Value::as_u64)
+                    .ok_or_else(|| invalid_params("subscription must be a subscription id"))?;
+                match self.subscriptions.remove(&id) {
+                    Some(active) => {
+                        active.store(false, Ordering::Relaxed);
+                        Ok(Value::Bool(true))
+                    }
+                    None => Err(invalid_params(&format!("No subscription {}", id))),
+                }
+            }
+            _ => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("No method {}", method),
+            }),
+        }
+    }
+
+    /// The response to one line, None for notifications, which get no response
+    pub fn handle(&mut self, line: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, &e.to_string())),
+        };
+        let id = request.get("id").cloned();
+        let method = request.get("method").and_then(Value::as_str);
+        let (Some(method), Some("2.0")) = (method, request.get("jsonrpc").and_then(Value::as_str))
+        else {
+            let message = "Expected a JSON-RPC 2.0 request with a method";
+            return Some(error_response(
+                id.unwrap_or_default(),
+                INVALID_REQUEST,
+                message,
+            ));
+        };
+        let params = request.get("params").cloned().unwrap_or(json!({}));
+        let result = self.call(method, &params);
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(e) => error_response(id, e.code, &e.message),
+        })
+    }
+
+    fn stop(&mut self) {
+        for (_, active) in self.subscriptions.drain() {
+            active.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+// Send the changes between scans as watch-event notifications until unsubscribed
+fn watch<F>(id: u64, mut snapshot: F, interval: Duration, active: &AtomicBool, out: &Output)
+where
+    F: FnMut() -> Result<Snapshot>,
+{
+    let notify = |mut event: Value| {
+        event["subscription"] = json!(id);
+        send(
+            out,
+            &json!({"jsonrpc": "2.0", "method": "watch-event", "params": event}),
+        );
+    };
+    let mut previous = Snapshot::new();
+    let mut first = true;
+    while active.load(Ordering::Relaxed) {
+        match snapshot() {
+            Ok(current) => {
+                for change in diff(&previous, &current) {
+                    let mut event = change_event(&change, now_secs());
+                    if first {
+                        event["event"] = json!("running");
+                    }
+                    notify(event);
+                }
+                previous = current;
+                first = false;
+            }
+            Err(e) => notify(json!({"event": "error", "message": format!("{:#}", e)})),
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Serve requests from stdin until it closes
+pub fn serve(settings: Settings) -> Result<()> {
+    let mut server = Server::new(Box::new(std::io::stdout()), settings);
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = server.handle(&line) {
+            send(&server.out, &response);
+        }
+    }
+    server.stop();
+    Ok(())
+}
+
+#[test]
+fn test_handle() {
+    let mut server = Server::new(Box::new(std::io::sink()), Settings::default());
+    let code = |response: Option<Value>| response.unwrap()["error"]["code"].as_i64();
+
+    assert_eq!(code(server.handle("{not json")), Some(PARSE_ERROR));
+    assert_eq!(code(server.handle(r#"{"id": 1}"#)), Some(INVALID_REQUEST));
+    assert_eq!(
+        code(server.handle(r#"{"jsonrpc": "2.0", "id": 2, "method": "reboot"}"#)),
+        Some(METHOD_NOT_FOUND)
+    );
+    assert_eq!(
+        code(server.handle(r#"{"jsonrpc": "2.0", "id": 3, "method": "kill", "params": {}}"#)),
+        Some(INVALID_PARAMS)
+    );
+    assert_eq!(
+        code(server.handle(
+            r#"{"jsonrpc": "2.0", "id": 4, "method": "scan", "params": {"args": ["--bogus"]}}"#
+        )),
+        Some(FAILED)
+    );
+    let response = server
+        .handle(r#"{"jsonrpc": "2.0", "id": "u", "method": "watch-unsubscribe", "params": {"subscription": 9}}"#)
+        .unwrap();
+    assert_eq!(response["id"], "u");
+    assert_eq!(response["error"]["message"], "No subscription 9");
+
+    // Notifications get no response, even when they fail
+    assert_eq!(
+        server.handle(r#"{"jsonrpc": "2.0", "method": "reboot"}"#),
+        None
+    );
+}
+
+#[test]
+fn test_watch_events() {
+    let buffer: Arc<Mutex<Vec<u8>>> = Arc::new(Mutex::new(Vec::new()));
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let out: Output = Arc::new(Mutex::new(Box::new(Shared(buffer.clone()))));
+
+    let entry = |port: i32| super::watch::Entry {
+        kind: "mongod".to_owned(),
+        port: Some(port),
+        line: format!("mongod {}", port),
+    };
+    let mut scans = vec![
+        std::collections::BTreeMap::from([(1, entry(20001)), (2, entry(20002))]),
+        std::collections::BTreeMap::from([(2, entry(20002))]),
+    ]
+    .into_iter();
+    let active = AtomicBool::new(true);
+    watch(
+        7,
+        || {
+            let snapshot = scans.next().unwrap();
+            if scans.len() == 0 {
+                active.store(false, Ordering::Relaxed);
+            }
+            Ok(snapshot)
+        },
+        Duration::ZERO,
+        &active,
+        &out,
+    );
+
+    let text = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+    let events: Vec<(String, i64)> = text
+        .lines()
+        .map(|l| serde_json::from_str::<Value>(l).unwrap())
+        .map(|m| {
+            assert_eq!(m["params"]["subscription"], 7);
+            (
+                m["params"]["event"].as_str().unwrap().to_owned(),
+                m["params"]["pid"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        events,
+        [
+            ("running".to_owned(), 1),
+            ("running".to_owned(), 2),
+            ("stopped".to_owned(), 1)
+        ]
+    );
+}
+
+#[test]
+fn test_scan_settings() {
+    let base = Settings {
+        timeout: Some(Duration::from_secs(2)),
+        ..Settings::default()
+    };
+    let server = Server::new(Box::new(std::io::sink()), base);
+    let settings = |args: &str| {
+        let args: Vec<String> = args.split_whitespace().map(str::to_owned).collect();
+        server.settings(&filter_args(&args).unwrap()).unwrap()
+    };
+
+    // Each request layers its own options over those of the server, not over an earlier request
+    let first = settings("-p 1 --connect-timeout 3s --tlsAllowInvalidCertificates");
+    assert_eq!(first.connect_timeout, Some(Duration::from_secs(3)));
+    assert_eq!(first.timeout, Some(Duration::from_secs(2)));
+    assert!(first.tls.is_some());
+
+    let second = settings("-p 1 --timeout 500ms");
+    assert_eq!(second.connect_timeout, None);
+    assert_eq!(second.timeout, Some(Duration::from_millis(500)));
+    assert!(second.tls.is_none());
+}
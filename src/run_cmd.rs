@@ -0,0 +1,67 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Run a command document given on the command line on every matched server at once
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use super::client::{Connection, Settings, DEFAULT_TIMEOUT};
+use super::fanout::{fan_out, Report};
+use super::probe::probe_all;
+use super::ServerTarget;
+
+/// Parse a command document, keeping the order of its fields since the first names the command
+pub fn parse_command(text: &str) -> Result<Value> {
+    let cmd: Value = serde_json::from_str(text)
+        .map_err(|e| anyhow::anyhow!("Command is not valid JSON: {}", e))?;
+    match cmd.as_object() {
+        Some(map) if !map.is_empty() => Ok(cmd),
+        _ => bail!("Command must be a non-empty JSON document, e.g. '{{\"ping\": 1}}'"),
+    }
+}
+
+fn run_one(settings: &Settings, target: &ServerTarget, db: &str, cmd: &Value) -> Result<Value> {
+    let mut conn = Connection::connect_local(settings, target.port, DEFAULT_TIMEOUT)?;
+    conn.run_command(db, cmd.clone())
+}
+
+/// Run the command on every target concurrently, returning the replies in the order of targets
+pub fn run_all(
+    settings: &Settings,
+    targets: &[ServerTarget],
+    db: &str,
+    cmd: &Value,
+) -> Vec<Result<Value>> {
+    probe_all(settings, targets, |t| run_one(settings, t, db, cmd))
+}
+
+/// Run the command on every target concurrently, reporting each reply or failure
+pub fn run_cmd(settings: &Settings, targets: &[ServerTarget], db: &str, cmd: &Value) -> Report {
+    fan_out(targets, settings.concurrency(), |t| {
+        run_one(settings, t, db, cmd)
+    })
+}
+
+#[test]
+fn test_parse_command() {
+    let cmd = parse_command(r#"{"setParameter": 1, "logLevel": 2}"#).unwrap();
+    assert_eq!(
+        cmd.as_object().unwrap().keys().next().map(|k| k.as_str()),
+        Some("setParameter")
+    );
+    assert!(parse_command("{}").is_err());
+    assert!(parse_command("[1]").is_err());
+    assert!(parse_command("setParameter").is_err());
+}
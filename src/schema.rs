@@ -0,0 +1,279 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Versions of the JSON summary. Bump SCHEMA_VERSION when fields are renamed or removed, or when
+// enough fields are added that scripts should be able to ask for the old shape.
+
+use anyhow::{bail, Result};
+use serde_json::{json, Map, Value};
+
+pub const SCHEMA_VERSION: u32 = 2;
+
+// Version 1 is the original output
+const V1_MONGOD_FIELDS: &[&str] = &["pid", "port", "server_type", "replica_set_name", "dbpath"];
+const V1_MONGOS_FIELDS: &[&str] = &["pid", "port", "configdb"];
+
+fn nullable(schema: Value) -> Value {
+    json!({"anyOf": [schema, {"type": "null"}]})
+}
+
+fn object(properties: Value) -> Value {
+    let required: Vec<&String> = properties.as_object().unwrap().keys().collect();
+    json!({"type": "object", "properties": properties, "required": required})
+}
+
+fn mongod_properties(version: u32) -> Value {
+    let mut properties = json!({
+        "pid": {"type": "integer"},
+        "port": {"type": "integer"},
+        "server_type": {"enum": ["Standalone", "ReplicaSet", "Config", "Shard"]},
+        "replica_set_name": nullable(json!({"type": "string"})),
+        "dbpath": nullable(json!({"type": "string"})),
+    });
+    if version >= 2 {
+        let map = properties.as_object_mut().unwrap();
+        map.insert("logpath".to_owned(), nullable(json!({"type": "string"})));
+        map.insert("fork".to_owned(), json!({"type": "boolean"}));
+        map.insert("pidfile".to_owned(), nullable(json!({"type": "string"})));
+        map.insert("user".to_owned(), nullable(json!({"type": "string"})));
+        map.insert("cwd".to_owned(), nullable(json!({"type": "string"})));
+        map.insert("listening_ports".to_owned(), listening_ports());
+        map.insert(
+            "special_modes".to_owned(),
+            json!({"type": "array", "items": {"type": "string"}}),
+        );
+        map.insert(
+            "storage".to_owned(),
+            object(json!({
+                "engine": {"type": "string"},
+                "cache_size_gb": nullable(json!({"type": "number"})),
+            })),
+        );
+        map.insert(
+            "encryption".to_owned(),
+            nullable(object(json!({
+                "key_source": {"enum": ["keyfile", "kmip"]},
+                "key_file": nullable(json!({"type": "string"})),
+                "kmip_server": nullable(json!({"type": "string"})),
+                "kmip_port": nullable(json!({"type": "integer"})),
+                "cipher_mode": nullable(json!({"type": "string"})),
+            }))),
+        );
+        add_common_v2(map);
+        map.insert(
+            "replication".to_owned(),
+            nullable(object(json!({
+                "state": {"type": "string"},
+                "lag_secs": nullable(json!({"type": "number"})),
+                "roles": {
+                    "type": "array",
+                    "items": {"enum": ["arbiter", "hidden", "delayed", "priority-zero", "votes-zero"]},
+                },
+                "tags": {"type": "object", "additionalProperties": {"type": "string"}},
+            }))),
+        );
+        map.insert("shard_name".to_owned(), nullable(json!({"type": "string"})));
+    }
+    properties
+}
+
+fn mongos_properties(version: u32) -> Value {
+    let mut properties = json!({
+        "pid": {"type": "integer"},
+        "port": {"type": "integer"},
+        "configdb": {"type": "string"},
+    });
+    if version >= 2 {
+        let map = properties.as_object_mut().unwrap();
+        map.insert("logpath".to_owned(), nullable(json!({"type": "string"})));
+        map.insert("fork".to_owned(), json!({"type": "boolean"}));
+        map.insert("pidfile".to_owned(), nullable(json!({"type": "string"})));
+        map.insert("user".to_owned(), nullable(json!({"type": "string"})));
+        map.insert("cwd".to_owned(), nullable(json!({"type": "string"})));
+        map.insert("listening_ports".to_owned(), listening_ports());
+        add_common_v2(map);
+    }
+    properties
+}
+
+fn listening_ports() -> Value {
+    nullable(json!({"type": "array", "items": {"type": "integer"}}))
+}
+
+fn add_common_v2(map: &mut Map<String, Value>) {
+    map.insert("set_parameters".to_owned(), json!({"type": "object"}));
+    map.insert("test_instance".to_owned(), json!({"type": "boolean"}));
+    map.insert(
+        "tls".to_owned(),
+        nullable(object(json!({
+            "mode": {"type": "string"},
+            "certificate_key_file": nullable(json!({"type": "string"})),
+            "ca_file": nullable(json!({"type": "string"})),
+            "cluster_file": nullable(json!({"type": "string"})),
+            "allow_connections_without_certificates": {"type": "boolean"},
+            "certificate_expiry": nullable(json!({"type": "string", "format": "date-time"})),
+            "certificate_expired": {"type": "boolean"},
+        }))),
+    );
+    map.insert(
+        "auth".to_owned(),
+        object(json!({
+            "enabled": {"type": "boolean"},
+            "mechanisms": {"type": "array", "items": {"type": "string"}},
+            "cluster_auth_mode": nullable(json!({"type": "string"})),
+            "key_file": nullable(json!({"type": "string"})),
+            "transition_to_auth": {"type": "boolean"},
+        })),
+    );
+    map.insert(
+        "unix_socket".to_owned(),
+        nullable(json!({"type": "string"})),
+    );
+    map.insert("start_dir".to_owned(), nullable(json!({"type": "string"})));
+}
+
+fn check_version(version: u32) -> Result<()> {
+    if version == 0 || version > SCHEMA_VERSION {
+        bail!(
+            "Unknown output version {}, versions 1 to {} are supported",
+            version,
+            SCHEMA_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// JSON Schema of the summary for an output version
+pub fn json_schema(version: u32) -> Result<Value> {
+    check_version(version)?;
+
+    let mut properties = json!({
+        "mongod": {"type": "array", "items": object(mongod_properties(version))},
+        "mongos": {"type": "array", "items": object(mongos_properties(version))},
+        "shell": {"type": "array", "items": {"type": "integer"}},
+    });
+    if version >= 2 {
+        let map = properties.as_object_mut().unwrap();
+        map.insert(
+            "schema_version".to_owned(),
+            json!({"type": "integer", "const": version}),
+        );
+        map.insert(
+            "mongocryptd".to_owned(),
+            json!({"type": "array", "items": object(json!({
+                "parent": nullable(object(json!({
+                    "pid": {"type": "integer"},
+                    "program": {"type": "string"},
+                    "cmdline": {"type": "string"},
+                }))),
+                "instances": {"type": "array", "items": object(json!({
+                    "pid": {"type": "integer"},
+                    "port": {"type": "integer"},
+                    "clients": {"type": "array", "items": {"type": "integer"}},
+                }))},
+            }))}),
+        );
+        let pids = json!({"type": "array", "items": {"type": "integer"}});
+        map.insert(
+            "clusters".to_owned(),
+            json!({"type": "array", "items": object(json!({
+                "config_replica_set": nullable(json!({"type": "string"})),
+                "config_servers": pids,
+                "mongos": pids,
+                "shards": {"type": "array", "items": object(json!({
+                    "name": {"type": "string"},
+                    "replica_set_name": nullable(json!({"type": "string"})),
+                    "pids": pids,
+                }))},
+            }))}),
+        );
+        map.insert(
+            "plugin_processes".to_owned(),
+            json!({"type": "array", "items": object(json!({
+                "pid": {"type": "integer"},
+                "type": {"type": "string"},
+                "plugin": {"type": "string"},
+                "fields": {"type": "object"},
+            }))}),
+        );
+    }
+
+    let mut schema = object(properties);
+    let map = schema.as_object_mut().unwrap();
+    map.insert(
+        "$schema".to_owned(),
+        json!("https://json-schema.org/draft/2020-12/schema"),
+    );
+    map.insert(
+        "title".to_owned(),
+        json!(format!("mpf output version {}", version)),
+    );
+    Ok(schema)
+}
+
+fn keep_fields(servers: Option<&mut Value>, fields: &[&str]) {
+    if let Some(Value::Array(servers)) = servers {
+        for server in servers.iter_mut().filter_map(|s| s.as_object_mut()) {
+            server.retain(|key, _| fields.contains(&key.as_str()));
+        }
+    }
+}
+
+/// Convert the current summary to the shape of an older output version
+pub fn to_output_version(mut summary: Value, version: u32) -> Result<Value> {
+    check_version(version)?;
+
+    if version == 1 {
+        let map = summary.as_object_mut().unwrap();
+        map.shift_remove("schema_version");
+        map.shift_remove("mongocryptd");
+        map.shift_remove("clusters");
+        map.shift_remove("plugin_processes");
+        keep_fields(map.get_mut("mongod"), V1_MONGOD_FIELDS);
+        keep_fields(map.get_mut("mongos"), V1_MONGOS_FIELDS);
+    }
+    Ok(summary)
+}
+
+#[test]
+fn test_to_output_version() {
+    let summary = json!({
+        "schema_version": 2,
+        "mongod": [{"pid": 1, "port": 20001, "server_type": "Standalone",
+                    "replica_set_name": null, "dbpath": null, "user": "me"}],
+        "mongos": [{"pid": 2, "port": 20000, "configdb": "configRS", "tls": null}],
+        "shell": [3],
+        "mongocryptd": [],
+        "clusters": [],
+        "plugin_processes": [],
+    });
+
+    assert_eq!(to_output_version(summary.clone(), 2).unwrap(), summary);
+    assert_eq!(
+        to_output_version(summary.clone(), 1).unwrap(),
+        json!({
+            "mongod": [{"pid": 1, "port": 20001, "server_type": "Standalone",
+                        "replica_set_name": null, "dbpath": null}],
+            "mongos": [{"pid": 2, "port": 20000, "configdb": "configRS"}],
+            "shell": [3],
+        })
+    );
+    assert!(to_output_version(summary, 3).is_err());
+
+    let schema = json_schema(SCHEMA_VERSION).unwrap();
+    assert!(schema["properties"]["mongod"]["items"]["required"]
+        .as_array()
+        .unwrap()
+        .contains(&json!("auth")));
+}
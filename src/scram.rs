@@ -0,0 +1,372 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// SCRAM-SHA-1 and SCRAM-SHA-256 authentication (RFC 5802, RFC 7677) over saslStart and
+// saslContinue
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use clap::ValueEnum;
+use hmac::digest::block_api::EagerHash;
+use hmac::{Hmac, KeyInit, Mac};
+use md5::Md5;
+use serde_json::{json, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use super::client::Connection;
+use super::credentials::Credentials;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Mechanism {
+    #[value(name = "SCRAM-SHA-1")]
+    ScramSha1,
+    #[value(name = "SCRAM-SHA-256")]
+    ScramSha256,
+}
+
+impl Mechanism {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mechanism::ScramSha1 => "SCRAM-SHA-1",
+            Mechanism::ScramSha256 => "SCRAM-SHA-256",
+        }
+    }
+
+    /// The mechanism of an authMechanism option, other mechanisms are not supported
+    pub fn from_name(name: &str) -> Result<Mechanism> {
+        Mechanism::from_str(name, false).map_err(|_| {
+            anyhow!(
+                "Authentication mechanism {} is not supported, use SCRAM-SHA-1 or SCRAM-SHA-256",
+                name
+            )
+        })
+    }
+
+    // SCRAM-SHA-1 salts the hex MD5 digest of user:mongo:password that the server stores
+    // instead of the password itself
+    fn password(self, creds: &Credentials) -> String {
+        match self {
+            Mechanism::ScramSha1 => hex::encode(Md5::digest(format!(
+                "{}:mongo:{}",
+                creds.username, creds.password
+            ))),
+            Mechanism::ScramSha256 => creds.password.clone(),
+        }
+    }
+
+    fn hmac(self, key: &[u8], message: &[u8]) -> Vec<u8> {
+        match self {
+            Mechanism::ScramSha1 => hmac::<Sha1>(key, message),
+            Mechanism::ScramSha256 => hmac::<Sha256>(key, message),
+        }
+    }
+
+    fn hash(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Mechanism::ScramSha1 => Sha1::digest(data).to_vec(),
+            Mechanism::ScramSha256 => Sha256::digest(data).to_vec(),
+        }
+    }
+
+    fn salt(self, password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        match self {
+            Mechanism::ScramSha1 => {
+                pbkdf2::pbkdf2_hmac_array::<Sha1, 20>(password, salt, iterations).to_vec()
+            }
+            Mechanism::ScramSha256 => {
+                pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(password, salt, iterations).to_vec()
+            }
+        }
+    }
+}
+
+fn hmac<D: EagerHash>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<D>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Salting takes thousands of rounds and every command opens a new connection, so keep the result
+// for each salt like the drivers do
+type SaltKey = (Mechanism, String, String, u32);
+static SALTED_PASSWORDS: Mutex<Option<HashMap<SaltKey, Vec<u8>>>> = Mutex::new(None);
+
+fn salted_password(
+    mechanism: Mechanism,
+    password: &str,
+    salt: &str,
+    iterations: u32,
+) -> Result<Vec<u8>> {
+    let key = (mechanism, password.to_owned(), salt.to_owned(), iterations);
+    let mut cache = SALTED_PASSWORDS
+        .lock()
+        .map_err(|_| anyhow!("Salted password cache is poisoned"))?;
+    let cache = cache.get_or_insert_with(HashMap::new);
+    if let Some(salted) = cache.get(&key) {
+        return Ok(salted.clone());
+    }
+    let salted = mechanism.salt(password.as_bytes(), &BASE64.decode(salt)?, iterations);
+    cache.insert(key, salted.clone());
+    Ok(salted)
+}
+
+// Names may not contain the separators of the SCRAM messages
+fn escape_username(username: &str) -> String {
+    username.replace('=', "=3D").replace(',', "=2C")
+}
+
+fn client_nonce() -> Result<String> {
+    let mut bytes = [0u8; 24];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .context("Failed to read /dev/urandom")?;
+    Ok(BASE64.encode(bytes))
+}
+
+fn attribute(message: &str, name: char) -> Result<&str> {
+    message
+        .split(',')
+        .find_map(|part| part.strip_prefix(name)?.strip_prefix('='))
+        .ok_or_else(|| anyhow!("SCRAM message is missing '{}': {}", name, message))
+}
+
+fn payload(reply: &Value) -> Result<String> {
+    let base64 = reply
+        .get("payload")
+        .and_then(|p| p.get("$binary"))
+        .and_then(|b| b.get("base64"))
+        .and_then(|b| b.as_str())
+        .ok_or_else(|| anyhow!("SASL reply has no payload"))?;
+    Ok(String::from_utf8(BASE64.decode(base64)?)?)
+}
+
+fn binary(message: &str) -> Value {
+    json!({"$binary": {"base64": BASE64.encode(message.as_bytes()), "subType": "00"}})
+}
+
+/// Client side of one conversation, split from the connection so it can be tested
+struct Conversation {
+    mechanism: Mechanism,
+    client_first_bare: String,
+    nonce: String,
+    server_signature: Option<Vec<u8>>,
+}
+
+impl Conversation {
+    fn new(mechanism: Mechanism, username: &str, nonce: String) -> Conversation {
+        Conversation {
+            mechanism,
+            client_first_bare: format!("n={},r={}", escape_username(username), nonce),
+            nonce,
+            server_signature: None,
+        }
+    }
+
+    fn client_first(&self) -> String {
+        format!("n,,{}", self.client_first_bare)
+    }
+
+    fn client_final(&mut self, password: &str, server_first: &str) -> Result<String> {
+        let server_nonce = attribute(server_first, 'r')?;
+        if !server_nonce.starts_with(&self.nonce) {
+            bail!("Server nonce does not extend the client nonce");
+        }
+        let salt = attribute(server_first, 's')?;
+        let iterations: u32 = attribute(server_first, 'i')?
+            .parse()
+            .context("Bad SCRAM iteration count")?;
+
+        let without_proof = format!("c=biws,r={}", server_nonce);
+        let auth_message = format!(
+            "{},{},{}",
+            self.client_first_bare, server_first, without_proof
+        );
+
+        let mechanism = self.mechanism;
+        let salted = salted_password(mechanism, password, salt, iterations)?;
+        let client_key = mechanism.hmac(&salted, b"Client Key");
+        let client_signature =
+            mechanism.hmac(&mechanism.hash(&client_key), auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(client_signature)
+            .map(|(k, s)| k ^ s)
+            .collect();
+        let server_key = mechanism.hmac(&salted, b"Server Key");
+        self.server_signature = Some(mechanism.hmac(&server_key, auth_message.as_bytes()));
+
+        Ok(format!("{},p={}", without_proof, BASE64.encode(proof)))
+    }
+
+    fn verify(&self, server_final: &str) -> Result<()> {
+        if let Ok(e) = attribute(server_final, 'e') {
+            bail!("Authentication failed: {}", e);
+        }
+        let expected = self
+            .server_signature
+            .as_ref()
+            .map(|s| BASE64.encode(s))
+            .unwrap_or_default();
+        if attribute(server_final, 'v')? != expected {
+            bail!("Server signature does not match, the server may not know this user");
+        }
+        Ok(())
+    }
+}
+
+// The mechanism asked for, else the best one the server offers for the user like the drivers
+// pick it. Servers that do not list the user's mechanisms get SCRAM-SHA-256.
+fn negotiate(conn: &mut Connection, creds: &Credentials) -> Result<Mechanism> {
+    if let Some(mechanism) = creds.mechanism {
+        return Ok(mechanism);
+    }
+    let hello = conn.run_command_raw(
+        "admin",
+        json!({"hello": 1, "saslSupportedMechs": format!("{}.{}", creds.source, creds.username)}),
+    )?;
+    let Some(offered) = hello.get("saslSupportedMechs").and_then(Value::as_array) else {
+        return Ok(Mechanism::ScramSha256);
+    };
+    let offered: Vec<&str> = offered.iter().filter_map(Value::as_str).collect();
+    [Mechanism::ScramSha256, Mechanism::ScramSha1]
+        .into_iter()
+        .find(|m| offered.contains(&m.name()))
+        .ok_or_else(|| {
+            anyhow!(
+                "{} can only authenticate with {}, mpf supports SCRAM-SHA-1 and SCRAM-SHA-256",
+                creds.username,
+                offered.join(", ")
+            )
+        })
+}
+
+/// Authenticate the connection with SCRAM-SHA-256 or SCRAM-SHA-1
+pub fn authenticate(conn: &mut Connection, creds: &Credentials) -> Result<()> {
+    let mechanism = negotiate(conn, creds)?;
+    let mut conversation = Conversation::new(mechanism, &creds.username, client_nonce()?);
+    let reply = conn.run_command(
+        &creds.source,
+        json!({
+            "saslStart": 1,
+            "mechanism": mechanism.name(),
+            "payload": binary(&conversation.client_first()),
+            "autoAuthorize": 1,
+            "options": {"skipEmptyExchange": true},
+        }),
+    )?;
+    let conversation_id = reply.get("conversationId").cloned().unwrap_or(json!(1));
+
+    let password = mechanism.password(creds);
+    let client_final = conversation.client_final(&password, &payload(&reply)?)?;
+    let mut reply = conn.run_command(
+        &creds.source,
+        json!({
+            "saslContinue": 1,
+            "conversationId": conversation_id,
+            "payload": binary(&client_final),
+        }),
+    )?;
+    conversation.verify(&payload(&reply)?)?;
+
+    // Servers without skipEmptyExchange want one more empty round
+    while !reply.get("done").and_then(|d| d.as_bool()).unwrap_or(true) {
+        reply = conn.run_command(
+            &creds.source,
+            json!({
+                "saslContinue": 1,
+                "conversationId": conversation_id,
+                "payload": binary(""),
+            }),
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_conversation() {
+    // The example exchange from RFC 7677
+    let mut conversation = Conversation::new(
+        Mechanism::ScramSha256,
+        "user",
+        "rOprNGfwEbeRWgbNEkqO".to_owned(),
+    );
+    assert_eq!(
+        conversation.client_first(),
+        "n,,n=user,r=rOprNGfwEbeRWgbNEkqO"
+    );
+
+    let server_first = "r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                        s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+    assert_eq!(
+        conversation.client_final("pencil", server_first).unwrap(),
+        "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+         p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+    );
+    conversation
+        .verify("v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=")
+        .unwrap();
+    assert!(conversation.verify("v=AAAA").is_err());
+    assert!(conversation.verify("e=invalid-proof").is_err());
+
+    // The example exchange from RFC 5802
+    let mut conversation = Conversation::new(
+        Mechanism::ScramSha1,
+        "user",
+        "fyko+d2lbbFgONRv9qkxdawL".to_owned(),
+    );
+    let server_first = "r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,\
+                        s=QSXCR+Q6sek8bf92,i=4096";
+    assert_eq!(
+        conversation.client_final("pencil", server_first).unwrap(),
+        "c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts="
+    );
+    conversation
+        .verify("v=rmF9pqV8S7suAoZWja4dJRkFsKQ=")
+        .unwrap();
+
+    assert_eq!(escape_username("a=b,c"), "a=3Db=2Cc");
+    assert!(
+        Conversation::new(Mechanism::ScramSha256, "user", "abc".to_owned())
+            .client_final("pencil", "r=xyz,s=QUJD,i=1")
+            .is_err()
+    );
+}
+
+#[test]
+fn test_mechanism() {
+    let creds = Credentials {
+        username: "user".to_owned(),
+        password: "pencil".to_owned(),
+        source: "admin".to_owned(),
+        mechanism: None,
+    };
+    // What mongod stores for SCRAM-SHA-1 users
+    assert_eq!(
+        Mechanism::ScramSha1.password(&creds),
+        "1c33006ec1ffd90f9cadcbcc0e118200"
+    );
+    assert_eq!(Mechanism::ScramSha256.password(&creds), "pencil");
+
+    assert_eq!(
+        Mechanism::from_name("SCRAM-SHA-1").unwrap(),
+        Mechanism::ScramSha1
+    );
+    assert!(Mechanism::from_name("MONGODB-X509").is_err());
+}
@@ -0,0 +1,214 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+// Filter scripts for --filter-script, written in Rhai. A script sees one process as `process` and
+// ends with true or false, or with a map like #{keep: true, disk: "nvme"} whose other entries are
+// annotations printed with the match. Scripts are limited in how much work they do, so a loop
+// that never ends fails the script instead of hanging mpf.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use rhai::{Dynamic, Engine, Scope, AST};
+use serde_json::{Map, Value};
+
+// Operations a script may run per process, far more than any filter needs
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    // Misspelled variables fail when the script is parsed, not on every process
+    engine.set_strict_variables(true);
+    // Fields of a missing field read as () too, e.g. process.replication.state on a standalone
+    engine.register_indexer_get(|_: &mut (), _: &str| ());
+    engine
+}
+
+/// A parsed filter script
+#[derive(Clone)]
+pub struct Script {
+    engine: Arc<Engine>,
+    ast: AST,
+}
+
+impl std::fmt::Debug for Script {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Script")
+    }
+}
+
+/// Parse a script, reporting the line and position of the first syntax error
+pub fn parse(text: &str) -> Result<Script> {
+    let engine = engine();
+    let mut scope = Scope::new();
+    scope.push_dynamic("process", Dynamic::UNIT);
+    let ast = engine
+        .compile_with_scope(&scope, text)
+        .map_err(|e| anyhow!("{}", e))?;
+    Ok(Script {
+        engine: Arc::new(engine),
+        ast,
+    })
+}
+
+/// Read and parse a script file, for clap
+pub fn parse_file(path: &str) -> Result<Script, String> {
+    let text = std::fs::read_to_string(Path::new(path))
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// A value as text, strings without quotes
+pub fn display(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+/// What a script decided about one process
+#[derive(Debug, PartialEq)]
+pub struct Verdict {
+    pub keep: bool,
+    /// The other entries of a map result, by name
+    pub annotations: Map<String, Value>,
+}
+
+impl Script {
+    /// Run the script with process bound to record
+    pub fn run(&self, record: &Value) -> Result<Verdict> {
+        let mut scope = Scope::new();
+        scope.push_dynamic("process", rhai::serde::to_dynamic(record)?);
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| anyhow!("{}", e))?;
+
+        let type_name = result.type_name();
+        match rhai::serde::from_dynamic::<Value>(&result)? {
+            Value::Bool(keep) => Ok(Verdict {
+                keep,
+                annotations: Map::new(),
+            }),
+            Value::Object(mut map) => match map.shift_remove("keep") {
+                Some(Value::Bool(keep)) => Ok(Verdict {
+                    keep,
+                    annotations: map,
+                }),
+                _ => bail!("A map result needs keep: true or keep: false"),
+            },
+            _ => bail!(
+                "The script must end with a bool or a map with keep, not {}",
+                type_name
+            ),
+        }
+    }
+}
+#[test]
+fn test_script() {
+    let record = serde_json::json!({
+        "type": "mongod",
+        "server_type": "Shard",
+        "dbpath": "/mnt/nvme/shard1-2",
+        "replica_set_index": 2,
+        "replication": null,
+        "cmdline": ["mongod", "--port", "20002"],
+    });
+    let run = |text: &str| parse(text).unwrap().run(&record).unwrap();
+
+    let example = r#"
+        // Shards on the fast disks, every other member
+        process.server_type == "Shard"
+            && process.dbpath.starts_with("/mnt/nvme")
+            && process.replica_set_index % 2 == 0
+    "#;
+    assert!(run(example).keep);
+    assert!(!run("process.replication.state == \"PRIMARY\"").keep);
+
+    let annotated = r#"
+        let disk = process.dbpath.split("/")[2];
+        if process.cmdline.len() < 3 { return false; }
+        #{keep: disk == "nvme", disk: disk, port: parse_int(process.cmdline[-1]) + 1}
+    "#;
+    let verdict = run(annotated);
+    assert!(verdict.keep);
+    assert_eq!(
+        Value::Object(verdict.annotations),
+        serde_json::json!({"disk": "nvme", "port": 20003})
+    );
+
+    assert!(run("if 1 + 2 * 3 == 7 { true } else { false }").keep);
+    assert!(parse("process.port ==").is_err());
+    assert!(parse("let x = \"open").is_err());
+    assert!(parse("process.dbpath + 1").unwrap().run(&record).is_err());
+    assert!(parse("process.port % 2 == 0")
+        .unwrap()
+        .run(&record)
+        .is_err());
+    assert!(parse("42").unwrap().run(&record).is_err());
+}
+
+#[test]
+fn test_script_language() {
+    let record = serde_json::json!({"port": 20001, "name": "shard01", "ratio": 0.5});
+    let keep = |text: &str| parse(text).unwrap().run(&record).unwrap().keep;
+
+    // Precedence: * before +, comparisons before &&, && before ||, unary ! binds tightest
+    assert!(keep("1 + 2 * 3 == 7"));
+    assert!(keep("(1 + 2) * 3 == 9"));
+    assert!(keep("false && false || true"));
+    assert!(!keep("false && (false || true)"));
+    assert!(keep("!false && 10 - 2 - 3 == 5"));
+    assert!(keep("process.port % 2 == 1 && process.port / 10 == 2000"));
+
+    // Strings compare by text, numbers by value across integers and floats, and a string is
+    // never equal to a number
+    assert!(keep(
+        r#"process.name < "shard02" && process.name >= "shard01""#
+    ));
+    assert!(keep("process.ratio < 1 && 1 < 1.5"));
+    assert!(!keep(r#"process.port == "20001""#));
+    assert!(keep(r#"process.name + process.port == "shard0120001""#));
+
+    // Unknown variables are parse errors, unknown fields read as ()
+    let e = parse("proces.port == 20001").unwrap_err().to_string();
+    assert!(
+        e.contains("proces") && e.contains("line 1, position 1"),
+        "{}",
+        e
+    );
+    assert!(keep(
+        "process.missing == () && process.missing.deeper == ()"
+    ));
+    assert!(parse("process.port.len() > 1")
+        .unwrap()
+        .run(&record)
+        .is_err());
+
+    // Syntax errors say where
+    let e = parse(
+        "let port = process.port;
+port ==",
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(e.contains("line 2, position 8"), "{}", e);
+    let e = parse("let x = ;").unwrap_err().to_string();
+    assert!(e.contains("line 1, position 9"), "{}", e);
+
+    // A script that never ends fails instead of hanging mpf
+    assert!(parse("loop {}").unwrap().run(&record).is_err());
+}
@@ -0,0 +1,275 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Replace the running binary with the latest GitHub release for this platform. The release
+// workflow signs every archive with the minisign key whose public half is minisign.pub, and
+// nothing is unpacked until the downloaded archive matches its signature.
+
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use minisign_verify::{PublicKey, Signature};
+use serde_json::Value;
+
+const PUBLIC_KEY: &str = include_str!("../minisign.pub");
+
+// Far above any mpf archive, ureq stops at 10MB by default
+const MAX_DOWNLOAD: u64 = 256 * 1024 * 1024;
+
+/// The latest release and the archive of it built for this platform
+#[derive(Debug, PartialEq)]
+pub struct Update {
+    pub version: String,
+    pub archive: String,
+    pub archive_url: String,
+    pub signature_url: String,
+}
+
+/// The target triple the release archives are named after, as in the cargo-dist targets
+pub fn current_target() -> Result<String> {
+    let arch = if cfg!(target_arch = "x86_64") {
+        "x86_64"
+    } else if cfg!(target_arch = "aarch64") {
+        "aarch64"
+    } else {
+        bail!("No mpf releases are built for this architecture");
+    };
+    let os = if cfg!(target_os = "linux") {
+        "unknown-linux-gnu"
+    } else if cfg!(target_os = "macos") {
+        "apple-darwin"
+    } else {
+        bail!("No mpf releases are built for this operating system");
+    };
+    Ok(format!("{}-{}", arch, os))
+}
+
+fn latest_release_url() -> String {
+    let repo = env!("CARGO_PKG_REPOSITORY").trim_start_matches("https://github.com/");
+    format!("https://api.github.com/repos/{}/releases/latest", repo)
+}
+
+// Version numbers of a tag like v0.2.3, compared numerically
+fn version_parts(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches('v')
+        .split(['.', '-'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+/// Whether the release tagged tag is newer than version
+pub fn is_newer(tag: &str, version: &str) -> bool {
+    version_parts(tag) > version_parts(version)
+}
+
+/// The archive for target in a GitHub release reply, with the signature published next to it
+pub fn find_update(release: &Value, target: &str) -> Result<Update> {
+    let version = release
+        .get("tag_name")
+        .and_then(Value::as_str)
+        .context("Release has no tag_name")?;
+    let assets: Vec<(&str, &str)> = release
+        .get("assets")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|a| {
+            Some((
+                a.get("name")?.as_str()?,
+                a.get("browser_download_url")?.as_str()?,
+            ))
+        })
+        .collect();
+    let archive = format!("mpf-{}.tar.gz", target);
+    let (_, archive_url) = assets
+        .iter()
+        .find(|(name, _)| *name == archive)
+        .with_context(|| format!("Release {} has no archive for {}", version, target))?;
+    let signature = format!("{}.minisig", archive);
+    let (_, signature_url) = assets
+        .iter()
+        .find(|(name, _)| *name == signature)
+        .with_context(|| format!("Release {} publishes no {}", version, signature))?;
+    Ok(Update {
+        version: version.to_owned(),
+        archive,
+        archive_url: archive_url.to_string(),
+        signature_url: signature_url.to_string(),
+    })
+}
+
+/// Check data against a minisign signature made with the secret half of public_key
+pub fn verify(public_key: &str, data: &[u8], signature: &str) -> Result<()> {
+    let key = PublicKey::decode(public_key).context("Failed to parse the release public key")?;
+    let signature = Signature::decode(signature).context("Failed to parse the signature")?;
+    key.verify(data, &signature, false)
+        .context("Signature does not match")
+}
+
+fn fetch(url: &str) -> Result<Vec<u8>> {
+    let agent = ureq::Agent::config_builder()
+        .https_only(true)
+        .user_agent("mpf")
+        .build()
+        .new_agent();
+    agent
+        .get(url)
+        .call()
+        .and_then(|mut response| {
+            response
+                .body_mut()
+                .with_config()
+                .limit(MAX_DOWNLOAD)
+                .read_to_vec()
+        })
+        .with_context(|| format!("Failed to download {}", url))
+}
+
+/// The latest release for this platform, None when it is not newer than the running binary
+pub fn check(force: bool) -> Result<Option<Update>> {
+    let body = fetch(&latest_release_url())?;
+    let release: Value =
+        serde_json::from_slice(&body).context("Failed to parse the latest GitHub release")?;
+    let update = find_update(&release, &current_target()?)?;
+    if force || is_newer(&update.version, env!("CARGO_PKG_VERSION")) {
+        Ok(Some(update))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The mpf binary in a gzipped tar, cargo-dist puts it in a directory named after the archive
+pub fn unpack_binary(archive: &[u8]) -> Result<Vec<u8>> {
+    let mut tar = tar::Archive::new(GzDecoder::new(archive));
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file()
+            && entry.path()?.file_name().is_some_and(|n| n == "mpf")
+        {
+            let mut binary = Vec::new();
+            entry.read_to_end(&mut binary)?;
+            return Ok(binary);
+        }
+    }
+    bail!("No mpf binary in the archive")
+}
+
+/// Download and verify update, then swap it in for the running binary with a rename so the
+/// old binary stays in place if anything fails
+pub fn install(update: &Update) -> Result<PathBuf> {
+    let exe = std::env::current_exe()
+        .and_then(|p| p.canonicalize())
+        .context("Failed to find the running binary")?;
+    let dir = exe
+        .parent()
+        .context("The running binary has no directory")?;
+
+    let signature = String::from_utf8_lossy(&fetch(&update.signature_url)?).into_owned();
+    let archive = fetch(&update.archive_url)?;
+    verify(PUBLIC_KEY, &archive, &signature)
+        .with_context(|| format!("Refusing to install {}", update.archive))?;
+    let binary =
+        unpack_binary(&archive).with_context(|| format!("Failed to unpack {}", update.archive))?;
+
+    // Write next to the binary so the final rename stays on one filesystem
+    let staged = dir.join(format!(".mpf-update-{}", std::process::id()));
+    let result = (|| {
+        std::fs::write(&staged, &binary)
+            .with_context(|| format!("Failed to write {}", staged.display()))?;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))?;
+        std::fs::rename(&staged, &exe)
+            .with_context(|| format!("Failed to replace {}", exe.display()))
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&staged);
+    }
+    result?;
+    Ok(exe)
+}
+
+#[test]
+fn test_find_update() {
+    let release = serde_json::json!({
+        "tag_name": "v0.3.0",
+        "assets": [
+            {"name": "mpf-installer.sh", "browser_download_url": "https://x/mpf-installer.sh"},
+            {"name": "mpf-x86_64-unknown-linux-gnu.tar.gz.minisig",
+             "browser_download_url": "https://x/linux.minisig"},
+            {"name": "mpf-x86_64-unknown-linux-gnu.tar.gz",
+             "browser_download_url": "https://x/linux.tar.gz"},
+            {"name": "mpf-aarch64-apple-darwin.tar.gz",
+             "browser_download_url": "https://x/mac.tar.gz"},
+        ],
+    });
+    assert_eq!(
+        find_update(&release, "x86_64-unknown-linux-gnu").unwrap(),
+        Update {
+            version: "v0.3.0".to_owned(),
+            archive: "mpf-x86_64-unknown-linux-gnu.tar.gz".to_owned(),
+            archive_url: "https://x/linux.tar.gz".to_owned(),
+            signature_url: "https://x/linux.minisig".to_owned(),
+        }
+    );
+    // Without a published signature there is nothing to verify against
+    assert!(find_update(&release, "aarch64-apple-darwin").is_err());
+    assert!(find_update(&release, "x86_64-apple-darwin").is_err());
+
+    assert!(is_newer("v0.10.0", "0.9.3"));
+    assert!(!is_newer("v0.2.2", "0.2.2"));
+    assert!(PublicKey::decode(PUBLIC_KEY).is_ok());
+}
+
+#[test]
+fn test_verify() {
+    // A throwaway key pair, not the release key, and a signature of "mpf release\n" made with it
+    let key = concat!(
+        "untrusted comment: minisign public key: F45BE742D49097ED\n",
+        "RWTtl5DUQudb9HcHN5UwuhGZCsOLhZHiYBuWqT2NsDqWE3MJbEQs1dvR\n",
+    );
+    let signature = concat!(
+        "untrusted comment: signature from rsign secret key\n",
+        "RUTtl5DUQudb9HaEnZXAuzR9wBXVUWkOuBKWACoJQWgWS9qld4h+8eg1haieFLjrpnkwSh93huk92omM1RXnNESpZ",
+        "GawDv92EA8=\n",
+        "trusted comment: timestamp:1792075321\tfile:/tmp/blob\tprehashed\n",
+        "vuru6PVU4wXTSBi2Gf+Bua8sSZhr+XlaloXStkLIselligt7CapBdFkg1I+48hxTeqeUeSRqnNKyERY7jD+hCQ==\n",
+    );
+    verify(key, b"mpf release\n", signature).unwrap();
+    assert!(verify(key, b"mpf release 2\n", signature).is_err());
+    assert!(verify(PUBLIC_KEY, b"mpf release\n", signature).is_err());
+    assert!(verify(key, b"mpf release\n", "404: Not Found").is_err());
+}
+
+#[test]
+fn test_unpack_binary() {
+    use flate2::{write::GzEncoder, Compression};
+
+    let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    for (path, data) in [
+        ("mpf-x86_64-unknown-linux-gnu/README.md", &b"readme"[..]),
+        ("mpf-x86_64-unknown-linux-gnu/mpf", &b"\x7fELF"[..]),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o755);
+        tar.append_data(&mut header, path, data).unwrap();
+    }
+    let archive = tar.into_inner().unwrap().finish().unwrap();
+    assert_eq!(unpack_binary(&archive).unwrap(), b"\x7fELF");
+    assert!(unpack_binary(b"not a tarball").is_err());
+}
@@ -0,0 +1,164 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Set runtime parameters on every matched server and read them back to confirm they took
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{Map, Value};
+
+use super::client::Settings;
+use super::run_cmd::run_all;
+use super::ServerTarget;
+
+/// Parse name=value, typing the value as JSON when it parses (2, true, {"replication": 2}) and
+/// as a string otherwise
+pub fn parse_assignment(s: &str) -> Result<(String, Value), String> {
+    let Some((name, value)) = s.split_once('=').filter(|(name, _)| !name.is_empty()) else {
+        return Err(format!("Invalid parameter {}, expected name=value", s));
+    };
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()));
+    Ok((name.to_owned(), value))
+}
+
+fn command(first: &str, params: &[(String, Value)], value: impl Fn(&Value) -> Value) -> Value {
+    let mut cmd = Map::new();
+    cmd.insert(first.to_owned(), Value::from(1));
+    for (name, v) in params {
+        cmd.insert(name.clone(), value(v));
+    }
+    Value::Object(cmd)
+}
+
+// Whether a value read back holds what was set: numbers compare by value and documents only
+// need the fields that were set, e.g. logComponentVerbosity reports every component
+fn applied(wanted: &Value, actual: &Value) -> bool {
+    match (wanted, actual) {
+        (Value::Number(w), Value::Number(a)) => w.as_f64() == a.as_f64(),
+        (Value::Object(w), Value::Object(a)) => w
+            .iter()
+            .all(|(k, v)| a.get(k).is_some_and(|actual| applied(v, actual))),
+        (w, a) => w == a,
+    }
+}
+
+/// Parameters whose value read back differs from what was set, with the value read
+fn not_applied(params: &[(String, Value)], reply: &Value) -> Vec<String> {
+    params
+        .iter()
+        .filter_map(|(name, wanted)| {
+            let actual = reply.get(name).unwrap_or(&Value::Null);
+            if applied(wanted, actual) {
+                None
+            } else {
+                Some(format!("{} is {} instead of {}", name, actual, wanted))
+            }
+        })
+        .collect()
+}
+
+/// Set the parameters on every target, then read them back and report the servers where they
+/// did not take
+pub fn set_parameters(
+    settings: &Settings,
+    targets: &[ServerTarget],
+    params: &[(String, Value)],
+) -> Result<()> {
+    let set = run_all(
+        settings,
+        targets,
+        "admin",
+        &command("setParameter", params, |v| v.clone()),
+    );
+    let read = run_all(
+        settings,
+        targets,
+        "admin",
+        &command("getParameter", params, |_| Value::from(1)),
+    );
+
+    let mut failures = 0;
+    for ((t, set), read) in targets.iter().zip(set).zip(read) {
+        let result = set.and_then(|reply| {
+            let problems = not_applied(params, &read?);
+            if !problems.is_empty() {
+                return Err(anyhow!(problems.join(", ")));
+            }
+            Ok(reply)
+        });
+        match result {
+            Ok(reply) => {
+                let was = reply.get("was").map(|w| format!(" (was {})", w));
+                let values: Vec<String> = params
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect();
+                println!(
+                    "{} port {}: set {}{}",
+                    t.pid,
+                    t.port,
+                    values.join(" "),
+                    was.unwrap_or_default()
+                );
+            }
+            Err(e) => {
+                eprintln!("{} port {}: {}", t.pid, t.port, e);
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        bail!("Failed on {} of {} server(s)", failures, targets.len());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_assignment() {
+    use serde_json::json;
+
+    assert_eq!(
+        parse_assignment("logLevel=2"),
+        Ok(("logLevel".to_owned(), json!(2)))
+    );
+    assert_eq!(
+        parse_assignment("ttlMonitorEnabled=false"),
+        Ok(("ttlMonitorEnabled".to_owned(), json!(false)))
+    );
+    assert_eq!(
+        parse_assignment("redactClientLogData=on"),
+        Ok(("redactClientLogData".to_owned(), json!("on")))
+    );
+    assert!(parse_assignment("=2").is_err());
+
+    let params = vec![
+        ("logLevel".to_owned(), json!(2)),
+        (
+            "logComponentVerbosity".to_owned(),
+            json!({"replication": 1}),
+        ),
+    ];
+    let reply = json!({
+        "logLevel": 2.0,
+        "logComponentVerbosity": {"verbosity": 2, "replication": 1},
+    });
+    assert!(not_applied(&params, &reply).is_empty());
+    assert_eq!(
+        not_applied(&params, &json!({"logLevel": 0})),
+        vec![
+            "logLevel is 0 instead of 2",
+            "logComponentVerbosity is null instead of {\"replication\":1}"
+        ]
+    );
+}
@@ -0,0 +1,469 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Sharded cluster state from a mongos, mapped back to local processes
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use super::client::{Connection, DEFAULT_TIMEOUT};
+use super::probe::probe_all;
+use super::{get_cmd_line_option, MongoDType, MongoPSInfo, ServerTarget};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shard {
+    pub name: String,
+    /// Connection string, e.g. shard01/localhost:20001,localhost:20002
+    pub host: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Migration {
+    pub ns: String,
+    pub from: String,
+    pub to: String,
+    pub secs_running: i64,
+}
+
+/// Ports of the hosts in a shard connection string
+pub fn host_ports(host: &str) -> Vec<i32> {
+    let hosts = host.split_once('/').map_or(host, |(_, hosts)| hosts);
+    hosts
+        .split(',')
+        .filter_map(|h| h.rsplit_once(':')?.1.parse().ok())
+        .collect()
+}
+
+/// Pids of the local mongods serving a shard
+pub fn shard_pids(info: &MongoPSInfo, host: &str) -> Vec<i32> {
+    let ports = host_ports(host);
+    info.mongod
+        .iter()
+        .filter(|d| ports.contains(&d.port))
+        .map(|d| d.pid)
+        .collect()
+}
+
+/// Matched mongos routers grouped by the config server replica set of their cluster, each sorted
+/// by port
+pub fn routers(info: &MongoPSInfo, pids: &[i32]) -> BTreeMap<String, Vec<ServerTarget>> {
+    let mut clusters: BTreeMap<String, Vec<ServerTarget>> = BTreeMap::new();
+    for s in info.mongos.iter().filter(|s| pids.contains(&s.pid)) {
+        clusters
+            .entry(s.configdb.clone())
+            .or_default()
+            .push(ServerTarget {
+                pid: s.pid,
+                port: s.port,
+            });
+    }
+    for routers in clusters.values_mut() {
+        routers.sort_by_key(|t| t.port);
+    }
+    clusters
+}
+
+/// One router per cluster, the one at index or else the first that answers. Clusters without a
+/// router at index are skipped.
+pub fn pick_routers<F>(
+    clusters: &BTreeMap<String, Vec<ServerTarget>>,
+    index: Option<usize>,
+    answers: F,
+) -> Vec<i32>
+where
+    F: Fn(&ServerTarget) -> bool,
+{
+    clusters
+        .values()
+        .filter_map(|routers| match index {
+            Some(i) => routers.get(i),
+            // Nothing answering still gives the same router every time
+            None => routers.iter().find(|t| answers(t)).or(routers.first()),
+        })
+        .map(|t| t.pid)
+        .collect()
+}
+
+/// Local ports of the config servers of each cluster, from the hosts in each mongos --configdb
+/// and the local config server mongods
+fn config_server_ports(info: &MongoPSInfo) -> BTreeMap<String, Vec<i32>> {
+    let mut clusters: BTreeMap<String, Vec<i32>> = BTreeMap::new();
+    for s in &info.mongos {
+        let Some(proc) = info.proc_info(s.pid) else {
+            continue;
+        };
+        if let Some(configdb) = get_cmd_line_option("--configdb", &proc.cmdline) {
+            clusters
+                .entry(s.configdb.clone())
+                .or_default()
+                .extend(host_ports(&configdb));
+        }
+    }
+    for d in info
+        .mongod
+        .iter()
+        .filter(|d| d.server_type == MongoDType::Config)
+    {
+        clusters
+            .entry(d.replica_set_name.clone().unwrap_or_default())
+            .or_default()
+            .push(d.port);
+    }
+    for ports in clusters.values_mut() {
+        ports.sort();
+        ports.dedup();
+    }
+    clusters
+}
+
+/// Shards registered in config.shards of each local cluster by config server replica set,
+/// asking the first config server of each that answers
+pub fn registered_shards(info: &MongoPSInfo) -> BTreeMap<String, Vec<Shard>> {
+    let clusters: Vec<(String, Vec<i32>)> = config_server_ports(info).into_iter().collect();
+    let shards = probe_all(&info.settings, &clusters, |(_, ports)| {
+        ports.iter().find_map(|port| {
+            let mut conn =
+                Connection::connect_local(&info.settings, *port, DEFAULT_TIMEOUT).ok()?;
+            let reply = conn.run_command("config", json!({"find": "shards"})).ok()?;
+            Some(
+                first_batch(&reply)
+                    .iter()
+                    .filter_map(|s| {
+                        Some(Shard {
+                            name: s.get("_id")?.as_str()?.to_owned(),
+                            host: s.get("host")?.as_str()?.to_owned(),
+                        })
+                    })
+                    .collect::<Vec<Shard>>(),
+            )
+        })
+    });
+    clusters
+        .into_iter()
+        .zip(shards)
+        .filter_map(|((name, _), shards)| Some((name, shards?)))
+        .collect()
+}
+
+/// Name a mongod is registered as, matched by port and else by a unique replica set name
+pub fn shard_name(shards: &[Shard], port: i32, replica_set_name: Option<&str>) -> Option<String> {
+    if let Some(shard) = shards.iter().find(|s| host_ports(&s.host).contains(&port)) {
+        return Some(shard.name.clone());
+    }
+    let set = replica_set_name?;
+    let mut named = shards
+        .iter()
+        .filter(|s| s.host.split_once('/').map(|(name, _)| name) == Some(set));
+    match (named.next(), named.next()) {
+        (Some(shard), None) => Some(shard.name.clone()),
+        _ => None,
+    }
+}
+
+pub fn list_shards(conn: &mut Connection) -> Result<Vec<Shard>> {
+    let reply = conn.run_command("admin", json!({"listShards": 1}))?;
+    Ok(reply
+        .get("shards")
+        .and_then(|s| s.as_array())
+        .map_or(&[][..], |s| s.as_slice())
+        .iter()
+        .filter_map(|s| {
+            Some(Shard {
+                name: s.get("_id")?.as_str()?.to_owned(),
+                host: s.get("host")?.as_str()?.to_owned(),
+            })
+        })
+        .collect())
+}
+
+fn string(value: Option<&Value>) -> String {
+    value.and_then(|v| v.as_str()).unwrap_or("").to_owned()
+}
+
+/// Chunk migrations in a currentOp reply, from the moveChunk command on the donor shard
+pub fn migrations(reply: &Value) -> Vec<Migration> {
+    reply
+        .get("inprog")
+        .and_then(|i| i.as_array())
+        .map_or(&[][..], |i| i.as_slice())
+        .iter()
+        .filter_map(|op| {
+            let command = op.get("command")?;
+            let ns = ["moveChunk", "_shardsvrMoveRange"]
+                .iter()
+                .find_map(|name| command.get(*name))?;
+            Some(Migration {
+                ns: ns.as_str().unwrap_or("").to_owned(),
+                from: string(command.get("fromShard")),
+                to: string(command.get("toShard")),
+                secs_running: op.get("secs_running").and_then(|s| s.as_i64()).unwrap_or(0),
+            })
+        })
+        .collect()
+}
+
+fn pids_text(pids: &[i32]) -> String {
+    if pids.is_empty() {
+        return "not local".to_owned();
+    }
+    let pids: Vec<String> = pids.iter().map(|p| p.to_string()).collect();
+    format!("pids {}", pids.join(","))
+}
+
+/// Shards and balancer activity of the cluster behind a mongos
+pub fn sh_status(info: &MongoPSInfo, mongos: &ServerTarget) -> Result<String> {
+    let mut conn = Connection::connect_local(&info.settings, mongos.port, DEFAULT_TIMEOUT)?;
+    let shards = list_shards(&mut conn)?;
+
+    let mut out = String::new();
+    writeln!(out, "mongos {} port {}", mongos.pid, mongos.port)?;
+    writeln!(out, "shards:")?;
+    for shard in &shards {
+        writeln!(
+            out,
+            "  {:<16} {} ({})",
+            shard.name,
+            shard.host,
+            pids_text(&shard_pids(info, &shard.host))
+        )?;
+    }
+
+    writeln!(out, "balancer:")?;
+    match conn.run_command("admin", json!({"balancerStatus": 1})) {
+        Ok(status) => writeln!(
+            out,
+            "  mode {}, {}",
+            string(status.get("mode")),
+            if status.get("inBalancerRound").and_then(|r| r.as_bool()) == Some(true) {
+                "running a round"
+            } else {
+                "idle"
+            }
+        )?,
+        Err(e) => writeln!(out, "  status unavailable: {}", e)?,
+    }
+
+    let reply = conn.run_command(
+        "admin",
+        json!({"currentOp": 1, "$all": true, "active": true}),
+    )?;
+    let migrations = migrations(&reply);
+    if migrations.is_empty() {
+        writeln!(out, "  no migrations in progress")?;
+    }
+    let shard_host = |name: &str| {
+        shards
+            .iter()
+            .find(|s| s.name == name)
+            .map_or(String::new(), |s| {
+                format!(" ({})", pids_text(&shard_pids(info, &s.host)))
+            })
+    };
+    for m in migrations {
+        writeln!(
+            out,
+            "  moving {} from {}{} to {}{} for {}s",
+            m.ns,
+            m.from,
+            shard_host(&m.from),
+            m.to,
+            shard_host(&m.to),
+            m.secs_running
+        )?;
+    }
+    Ok(out)
+}
+
+// Documents of the first batch of a cursor reply
+fn first_batch(reply: &Value) -> &[Value] {
+    reply
+        .get("cursor")
+        .and_then(|c| c.get("firstBatch"))
+        .and_then(|b| b.as_array())
+        .map_or(&[][..], |b| b.as_slice())
+}
+
+/// Number of chunks of a sharded collection on each shard, including shards without chunks
+pub fn chunk_counts(
+    conn: &mut Connection,
+    ns: &str,
+    shards: &[Shard],
+) -> Result<Vec<(String, i64)>> {
+    let reply = conn.run_command(
+        "config",
+        json!({"find": "collections", "filter": {"_id": ns}, "limit": 1}),
+    )?;
+    let Some(collection) = first_batch(&reply).first() else {
+        bail!("{} is not a sharded collection", ns);
+    };
+    // Chunks refer to the collection by uuid since 5.0
+    let filter = match collection.get("uuid") {
+        Some(uuid) => json!({"uuid": uuid}),
+        None => json!({"ns": ns}),
+    };
+    let reply = conn.run_command(
+        "config",
+        json!({
+            "aggregate": "chunks",
+            "pipeline": [
+                {"$match": filter},
+                {"$group": {"_id": "$shard", "count": {"$sum": 1}}},
+            ],
+            "cursor": {},
+        }),
+    )?;
+
+    let mut counts: Vec<(String, i64)> = shards.iter().map(|s| (s.name.clone(), 0)).collect();
+    for group in first_batch(&reply) {
+        let (Some(shard), Some(count)) = (
+            group.get("_id").and_then(|s| s.as_str()),
+            group.get("count").and_then(|c| c.as_i64()),
+        ) else {
+            continue;
+        };
+        match counts.iter_mut().find(|(name, _)| name == shard) {
+            Some(entry) => entry.1 = count,
+            None => counts.push((shard.to_owned(), count)),
+        }
+    }
+    Ok(counts)
+}
+
+/// Describe the imbalance if the difference between the shards with the most and fewest chunks
+/// is above the migration threshold the chunk count based balancer used
+pub fn imbalance(counts: &[(String, i64)]) -> Option<String> {
+    let most = counts.iter().max_by_key(|(_, c)| *c)?;
+    let fewest = counts.iter().min_by_key(|(_, c)| *c)?;
+    let total: i64 = counts.iter().map(|(_, c)| c).sum();
+    let threshold = match total {
+        t if t < 20 => 2,
+        t if t < 80 => 4,
+        _ => 8,
+    };
+    let difference = most.1 - fewest.1;
+    (difference >= threshold).then(|| {
+        format!(
+            "imbalanced: {} has {} more chunks than {} (threshold {})",
+            most.0, difference, fewest.0, threshold
+        )
+    })
+}
+
+/// Chunk distribution of a collection through a mongos
+pub fn chunks(info: &MongoPSInfo, mongos: &ServerTarget, ns: &str) -> Result<String> {
+    let mut conn = Connection::connect_local(&info.settings, mongos.port, DEFAULT_TIMEOUT)?;
+    let shards = list_shards(&mut conn)?;
+    let counts = chunk_counts(&mut conn, ns, &shards)?;
+
+    let mut out = String::new();
+    writeln!(out, "{}", ns)?;
+    for (name, count) in &counts {
+        let pids = shards
+            .iter()
+            .find(|s| s.name == *name)
+            .map_or(vec![], |s| shard_pids(info, &s.host));
+        writeln!(
+            out,
+            "  {:<16} {:>6} chunks ({})",
+            name,
+            count,
+            pids_text(&pids)
+        )?;
+    }
+    writeln!(
+        out,
+        "  {}",
+        imbalance(&counts).unwrap_or_else(|| "balanced".to_owned())
+    )?;
+    Ok(out)
+}
+
+#[test]
+fn test_shard_name() {
+    let shards = [
+        Shard {
+            name: "shardA".to_owned(),
+            host: "rs0/localhost:20001,localhost:20002".to_owned(),
+        },
+        Shard {
+            name: "shardB".to_owned(),
+            host: "rs1/localhost:20011".to_owned(),
+        },
+        Shard {
+            name: "shardC".to_owned(),
+            host: "rs1/otherhost:20011".to_owned(),
+        },
+    ];
+    assert_eq!(shard_name(&shards, 20002, None).unwrap(), "shardA");
+    // A member added after the shard was registered is found by its set name
+    assert_eq!(shard_name(&shards, 20003, Some("rs0")).unwrap(), "shardA");
+    assert_eq!(shard_name(&shards, 20012, Some("rs1")), None);
+    assert_eq!(shard_name(&shards, 20011, Some("rs1")).unwrap(), "shardB");
+}
+
+#[test]
+fn test_pick_routers() {
+    let target = |pid, port| ServerTarget { pid, port };
+    let mut clusters = BTreeMap::new();
+    clusters.insert("a".to_owned(), vec![target(1, 20000), target(2, 20001)]);
+    clusters.insert("b".to_owned(), vec![target(3, 30000)]);
+
+    assert_eq!(pick_routers(&clusters, Some(0), |_| false), vec![1, 3]);
+    assert_eq!(pick_routers(&clusters, Some(1), |_| false), vec![2]);
+    assert_eq!(pick_routers(&clusters, None, |t| t.pid != 1), vec![2, 3]);
+    assert_eq!(pick_routers(&clusters, None, |_| false), vec![1, 3]);
+}
+
+#[test]
+fn test_migrations() {
+    assert_eq!(
+        host_ports("shard01/localhost:20001,localhost:20002"),
+        vec![20001, 20002]
+    );
+    assert_eq!(host_ports("localhost:20003"), vec![20003]);
+
+    let reply = json!({
+        "inprog": [
+            {"op": "command", "secs_running": 4,
+             "command": {"moveChunk": "test.c", "fromShard": "shard01", "toShard": "shard02"}},
+            {"op": "query", "command": {"find": "c"}},
+        ],
+    });
+    assert_eq!(
+        migrations(&reply),
+        vec![Migration {
+            ns: "test.c".to_owned(),
+            from: "shard01".to_owned(),
+            to: "shard02".to_owned(),
+            secs_running: 4,
+        }]
+    );
+
+    let counts = |c: &[i64]| -> Vec<(String, i64)> {
+        c.iter()
+            .enumerate()
+            .map(|(i, c)| (format!("shard{:02}", i), *c))
+            .collect()
+    };
+    assert_eq!(imbalance(&counts(&[5, 4])), None);
+    assert_eq!(
+        imbalance(&counts(&[10, 0, 5])).unwrap(),
+        "imbalanced: shard00 has 10 more chunks than shard01 (threshold 2)"
+    );
+    assert_eq!(imbalance(&counts(&[45, 42])), None);
+    assert!(imbalance(&counts(&[])).is_none());
+}
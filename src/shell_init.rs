@@ -0,0 +1,87 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Shell functions built on top of mpf's filters
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+// Name, mpf field and the command run with the single match
+const FUNCTIONS: &[(&str, &str, &str)] = &[
+    ("mcd", "dbpath", "cd"),
+    ("mlog", "log", "tail -F"),
+    ("mport", "port", "echo"),
+];
+
+fn posix_function(name: &str, field: &str, command: &str) -> String {
+    format!(
+        r#"{name}() {{
+    local matches
+    matches=$(mpf --field {field} "$@") || return
+    if [ -z "$matches" ] || [ "$(printf '%s\n' "$matches" | wc -l)" -ne 1 ]; then
+        echo "{name}: need exactly one match, found:" >&2
+        printf '%s\n' "$matches" >&2
+        return 1
+    fi
+    {command} "$matches"
+}}
+"#
+    )
+}
+
+fn fish_function(name: &str, field: &str, command: &str) -> String {
+    format!(
+        r#"function {name}
+    set -l matches (mpf --field {field} $argv); or return
+    if test (count $matches) -ne 1
+        echo "{name}: need exactly one match, found:" >&2
+        printf '%s\n' $matches >&2
+        return 1
+    end
+    {command} $matches
+end
+"#
+    )
+}
+
+/// Functions to source from a shell's startup file, filters are passed through to mpf
+pub fn shell_init(shell: &Shell) -> String {
+    let mut out =
+        String::from("# Generated by mpf shell-init, filters are passed through to mpf\n");
+    for (name, field, command) in FUNCTIONS {
+        out.push_str(&match shell {
+            Shell::Bash | Shell::Zsh => posix_function(name, field, command),
+            Shell::Fish => fish_function(name, field, command),
+        });
+    }
+    out
+}
+
+#[test]
+fn test_shell_init() {
+    let bash = shell_init(&Shell::Bash);
+    assert!(bash.contains("mcd() {"));
+    assert!(bash.contains("matches=$(mpf --field port \"$@\")"));
+    assert!(bash.contains("tail -F \"$matches\""));
+
+    let fish = shell_init(&Shell::Fish);
+    assert!(fish.contains("function mport"));
+    assert!(fish.contains("set -l matches (mpf --field dbpath $argv); or return"));
+}
@@ -0,0 +1,89 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::ErrorKind;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use super::client::{Connection, Settings, DEFAULT_TIMEOUT};
+use super::fanout::fan_out;
+use super::signal::send_signal;
+use super::ServerTarget;
+
+pub enum ShutdownResult {
+    Command,
+    Signal,
+}
+
+/// Ask a server to shut down with the shutdown command, falling back to SIGTERM if it
+/// cannot be reached
+pub fn shutdown_server(
+    settings: &Settings,
+    target: &ServerTarget,
+    force: bool,
+    timeout_secs: Option<u32>,
+) -> Result<ShutdownResult> {
+    let mut conn = match Connection::connect_local(settings, target.port, DEFAULT_TIMEOUT) {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!(
+                "Could not connect to {} on port {} ({}), sending SIGTERM",
+                target.pid, target.port, e
+            );
+            send_signal(target.pid, libc::SIGTERM)?;
+            return Ok(ShutdownResult::Signal);
+        }
+    };
+
+    let mut cmd = json!({"shutdown": 1, "force": force});
+    if let Some(secs) = timeout_secs {
+        cmd["timeoutSecs"] = Value::from(secs);
+    }
+
+    // A successful shutdown closes the connection before a reply is sent
+    match conn.run_command("admin", cmd) {
+        Ok(_) => Ok(ShutdownResult::Command),
+        Err(e) => match e.downcast_ref::<std::io::Error>() {
+            Some(io) if is_disconnect(io.kind()) => Ok(ShutdownResult::Command),
+            _ => Err(e),
+        },
+    }
+}
+
+fn is_disconnect(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::UnexpectedEof | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted
+    )
+}
+
+/// Shut down every target one at a time: a primary waits for a secondary to catch up, which
+/// fails when its secondaries are shutting down at the same time
+pub fn shutdown_servers(
+    settings: &Settings,
+    targets: &[ServerTarget],
+    force: bool,
+    timeout_secs: Option<u32>,
+    as_json: bool,
+) -> Result<()> {
+    fan_out(targets, 1, |target| {
+        let message = match shutdown_server(settings, target, force, timeout_secs)? {
+            ShutdownResult::Command => "shut down via shutdown command",
+            ShutdownResult::Signal => "sent SIGTERM",
+        };
+        Ok(Value::from(message))
+    })
+    .print(as_json)
+}
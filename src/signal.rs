@@ -0,0 +1,124 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+use super::fanout::fan_out;
+use super::probe::DEFAULT_CONCURRENCY;
+
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// Parse a signal given as a number or a name with or without the SIG prefix
+pub fn parse_signal(s: &str) -> Result<i32, String> {
+    if let Ok(n) = s.parse::<i32>() {
+        return Ok(n);
+    }
+
+    let upper = s.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, sig)| *sig)
+        .ok_or_else(|| format!("Unknown signal {}", s))
+}
+
+pub fn signal_name(signal: i32) -> String {
+    SIGNALS
+        .iter()
+        .find(|(_, sig)| *sig == signal)
+        .map_or(signal.to_string(), |(name, _)| format!("SIG{}", name))
+}
+
+pub fn send_signal(pid: i32, signal: i32) -> Result<()> {
+    let ret = unsafe { libc::kill(pid, signal) };
+    if ret != 0 {
+        bail!(
+            "Failed to send {} to {}: {}",
+            signal_name(signal),
+            pid,
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+/// Send a signal to every pid, reporting each one
+pub fn signal_all(pids: &[i32], signal: i32, as_json: bool) -> Result<()> {
+    fan_out(pids, DEFAULT_CONCURRENCY, |pid| {
+        send_signal(*pid, signal)?;
+        Ok(Value::from(format!("sent {}", signal_name(signal))))
+    })
+    .print(as_json)
+}
+
+pub fn process_exists(pid: i32) -> bool {
+    // Signal 0 only checks for existence, EPERM means it exists but is owned by someone else
+    let ret = unsafe { libc::kill(pid, 0) };
+    ret == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Wait for a process to exit, returns false if it is still running after the timeout
+pub fn wait_for_exit(pid: i32, timeout: Duration) -> bool {
+    let start = Instant::now();
+    while process_exists(pid) {
+        if start.elapsed() > timeout {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    true
+}
+
+#[test]
+fn test_parse_signal() {
+    assert_eq!(parse_signal("SIGUSR2"), Ok(libc::SIGUSR2));
+    assert_eq!(parse_signal("usr2"), Ok(libc::SIGUSR2));
+    assert_eq!(parse_signal("9"), Ok(9));
+    assert!(parse_signal("SIGBOGUS").is_err());
+    assert_eq!(signal_name(libc::SIGTERM), "SIGTERM");
+}
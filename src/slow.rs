@@ -0,0 +1,250 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// A quick profiler from the logs: collect the "Slow query" entries of the servers' structured
+// logs and group them by namespace and query shape, most total time first
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde_derive::Serialize;
+use serde_json::{Map, Value};
+
+// Fields every command carries that say nothing about its shape
+const IGNORED_FIELDS: &[&str] = &[
+    "lsid",
+    "$db",
+    "$clusterTime",
+    "$readPreference",
+    "txnNumber",
+    "comment",
+    "maxTimeMS",
+    "cursor",
+    "batchSize",
+    "autocommit",
+    "startTransaction",
+    "readConcern",
+    "writeConcern",
+    "shardVersion",
+    "databaseVersion",
+];
+
+/// Slow operations with the same namespace and shape
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SlowQuery {
+    pub ns: String,
+    pub shape: String,
+    pub count: u64,
+    pub total_millis: u64,
+    pub max_millis: u64,
+    /// Plan of the slowest occurrence, e.g. COLLSCAN
+    pub plan: Option<String>,
+    pub pids: Vec<i32>,
+}
+
+impl SlowQuery {
+    pub fn mean_millis(&self) -> u64 {
+        self.total_millis / self.count.max(1)
+    }
+}
+
+// Values replaced by ?, so queries differing only in constants have one shape
+fn mask(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), mask(v)))
+                .collect::<Map<String, Value>>(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(mask).collect()),
+        _ => Value::from("?"),
+    }
+}
+
+/// The command name with its filter, update or pipeline stages masked
+pub fn query_shape(command: &Value) -> String {
+    let Some(map) = command.as_object() else {
+        return "?".to_owned();
+    };
+    let Some(name) = map.keys().next() else {
+        return "?".to_owned();
+    };
+    if let Some(Value::Array(stages)) = map.get("pipeline") {
+        let stages: Vec<String> = stages
+            .iter()
+            .filter_map(|s| s.as_object()?.keys().next().cloned())
+            .collect();
+        return format!("{} [{}]", name, stages.join(", "));
+    }
+    let shape: Map<String, Value> = map
+        .iter()
+        .skip(1)
+        .filter(|(k, _)| !IGNORED_FIELDS.contains(&k.as_str()))
+        .map(|(k, v)| (k.clone(), mask(v)))
+        .collect();
+    if shape.is_empty() {
+        name.clone()
+    } else {
+        format!("{} {}", name, Value::Object(shape))
+    }
+}
+
+/// The namespace, shape, duration and plan of a "Slow query" log line
+pub fn parse_slow_line(line: &str) -> Option<(String, String, u64, Option<String>)> {
+    // Most lines are not slow queries, skip them before parsing
+    if !line.contains("\"Slow query\"") {
+        return None;
+    }
+    let entry: Value = serde_json::from_str(line).ok()?;
+    if entry.get("msg")?.as_str()? != "Slow query" {
+        return None;
+    }
+    let attr = entry.get("attr")?;
+    let millis = attr.get("durationMillis")?.as_u64()?;
+    let ns = attr.get("ns").and_then(Value::as_str).unwrap_or("-");
+    let shape = attr.get("command").map_or("?".to_owned(), query_shape);
+    let plan = attr
+        .get("planSummary")
+        .and_then(Value::as_str)
+        .map(str::to_owned);
+    Some((ns.to_owned(), shape, millis, plan))
+}
+
+/// Group the slow queries at least threshold long from the logs, most total time first
+pub fn slow_queries(logs: &[(i32, String)], threshold: Duration) -> Result<Vec<SlowQuery>> {
+    let threshold = threshold.as_millis() as u64;
+    let mut groups: HashMap<(String, String), SlowQuery> = HashMap::new();
+
+    for (pid, path) in logs {
+        // A rotated or deleted log must not hide the slow queries of the other servers
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Failed to open log {} of {}: {}, skipping it", path, pid, e);
+                continue;
+            }
+        };
+        for line in BufReader::new(file).lines() {
+            let Some((ns, shape, millis, plan)) = parse_slow_line(&line?) else {
+                continue;
+            };
+            if millis < threshold {
+                continue;
+            }
+            let query = groups
+                .entry((ns.clone(), shape.clone()))
+                .or_insert_with(|| SlowQuery {
+                    ns,
+                    shape,
+                    count: 0,
+                    total_millis: 0,
+                    max_millis: 0,
+                    plan: None,
+                    pids: vec![],
+                });
+            query.count += 1;
+            query.total_millis += millis;
+            if millis >= query.max_millis {
+                query.max_millis = millis;
+                query.plan = plan.or(query.plan.take());
+            }
+            if !query.pids.contains(pid) {
+                query.pids.push(*pid);
+            }
+        }
+    }
+
+    let mut queries: Vec<SlowQuery> = groups.into_values().collect();
+    queries.sort_by(|a, b| {
+        b.total_millis
+            .cmp(&a.total_millis)
+            .then(a.ns.cmp(&b.ns))
+            .then(a.shape.cmp(&b.shape))
+    });
+    Ok(queries)
+}
+
+fn format_millis(millis: u64) -> String {
+    if millis < 1000 {
+        format!("{}ms", millis)
+    } else {
+        format!("{:.1}s", millis as f64 / 1000.0)
+    }
+}
+
+pub fn print_slow_queries(queries: &[SlowQuery]) {
+    println!(
+        "{:>6} {:>8} {:>8} {:>8}  {:<12} {:<24} SHAPE",
+        "COUNT", "TOTAL", "MAX", "MEAN", "PLAN", "NS"
+    );
+    for q in queries {
+        println!(
+            "{:>6} {:>8} {:>8} {:>8}  {:<12} {:<24} {}",
+            q.count,
+            format_millis(q.total_millis),
+            format_millis(q.max_millis),
+            format_millis(q.mean_millis()),
+            q.plan.as_deref().unwrap_or("-"),
+            q.ns,
+            q.shape
+        );
+    }
+}
+
+#[test]
+fn test_slow_queries() {
+    let line = |ms: u64, status: &str| {
+        format!(
+            r#"{{"t":{{"$date":"2024-01-01T00:00:00.000+00:00"}},"s":"I","c":"COMMAND","id":51803,"ctx":"conn5","msg":"Slow query","attr":{{"type":"command","ns":"test.orders","command":{{"find":"orders","filter":{{"status":"{}"}},"lsid":{{"id":1}},"$db":"test"}},"planSummary":"COLLSCAN","durationMillis":{}}}}}"#,
+            status, ms
+        )
+    };
+    let (ns, shape, millis, plan) = parse_slow_line(&line(150, "new")).unwrap();
+    assert_eq!(
+        (ns.as_str(), shape.as_str(), millis, plan.as_deref()),
+        (
+            "test.orders",
+            r#"find {"filter":{"status":"?"}}"#,
+            150,
+            Some("COLLSCAN")
+        )
+    );
+    assert!(parse_slow_line(r#"{"msg":"Connection ended"}"#).is_none());
+    assert_eq!(
+        query_shape(
+            &serde_json::json!({"aggregate": "c", "pipeline": [{"$match": {"a": 1}}, {"$group": {}}]})
+        ),
+        "aggregate [$match, $group]"
+    );
+
+    let path = std::env::temp_dir().join(format!("mpf-slow-{}.log", std::process::id()));
+    let log = [line(150, "new"), line(50, "old"), line(300, "done")].join("\n");
+    std::fs::write(&path, log).unwrap();
+    let logs = [(7, path.display().to_string())];
+    let queries = slow_queries(&logs, Duration::from_millis(100)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(queries.len(), 1);
+    assert_eq!(
+        (
+            queries[0].count,
+            queries[0].total_millis,
+            queries[0].max_millis
+        ),
+        (2, 450, 300)
+    );
+    assert_eq!(queries[0].pids, [7]);
+}
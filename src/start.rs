@@ -0,0 +1,494 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Launch local topologies for development, similar to mlaunch
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::client::{wait_for_server, Connection, Settings, DEFAULT_TIMEOUT};
+use super::launch::LaunchSpec;
+use super::shutdown::shutdown_server;
+use super::signal::{send_signal, wait_for_exit};
+use super::MongoPSInfo;
+
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub const STATE_FILE: &str = "mpf-start.json";
+
+#[derive(Serialize, Deserialize, Debug, ValueEnum, Clone, PartialEq)]
+pub enum Topology {
+    Standalone,
+    ReplicaSet,
+    Sharded,
+}
+
+pub struct StartOptions {
+    pub topology: Topology,
+    pub bin_dir: Option<PathBuf>,
+    pub dir: PathBuf,
+    pub port: i32,
+    pub repl_set: String,
+    pub nodes: i32,
+    pub shards: i32,
+}
+
+/// A process started by mpf, recorded in the state file in the data directory
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StartedProcess {
+    pub pid: u32,
+    pub port: i32,
+    pub role: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StartState {
+    pub topology: Topology,
+    pub processes: Vec<StartedProcess>,
+}
+
+struct Launcher<'a> {
+    settings: &'a Settings,
+    options: &'a StartOptions,
+    state: StartState,
+}
+
+impl<'a> Launcher<'a> {
+    fn binary(&self, name: &str) -> PathBuf {
+        match self.options.bin_dir {
+            Some(ref dir) => dir.join(name),
+            None => PathBuf::from(name),
+        }
+    }
+
+    fn launch(&mut self, binary: &str, port: i32, role: &str, args: Vec<String>) -> Result<()> {
+        let spec = LaunchSpec {
+            binary: self.binary(binary),
+            args,
+            env: std::env::vars_os().collect(),
+            cwd: std::env::current_dir()?,
+        };
+
+        let log_file = spec.log_file(port);
+        let pid = spec.spawn(&log_file)?;
+        println!("{}: started {} on port {}", pid, role, port);
+        self.state.processes.push(StartedProcess {
+            pid,
+            port,
+            role: role.to_owned(),
+        });
+        // Recorded as soon as it runs, so an interrupted start still says what it left behind
+        write_state(&self.options.dir, &self.state)
+    }
+
+    fn launch_mongod(&mut self, port: i32, role: &str, extra_args: &[&str]) -> Result<()> {
+        let dbpath = self.options.dir.join(format!("db{}", port));
+        std::fs::create_dir_all(&dbpath)
+            .with_context(|| format!("Failed to create {}", dbpath.display()))?;
+
+        let mut args = vec![
+            "--port".to_owned(),
+            port.to_string(),
+            "--dbpath".to_owned(),
+            dbpath.to_string_lossy().into_owned(),
+            "--logpath".to_owned(),
+            dbpath.join("mongod.log").to_string_lossy().into_owned(),
+        ];
+        args.extend(extra_args.iter().map(|s| s.to_string()));
+        self.launch("mongod", port, role, args)
+    }
+
+    /// Start a replica set on consecutive ports and initiate it, returns the connection string
+    fn launch_replica_set(
+        &mut self,
+        name: &str,
+        first_port: i32,
+        nodes: i32,
+        role: &str,
+        extra_args: &[&str],
+    ) -> Result<String> {
+        let ports: Vec<i32> = (first_port..first_port + nodes).collect();
+        for port in &ports {
+            let mut args = vec!["--replSet", name];
+            args.extend_from_slice(extra_args);
+            self.launch_mongod(*port, role, &args)?;
+        }
+        for port in &ports {
+            wait_for_server(self.settings, *port, READY_TIMEOUT)?;
+        }
+
+        let members: Vec<serde_json::Value> = ports
+            .iter()
+            .enumerate()
+            .map(|(i, port)| json!({"_id": i, "host": format!("localhost:{}", port)}))
+            .collect();
+        let mut config = json!({"_id": name, "members": members});
+        if role == "config" {
+            config["configsvr"] = json!(true);
+        }
+
+        let mut conn = Connection::connect_local(self.settings, first_port, DEFAULT_TIMEOUT)?;
+        conn.run_command("admin", json!({ "replSetInitiate": config }))?;
+        wait_for_primary(self.settings, first_port)?;
+
+        let hosts: Vec<String> = ports.iter().map(|p| format!("localhost:{}", p)).collect();
+        Ok(format!("{}/{}", name, hosts.join(",")))
+    }
+
+    /// Launch and connect the processes of the topology, initiating replica sets and adding
+    /// shards
+    fn launch_topology(&mut self) -> Result<()> {
+        let options = self.options;
+        match options.topology {
+            Topology::Standalone => {
+                self.launch_mongod(options.port, "standalone", &[])?;
+                wait_for_server(self.settings, options.port, READY_TIMEOUT)?;
+            }
+            Topology::ReplicaSet => {
+                self.launch_replica_set(
+                    &options.repl_set,
+                    options.port,
+                    options.nodes,
+                    "replica-set",
+                    &[],
+                )?;
+            }
+            Topology::Sharded => {
+                // mongos on the first port, then the config server, then the shards
+                let config_port = options.port + 1;
+                let configdb = self.launch_replica_set(
+                    "configRS",
+                    config_port,
+                    1,
+                    "config",
+                    &["--configsvr"],
+                )?;
+
+                let mut shards = Vec::new();
+                for i in 0..options.shards {
+                    let name = format!("shard{:02}", i + 1);
+                    let port = config_port + 1 + i * options.nodes;
+                    shards.push(self.launch_replica_set(
+                        &name,
+                        port,
+                        options.nodes,
+                        "shard",
+                        &["--shardsvr"],
+                    )?);
+                }
+
+                let mongos_args = vec![
+                    "--port".to_owned(),
+                    options.port.to_string(),
+                    "--configdb".to_owned(),
+                    configdb,
+                    "--logpath".to_owned(),
+                    options
+                        .dir
+                        .join(format!("mongos{}.log", options.port))
+                        .to_string_lossy()
+                        .into_owned(),
+                ];
+                self.launch("mongos", options.port, "mongos", mongos_args)?;
+                wait_for_server(self.settings, options.port, READY_TIMEOUT)?;
+
+                let mut conn =
+                    Connection::connect_local(self.settings, options.port, DEFAULT_TIMEOUT)?;
+                for shard in shards {
+                    conn.run_command("admin", json!({ "addShard": shard }))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn wait_for_primary(settings: &Settings, port: i32) -> Result<()> {
+    let start = Instant::now();
+    loop {
+        let hello = wait_for_server(settings, port, READY_TIMEOUT)?;
+        if hello.get("isWritablePrimary").and_then(|v| v.as_bool()) == Some(true) {
+            return Ok(());
+        }
+        if start.elapsed() > READY_TIMEOUT {
+            bail!(
+                "No primary elected on port {} after {:?}",
+                port,
+                READY_TIMEOUT
+            );
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+}
+
+// Stop what a failed start launched so it does not leave half a topology running. They are
+// children of this process, so they are reaped rather than waited on.
+fn stop_started(started: &[StartedProcess]) {
+    for p in started {
+        let _ = send_signal(p.pid as i32, libc::SIGTERM);
+    }
+    for p in started {
+        let pid = p.pid as i32;
+        if !reap(pid, STOP_TIMEOUT) {
+            let _ = send_signal(pid, libc::SIGKILL);
+            reap(pid, STOP_TIMEOUT);
+        }
+        println!("{}: stopped {} on port {}", pid, p.role, p.port);
+    }
+}
+
+fn reap(pid: i32, timeout: Duration) -> bool {
+    let start = Instant::now();
+    loop {
+        // Not a child anymore means someone else already reaped it
+        if unsafe { libc::waitpid(pid, std::ptr::null_mut(), libc::WNOHANG) } != 0 {
+            return true;
+        }
+        if start.elapsed() > timeout {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn validate(options: &StartOptions) -> Result<()> {
+    if options.nodes < 1 {
+        bail!("--nodes must be at least 1, not {}", options.nodes);
+    }
+    if options.shards < 1 {
+        bail!("--shards must be at least 1, not {}", options.shards);
+    }
+    Ok(())
+}
+
+pub fn start_topology(settings: &Settings, options: &StartOptions) -> Result<StartState> {
+    validate(options)?;
+    std::fs::create_dir_all(&options.dir)
+        .with_context(|| format!("Failed to create {}", options.dir.display()))?;
+
+    let mut launcher = Launcher {
+        settings,
+        options,
+        state: StartState {
+            topology: options.topology.clone(),
+            processes: Vec::new(),
+        },
+    };
+    if let Err(e) = launcher.launch_topology() {
+        eprintln!(
+            "Failed to start, stopping the {} processes started so far",
+            launcher.state.processes.len()
+        );
+        stop_started(&launcher.state.processes);
+        launcher.state.processes.clear();
+        write_state(&options.dir, &launcher.state)?;
+        return Err(e);
+    }
+    Ok(launcher.state)
+}
+
+/// The state file of a start directory, None when nothing was started there
+pub fn read_state(dir: &Path) -> Result<Option<StartState>> {
+    let path = dir.join(STATE_FILE);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let state = serde_json::from_str(&text)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(state))
+}
+
+// The state file of a started mongod is next to its db directory, and of a started mongos next
+// to its log
+fn state_dir(path: Option<&str>) -> Option<PathBuf> {
+    Path::new(path?).parent().map(Path::to_path_buf)
+}
+
+/// Set the start directory of the servers recorded in a state file. A pid only counts as
+/// started by mpf with the port it was recorded with, pids get reused.
+pub fn label_started(info: &mut MongoPSInfo) {
+    let mut states: HashMap<PathBuf, Option<StartState>> = HashMap::new();
+    let mut lookup = |dir: Option<PathBuf>, pid: i32, port: i32| {
+        let dir = dir?;
+        let state = states
+            .entry(dir.clone())
+            .or_insert_with(|| match read_state(&dir) {
+                Ok(state) => state,
+                Err(e) => {
+                    eprintln!("WARNING: {:#}", e);
+                    None
+                }
+            });
+        let recorded = state
+            .as_ref()?
+            .processes
+            .iter()
+            .any(|p| p.pid as i32 == pid && p.port == port);
+        recorded.then(|| dir.display().to_string())
+    };
+    for d in info.mongod.iter_mut() {
+        d.start_dir = lookup(state_dir(d.dbpath.as_deref()), d.pid, d.port);
+    }
+    for s in info.mongos.iter_mut() {
+        s.start_dir = lookup(state_dir(s.logpath.as_deref()), s.pid, s.port);
+    }
+}
+
+/// Shut down what mpf start recorded in dir and is still running, the last started first so
+/// routers go before shards and config servers, then remove the state file
+pub fn stop_topology(info: &MongoPSInfo, dir: &Path) -> Result<()> {
+    let Some(mut state) = read_state(dir)? else {
+        bail!(
+            "Nothing was started in {}, it has no {}",
+            dir.display(),
+            STATE_FILE
+        );
+    };
+
+    let mut running = Vec::new();
+    for p in state.processes.iter().rev() {
+        let pid = p.pid as i32;
+        let Some(target) = info.server_target(pid).filter(|t| t.port == p.port) else {
+            println!("{}: {} on port {} is not running", pid, p.role, p.port);
+            continue;
+        };
+        let stopped = shutdown_server(&info.settings, &target, true, None)
+            .map(|_| wait_for_exit(pid, STOP_TIMEOUT));
+        match stopped {
+            Ok(true) => println!("{}: stopped {} on port {}", pid, p.role, p.port),
+            Ok(false) => {
+                eprintln!("{}: did not exit within {:?}", pid, STOP_TIMEOUT);
+                running.push(p.pid);
+            }
+            Err(e) => {
+                eprintln!("{}: {:#}", pid, e);
+                running.push(p.pid);
+            }
+        }
+    }
+
+    if running.is_empty() {
+        let path = dir.join(STATE_FILE);
+        return std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove {}", path.display()));
+    }
+    // Keep what is still running recorded for the next attempt
+    state.processes.retain(|p| running.contains(&p.pid));
+    write_state(dir, &state)?;
+    bail!("Failed to stop {} processes", running.len())
+}
+
+fn write_state(dir: &Path, state: &StartState) -> Result<()> {
+    let path = dir.join(STATE_FILE);
+    std::fs::write(&path, serde_json::to_string_pretty(state)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+fn test_options(dir: &Path, nodes: i32, shards: i32) -> StartOptions {
+    StartOptions {
+        topology: Topology::ReplicaSet,
+        bin_dir: Some(dir.join("bin")),
+        dir: dir.to_path_buf(),
+        port: 20010,
+        repl_set: "rs0".to_owned(),
+        nodes,
+        shards,
+    }
+}
+
+#[test]
+fn test_validate() {
+    let dir = Path::new("/nonexistent");
+    assert!(validate(&test_options(dir, 3, 2)).is_ok());
+    assert!(validate(&test_options(dir, 1, 1)).is_ok());
+    assert_eq!(
+        validate(&test_options(dir, 0, 2)).unwrap_err().to_string(),
+        "--nodes must be at least 1, not 0"
+    );
+    assert!(validate(&test_options(dir, 3, -1)).is_err());
+    // Rejected before anything is created
+    assert!(start_topology(&Settings::default(), &test_options(dir, 0, 2)).is_err());
+    assert!(!dir.exists());
+}
+
+#[test]
+fn test_start_stops_on_failure() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("mpf-start-{}", std::process::id()));
+    let bin = dir.join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let mongod = bin.join("mongod");
+    std::fs::write(&mongod, "#!/bin/sh\nwhile :; do sleep 1; done\n").unwrap();
+    std::fs::set_permissions(&mongod, std::fs::Permissions::from_mode(0o755)).unwrap();
+    // The second member cannot get a dbpath, after the first one is already running
+    std::fs::write(dir.join("db20011"), "").unwrap();
+
+    assert!(start_topology(&Settings::default(), &test_options(&dir, 2, 1)).is_err());
+
+    let running = super::get_procs()
+        .unwrap()
+        .into_iter()
+        .filter(|p| {
+            p.cmdline
+                .iter()
+                .any(|a| a.contains(&*dir.to_string_lossy()))
+        })
+        .count();
+    assert_eq!(running, 0);
+    let state: StartState =
+        serde_json::from_str(&std::fs::read_to_string(dir.join(STATE_FILE)).unwrap()).unwrap();
+    assert!(state.processes.is_empty());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_read_state() {
+    let dir = std::env::temp_dir().join(format!("mpf-state-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    assert!(read_state(&dir).unwrap().is_none());
+
+    let state = StartState {
+        topology: Topology::Standalone,
+        processes: vec![StartedProcess {
+            pid: 1234,
+            port: 20010,
+            role: "standalone".to_owned(),
+        }],
+    };
+    write_state(&dir, &state).unwrap();
+    let read = read_state(&dir).unwrap().unwrap();
+    assert_eq!(read.processes[0].pid, 1234);
+    assert_eq!(read.processes[0].port, 20010);
+    assert_eq!(
+        state_dir(Some("/data/db20010")),
+        Some(PathBuf::from("/data"))
+    );
+
+    std::fs::write(dir.join(STATE_FILE), "{").unwrap();
+    assert!(read_state(&dir).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,212 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// What a mongod that is running but not yet listening is busy with, from the last lines of its
+// structured log: opening WiredTiger, recovering its journal, replaying the oplog or building
+// indexes before it waits for connections
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::Result;
+use serde_json::Value;
+
+use super::logq::{parse_record, LogRecord};
+
+// Recovery of a large journal logs little, so this reaches back far enough for one startup
+const TAIL_BYTES: u64 = 1024 * 1024;
+
+/// The last lines of a file, dropping the first one when it starts mid-line
+pub fn tail_lines(path: &str, max_bytes: u64) -> Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub(max_bytes);
+    file.seek(SeekFrom::Start(start))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    let text = String::from_utf8_lossy(&buf);
+    let mut lines: Vec<String> = text.lines().map(str::to_owned).collect();
+    if start > 0 && !lines.is_empty() {
+        lines.remove(0);
+    }
+    Ok(lines)
+}
+
+fn msg(record: &LogRecord) -> &str {
+    record
+        .entry
+        .get("msg")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+}
+
+// WiredTiger messages are a string before 6.0 and a document with msg after
+fn wiredtiger_message(record: &LogRecord) -> Option<&str> {
+    let message = record.entry.get("attr")?.get("message")?;
+    message
+        .as_str()
+        .or_else(|| message.get("msg").and_then(Value::as_str))
+}
+
+// "Recovering log 3 through 7" as (3, 7)
+fn recovering_log(text: &str) -> Option<(u64, u64)> {
+    let rest = &text[text.find("Recovering log ")? + "Recovering log ".len()..];
+    let mut words = rest.split_whitespace();
+    let current = words.next()?.parse().ok()?;
+    if words.next()? != "through" {
+        return None;
+    }
+    let last = words
+        .next()?
+        .trim_end_matches(|c: char| !c.is_ascii_digit());
+    Some((current, last.parse().ok()?))
+}
+
+// The first timestamp in a value, as seconds and increment packed into one number
+fn find_timestamp(value: &Value) -> Option<u64> {
+    if let Some(ts) = value.get("$timestamp") {
+        let t = ts.get("t")?.as_u64()?;
+        let i = ts.get("i")?.as_u64()?;
+        return Some(t << 32 | i);
+    }
+    match value {
+        Value::Object(map) => map.values().find_map(find_timestamp),
+        Value::Array(items) => items.iter().find_map(find_timestamp),
+        _ => None,
+    }
+}
+
+fn percent(done: u64, total: u64) -> String {
+    format!("{}%", (done * 100 / total.max(1)).min(99))
+}
+
+/// The startup phase the records of a log end in, None once the server waits for connections
+/// or when the log has no startup at all
+pub fn startup_phase(records: &[LogRecord]) -> Option<String> {
+    let start = records.iter().rposition(|r| msg(r) == "MongoDB starting")?;
+    let mut phase = "starting".to_owned();
+    let mut first_log = None;
+    let mut replay: Option<(u64, u64)> = None;
+
+    for record in &records[start + 1..] {
+        let text = msg(record);
+        if text == "Waiting for connections" {
+            return None;
+        } else if text == "Opening WiredTiger" {
+            phase = "opening WiredTiger".to_owned();
+        } else if let Some(message) = wiredtiger_message(record) {
+            if let Some((current, last)) = recovering_log(message) {
+                let first = *first_log.get_or_insert(current);
+                phase = format!(
+                    "WT recovery ({})",
+                    percent(current - first.min(current), last + 1 - first.min(last))
+                );
+            } else if (message.contains("recovery") || message.contains("Recovering"))
+                && !phase.starts_with("WT recovery")
+            {
+                phase = "WT recovery".to_owned();
+            }
+        } else if text.starts_with("Replaying stored operations") {
+            let attr = record.entry.get("attr");
+            let point = |name: &str| attr.and_then(|a| a.get(name)).and_then(find_timestamp);
+            replay = point("startPoint").zip(point("endPoint"));
+            phase = "replaying oplog".to_owned();
+        } else if text.starts_with("Index build") || text.starts_with("Rebuilding") {
+            phase = "building indexes".to_owned();
+            replay = None;
+        } else if let Some((from, to)) = replay {
+            // Progress lines of the replay carry the optime applied so far
+            if let Some(ts) = record.entry.get("attr").and_then(find_timestamp) {
+                if (from..=to).contains(&ts) {
+                    phase = format!("replaying oplog ({})", percent(ts - from, to - from));
+                }
+            }
+        }
+    }
+    Some(phase)
+}
+
+/// The startup phase of a server from the end of its log file
+pub fn log_startup_phase(logpath: Option<&str>) -> Option<String> {
+    let records: Vec<LogRecord> = tail_lines(logpath?, TAIL_BYTES)
+        .ok()?
+        .iter()
+        .filter_map(|line| parse_record(line))
+        .collect();
+    startup_phase(&records)
+}
+
+/// "starting: PHASE" for a server that does not listen yet, or "not listening" when its log
+/// does not tell
+pub fn describe_not_listening(logpath: Option<&str>) -> String {
+    match log_startup_phase(logpath) {
+        Some(phase) => format!("starting: {}", phase),
+        None => "not listening".to_owned(),
+    }
+}
+
+#[test]
+fn test_startup_phase() {
+    use serde_json::json;
+
+    let record = |msg: &str, attr: Value| {
+        let line = json!({
+            "t": {"$date": "2024-05-01T12:00:00.000+00:00"},
+            "s": "I", "c": "CONTROL", "id": 1, "ctx": "initandlisten",
+            "msg": msg, "attr": attr
+        });
+        parse_record(&line.to_string()).unwrap()
+    };
+    let ts = |t: u64| json!({"$timestamp": {"t": t, "i": 1}});
+    let mut records = vec![
+        record("Waiting for connections", json!({})),
+        record("MongoDB starting", json!({"port": 20001})),
+    ];
+    assert_eq!(startup_phase(&records).as_deref(), Some("starting"));
+
+    records.push(record("Opening WiredTiger", json!({})));
+    records.push(record(
+        "WiredTiger message",
+        json!({"message": {"msg": "Recovering log 3 through 7"}}),
+    ));
+    records.push(record(
+        "WiredTiger message",
+        json!({"message": "[1714564800:1][1:0x7f], txn-recover: [WT_VERB_RECOVERY_PROGRESS] Recovering log 5 through 7"}),
+    ));
+    assert_eq!(
+        startup_phase(&records).as_deref(),
+        Some("WT recovery (40%)")
+    );
+
+    records.push(record(
+        "Replaying stored operations from startPoint (inclusive) to endPoint (inclusive).",
+        json!({"startPoint": ts(1000), "endPoint": ts(1100)}),
+    ));
+    assert_eq!(startup_phase(&records).as_deref(), Some("replaying oplog"));
+    records.push(record(
+        "Applied operations",
+        json!({"lastApplied": {"ts": ts(1034)}}),
+    ));
+    assert_eq!(
+        startup_phase(&records).as_deref(),
+        Some("replaying oplog (34%)")
+    );
+
+    records.push(record("Index build: starting", json!({})));
+    assert_eq!(startup_phase(&records).as_deref(), Some("building indexes"));
+
+    records.push(record("Waiting for connections", json!({})));
+    assert_eq!(startup_phase(&records), None);
+    assert_eq!(describe_not_listening(None), "not listening");
+}
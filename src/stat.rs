@@ -0,0 +1,207 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// mongostat for every local server: poll serverStatus and print rows of operation rates, cache
+// usage and connections without having to list hosts
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use super::client::{Connection, Settings};
+use super::probe::probe_all;
+use super::watch::timestamp;
+use super::{MongoPSInfo, ServerTarget};
+
+// Print the column names again after this many polls so they stay on screen
+const HEADER_EVERY: u64 = 10;
+
+/// Counters and gauges of one serverStatus reply
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// insert, query, update and delete opcounters
+    pub ops: [f64; 4],
+    pub dirty_percent: Option<f64>,
+    pub used_percent: Option<f64>,
+    pub connections: Option<i64>,
+}
+
+impl Sample {
+    pub fn from_status(status: &Value) -> Sample {
+        let counter = |name: &str| {
+            status
+                .get("opcounters")
+                .and_then(|o| o.get(name))
+                .and_then(Value::as_f64)
+                .unwrap_or(0.0)
+        };
+        // mongos and in-memory servers have no wiredTiger cache
+        let cache = status.get("wiredTiger").and_then(|w| w.get("cache"));
+        let cache_value = |name: &str| cache.and_then(|c| c.get(name)).and_then(Value::as_f64);
+        let percent =
+            |name: &str| match (cache_value(name), cache_value("maximum bytes configured")) {
+                (Some(bytes), Some(max)) if max > 0.0 => Some(bytes * 100.0 / max),
+                _ => None,
+            };
+        Sample {
+            ops: [
+                counter("insert"),
+                counter("query"),
+                counter("update"),
+                counter("delete"),
+            ],
+            dirty_percent: percent("tracked dirty bytes in the cache"),
+            used_percent: percent("bytes currently in the cache"),
+            connections: status
+                .get("connections")
+                .and_then(|c| c.get("current"))
+                .and_then(Value::as_i64),
+        }
+    }
+}
+
+fn server_status(settings: &Settings, port: i32) -> Result<Value> {
+    let mut conn = Connection::connect_local(settings, port, Duration::from_secs(1))?;
+    conn.run_command(
+        "admin",
+        json!({"serverStatus": 1, "repl": 0, "metrics": 0, "locks": 0}),
+    )
+}
+
+pub fn header() -> String {
+    format!(
+        "{:<8} {:>8} {:>6} {:>7} {:>7} {:>7} {:>7} {:>6} {:>6} {:>6}",
+        "TIME", "PID", "PORT", "INSERT", "QUERY", "UPDATE", "DELETE", "DIRTY", "USED", "CONN"
+    )
+}
+
+/// One row with the operations per second since the previous sample, or - for the first one
+pub fn render_row(
+    time: &str,
+    target: &ServerTarget,
+    sample: &Sample,
+    previous: Option<(&Sample, Duration)>,
+) -> String {
+    let rates: Vec<String> = (0..4)
+        .map(|i| match previous {
+            Some((before, elapsed)) if !elapsed.is_zero() => {
+                let delta = (sample.ops[i] - before.ops[i]).max(0.0);
+                format!("{:.0}", delta / elapsed.as_secs_f64())
+            }
+            _ => "-".to_owned(),
+        })
+        .collect();
+    let percent = |p: Option<f64>| p.map_or("-".to_owned(), |p| format!("{:.1}%", p));
+    format!(
+        "{:<8} {:>8} {:>6} {:>7} {:>7} {:>7} {:>7} {:>6} {:>6} {:>6}",
+        time,
+        target.pid,
+        target.port,
+        rates[0],
+        rates[1],
+        rates[2],
+        rates[3],
+        percent(sample.dirty_percent),
+        percent(sample.used_percent),
+        sample.connections.map_or("-".to_owned(), |c| c.to_string()),
+    )
+}
+
+/// Print a row per server from scan every interval, until interrupted or after count polls
+pub fn stat<F>(mut scan: F, interval: Duration, count: Option<u64>) -> Result<()>
+where
+    F: FnMut() -> Result<(MongoPSInfo, Vec<ServerTarget>)>,
+{
+    let mut previous: HashMap<i32, (Sample, Instant)> = HashMap::new();
+    let mut polls = 0;
+
+    while count.is_none_or(|count| polls < count) {
+        let started = Instant::now();
+        let (info, targets) = scan()?;
+        let replies = probe_all(&info.settings, &targets, |t| {
+            server_status(&info.settings, t.port)
+        });
+        let now = Instant::now();
+        let time = timestamp();
+
+        if polls % HEADER_EVERY == 0 {
+            println!("{}", header());
+        }
+        for (t, reply) in targets.iter().zip(replies) {
+            match reply {
+                Ok(status) => {
+                    let sample = Sample::from_status(&status);
+                    let before = previous.get(&t.pid).map(|(s, at)| (s, now - *at));
+                    println!("{}", render_row(&time, t, &sample, before));
+                    previous.insert(t.pid, (sample, now));
+                }
+                Err(e) => {
+                    println!("{:<8} {:>8} {:>6} no reply: {}", time, t.pid, t.port, e);
+                    previous.remove(&t.pid);
+                }
+            }
+        }
+        previous.retain(|pid, _| targets.iter().any(|t| t.pid == *pid));
+
+        polls += 1;
+        if count.is_none_or(|count| polls < count) {
+            std::thread::sleep(interval.saturating_sub(started.elapsed()));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_stat_row() {
+    let status = json!({
+        "opcounters": {"insert": 100, "query": 50, "update": 0, "delete": 10},
+        "connections": {"current": 12},
+        "wiredTiger": {"cache": {
+            "maximum bytes configured": 1000,
+            "bytes currently in the cache": 250,
+            "tracked dirty bytes in the cache": 5
+        }}
+    });
+    let sample = Sample::from_status(&status);
+    assert_eq!(sample.dirty_percent, Some(0.5));
+    assert_eq!(sample.used_percent, Some(25.0));
+    assert_eq!(sample.connections, Some(12));
+
+    let target = ServerTarget {
+        pid: 10,
+        port: 20001,
+    };
+    let first = render_row("12:00:00", &target, &sample, None);
+    assert_eq!(
+        first.split_whitespace().collect::<Vec<&str>>(),
+        ["12:00:00", "10", "20001", "-", "-", "-", "-", "0.5%", "25.0%", "12"]
+    );
+
+    let before = Sample::from_status(&json!({
+        "opcounters": {"insert": 80, "query": 50, "update": 0, "delete": 0}
+    }));
+    assert_eq!(before.used_percent, None);
+    let row = render_row(
+        "12:00:02",
+        &target,
+        &sample,
+        Some((&before, Duration::from_secs(2))),
+    );
+    assert_eq!(
+        row.split_whitespace().collect::<Vec<&str>>()[3..7],
+        ["10", "0", "0", "5"]
+    );
+}
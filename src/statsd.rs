@@ -0,0 +1,173 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Push process counts and per-server gauges to a statsd daemon over UDP
+
+use std::collections::HashMap;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde_json::json;
+
+use super::client::{Connection, Settings};
+use super::probe::probe_all;
+use super::top::cpu_percent;
+use super::{cpu_time, MongoPSInfo, ServerTarget};
+
+// Stay below the usual MTU so packets are not fragmented
+const MAX_PACKET: usize = 1400;
+
+/// Gauges of one server
+pub struct ServerSample {
+    pub port: i32,
+    pub program: String,
+    pub rss: Option<u64>,
+    pub cpu: Option<f64>,
+    pub up: bool,
+}
+
+/// statsd gauge lines for the process counts and each server
+pub fn gauges(prefix: &str, info: &MongoPSInfo, samples: &[ServerSample]) -> Vec<String> {
+    let mut lines = vec![
+        format!("{}.processes.mongod:{}|g", prefix, info.mongod.len()),
+        format!("{}.processes.mongos:{}|g", prefix, info.mongos.len()),
+        format!("{}.processes.shell:{}|g", prefix, info.shell.len()),
+        format!(
+            "{}.processes.mongocryptd:{}|g",
+            prefix,
+            info.cryptd_instances().count()
+        ),
+    ];
+    for s in samples {
+        let name = format!("{}.{}.{}", prefix, s.program, s.port);
+        lines.push(format!("{}.up:{}|g", name, s.up as u8));
+        if let Some(rss) = s.rss {
+            lines.push(format!("{}.rss:{}|g", name, rss));
+        }
+        if let Some(cpu) = s.cpu {
+            lines.push(format!("{}.cpu:{:.1}|g", name, cpu));
+        }
+    }
+    lines
+}
+
+/// Join lines into newline separated packets of at most MAX_PACKET bytes
+pub fn packets(lines: &[String]) -> Vec<String> {
+    let mut packets: Vec<String> = Vec::new();
+    for line in lines {
+        match packets.last_mut() {
+            Some(packet) if packet.len() + 1 + line.len() <= MAX_PACKET => {
+                packet.push('\n');
+                packet.push_str(line);
+            }
+            _ => packets.push(line.clone()),
+        }
+    }
+    packets
+}
+
+fn is_up(settings: &Settings, port: i32) -> bool {
+    Connection::connect_local(settings, port, Duration::from_secs(1))
+        .and_then(|mut conn| conn.run_command("admin", json!({"hello": 1})))
+        .is_ok()
+}
+
+/// Send the gauges of the servers from scan to address every interval until interrupted
+pub fn push<F>(mut scan: F, address: &str, prefix: &str, interval: Duration) -> Result<()>
+where
+    F: FnMut() -> Result<(MongoPSInfo, Vec<ServerTarget>)>,
+{
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket
+        .connect(address)
+        .with_context(|| format!("Failed to resolve statsd address {}", address))?;
+    let mut cpu_samples: HashMap<i32, (Duration, Instant)> = HashMap::new();
+
+    loop {
+        let started = Instant::now();
+        let (info, targets) = scan()?;
+        let ports: Vec<i32> = targets.iter().map(|t| t.port).collect();
+        let up = probe_all(&info.settings, &ports, |port| is_up(&info.settings, *port));
+
+        let now = Instant::now();
+        let samples: Vec<ServerSample> = targets
+            .iter()
+            .zip(up)
+            .map(|(t, up)| {
+                let time = cpu_time(t.pid).ok();
+                let cpu = match (time, cpu_samples.get(&t.pid)) {
+                    (Some(time), Some((before, at))) => Some(cpu_percent(*before, time, now - *at)),
+                    _ => None,
+                };
+                if let Some(time) = time {
+                    cpu_samples.insert(t.pid, (time, now));
+                }
+                let proc = info.proc_info(t.pid);
+                ServerSample {
+                    port: t.port,
+                    program: proc.map_or("mongo".to_owned(), |p| p.program.clone()),
+                    rss: proc.and_then(|p| p.rss),
+                    cpu,
+                    up,
+                }
+            })
+            .collect();
+        cpu_samples.retain(|pid, _| targets.iter().any(|t| t.pid == *pid));
+
+        // A statsd daemon that is down must not stop the pushes
+        for packet in packets(&gauges(prefix, &info, &samples)) {
+            if let Err(e) = socket.send(packet.as_bytes()) {
+                eprintln!("Failed to send to statsd at {}: {}", address, e);
+                break;
+            }
+        }
+        std::thread::sleep(interval.saturating_sub(started.elapsed()));
+    }
+}
+
+#[test]
+fn test_packets() {
+    let lines: Vec<String> = (0..200)
+        .map(|i| format!("mpf.mongod.{}.up:1|g", i))
+        .collect();
+    let packets = packets(&lines);
+    assert!(packets.len() > 1);
+    assert!(packets.iter().all(|p| p.len() <= MAX_PACKET));
+    assert_eq!(packets.join("\n"), lines.join("\n"));
+
+    let sample = ServerSample {
+        port: 20001,
+        program: "mongod".to_owned(),
+        rss: Some(1024),
+        cpu: None,
+        up: true,
+    };
+    let name = "mpf.mongod.20001";
+    let expected = [format!("{}.up:1|g", name), format!("{}.rss:1024|g", name)];
+    let info = MongoPSInfo {
+        schema_version: 2,
+        mongod: vec![],
+        mongos: vec![],
+        shell: vec![7],
+        mongocryptd: vec![],
+        clusters: vec![],
+        plugin_processes: vec![],
+        procs: vec![],
+        settings: Default::default(),
+    };
+    let lines = gauges("mpf", &info, &[sample]);
+    assert_eq!(lines[2], "mpf.processes.shell:1|g");
+    assert_eq!(lines[4..], expected);
+}
@@ -0,0 +1,141 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Storage engine settings and the memory the storage engine caches will use
+
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::audit::{mixed_setting, Finding};
+use super::config::get_path;
+use super::MongoPSInfo;
+
+const GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct StorageInfo {
+    pub engine: String,
+    /// Configured cache size, None means the default share of RAM
+    pub cache_size_gb: Option<f64>,
+}
+
+pub fn storage_info(config: &Value) -> StorageInfo {
+    let engine = get_path(config, "storage.engine")
+        .and_then(|v| v.as_str())
+        .unwrap_or("wiredTiger")
+        .to_owned();
+    let cache_path = match engine.as_str() {
+        "inMemory" => "storage.inMemory.engineConfig.inMemorySizeGB",
+        _ => "storage.wiredTiger.engineConfig.cacheSizeGB",
+    };
+
+    StorageInfo {
+        cache_size_gb: get_path(config, cache_path).and_then(|v| v.as_f64()),
+        engine,
+    }
+}
+
+/// Physical memory in bytes
+pub fn physical_memory() -> Option<u64> {
+    let pages = unsafe { libc::sysconf(libc::_SC_PHYS_PAGES) };
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if pages <= 0 || page_size <= 0 {
+        return None;
+    }
+    Some(pages as u64 * page_size as u64)
+}
+
+/// The default cache is the larger of 50% of (RAM - 1 GB) and 256 MB
+pub fn default_cache_size_gb(memory: u64) -> f64 {
+    (0.5 * (memory as f64 / GB - 1.0)).max(0.25)
+}
+
+/// Warn when the caches of all mongods together can grow beyond physical memory
+pub fn cache_warning(info: &MongoPSInfo) -> Option<String> {
+    let memory = physical_memory()?;
+    let total: f64 = info
+        .mongod
+        .iter()
+        .map(|d| {
+            d.storage
+                .cache_size_gb
+                .unwrap_or_else(|| default_cache_size_gb(memory))
+        })
+        .sum();
+
+    let memory_gb = memory as f64 / GB;
+    if total <= memory_gb {
+        return None;
+    }
+    Some(format!(
+        "WARNING: the storage engine caches of {} mongods add up to {:.1} GB but there is only {:.1} GB of RAM, set --wiredTigerCacheSizeGB to avoid swapping",
+        info.mongod.len(),
+        total,
+        memory_gb
+    ))
+}
+
+/// Storage engine a mongod runs, queryable when it serves a backup in queryableBackupMode
+pub fn engine_name(storage: &StorageInfo, special_modes: &[String]) -> String {
+    if special_modes.iter().any(|m| m == "queryableBackupMode") {
+        "queryable".to_owned()
+    } else {
+        storage.engine.clone()
+    }
+}
+
+/// Flag replica sets whose members run different storage engines, usually test flags leaking
+/// from another fixture configuration
+pub fn check_mixed_engines(info: &MongoPSInfo, pids: &[i32]) -> Vec<Finding> {
+    let mut sets: BTreeMap<&str, Vec<(i32, String)>> = BTreeMap::new();
+    for d in info.mongod.iter().filter(|d| pids.contains(&d.pid)) {
+        if let Some(ref repl_set) = d.replica_set_name {
+            sets.entry(repl_set)
+                .or_default()
+                .push((d.pid, engine_name(&d.storage, &d.special_modes)));
+        }
+    }
+    sets.iter()
+        .flat_map(|(set, members)| {
+            mixed_setting("storage engine", &format!("replica set {}", set), members)
+        })
+        .collect()
+}
+
+#[test]
+fn test_storage_info() {
+    use serde_json::json;
+
+    let config = json!({"storage": {"wiredTiger": {"engineConfig": {"cacheSizeGB": 0.5}}}});
+    assert_eq!(
+        storage_info(&config),
+        StorageInfo {
+            engine: "wiredTiger".to_owned(),
+            cache_size_gb: Some(0.5)
+        }
+    );
+
+    let config = json!({"storage": {"engine": "inMemory"}});
+    assert_eq!(storage_info(&config).cache_size_gb, None);
+    assert_eq!(engine_name(&storage_info(&config), &[]), "inMemory");
+    assert_eq!(
+        engine_name(&storage_info(&config), &["queryableBackupMode".to_owned()]),
+        "queryable"
+    );
+
+    assert_eq!(default_cache_size_gb(16 * 1024 * 1024 * 1024), 7.5);
+    assert_eq!(default_cache_size_gb(1024 * 1024 * 1024), 0.25);
+}
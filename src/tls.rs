@@ -0,0 +1,203 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// TLS settings of a server and the expiry of its certificates
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::config::get_path;
+use super::types::CommonProcInfo;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TlsInfo {
+    pub mode: String,
+    pub certificate_key_file: Option<String>,
+    pub ca_file: Option<String>,
+    pub cluster_file: Option<String>,
+    pub allow_connections_without_certificates: bool,
+    pub certificate_expiry: Option<String>,
+    pub certificate_expired: bool,
+}
+
+// Settings are read from net.tls and fall back to the deprecated net.ssl names
+fn setting<'a>(config: &'a Value, tls: &str, ssl: &str) -> Option<&'a Value> {
+    get_path(config, &format!("net.tls.{}", tls))
+        .or_else(|| get_path(config, &format!("net.ssl.{}", ssl)))
+}
+
+fn string_setting(config: &Value, tls: &str, ssl: &str) -> Option<String> {
+    setting(config, tls, ssl)
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_owned())
+}
+
+/// TLS settings of a server, None when TLS is disabled
+pub fn tls_info(proc: &CommonProcInfo, config: &Value) -> Option<TlsInfo> {
+    let mode = string_setting(config, "mode", "mode")?;
+    if mode == "disabled" {
+        return None;
+    }
+
+    let certificate_key_file = string_setting(config, "certificateKeyFile", "PEMKeyFile");
+    let expiry = certificate_key_file.as_ref().and_then(|file| {
+        let path = match proc.cwd {
+            Some(ref cwd) => cwd.join(file),
+            None => file.into(),
+        };
+        certificate_expiry(&path)
+    });
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+
+    Some(TlsInfo {
+        mode,
+        certificate_key_file,
+        ca_file: string_setting(config, "CAFile", "CAFile"),
+        cluster_file: string_setting(config, "clusterFile", "clusterFile"),
+        allow_connections_without_certificates: setting(
+            config,
+            "allowConnectionsWithoutCertificates",
+            "allowConnectionsWithoutCertificates",
+        )
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false),
+        certificate_expired: expiry.as_ref().is_some_and(|(_, secs)| *secs < now),
+        certificate_expiry: expiry.map(|(text, _)| text),
+    })
+}
+
+/// Expiry of the first certificate in a PEM file, as text and seconds since the epoch
+pub fn certificate_expiry(path: &Path) -> Option<(String, i64)> {
+    let pem = std::fs::read_to_string(path).ok()?;
+    let start = pem.find("-----BEGIN CERTIFICATE-----")? + "-----BEGIN CERTIFICATE-----".len();
+    let end = start + pem[start..].find("-----END CERTIFICATE-----")?;
+    let base64: String = pem[start..end]
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    let der = BASE64.decode(base64).ok()?;
+    not_after(&der)
+}
+
+// A DER element as (tag, contents)
+fn der_element(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first = *data.get(1)? as usize;
+    let (len, header) = if first & 0x80 == 0 {
+        (first, 2)
+    } else {
+        let count = first & 0x7f;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let bytes = data.get(2..2 + count)?;
+        (
+            bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize),
+            2 + count,
+        )
+    };
+    let contents = data.get(header..header + len)?;
+    Some((tag, contents, &data[header + len..]))
+}
+
+// Certificate -> TBSCertificate -> [version], serial, signature, issuer, validity
+fn not_after(der: &[u8]) -> Option<(String, i64)> {
+    let (_, cert, _) = der_element(der)?;
+    let (_, tbs, _) = der_element(cert)?;
+
+    let mut rest = tbs;
+    let (tag, _, after) = der_element(rest)?;
+    if tag == 0xa0 {
+        rest = after;
+    }
+    for _ in 0..3 {
+        rest = der_element(rest)?.2;
+    }
+    let (_, validity, _) = der_element(rest)?;
+    let not_before_rest = der_element(validity)?.2;
+    let (tag, time, _) = der_element(not_before_rest)?;
+    parse_asn1_time(tag, std::str::from_utf8(time).ok()?)
+}
+
+// UTCTime is YYMMDDHHMMSSZ and GeneralizedTime is YYYYMMDDHHMMSSZ
+fn parse_asn1_time(tag: u8, text: &str) -> Option<(String, i64)> {
+    let digits = text.strip_suffix('Z')?;
+    let (year, rest) = match tag {
+        0x17 => {
+            let yy: i64 = digits.get(0..2)?.parse().ok()?;
+            (if yy < 50 { 2000 + yy } else { 1900 + yy }, &digits[2..])
+        }
+        0x18 => (digits.get(0..4)?.parse().ok()?, &digits[4..]),
+        _ => return None,
+    };
+    let field = |i: usize| -> Option<i64> { rest.get(i..i + 2)?.parse().ok() };
+    let (month, day, hour, minute, second) =
+        (field(0)?, field(2)?, field(4)?, field(6)?, field(8)?);
+
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Some((
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year, month, day, hour, minute, second
+        ),
+        secs,
+    ))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Year, month and day of a count of days since 1970-01-01, the inverse of days_from_civil
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[test]
+fn test_parse_asn1_time() {
+    assert_eq!(
+        parse_asn1_time(0x17, "300101120000Z"),
+        Some(("2030-01-01T12:00:00Z".to_owned(), 1893499200))
+    );
+    assert_eq!(
+        parse_asn1_time(0x18, "19991231235959Z"),
+        Some(("1999-12-31T23:59:59Z".to_owned(), 946684799))
+    );
+    assert_eq!(parse_asn1_time(0x17, "bogus"), None);
+    assert_eq!(civil_from_days(days_from_civil(2024, 2, 29)), (2024, 2, 29));
+    assert_eq!(civil_from_days(-1), (1969, 12, 31));
+}
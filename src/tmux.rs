@@ -0,0 +1,161 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Open a tmux pane per server running something useful against it
+
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+
+use super::{get_cmd_line_option, MongoPSInfo, ServerTarget};
+
+#[derive(Debug, ValueEnum, Clone, PartialEq)]
+pub enum PaneMode {
+    /// Follow the server's log file
+    Log,
+    /// mongosh connected to the server
+    Shell,
+    /// Debugger attached to the server
+    Debugger,
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Shell command running mode against a server
+pub fn pane_command(info: &MongoPSInfo, target: &ServerTarget, mode: &PaneMode) -> Result<String> {
+    Ok(match mode {
+        PaneMode::Log => {
+            let proc = info
+                .proc_info(target.pid)
+                .with_context(|| format!("Process {} exited", target.pid))?;
+            match get_cmd_line_option("--logpath", &proc.cmdline) {
+                Some(logpath) => {
+                    let path = match proc.cwd {
+                        Some(ref cwd) => cwd.join(logpath),
+                        None => logpath.into(),
+                    };
+                    format!("tail -F {}", shell_quote(&path.to_string_lossy()))
+                }
+                None => format!(
+                    "echo {} has no --logpath, it logs to stdout; exec $SHELL",
+                    target.pid
+                ),
+            }
+        }
+        PaneMode::Shell => format!("mongosh --port {}", target.port),
+        PaneMode::Debugger => {
+            if cfg!(target_os = "macos") {
+                format!("lldb -p {}", target.pid)
+            } else {
+                format!("gdb -p {}", target.pid)
+            }
+        }
+    })
+}
+
+fn tmux(args: &[&str]) -> Result<String> {
+    let output = Command::new("tmux")
+        .args(args)
+        .output()
+        .context("Failed to run tmux, is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "tmux {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Open a window with a pane per server, attaching to it if we are not inside tmux already
+pub fn open_panes(info: &MongoPSInfo, targets: &[ServerTarget], mode: &PaneMode) -> Result<()> {
+    let Some((first, rest)) = targets.split_first() else {
+        bail!("No servers matched");
+    };
+
+    let inside_tmux = std::env::var_os("TMUX").is_some();
+    let first_cmd = pane_command(info, first, mode)?;
+    let window = if inside_tmux {
+        tmux(&[
+            "new-window",
+            "-P",
+            "-F",
+            "#{window_id}",
+            "-n",
+            "mpf",
+            &first_cmd,
+        ])?
+    } else {
+        tmux(&[
+            "new-session",
+            "-d",
+            "-P",
+            "-F",
+            "#{window_id}",
+            "-n",
+            "mpf",
+            &first_cmd,
+        ])?
+    };
+    tmux(&[
+        "select-pane",
+        "-t",
+        &window,
+        "-T",
+        &format!("{} port {}", first.pid, first.port),
+    ])?;
+
+    for target in rest {
+        let cmd = pane_command(info, target, mode)?;
+        let pane = tmux(&[
+            "split-window",
+            "-P",
+            "-F",
+            "#{pane_id}",
+            "-t",
+            &window,
+            &cmd,
+        ])?;
+        tmux(&[
+            "select-pane",
+            "-t",
+            &pane,
+            "-T",
+            &format!("{} port {}", target.pid, target.port),
+        ])?;
+        // Re-tile after each split so there is always room for the next pane
+        tmux(&["select-layout", "-t", &window, "tiled"])?;
+    }
+    tmux(&[
+        "set-option",
+        "-w",
+        "-t",
+        &window,
+        "pane-border-status",
+        "top",
+    ])?;
+
+    if !inside_tmux {
+        let err = Command::new("tmux")
+            .args(["attach-session", "-t", &window])
+            .exec();
+        bail!("Failed to attach to tmux: {}", err);
+    }
+    Ok(())
+}
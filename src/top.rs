@@ -0,0 +1,384 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Full screen live view of the mongo processes
+
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use serde_json::json;
+
+use super::client::{Connection, Settings};
+use super::probe::probe_all;
+use super::report::format_bytes;
+use super::signal::send_signal;
+use super::tmux::{pane_command, PaneMode};
+use super::{cpu_time, MongoPSInfo, ServerTarget};
+
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub pid: i32,
+    pub port: i32,
+    pub program: String,
+    /// Replica set name, or what the server is if it is not in one
+    pub group: String,
+    pub cpu: Option<f64>,
+    pub rss: Option<u64>,
+    pub connections: Option<i64>,
+}
+
+pub fn cpu_percent(before: Duration, after: Duration, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    after.saturating_sub(before).as_secs_f64() * 100.0 / elapsed.as_secs_f64()
+}
+
+fn group_name(info: &MongoPSInfo, pid: i32) -> String {
+    if let Some(d) = info.mongod.iter().find(|d| d.pid == pid) {
+        return d
+            .replica_set_name
+            .clone()
+            .unwrap_or_else(|| "standalone".to_owned());
+    }
+    match info.mongos.iter().find(|s| s.pid == pid) {
+        Some(s) if !s.configdb.is_empty() => format!("mongos ({})", s.configdb),
+        _ => "mongos".to_owned(),
+    }
+}
+
+// Current client connections from serverStatus, kept short so a hung server does not stall
+// the screen
+fn connections(settings: &Settings, port: i32) -> Option<i64> {
+    let mut conn = Connection::connect_local(settings, port, Duration::from_millis(250)).ok()?;
+    let status = conn
+        .run_command("admin", json!({"serverStatus": 1, "repl": 0, "metrics": 0}))
+        .ok()?;
+    status.get("connections")?.get("current")?.as_i64()
+}
+
+/// Rows sorted by group and port, so members of a replica set are together
+pub fn sort_rows(rows: &mut [Row]) {
+    rows.sort_by(|a, b| a.group.cmp(&b.group).then(a.port.cmp(&b.port)));
+}
+
+fn truncate(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
+
+/// Lines of the table with a heading per group, the selected row is highlighted
+pub fn render_rows(rows: &[Row], selected: usize, width: usize) -> Vec<String> {
+    let mut lines = vec![truncate(
+        &format!(
+            "{:>8} {:>6} {:<8} {:>6} {:>10} {:>6}",
+            "PID", "PORT", "PROGRAM", "CPU%", "RSS", "CONNS"
+        ),
+        width,
+    )];
+    let mut group: Option<&str> = None;
+    for (i, row) in rows.iter().enumerate() {
+        if group != Some(row.group.as_str()) {
+            lines.push(truncate(&format!("[{}]", row.group), width));
+            group = Some(&row.group);
+        }
+        let line = truncate(
+            &format!(
+                "{:>8} {:>6} {:<8} {:>6} {:>10} {:>6}",
+                row.pid,
+                row.port,
+                row.program,
+                row.cpu.map_or("-".to_owned(), |c| format!("{:.1}", c)),
+                row.rss.map_or("-".to_owned(), format_bytes),
+                row.connections.map_or("-".to_owned(), |c| c.to_string()),
+            ),
+            width,
+        );
+        if i == selected {
+            lines.push(format!("\x1b[7m{:<width$}\x1b[0m", line, width = width));
+        } else {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+// Raw mode on the alternate screen, restored on drop so errors do not leave the terminal broken
+struct Screen {
+    original: libc::termios,
+}
+
+impl Screen {
+    fn enter() -> Result<Screen> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            bail!(
+                "Failed to read terminal settings: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+        let screen = Screen { original };
+        screen.resume()?;
+        Ok(screen)
+    }
+
+    fn resume(&self) -> Result<()> {
+        let mut raw = self.original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) };
+        print!("\x1b[?1049h\x1b[?25l");
+        std::io::stdout().flush()?;
+        Ok(())
+    }
+
+    fn size() -> (usize, usize) {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if ret != 0 || size.ws_col == 0 {
+            return (80, 24);
+        }
+        (size.ws_col as usize, size.ws_row as usize)
+    }
+
+    fn leave(&self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = std::io::stdout().flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
+
+enum Key {
+    Up,
+    Down,
+    Char(u8),
+}
+
+fn read_key(timeout: Duration) -> Option<Key> {
+    let mut fds = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    if unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) } <= 0 {
+        return None;
+    }
+    let mut buf = [0u8; 8];
+    let n = std::io::stdin().read(&mut buf).ok()?;
+    match &buf[..n] {
+        [0x1b, b'[', b'A', ..] => Some(Key::Up),
+        [0x1b, b'[', b'B', ..] => Some(Key::Down),
+        [c, ..] => Some(Key::Char(*c)),
+        [] => None,
+    }
+}
+
+// Run a command on the normal screen until it exits, Ctrl-C only stops the command
+fn run_outside(screen: &Screen, command: &str) -> Result<()> {
+    screen.leave();
+    let previous = unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN) };
+    let status = unsafe {
+        std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .pre_exec(|| {
+                libc::signal(libc::SIGINT, libc::SIG_DFL);
+                Ok(())
+            })
+            .status()
+    };
+    unsafe { libc::signal(libc::SIGINT, previous) };
+    screen.resume()?;
+    status?;
+    Ok(())
+}
+
+/// Show the servers from scan until q is pressed, refreshing every interval
+pub fn top<F>(mut scan: F, interval: Duration) -> Result<()>
+where
+    F: FnMut() -> Result<(MongoPSInfo, Vec<ServerTarget>)>,
+{
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        bail!("top needs a terminal");
+    }
+
+    let screen = Screen::enter()?;
+    let mut samples: HashMap<i32, (Duration, Instant)> = HashMap::new();
+    let mut selected = 0;
+    let mut status = String::new();
+    let mut refresh_at = Instant::now();
+    let mut state: Option<(MongoPSInfo, Vec<Row>)> = None;
+
+    loop {
+        if state.is_none() || Instant::now() >= refresh_at {
+            let (info, targets) = scan()?;
+            let now = Instant::now();
+            let ports: Vec<i32> = targets.iter().map(|t| t.port).collect();
+            let connection_counts = probe_all(&info.settings, &ports, |port| {
+                connections(&info.settings, *port)
+            });
+            let mut rows: Vec<Row> = targets
+                .iter()
+                .zip(connection_counts)
+                .map(|(t, connections)| {
+                    let time = cpu_time(t.pid).ok();
+                    let cpu = match (time, samples.get(&t.pid)) {
+                        (Some(time), Some((before, at))) => {
+                            Some(cpu_percent(*before, time, now - *at))
+                        }
+                        _ => None,
+                    };
+                    if let Some(time) = time {
+                        samples.insert(t.pid, (time, now));
+                    }
+                    Row {
+                        pid: t.pid,
+                        port: t.port,
+                        program: info
+                            .proc_info(t.pid)
+                            .map_or(String::new(), |p| p.program.clone()),
+                        group: group_name(&info, t.pid),
+                        cpu,
+                        rss: info.proc_info(t.pid).and_then(|p| p.rss),
+                        connections,
+                    }
+                })
+                .collect();
+            sort_rows(&mut rows);
+            samples.retain(|pid, _| rows.iter().any(|r| r.pid == *pid));
+            state = Some((info, rows));
+            refresh_at = Instant::now() + interval;
+        }
+        let (info, rows) = state.as_ref().unwrap();
+        selected = selected.min(rows.len().saturating_sub(1));
+
+        let (width, height) = Screen::size();
+        let mut out = String::from("\x1b[H\x1b[2J");
+        out.push_str(&truncate(
+            &format!(
+                "mpf top - {} servers - up/down select, x kill, a attach, l log, s shell, q quit",
+                rows.len()
+            ),
+            width,
+        ));
+        out.push_str("\r\n");
+        for line in render_rows(rows, selected, width)
+            .iter()
+            .take(height.saturating_sub(3))
+        {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+        out.push_str(&format!("\x1b[{};1H{}", height, truncate(&status, width)));
+        print!("{}", out);
+        std::io::stdout().flush()?;
+
+        let Some(key) = read_key(refresh_at.saturating_duration_since(Instant::now())) else {
+            continue;
+        };
+        let row = rows.get(selected);
+        let target = row.map(|r| ServerTarget {
+            pid: r.pid,
+            port: r.port,
+        });
+        status.clear();
+        match key {
+            Key::Char(b'q') | Key::Char(3) => return Ok(()),
+            Key::Up | Key::Char(b'k') => selected = selected.saturating_sub(1),
+            Key::Down | Key::Char(b'j') => selected += 1,
+            Key::Char(b'x') => {
+                if let Some(row) = row {
+                    status = format!("Send SIGTERM to {} on port {}? [y/N]", row.pid, row.port);
+                    print!("\x1b[{};1H\x1b[2K{}", height, status);
+                    std::io::stdout().flush()?;
+                    status = match read_key(Duration::from_secs(30)) {
+                        Some(Key::Char(b'y')) => match send_signal(row.pid, libc::SIGTERM) {
+                            Ok(()) => format!("Sent SIGTERM to {}", row.pid),
+                            Err(e) => e.to_string(),
+                        },
+                        _ => String::new(),
+                    };
+                    refresh_at = Instant::now();
+                }
+            }
+            Key::Char(c @ (b'a' | b'l' | b's')) => {
+                if let Some(target) = target {
+                    let mode = match c {
+                        b'a' => PaneMode::Debugger,
+                        b'l' => PaneMode::Log,
+                        _ => PaneMode::Shell,
+                    };
+                    match pane_command(info, &target, &mode) {
+                        Ok(command) => {
+                            if let Err(e) = run_outside(&screen, &command) {
+                                status = format!("{}: {}", command, e);
+                            }
+                        }
+                        Err(e) => status = e.to_string(),
+                    }
+                    refresh_at = Instant::now();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_render_rows() {
+    let row = |pid, port, group: &str| Row {
+        pid,
+        port,
+        program: "mongod".to_owned(),
+        group: group.to_owned(),
+        cpu: Some(12.5),
+        rss: None,
+        connections: Some(3),
+    };
+    let mut rows = vec![
+        row(3, 20002, "rs1"),
+        row(1, 20001, "rs0"),
+        row(2, 20000, "rs1"),
+    ];
+    sort_rows(&mut rows);
+    assert_eq!(
+        rows.iter().map(|r| r.pid).collect::<Vec<i32>>(),
+        vec![1, 2, 3]
+    );
+
+    let lines = render_rows(&rows, 1, 60);
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[1], "[rs0]");
+    assert_eq!(lines[3], "[rs1]");
+    assert!(lines[4].starts_with("\x1b[7m"));
+    assert!(lines[2].contains("12.5"));
+
+    assert_eq!(
+        cpu_percent(
+            Duration::from_millis(100),
+            Duration::from_millis(600),
+            Duration::from_secs(1)
+        ),
+        50.0
+    );
+}
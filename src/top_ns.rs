@@ -0,0 +1,182 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// mongotop for one local server: poll the top command and print the time each namespace spent
+// reading and writing since the previous poll
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use super::client::{Connection, Settings, DEFAULT_TIMEOUT};
+use super::watch::timestamp;
+use super::ServerTarget;
+
+/// Microseconds a namespace spent in operations, cumulative since the server started
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NsTimes {
+    pub total: i64,
+    pub read: i64,
+    pub write: i64,
+}
+
+/// Times per namespace from a top reply
+pub fn parse_top(reply: &Value) -> BTreeMap<String, NsTimes> {
+    let time = |ns: &Value, name: &str| {
+        ns.get(name)
+            .and_then(|n| n.get("time"))
+            .and_then(Value::as_i64)
+            .unwrap_or(0)
+    };
+    reply
+        .get("totals")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .filter(|(_, ns)| ns.is_object())
+        .map(|(name, ns)| {
+            let times = NsTimes {
+                total: time(ns, "total"),
+                read: time(ns, "readLock"),
+                write: time(ns, "writeLock"),
+            };
+            (name.clone(), times)
+        })
+        .collect()
+}
+
+/// Time spent per namespace between two polls, busiest first
+pub fn deltas(
+    before: &BTreeMap<String, NsTimes>,
+    after: &BTreeMap<String, NsTimes>,
+) -> Vec<(String, NsTimes)> {
+    let mut deltas: Vec<(String, NsTimes)> = after
+        .iter()
+        .map(|(name, times)| {
+            let old = before.get(name).copied().unwrap_or_default();
+            let delta = NsTimes {
+                total: (times.total - old.total).max(0),
+                read: (times.read - old.read).max(0),
+                write: (times.write - old.write).max(0),
+            };
+            (name.clone(), delta)
+        })
+        .collect();
+    deltas.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(&b.0)));
+    deltas
+}
+
+pub fn render(time: &str, deltas: &[(String, NsTimes)], limit: usize) -> Vec<String> {
+    let width = deltas
+        .iter()
+        .take(limit)
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(2);
+    let mut lines = vec![format!(
+        "{:<width$} {:>9} {:>9} {:>9}    {}",
+        "ns",
+        "total",
+        "read",
+        "write",
+        time,
+        width = width
+    )];
+    for (name, times) in deltas.iter().take(limit) {
+        lines.push(format!(
+            "{:<width$} {:>7}ms {:>7}ms {:>7}ms",
+            name,
+            times.total / 1000,
+            times.read / 1000,
+            times.write / 1000,
+            width = width
+        ));
+    }
+    lines
+}
+
+fn top(conn: &mut Connection) -> Result<BTreeMap<String, NsTimes>> {
+    Ok(parse_top(&conn.run_command("admin", json!({"top": 1}))?))
+}
+
+/// Print the busiest namespaces of the server every interval, until interrupted or after count
+/// polls
+pub fn top_ns(
+    settings: &Settings,
+    target: &ServerTarget,
+    interval: Duration,
+    count: Option<u64>,
+    limit: usize,
+) -> Result<()> {
+    let mut conn = Connection::connect_local(settings, target.port, DEFAULT_TIMEOUT)?;
+    let mut before = top(&mut conn)?;
+    let mut polls = 0;
+    let mut started = Instant::now();
+
+    while count.is_none_or(|count| polls < count) {
+        std::thread::sleep(interval.saturating_sub(started.elapsed()));
+        started = Instant::now();
+        let after = top(&mut conn)?;
+        if polls > 0 {
+            println!();
+        }
+        for line in render(&timestamp(), &deltas(&before, &after), limit) {
+            println!("{}", line);
+        }
+        before = after;
+        polls += 1;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_top_deltas() {
+    let before = parse_top(&json!({"totals": {
+        "note": "all times in microseconds",
+        "test.orders": {"total": {"time": 1000, "count": 1},
+                        "readLock": {"time": 1000, "count": 1},
+                        "writeLock": {"time": 0, "count": 0}}
+    }}));
+    assert_eq!(before.len(), 1);
+
+    let after = parse_top(&json!({"totals": {
+        "note": "all times in microseconds",
+        "test.orders": {"total": {"time": 3000, "count": 2},
+                        "readLock": {"time": 3000, "count": 2},
+                        "writeLock": {"time": 0, "count": 0}},
+        "test.users": {"total": {"time": 9000, "count": 5},
+                       "readLock": {"time": 1000, "count": 1},
+                       "writeLock": {"time": 8000, "count": 4}}
+    }}));
+    let deltas = deltas(&before, &after);
+    assert_eq!(deltas[0].0, "test.users");
+    assert_eq!(
+        deltas[1].1,
+        NsTimes {
+            total: 2000,
+            read: 2000,
+            write: 0
+        }
+    );
+
+    let lines = render("12:00:00", &deltas, 1);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(
+        lines[1].split_whitespace().collect::<Vec<&str>>(),
+        ["test.users", "9ms", "1ms", "8ms"]
+    );
+}
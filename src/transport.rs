@@ -0,0 +1,284 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Byte streams to servers: plain TCP, unix domain sockets, or TLS over TCP with rustls
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{
+    ring, verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms,
+};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    StreamOwned,
+};
+
+/// Client side TLS settings, named like the mongosh options
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    pub ca_file: Option<String>,
+    pub certificate_key_file: Option<String>,
+    pub allow_invalid_certificates: bool,
+}
+
+impl TlsOptions {
+    /// The same settings as connection string options for the shell
+    pub fn uri_options(&self) -> Vec<String> {
+        let mut options = vec!["tls=true".to_owned()];
+        if let Some(ca_file) = &self.ca_file {
+            options.push(format!("tlsCAFile={}", ca_file));
+        }
+        if let Some(key_file) = &self.certificate_key_file {
+            options.push(format!("tlsCertificateKeyFile={}", key_file));
+        }
+        if self.allow_invalid_certificates {
+            options.push("tlsAllowInvalidCertificates=true".to_owned());
+        }
+        options
+    }
+
+    // The CA file, or the certificates of the system like the shell when there is none
+    fn root_store(&self) -> Result<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        match &self.ca_file {
+            Some(ca_file) => {
+                for cert in CertificateDer::pem_file_iter(ca_file)
+                    .with_context(|| format!("Failed to read {}", ca_file))?
+                {
+                    let cert = cert.with_context(|| format!("Failed to read {}", ca_file))?;
+                    roots
+                        .add(cert)
+                        .with_context(|| format!("Bad CA certificate in {}", ca_file))?;
+                }
+                if roots.is_empty() {
+                    bail!("No certificates in {}", ca_file);
+                }
+            }
+            None => {
+                roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+                if roots.is_empty() {
+                    bail!("No system CA certificates found, use --tlsCAFile");
+                }
+            }
+        }
+        Ok(roots)
+    }
+
+    fn client_config(&self) -> Result<ClientConfig> {
+        let provider = Arc::new(ring::default_provider());
+        let algorithms = provider.signature_verification_algorithms;
+        let builder =
+            ClientConfig::builder_with_provider(provider).with_safe_default_protocol_versions()?;
+        let builder = if self.allow_invalid_certificates {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(AllowInvalidCertificates(algorithms)))
+        } else {
+            builder.with_root_certificates(self.root_store()?)
+        };
+
+        let Some(key_file) = &self.certificate_key_file else {
+            return Ok(builder.with_no_client_auth());
+        };
+        // The PEM file holds both the certificate and its key
+        let certs = CertificateDer::pem_file_iter(key_file)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| format!("Failed to read certificates from {}", key_file))?;
+        if certs.is_empty() {
+            bail!("No certificate in {}", key_file);
+        }
+        let key = PrivateKeyDer::from_pem_file(key_file)
+            .with_context(|| format!("Failed to read the private key from {}", key_file))?;
+        builder
+            .with_client_auth_cert(certs, key)
+            .with_context(|| format!("Bad certificate or key in {}", key_file))
+    }
+}
+
+// --tlsAllowInvalidCertificates accepts any certificate for any name, the handshake
+// signatures are still checked
+#[derive(Debug)]
+struct AllowInvalidCertificates(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for AllowInvalidCertificates {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
+
+/// TLS options with the rustls configuration built from them once, so a bad CA or key file
+/// is reported before connecting
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    pub options: TlsOptions,
+    config: Arc<ClientConfig>,
+}
+
+impl TlsSettings {
+    pub fn new(options: TlsOptions) -> Result<Self> {
+        let config = Arc::new(options.client_config()?);
+        Ok(TlsSettings { options, config })
+    }
+
+    /// TLS over a connected stream, with the handshake done within timeout so a server
+    /// without TLS or with a certificate that fails verification is reported here
+    pub fn connect(
+        &self,
+        host: &str,
+        mut stream: TcpStream,
+        timeout: Duration,
+    ) -> Result<TlsStream> {
+        let name = ServerName::try_from(host.to_owned())
+            .with_context(|| format!("{} is not a valid TLS server name", host))?;
+        let mut conn = ClientConnection::new(self.config.clone(), name)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut stream)
+                .map_err(|e| handshake_error(e, host, timeout))?;
+        }
+        Ok(StreamOwned::new(conn, stream))
+    }
+}
+
+fn handshake_error(e: std::io::Error, host: &str, timeout: Duration) -> anyhow::Error {
+    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+        return anyhow!("TLS handshake with {} not done within {:?}", host, timeout);
+    }
+    let hint = match e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()) {
+        Some(rustls::Error::InvalidCertificate(_)) => {
+            ", check --tlsCAFile or use --tlsAllowInvalidCertificates"
+        }
+        _ => "",
+    };
+    anyhow!("TLS handshake with {} failed: {}{}", host, e, hint)
+}
+
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+pub enum Transport {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+    Tls(Box<TlsStream>),
+}
+
+impl Transport {
+    /// Time limit for each read and write
+    pub fn set_timeout(&self, timeout: Duration) -> std::io::Result<()> {
+        match self {
+            Transport::Tcp(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+            Transport::Unix(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))
+            }
+            Transport::Tls(stream) => {
+                stream.sock.set_read_timeout(Some(timeout))?;
+                stream.sock.set_write_timeout(Some(timeout))
+            }
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Tcp(stream) => stream.read(buf),
+            Transport::Unix(stream) => stream.read(buf),
+            Transport::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Tcp(stream) => stream.write(buf),
+            Transport::Unix(stream) => stream.write(buf),
+            Transport::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Transport::Tcp(stream) => stream.flush(),
+            Transport::Unix(stream) => stream.flush(),
+            Transport::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+#[test]
+fn test_tls_options() {
+    let options = TlsOptions {
+        ca_file: Some("ca.pem".to_owned()),
+        certificate_key_file: Some("client.pem".to_owned()),
+        allow_invalid_certificates: false,
+    };
+    assert_eq!(
+        options.uri_options(),
+        [
+            "tls=true",
+            "tlsCAFile=ca.pem",
+            "tlsCertificateKeyFile=client.pem"
+        ]
+    );
+    let missing = TlsSettings::new(options).unwrap_err();
+    assert!(format!("{:#}", missing).contains("ca.pem"));
+
+    let insecure = TlsOptions {
+        allow_invalid_certificates: true,
+        ..TlsOptions::default()
+    };
+    assert!(TlsSettings::new(insecure).is_ok());
+}
@@ -12,10 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
 #[derive(Debug)]
 pub struct CommonProcInfo {
     pub pid: i32,
+    pub ppid: Option<i32>,
     pub program: String,
     pub cmdline: Vec<String>,
-    // env: HashMap<OsString, OsString>
+    pub env: HashMap<OsString, OsString>,
+    pub cwd: Option<PathBuf>,
+    pub exe: Option<PathBuf>,
+    pub uid: Option<u32>,
+    /// Seconds since the epoch
+    pub start_time: Option<u64>,
+    /// Resident memory in bytes
+    pub rss: Option<u64>,
 }
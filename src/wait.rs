@@ -0,0 +1,330 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Block until starting servers are ready, printing what each one is doing on the way: its
+// startup phase from the log while it does not listen, then its replica set state. Or block
+// until a whole replica set has a primary or only healthy members, as seen by any member.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use super::client::{Connection, Settings};
+use super::probe::probe_all;
+use super::signal::process_exists;
+use super::startup::log_startup_phase;
+use super::watch::timestamp;
+use super::{MongoPSInfo, ServerTarget};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What a replica set has to reach for wait --cluster
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Until {
+    /// A member is PRIMARY
+    Primary,
+    /// Every member is up and PRIMARY, SECONDARY or ARBITER
+    AllMembersHealthy,
+}
+
+/// Where a server is on its way to ready
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub milestone: String,
+    pub ready: bool,
+}
+
+/// Progress from a hello reply: anything answering is ready unless it is a replica set member
+/// that is not yet PRIMARY, SECONDARY or ARBITER. state is the member state name from
+/// replSetGetStatus, if it could be read.
+pub fn hello_progress(hello: &Value, member: bool, state: Option<&str>) -> Progress {
+    let is = |field: &str| hello.get(field).and_then(Value::as_bool) == Some(true);
+    let ready = |milestone: &str| Progress {
+        milestone: milestone.to_owned(),
+        ready: true,
+    };
+    if !member {
+        return ready("accepting connections");
+    }
+    if is("isWritablePrimary") {
+        ready("PRIMARY")
+    } else if is("secondary") {
+        ready("SECONDARY")
+    } else if is("arbiterOnly") {
+        ready("ARBITER")
+    } else {
+        let milestone = match (hello.get("setName"), state) {
+            (None, _) => "accepting connections, replica set not initiated".to_owned(),
+            (Some(_), Some(state)) => format!("accepting connections, {}", state),
+            (Some(_), None) => "accepting connections, joining the replica set".to_owned(),
+        };
+        Progress {
+            milestone,
+            ready: false,
+        }
+    }
+}
+
+fn member_state(conn: &mut Connection) -> Option<String> {
+    let status = conn
+        .run_command("admin", json!({"replSetGetStatus": 1}))
+        .ok()?;
+    status
+        .get("members")?
+        .as_array()?
+        .iter()
+        .find(|m| m.get("self").and_then(Value::as_bool) == Some(true))?
+        .get("stateStr")?
+        .as_str()
+        .map(str::to_owned)
+}
+
+fn progress(
+    settings: &Settings,
+    target: &ServerTarget,
+    member: bool,
+    logpath: Option<&str>,
+) -> Progress {
+    let hello = Connection::connect_local(settings, target.port, Duration::from_secs(1)).and_then(
+        |mut c| {
+            let hello = c.run_command("admin", json!({"hello": 1}))?;
+            Ok((hello, c))
+        },
+    );
+    match hello {
+        Ok((hello, mut conn)) => {
+            let state = if member {
+                member_state(&mut conn)
+            } else {
+                None
+            };
+            hello_progress(&hello, member, state.as_deref())
+        }
+        Err(_) => Progress {
+            milestone: match log_startup_phase(logpath) {
+                Some(phase) => format!("starting: {}", phase),
+                None => "not answering".to_owned(),
+            },
+            ready: false,
+        },
+    }
+}
+
+/// Wait until every target answers hello and replica set members reach a steady state,
+/// printing each change of milestone. Fails when a server exits or timeout passes first.
+pub fn until_ready(info: &MongoPSInfo, targets: &[ServerTarget], timeout: Duration) -> Result<()> {
+    if targets.is_empty() {
+        bail!("No servers matched");
+    }
+    let details: Vec<(bool, Option<&str>)> = targets
+        .iter()
+        .map(|t| match info.mongod.iter().find(|d| d.pid == t.pid) {
+            Some(d) => (d.replica_set_name.is_some(), d.logpath.as_deref()),
+            None => (
+                false,
+                info.mongos
+                    .iter()
+                    .find(|s| s.pid == t.pid)
+                    .and_then(|s| s.logpath.as_deref()),
+            ),
+        })
+        .collect();
+
+    let start = Instant::now();
+    let mut last: HashMap<i32, Progress> = HashMap::new();
+    loop {
+        let pending: Vec<(&ServerTarget, (bool, Option<&str>))> = targets
+            .iter()
+            .zip(details.iter().copied())
+            .filter(|(t, _)| !last.get(&t.pid).is_some_and(|p| p.ready))
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let polled = probe_all(&info.settings, &pending, |(t, (member, logpath))| {
+            progress(&info.settings, t, *member, *logpath)
+        });
+        for ((t, _), progress) in pending.iter().zip(polled) {
+            if last.get(&t.pid) != Some(&progress) {
+                println!(
+                    "{} {} port {}: {}",
+                    timestamp(),
+                    t.pid,
+                    t.port,
+                    progress.milestone
+                );
+            }
+            if !progress.ready && !process_exists(t.pid) {
+                bail!("{} on port {} exited before it was ready", t.pid, t.port);
+            }
+            last.insert(t.pid, progress);
+        }
+
+        if start.elapsed() > timeout {
+            let waiting: Vec<String> = targets
+                .iter()
+                .filter_map(|t| {
+                    let progress = last.get(&t.pid)?;
+                    (!progress.ready).then(|| format!("{} ({})", t.port, progress.milestone))
+                })
+                .collect();
+            if !waiting.is_empty() {
+                bail!("Not ready after {:?}: {}", timeout, waiting.join(", "));
+            }
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn state(member: &Value) -> &str {
+    member
+        .get("stateStr")
+        .and_then(Value::as_str)
+        .unwrap_or("UNKNOWN")
+}
+
+/// Progress of a replica set from a replSetGetStatus reply, with every member and its state
+pub fn set_progress(status: &Value, until: Until) -> Progress {
+    let members = status
+        .get("members")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let healthy = |m: &Value| {
+        m.get("health").and_then(Value::as_f64) != Some(0.0)
+            && matches!(state(m), "PRIMARY" | "SECONDARY" | "ARBITER")
+    };
+    let ready = match until {
+        Until::Primary => members.iter().any(|m| state(m) == "PRIMARY"),
+        Until::AllMembersHealthy => !members.is_empty() && members.iter().all(healthy),
+    };
+    let milestone = members
+        .iter()
+        .map(|m| {
+            let name = m.get("name").and_then(Value::as_str).unwrap_or("?");
+            format!("{} {}", name, state(m))
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    Progress { milestone, ready }
+}
+
+fn cluster_status(settings: &Settings, ports: &[i32]) -> Option<Value> {
+    ports.iter().find_map(|port| {
+        Connection::connect_local(settings, *port, Duration::from_secs(1))
+            .and_then(|mut c| c.run_command("admin", json!({"replSetGetStatus": 1})))
+            .ok()
+    })
+}
+
+/// Wait until the replica set called name reaches until, printing its members each time one
+/// changes state. The status comes from the first local member that answers.
+pub fn until_cluster(
+    info: &MongoPSInfo,
+    name: &str,
+    until: Until,
+    timeout: Duration,
+) -> Result<()> {
+    let ports: Vec<i32> = info
+        .mongod
+        .iter()
+        .filter(|d| d.replica_set_name.as_deref() == Some(name))
+        .map(|d| d.port)
+        .collect();
+    if ports.is_empty() {
+        bail!("No running members of replica set {}", name);
+    }
+
+    let start = Instant::now();
+    let mut last: Option<Progress> = None;
+    loop {
+        let progress = match cluster_status(&info.settings, &ports) {
+            Some(status) => set_progress(&status, until),
+            None => Progress {
+                milestone: "no member answering".to_owned(),
+                ready: false,
+            },
+        };
+        if last.as_ref() != Some(&progress) {
+            println!("{} {}: {}", timestamp(), name, progress.milestone);
+        }
+        if progress.ready {
+            return Ok(());
+        }
+        if start.elapsed() > timeout {
+            bail!(
+                "Replica set {} not ready after {:?}: {}",
+                name,
+                timeout,
+                progress.milestone
+            );
+        }
+        last = Some(progress);
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[test]
+fn test_hello_progress() {
+    let standalone = hello_progress(&json!({"isWritablePrimary": true}), false, None);
+    assert!(standalone.ready);
+    assert_eq!(standalone.milestone, "accepting connections");
+
+    let secondary = json!({"setName": "rs0", "isWritablePrimary": false, "secondary": true});
+    assert_eq!(
+        hello_progress(&secondary, true, None),
+        Progress {
+            milestone: "SECONDARY".to_owned(),
+            ready: true
+        }
+    );
+
+    let syncing = json!({"setName": "rs0", "isWritablePrimary": false, "secondary": false});
+    let progress = hello_progress(&syncing, true, Some("STARTUP2"));
+    assert!(!progress.ready);
+    assert_eq!(progress.milestone, "accepting connections, STARTUP2");
+
+    let uninitiated = hello_progress(&json!({"isWritablePrimary": false}), true, None);
+    assert_eq!(
+        uninitiated.milestone,
+        "accepting connections, replica set not initiated"
+    );
+}
+
+#[test]
+fn test_set_progress() {
+    let status = json!({"set": "rs0", "members": [
+        {"name": "localhost:20001", "stateStr": "PRIMARY", "health": 1.0},
+        {"name": "localhost:20002", "stateStr": "STARTUP2", "health": 1.0},
+        {"name": "localhost:20003", "stateStr": "(not reachable/healthy)", "health": 0.0},
+    ]});
+    let primary = set_progress(&status, Until::Primary);
+    assert!(primary.ready);
+    assert_eq!(
+        primary.milestone,
+        "localhost:20001 PRIMARY, localhost:20002 STARTUP2, localhost:20003 (not reachable/healthy)"
+    );
+    assert!(!set_progress(&status, Until::AllMembersHealthy).ready);
+
+    let healthy = json!({"members": [
+        {"name": "localhost:20001", "stateStr": "SECONDARY", "health": 1.0},
+        {"name": "localhost:20002", "stateStr": "ARBITER", "health": 1.0},
+    ]});
+    assert!(set_progress(&healthy, Until::AllMembersHealthy).ready);
+    assert!(!set_progress(&healthy, Until::Primary).ready);
+}
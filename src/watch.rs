@@ -0,0 +1,214 @@
+// Copyright [2022] [Mark Benvenuto]
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Rescan periodically and report what changed between scans
+
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+/// A process as seen by one scan
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// mongod, mongos, mongocryptd or shell
+    pub kind: String,
+    pub port: Option<i32>,
+    pub line: String,
+}
+
+/// One entry per process, keyed by pid
+pub type Snapshot = BTreeMap<i32, Entry>;
+
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Added(i32, Entry),
+    Removed(i32, Entry),
+    Changed(i32, Entry, Entry),
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum WatchOutput {
+    /// Colored lines for people
+    Text,
+    /// One JSON event per line for other programs
+    Ndjson,
+}
+
+pub fn diff(old: &Snapshot, new: &Snapshot) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (pid, entry) in old {
+        match new.get(pid) {
+            None => changes.push(Change::Removed(*pid, entry.clone())),
+            Some(new_entry) if new_entry != entry => {
+                changes.push(Change::Changed(*pid, entry.clone(), new_entry.clone()))
+            }
+            _ => {}
+        }
+    }
+    for (pid, entry) in new {
+        if !old.contains_key(pid) {
+            changes.push(Change::Added(*pid, entry.clone()));
+        }
+    }
+    changes
+}
+
+/// Wrap text in an ANSI color when enabled
+pub fn paint(text: String, color: &str, enabled: bool) -> String {
+    if enabled {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text
+    }
+}
+
+pub fn render(change: &Change, color: bool) -> String {
+    match change {
+        Change::Added(_, e) => paint(format!("+ {}", e.line), "32", color),
+        Change::Removed(_, e) => paint(format!("- {}", e.line), "31", color),
+        Change::Changed(_, old, new) => {
+            paint(format!("~ {} -> {}", old.line, new.line), "33", color)
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn event(name: &str, pid: i32, entry: &Entry, time: u64) -> Value {
+    json!({
+        "event": name,
+        "type": entry.kind,
+        "pid": pid,
+        "port": entry.port,
+        "summary": entry.line,
+        "time": time,
+    })
+}
+
+/// The event of a change, processes already running when watching starts are "running"
+pub fn change_event(change: &Change, time: u64) -> Value {
+    match change {
+        Change::Added(pid, e) => event("started", *pid, e, time),
+        Change::Removed(pid, e) => event("stopped", *pid, e, time),
+        Change::Changed(pid, old, new) => {
+            let mut event = event("changed", *pid, new, time);
+            event["previous"] = json!(old.line);
+            event
+        }
+    }
+}
+
+/// UTC time of day, enough to line events up with server logs
+pub fn timestamp() -> String {
+    let secs = now_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Print the processes from scan, then the changes every interval until interrupted. With
+/// changes_only nothing is printed for scans without changes, and ndjson events leave out the
+/// processes running at the start.
+pub fn watch<F>(
+    mut scan: F,
+    interval: Duration,
+    changes_only: bool,
+    output: &WatchOutput,
+) -> Result<()>
+where
+    F: FnMut() -> Result<Snapshot>,
+{
+    let color = std::io::stdout().is_terminal();
+    let mut previous = scan()?;
+    if !changes_only {
+        for (pid, entry) in &previous {
+            match output {
+                WatchOutput::Text => println!("{} {}", timestamp(), entry.line),
+                WatchOutput::Ndjson => println!("{}", event("running", *pid, entry, now_secs())),
+            }
+        }
+    }
+
+    loop {
+        std::thread::sleep(interval);
+        let current = scan()?;
+        let changes = diff(&previous, &current);
+        match output {
+            WatchOutput::Text => {
+                if changes.is_empty() && !changes_only {
+                    println!("{} no changes", timestamp());
+                }
+                for change in &changes {
+                    println!("{} {}", timestamp(), render(change, color));
+                }
+            }
+            WatchOutput::Ndjson => {
+                for change in &changes {
+                    println!("{}", change_event(change, now_secs()));
+                }
+            }
+        }
+        previous = current;
+    }
+}
+
+#[test]
+fn test_diff() {
+    let entry = |kind: &str, port: Option<i32>, line: &str| Entry {
+        kind: kind.to_owned(),
+        port,
+        line: line.to_owned(),
+    };
+    let old: Snapshot = [
+        (1, entry("mongod", Some(20001), "1 mongod a")),
+        (2, entry("mongod", Some(20002), "2 mongod b")),
+    ]
+    .into();
+    let new: Snapshot = [
+        (2, entry("mongod", Some(20002), "2 mongod c")),
+        (3, entry("shell", None, "3 shell")),
+    ]
+    .into();
+    let changes = diff(&old, &new);
+    assert_eq!(
+        changes,
+        vec![
+            Change::Removed(1, old[&1].clone()),
+            Change::Changed(2, old[&2].clone(), new[&2].clone()),
+            Change::Added(3, new[&3].clone()),
+        ]
+    );
+    assert!(diff(&new, &new).is_empty());
+    assert_eq!(render(&changes[2], false), "+ 3 shell");
+    assert_eq!(render(&changes[0], true), "\x1b[31m- 1 mongod a\x1b[0m");
+
+    assert_eq!(
+        change_event(&changes[0], 100),
+        json!({"event": "stopped", "type": "mongod", "pid": 1, "port": 20001,
+               "summary": "1 mongod a", "time": 100})
+    );
+    assert_eq!(change_event(&changes[1], 100)["previous"], "2 mongod b");
+    assert_eq!(change_event(&changes[2], 100)["port"], Value::Null);
+}